@@ -0,0 +1,104 @@
+use samesame_protocol::clipboard::Clipboard;
+use std::sync::Arc;
+
+/// The clipboard of this machine
+#[cfg(target_os = "macos")]
+pub fn system_clipboard() -> Arc<dyn Clipboard> {
+    Arc::new(mac::MacClipboard)
+}
+
+/// Stand-in for platforms without clipboard support
+#[cfg(not(target_os = "macos"))]
+pub fn system_clipboard() -> Arc<dyn Clipboard> {
+    Arc::new(samesame_protocol::clipboard::MemoryClipboard::new())
+}
+
+// The cocoa crate is deprecated in favour of objc2, which the client doesn't use yet
+#[cfg(target_os = "macos")]
+#[allow(deprecated)]
+mod mac {
+    use cocoa::appkit::{NSPasteboard, NSPasteboardTypeHTML, NSPasteboardTypePNG, NSPasteboardTypeString};
+    use cocoa::base::{id, nil, NO};
+    use cocoa::foundation::{NSAutoreleasePool, NSData, NSString};
+    use samesame_protocol::clipboard::{Clipboard, ClipboardContent};
+    use std::ffi::c_void;
+
+    /// The general pasteboard (NSPasteboard)
+    pub struct MacClipboard;
+
+    unsafe fn string_for_type(pasteboard: id, pasteboard_type: id) -> Option<String> {
+        let string = pasteboard.stringForType(pasteboard_type);
+        if string == nil {
+            return None;
+        }
+        let bytes = std::slice::from_raw_parts(string.UTF8String() as *const u8, string.len());
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    unsafe fn data_for_type(pasteboard: id, pasteboard_type: id) -> Option<Vec<u8>> {
+        let data = pasteboard.dataForType(pasteboard_type);
+        if data == nil {
+            return None;
+        }
+        let length = data.length() as usize;
+        if length == 0 {
+            return Some(Vec::new());
+        }
+        Some(std::slice::from_raw_parts(data.bytes() as *const u8, length).to_vec())
+    }
+
+    impl Clipboard for MacClipboard {
+        fn read(&self) -> Result<ClipboardContent, String> {
+            unsafe {
+                // Called from background threads, which have no pool of their own
+                let pool = NSAutoreleasePool::new(nil);
+                let pasteboard = NSPasteboard::generalPasteboard(nil);
+                let content = ClipboardContent {
+                    text: string_for_type(pasteboard, NSPasteboardTypeString),
+                    html: string_for_type(pasteboard, NSPasteboardTypeHTML),
+                    png: data_for_type(pasteboard, NSPasteboardTypePNG),
+                };
+                pool.drain();
+                Ok(content)
+            }
+        }
+
+        fn write(&self, content: &ClipboardContent) -> Result<(), String> {
+            unsafe {
+                let pool = NSAutoreleasePool::new(nil);
+                let pasteboard = NSPasteboard::generalPasteboard(nil);
+                pasteboard.clearContents();
+
+                let mut written = true;
+                if let Some(text) = &content.text {
+                    let string = NSString::alloc(nil).init_str(text).autorelease();
+                    written &= pasteboard.setString_forType(string, NSPasteboardTypeString) != NO;
+                }
+                if let Some(html) = &content.html {
+                    let string = NSString::alloc(nil).init_str(html).autorelease();
+                    written &= pasteboard.setString_forType(string, NSPasteboardTypeHTML) != NO;
+                }
+                if let Some(png) = &content.png {
+                    let data = NSData::dataWithBytes_length_(nil, png.as_ptr() as *const c_void, png.len() as u64);
+                    written &= pasteboard.setData_forType(data, NSPasteboardTypePNG) != NO;
+                }
+
+                pool.drain();
+                if written {
+                    Ok(())
+                } else {
+                    Err("Pasteboard rejected the content".to_string())
+                }
+            }
+        }
+
+        fn change_count(&self) -> Option<u64> {
+            unsafe {
+                let pool = NSAutoreleasePool::new(nil);
+                let count = NSPasteboard::generalPasteboard(nil).changeCount();
+                pool.drain();
+                Some(count as u64)
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::Result;
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent};
+use samesame_protocol::discovery::{Announcement, SERVICE_TYPE};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::profiles::ProfileId;
+
+/// How long to listen for announcements
+pub const BROWSE_TIME: Duration = Duration::from_secs(2);
+
+/// A server found on the local network
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveredServer {
+    #[serde(flatten)]
+    pub announcement: Announcement,
+    /// Addresses of the server, IPv4 first
+    pub addresses: Vec<IpAddr>,
+    /// The server speaks the protocol version of this app
+    pub compatible: bool,
+    /// Profile that already points at the server
+    pub profile: Option<ProfileId>,
+}
+
+/// Servers that announce themselves within `duration`, sorted by name
+///
+/// Loopback is browsed too, so a server on this machine is listed.
+/// Malformed announcements are skipped.
+pub async fn browse(duration: Duration) -> Result<Vec<DiscoveredServer>> {
+    let daemon = ServiceDaemon::new()?;
+    daemon.enable_interface(IfKind::LoopbackV4)?;
+    let events = daemon.browse(SERVICE_TYPE)?;
+
+    let mut found: HashMap<String, DiscoveredServer> = HashMap::new();
+    let deadline = tokio::time::Instant::now() + duration;
+    while let Ok(Ok(event)) = tokio::time::timeout_at(deadline, events.recv_async()).await {
+        match event {
+            ServiceEvent::ServiceResolved(info) => {
+                let fullname = info.get_fullname();
+                let name = fullname
+                    .strip_suffix(SERVICE_TYPE)
+                    .and_then(|name| name.strip_suffix('.'))
+                    .unwrap_or(fullname);
+                let announcement =
+                    match Announcement::from_properties(name, info.get_port(), |key| info.get_property_val_str(key)) {
+                        Ok(announcement) => announcement,
+                        Err(e) => {
+                            debug!("Ignoring announcement of {}: {}", fullname, e);
+                            continue;
+                        }
+                    };
+
+                // A server answers on each interface, with that interface's addresses
+                let server = found.entry(fullname.to_string()).or_insert_with(|| DiscoveredServer {
+                    announcement: announcement.clone(),
+                    addresses: Vec::new(),
+                    compatible: false,
+                    profile: None,
+                });
+                server.compatible = announcement.is_compatible();
+                server.announcement = announcement;
+                for address in info.get_addresses() {
+                    if !server.addresses.contains(address) {
+                        server.addresses.push(*address);
+                    }
+                }
+            }
+            ServiceEvent::ServiceRemoved(_, fullname) => {
+                found.remove(&fullname);
+            }
+            _ => {}
+        }
+    }
+    let _ = daemon.shutdown();
+
+    let mut servers: Vec<DiscoveredServer> = found
+        .into_values()
+        .filter(|server| !server.addresses.is_empty())
+        .collect();
+    for server in &mut servers {
+        server.addresses.sort_by_key(|address| (address.is_ipv6(), address.is_loopback(), *address));
+    }
+    servers.sort_by(|a, b| a.announcement.name.cmp(&b.announcement.name));
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdns_sd::ServiceInfo;
+
+    /// A second daemon announcing `name` on loopback, like a server on this machine
+    fn announce(name: &str, properties: &[(&str, &str)]) -> ServiceDaemon {
+        let info = ServiceInfo::new(SERVICE_TYPE, name, "discovery-test.local.", "127.0.0.1", 24800, properties).unwrap();
+        let daemon = ServiceDaemon::new().unwrap();
+        daemon.enable_interface(IfKind::LoopbackV4).unwrap();
+        daemon.register(info).unwrap();
+        daemon
+    }
+
+    #[tokio::test]
+    async fn servers_on_loopback_are_found() {
+        // Unique per run, so servers announced by other runs are told apart
+        let name = format!("Browse Test {}", std::process::id());
+        let fingerprint = vec!["AB"; 32].join(":");
+        let announcement = Announcement::new(&name, 24800, true, Some(fingerprint), Some(24801));
+        let properties = announcement.properties();
+        let properties: Vec<(&str, &str)> = properties.iter().map(|(key, value)| (*key, value.as_str())).collect();
+        let server = announce(&name, &properties);
+        let malformed_name = format!("{} Malformed", name);
+        let malformed = announce(&malformed_name, &[("version", "one")]);
+
+        let servers = browse(BROWSE_TIME).await.unwrap();
+        let _ = server.shutdown();
+        let _ = malformed.shutdown();
+
+        let found = servers
+            .iter()
+            .find(|server| server.announcement.name == name)
+            .expect("server not found");
+        assert_eq!(found.announcement, announcement);
+        assert!(found.compatible);
+        assert!(found.addresses.contains(&IpAddr::from([127, 0, 0, 1])));
+        assert!(!servers.iter().any(|server| server.announcement.name == malformed_name));
+    }
+}
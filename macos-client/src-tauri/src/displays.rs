@@ -0,0 +1,39 @@
+use core_graphics::display::CGDisplay;
+use samesame_protocol::mapping::{CoordinateSpace, MonitorInfo, ScreenInfo};
+use tracing::error;
+
+/// Monitor topology of this Mac in points (global display coordinates)
+pub fn screen_info() -> ScreenInfo {
+    let displays = CGDisplay::active_displays().unwrap_or_else(|e| {
+        error!("Failed to list displays: {:?}", e);
+        Vec::new()
+    });
+
+    let monitors = displays
+        .into_iter()
+        .map(|id| {
+            let display = CGDisplay::new(id);
+            let bounds = display.bounds();
+            let scale_factor = if bounds.size.width > 0.0 {
+                display.pixels_wide() as f64 / bounds.size.width
+            } else {
+                1.0
+            };
+
+            MonitorInfo {
+                id,
+                x: bounds.origin.x,
+                y: bounds.origin.y,
+                width: bounds.size.width,
+                height: bounds.size.height,
+                scale_factor,
+                primary: display.is_main(),
+            }
+        })
+        .collect();
+
+    ScreenInfo {
+        coordinate_space: CoordinateSpace::Logical,
+        monitors,
+    }
+}
@@ -1,80 +1,125 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use core_foundation::base::TCFType;
+use core_foundation::mach_port::{CFMachPort, CFMachPortRef};
 use core_foundation::runloop::{kCFRunLoopCommonModes, CFRunLoop};
+use core_graphics::display::CGDisplay;
+use core_graphics::geometry::CGPoint;
 use core_graphics::event::{
-    CGEvent, CGEventFlags, CGEventTap, CGEventTapLocation, CGEventTapOptions,
-    CGEventTapPlacement, CGEventType, EventField,
+    CGEvent, CGEventField, CGEventFlags, CGEventMask, CGEventTapLocation, CGEventTapOptions, CGEventTapPlacement,
+    CGEventTapProxy, CGEventType, EventField,
 };
+use core_graphics::sys::CGEventRef;
+use foreign_types::ForeignType;
+use objc::runtime::Sel;
+use objc::Message;
+use parking_lot::Mutex;
 use samesame_protocol::{
-    GestureEvent, GestureType, InputEvent, KeyboardEvent, Message, Modifiers, MouseButton,
-    MouseButtonEvent, MouseMoveEvent, MouseScrollEvent,
+    InputEvent, KeyboardEvent, Modifiers, MomentumPhase, MouseButton, MouseButtonEvent, MouseMoveEvent,
+    MouseScrollEvent, ScrollPhase,
 };
-use std::sync::{Arc, Mutex};
+use samesame_protocol::mapping::ScreenInfo;
+use std::cell::Cell;
+use std::ffi::{c_ulong, c_void};
+use std::mem::ManuallyDrop;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
 use tauri::AppHandle;
-use tracing::{debug, error, info, warn};
+use tracing::{error, info, warn};
 
+use crate::hotkeys::{match_hotkey, HotkeyAction};
+use crate::media_keys::{decode_aux_button, NX_SUBTYPE_AUX_CONTROL_BUTTONS, NX_SYSDEFINED};
 use crate::state::{AppState, InputMode};
 
+/// kCGScrollWheelEventScrollPhase (missing from core-graphics)
+const SCROLL_WHEEL_EVENT_SCROLL_PHASE: CGEventField = 99;
+/// kCGScrollWheelEventMomentumPhase (missing from core-graphics)
+const SCROLL_WHEEL_EVENT_MOMENTUM_PHASE: CGEventField = 123;
+
+/// kCGEventTapDisabledByTimeout and kCGEventTapDisabledByUserInput
+const TAP_DISABLED_BY_TIMEOUT: u32 = 0xFFFF_FFFE;
+const TAP_DISABLED_BY_USER_INPUT: u32 = 0xFFFF_FFFF;
+
+/// Event types we want to capture, next to the media keys
+const CAPTURED_EVENTS: [CGEventType; 14] = [
+    CGEventType::KeyDown,
+    CGEventType::KeyUp,
+    CGEventType::FlagsChanged,
+    CGEventType::LeftMouseDown,
+    CGEventType::LeftMouseUp,
+    CGEventType::RightMouseDown,
+    CGEventType::RightMouseUp,
+    CGEventType::MouseMoved,
+    CGEventType::LeftMouseDragged,
+    CGEventType::RightMouseDragged,
+    CGEventType::ScrollWheel,
+    CGEventType::OtherMouseDown,
+    CGEventType::OtherMouseUp,
+    CGEventType::OtherMouseDragged,
+];
+
+// The tap is created directly: the core-graphics wrapper cannot block events
+// (its callback returning None passes the original on) and its event type
+// enum has no value for NX_SYSDEFINED, which carries the media keys.
+type TapCallback = unsafe extern "C" fn(CGEventTapProxy, u32, CGEventRef, *mut c_void) -> CGEventRef;
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGEventTapCreate(
+        tap: CGEventTapLocation,
+        place: CGEventTapPlacement,
+        options: CGEventTapOptions,
+        events_of_interest: CGEventMask,
+        callback: TapCallback,
+        user_info: *mut c_void,
+    ) -> CFMachPortRef;
+    fn CGEventTapEnable(tap: CFMachPortRef, enable: bool);
+    fn CGEventKeyboardGetUnicodeString(event: CGEventRef, max_length: c_ulong, length: *mut c_ulong, string: *mut u16);
+}
+
+/// What the tap callback works with, on the thread running the tap
+struct Tap {
+    state: Arc<Mutex<AppState>>,
+    port: Cell<CFMachPortRef>,
+}
+
 /// Start the event tap to capture keyboard and mouse events
-pub fn start_event_tap(app_handle: AppHandle, state: Arc<Mutex<AppState>>) -> Result<()> {
+///
+/// Runs the run loop of the calling thread and does not return while the tap
+/// is active.
+pub fn start_event_tap(_app_handle: AppHandle, state: Arc<Mutex<AppState>>) -> Result<()> {
     info!("Starting event tap...");
 
-    // Event types we want to capture
-    let event_mask = CGEventType::KeyDown as u64
-        | CGEventType::KeyUp as u64
-        | CGEventType::FlagsChanged as u64
-        | CGEventType::LeftMouseDown as u64
-        | CGEventType::LeftMouseUp as u64
-        | CGEventType::RightMouseDown as u64
-        | CGEventType::RightMouseUp as u64
-        | CGEventType::MouseMoved as u64
-        | CGEventType::LeftMouseDragged as u64
-        | CGEventType::RightMouseDragged as u64
-        | CGEventType::ScrollWheel as u64
-        | CGEventType::OtherMouseDown as u64
-        | CGEventType::OtherMouseUp as u64
-        | CGEventType::OtherMouseDragged as u64;
-
-    // Create the event tap callback
-    let state_clone = state.clone();
-    let callback = move |_proxy: CGEventTapProxy, event_type: CGEventType, event: &CGEvent| {
-        match handle_event(event_type, event, &state_clone) {
-            Ok(should_block) => {
-                if should_block {
-                    // Block the event (don't pass it to macOS)
-                    None
-                } else {
-                    // Pass through the event
-                    Some(event.to_owned())
-                }
-            }
-            Err(e) => {
-                error!("Error handling event: {}", e);
-                Some(event.to_owned())
-            }
-        }
+    let event_mask = CAPTURED_EVENTS
+        .iter()
+        .fold(1 << NX_SYSDEFINED as CGEventMask, |mask, &event_type| mask | 1 << event_type as CGEventMask);
+
+    // Used by the callback for as long as the run loop runs
+    let tap: &'static Tap = Box::leak(Box::new(Tap {
+        state,
+        port: Cell::new(std::ptr::null_mut()),
+    }));
+    let port_ref = unsafe {
+        CGEventTapCreate(
+            CGEventTapLocation::HID,
+            CGEventTapPlacement::HeadInsertEventTap,
+            CGEventTapOptions::Default,
+            event_mask,
+            tap_callback,
+            tap as *const Tap as *mut c_void,
+        )
     };
-
-    // Create event tap
-    let event_tap = CGEventTap::new(
-        CGEventTapLocation::HID,
-        CGEventTapPlacement::HeadInsertEventTap,
-        CGEventTapOptions::Default,
-        event_mask,
-        callback,
-    )
-    .ok_or_else(|| anyhow::anyhow!("Failed to create event tap. Please grant accessibility permissions."))?;
-
-    // Enable the event tap
-    event_tap.enable();
+    if port_ref.is_null() {
+        bail!("Failed to create event tap. Please grant accessibility permissions.");
+    }
+    let port = unsafe { CFMachPort::wrap_under_create_rule(port_ref) };
+    tap.port.set(port_ref);
+    unsafe { CGEventTapEnable(port_ref, true) };
 
     // Create a run loop source and add it to the current run loop
-    let run_loop_source = event_tap
-        .mach_port
+    let run_loop_source = port
         .create_runloop_source(0)
-        .ok_or_else(|| anyhow::anyhow!("Failed to create run loop source"))?;
-
-    let run_loop = CFRunLoop::get_current();
-    run_loop.add_source(&run_loop_source, unsafe { kCFRunLoopCommonModes });
+        .map_err(|_| anyhow!("Failed to create run loop source"))?;
+    CFRunLoop::get_current().add_source(&run_loop_source, unsafe { kCFRunLoopCommonModes });
 
     info!("Event tap started successfully");
 
@@ -84,59 +129,103 @@ pub fn start_event_tap(app_handle: AppHandle, state: Arc<Mutex<AppState>>) -> Re
     Ok(())
 }
 
+/// Called by the run loop for every captured event
+///
+/// Returning null drops the event, so macOS never sees it.
+unsafe extern "C" fn tap_callback(
+    _proxy: CGEventTapProxy,
+    event_type: u32,
+    event_ref: CGEventRef,
+    user_info: *mut c_void,
+) -> CGEventRef {
+    let tap = &*(user_info as *const Tap);
+
+    // macOS turns off a tap that answers too slowly
+    if event_type == TAP_DISABLED_BY_TIMEOUT || event_type == TAP_DISABLED_BY_USER_INPUT {
+        warn!("Event tap was disabled, enabling it again");
+        CGEventTapEnable(tap.port.get(), true);
+        return event_ref;
+    }
+
+    // The event stays owned by the caller
+    let event = ManuallyDrop::new(CGEvent::from_ptr(event_ref));
+    match handle_event(event_type, &event, &tap.state) {
+        Ok(true) => std::ptr::null_mut(),
+        Ok(false) => event_ref,
+        Err(e) => {
+            error!("Error handling event: {}", e);
+            event_ref
+        }
+    }
+}
+
 /// Handle a single event and decide whether to block it
-fn handle_event(
-    event_type: CGEventType,
-    event: &CGEvent,
-    state: &Arc<Mutex<AppState>>,
-) -> Result<bool> {
-    let mut app_state = state.lock().unwrap();
-
-    // Check for Option+1 hotkey to toggle mode
-    if is_toggle_hotkey(event_type, event) {
-        app_state.mode = match app_state.mode {
-            InputMode::MacOS => {
-                info!("Switched to Windows mode");
-                InputMode::Windows
-            }
-            InputMode::Windows => {
-                info!("Switched to macOS mode");
-                InputMode::MacOS
-            }
+fn handle_event(event_type: u32, event: &CGEvent, state: &Arc<Mutex<AppState>>) -> Result<bool> {
+    let mut app_state = state.lock();
+
+    // Media keys arrive as system-defined events, not as key presses
+    if event_type == NX_SYSDEFINED {
+        let Some((key, pressed)) = aux_control_button(event) else {
+            return Ok(false);
         };
-        // Don't block the toggle hotkey itself
+        return Ok(app_state.handle_media_key(key, pressed));
+    }
+    let Some(&event_type) = CAPTURED_EVENTS.iter().find(|&&captured| captured as u32 == event_type) else {
+        return Ok(false);
+    };
+
+    // Check for Option+1 / Ctrl+Option+N hotkeys to switch targets
+    if let Some(action) = match_hotkey_event(event_type, event) {
+        app_state.handle_hotkey(action);
+        // Don't block the hotkey itself
         return Ok(false);
     }
 
-    // If in macOS mode, pass through all events
-    if app_state.mode == InputMode::MacOS {
+    // Caps Lock is a modifier flag on the Mac, not a key press
+    if matches!(event_type, CGEventType::FlagsChanged) {
+        app_state.update_local_locks(crate::keyboard::lock_state_from_flags(event.get_flags()));
+    }
+
+    // Screen-edge switching follows every pointer movement
+    if is_mouse_move(event_type) {
+        let location = event.location();
+        let delta = (
+            event.get_double_value_field(EventField::MOUSE_EVENT_DELTA_X),
+            event.get_double_value_field(EventField::MOUSE_EVENT_DELTA_Y),
+        );
+        if let Some(transition) = app_state.track_pointer((location.x, location.y), delta, Instant::now()) {
+            if transition.to == InputMode::Local {
+                warp_local_cursor(transition.entry_x, transition.entry_y);
+            }
+            // The crossing movement itself is not forwarded
+            return Ok(true);
+        }
+    }
+
+    // If in local mode, pass through all events
+    if app_state.mode == InputMode::Local {
+        return Ok(false);
+    }
+
+    // Keys of composing input sources go to the app's text capture field, which
+    // forwards the composed text (see send_text_input)
+    if matches!(event_type, CGEventType::KeyDown | CGEventType::KeyUp)
+        && app_state.forwards_text(crate::keyboard::current_input_source().as_deref())
+    {
         return Ok(false);
     }
 
-    // If in Windows mode but not connected, still block to avoid duplicate input
-    if app_state.network_client.is_none() {
-        warn!("In Windows mode but not connected to server. Blocking input.");
+    // If forwarding but the target is not connected, still block to avoid duplicate input
+    if !app_state.should_forward() {
+        warn!("Target {:?} is not connected. Blocking input.", app_state.mode);
         return Ok(true);
     }
 
     // Convert and forward the event
-    if let Some(input_event) = convert_cg_event_to_input_event(event_type, event)? {
-        let sequence = app_state.next_sequence();
-        let message = Message::new(sequence, input_event);
-
-        // Send to Windows server
-        if let Some(ref mut client) = app_state.network_client {
-            let client_clone = client.try_clone()?;
-            let msg_clone = message.clone();
-
-            // Send in background to avoid blocking event loop
-            tokio::spawn(async move {
-                let mut stream = client_clone;
-                if let Err(e) = crate::network::send_event(&mut stream, msg_clone).await {
-                    error!("Failed to send event: {}", e);
-                }
-            });
-        }
+    let screens = app_state.local_screens.get_or_insert_with(crate::displays::screen_info);
+    if let Some(input_event) = convert_cg_event_to_input_event(event_type, event, screens)? {
+        // Queue for the active target (and broadcast targets); connections write in the background
+        app_state.forward(input_event);
 
         // Block the event so it doesn't go to macOS
         return Ok(true);
@@ -146,74 +235,117 @@ fn handle_event(
     Ok(false)
 }
 
-/// Check if the event is the toggle hotkey (Option+1)
-fn is_toggle_hotkey(event_type: CGEventType, event: &CGEvent) -> bool {
-    if event_type != CGEventType::KeyDown {
-        return false;
+/// Media key carried by a system-defined event
+// The cocoa crate is deprecated in favour of objc2, which the client doesn't use yet
+#[allow(deprecated)]
+fn aux_control_button(event: &CGEvent) -> Option<(samesame_protocol::consumer::ConsumerKey, bool)> {
+    use cocoa::appkit::NSEvent;
+    use cocoa::base::nil;
+
+    unsafe {
+        let ns_event = NSEvent::eventWithCGEvent_(nil, event.as_ptr() as *mut c_void);
+        if ns_event == nil {
+            return None;
+        }
+        // Read as the raw `short`, cocoa's NSEventSubtype has no value for most subtypes
+        let subtype: i16 = (*ns_event).send_message(Sel::register("subtype"), ()).ok()?;
+        if subtype != NX_SUBTYPE_AUX_CONTROL_BUTTONS {
+            return None;
+        }
+        decode_aux_button(ns_event.data1())
+    }
+}
+
+/// Character a key press produces with the current input source
+fn key_character(event: &CGEvent) -> Option<char> {
+    let mut buffer = [0u16; 4];
+    let mut length: c_ulong = 0;
+    unsafe {
+        CGEventKeyboardGetUnicodeString(event.as_ptr(), buffer.len() as c_ulong, &mut length, buffer.as_mut_ptr());
+    }
+    let length = (length as usize).min(buffer.len());
+    char::decode_utf16(buffer[..length].iter().copied()).next()?.ok()
+}
+
+/// Check if the event moves the pointer
+fn is_mouse_move(event_type: CGEventType) -> bool {
+    matches!(
+        event_type,
+        CGEventType::MouseMoved
+            | CGEventType::LeftMouseDragged
+            | CGEventType::RightMouseDragged
+            | CGEventType::OtherMouseDragged
+    )
+}
+
+/// Place the Mac cursor at the entry point after returning from a target
+fn warp_local_cursor(x: f64, y: f64) {
+    if let Err(e) = CGDisplay::warp_mouse_cursor_position(CGPoint::new(x, y)) {
+        error!("Failed to warp cursor: {:?}", e);
     }
+}
 
-    let keycode = event.get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE);
-    let flags = event.get_flags();
+/// Check if the event is one of the global hotkeys
+fn match_hotkey_event(event_type: CGEventType, event: &CGEvent) -> Option<HotkeyAction> {
+    if !matches!(event_type, CGEventType::KeyDown) {
+        return None;
+    }
 
-    // Option+1: keycode 18 is '1', Option flag is 0x80000 (524288)
-    keycode == 18 && flags.contains(CGEventFlags::CGEventFlagAlternate)
+    let keycode = event.get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE) as u16;
+    match_hotkey(keycode, &extract_modifiers(event.get_flags()))
 }
 
 /// Convert CGEvent to our InputEvent
 fn convert_cg_event_to_input_event(
     event_type: CGEventType,
     event: &CGEvent,
+    screens: &ScreenInfo,
 ) -> Result<Option<InputEvent>> {
     let result = match event_type {
         CGEventType::KeyDown | CGEventType::KeyUp => {
             let keycode = event.get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE) as u16;
             let flags = event.get_flags();
-            let pressed = event_type == CGEventType::KeyDown;
-
-            // Try to get the character
-            let mut character = None;
-            if pressed {
-                // Get Unicode string from event
-                // This is a simplified version - full implementation would need more work
-                let chars = event.get_string();
-                if let Some(s) = chars {
-                    character = s.chars().next();
-                }
-            }
+            let pressed = matches!(event_type, CGEventType::KeyDown);
+
+            let character = if pressed { key_character(event) } else { None };
 
             Some(InputEvent::Keyboard(KeyboardEvent {
                 key_code: keycode,
                 character,
                 pressed,
                 modifiers: extract_modifiers(flags),
+                is_repeat: event.get_integer_value_field(EventField::KEYBOARD_EVENT_AUTOREPEAT) != 0,
             }))
         }
 
         CGEventType::MouseMoved | CGEventType::LeftMouseDragged | CGEventType::RightMouseDragged => {
             let location = event.location();
-            let delta_x = event.get_integer_value_field(EventField::MOUSE_EVENT_DELTA_X) as f64;
-            let delta_y = event.get_integer_value_field(EventField::MOUSE_EVENT_DELTA_Y) as f64;
+            // Double fields keep the sub-pixel part of the movement
+            let delta_x = event.get_double_value_field(EventField::MOUSE_EVENT_DELTA_X);
+            let delta_y = event.get_double_value_field(EventField::MOUSE_EVENT_DELTA_Y);
 
-            // TODO: Normalize to 0.0-1.0 based on screen dimensions
+            // Normalize to the whole desktop (all displays); the server maps it per monitor
+            let (x, y) = screens.normalize(location.x, location.y);
             Some(InputEvent::MouseMove(MouseMoveEvent {
-                x: location.x / 2560.0, // Simplified - should get actual screen width
-                y: location.y / 1440.0, // Simplified - should get actual screen height
+                x,
+                y,
                 delta_x,
                 delta_y,
+                timestamp_us: timestamp_us(),
             }))
         }
 
         CGEventType::LeftMouseDown | CGEventType::LeftMouseUp => {
             Some(InputEvent::MouseButton(MouseButtonEvent {
                 button: MouseButton::Left,
-                pressed: event_type == CGEventType::LeftMouseDown,
+                pressed: matches!(event_type, CGEventType::LeftMouseDown),
             }))
         }
 
         CGEventType::RightMouseDown | CGEventType::RightMouseUp => {
             Some(InputEvent::MouseButton(MouseButtonEvent {
                 button: MouseButton::Right,
-                pressed: event_type == CGEventType::RightMouseDown,
+                pressed: matches!(event_type, CGEventType::RightMouseDown),
             }))
         }
 
@@ -228,19 +360,31 @@ fn convert_cg_event_to_input_event(
 
             Some(InputEvent::MouseButton(MouseButtonEvent {
                 button,
-                pressed: event_type == CGEventType::OtherMouseDown,
+                pressed: matches!(event_type, CGEventType::OtherMouseDown),
             }))
         }
 
         CGEventType::ScrollWheel => {
-            let delta_y = event.get_integer_value_field(EventField::SCROLL_WHEEL_EVENT_DELTA_AXIS_1) as f64;
-            let delta_x = event.get_integer_value_field(EventField::SCROLL_WHEEL_EVENT_DELTA_AXIS_2) as f64;
             let is_pixel_based = event.get_integer_value_field(EventField::SCROLL_WHEEL_EVENT_IS_CONTINUOUS) != 0;
+            let (delta_x, delta_y) = if is_pixel_based {
+                // Trackpads and Magic Mouse report fractional pixels
+                (
+                    event.get_double_value_field(EventField::SCROLL_WHEEL_EVENT_POINT_DELTA_AXIS_2),
+                    event.get_double_value_field(EventField::SCROLL_WHEEL_EVENT_POINT_DELTA_AXIS_1),
+                )
+            } else {
+                (
+                    event.get_integer_value_field(EventField::SCROLL_WHEEL_EVENT_DELTA_AXIS_2) as f64,
+                    event.get_integer_value_field(EventField::SCROLL_WHEEL_EVENT_DELTA_AXIS_1) as f64,
+                )
+            };
 
             Some(InputEvent::MouseScroll(MouseScrollEvent {
                 delta_x,
                 delta_y,
                 is_pixel_based,
+                phase: scroll_phase(event.get_integer_value_field(SCROLL_WHEEL_EVENT_SCROLL_PHASE)),
+                momentum_phase: momentum_phase(event.get_integer_value_field(SCROLL_WHEEL_EVENT_MOMENTUM_PHASE)),
             }))
         }
 
@@ -250,6 +394,34 @@ fn convert_cg_event_to_input_event(
     Ok(result)
 }
 
+/// Map kCGScrollWheelEventScrollPhase (an NSEventPhase mask)
+fn scroll_phase(value: i64) -> ScrollPhase {
+    match value {
+        1 => ScrollPhase::Began,
+        2 => ScrollPhase::Changed,
+        4 => ScrollPhase::Ended,
+        8 => ScrollPhase::Cancelled,
+        128 => ScrollPhase::MayBegin,
+        _ => ScrollPhase::None,
+    }
+}
+
+/// Map kCGScrollWheelEventMomentumPhase (CGMomentumScrollPhase)
+fn momentum_phase(value: i64) -> MomentumPhase {
+    match value {
+        1 => MomentumPhase::Began,
+        2 => MomentumPhase::Changed,
+        3 => MomentumPhase::Ended,
+        _ => MomentumPhase::None,
+    }
+}
+
+/// Microseconds on a monotonic clock, for the server's pointer speed calculation
+fn timestamp_us() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
+
 /// Extract modifier keys from CGEventFlags
 fn extract_modifiers(flags: CGEventFlags) -> Modifiers {
     Modifiers {
@@ -260,5 +432,3 @@ fn extract_modifiers(flags: CGEventFlags) -> Modifiers {
     }
 }
 
-// Placeholder type for CGEventTapProxy
-type CGEventTapProxy = u32;
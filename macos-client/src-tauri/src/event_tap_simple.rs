@@ -1,28 +0,0 @@
-// Simplified event tap implementation
-// This is a placeholder - full implementation would require more work with CGEventTap
-
-use anyhow::Result;
-use parking_lot::Mutex;
-use std::sync::Arc;
-use tauri::AppHandle;
-use tracing::{info, warn};
-
-use crate::state::AppState;
-
-/// Start the event tap (simplified version)
-pub fn start_event_tap(_app_handle: AppHandle, _state: Arc<Mutex<AppState>>) -> Result<()> {
-    info!("Event tap starting (simplified version)...");
-    warn!("Full CGEventTap implementation requires additional work");
-    warn!("For now, use the UI to toggle modes and test connectivity");
-
-    // In a full implementation, this would:
-    // 1. Create a CGEventTap
-    // 2. Set up event callbacks
-    // 3. Block keyboard/mouse when in Windows mode
-    // 4. Forward events to the network client
-
-    // For now, just keep the thread alive
-    std::thread::park();
-
-    Ok(())
-}
@@ -0,0 +1,263 @@
+use anyhow::{anyhow, bail, Context, Result};
+use parking_lot::Mutex;
+use samesame_protocol::transfer::{sha256_file, FileChunk, FileOffer, FileTransfer, FILE_CHUNK_SIZE, SEND_WINDOW};
+use samesame_protocol::InputEvent;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::network::{self, CliTransport};
+use crate::profiles::ProfileId;
+use crate::state::AppState;
+
+/// Longest wait for an answer from the server (verifying a large file takes a while)
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Port the server listens on
+const DEFAULT_PORT: u16 = 24800;
+
+static NEXT_TRANSFER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Result of sending a file
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    pub name: String,
+    pub size: u64,
+    /// Bytes the server already had from an interrupted transfer
+    pub resumed_from: u64,
+    /// Where the server stored the file
+    pub path: String,
+}
+
+/// Send a file over any connection
+///
+/// `send` queues a message as bulk data, `replies` delivers the server's
+/// answers for this transfer.
+pub async fn send_file<S>(
+    path: &Path,
+    transfer_id: u64,
+    mut send: S,
+    replies: &mut mpsc::UnboundedReceiver<FileTransfer>,
+) -> Result<FileReport>
+where
+    S: FnMut(FileTransfer) -> Result<()>,
+{
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file", path.display()))?
+        .to_string_lossy()
+        .into_owned();
+    let size = tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("Cannot read {}", path.display()))?
+        .len();
+    let hash_path = path.to_path_buf();
+    let sha256 = tokio::task::spawn_blocking(move || sha256_file(hash_path)).await??;
+
+    send(FileTransfer::Offer(FileOffer {
+        transfer_id,
+        name: name.clone(),
+        size,
+        sha256,
+    }))?;
+    let offset = match next_reply(replies).await? {
+        FileTransfer::Accept { offset, .. } if offset <= size => offset,
+        FileTransfer::Reject { reason, .. } => bail!("Server refused {}: {}", name, reason),
+        other => bail!("Unexpected answer to offer: {:?}", other),
+    };
+    if offset > 0 {
+        info!("Resuming {} at {} of {} bytes", name, offset, size);
+    }
+
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let mut buffer = vec![0u8; FILE_CHUNK_SIZE];
+    let mut sent = offset;
+    let mut acknowledged = offset;
+    let mut stored = None;
+
+    while sent < size && stored.is_none() {
+        // Wait for progress while the window is full, handle what already arrived otherwise
+        while sent - acknowledged >= SEND_WINDOW {
+            stored = apply_reply(next_reply(replies).await?, &mut acknowledged)?;
+        }
+        while let Ok(reply) = replies.try_recv() {
+            stored = apply_reply(reply, &mut acknowledged)?;
+        }
+
+        let n = file.read(&mut buffer[..(size - sent).min(FILE_CHUNK_SIZE as u64) as usize]).await?;
+        if n == 0 {
+            bail!("{} got shorter while sending it", path.display());
+        }
+        send(FileTransfer::Chunk(FileChunk {
+            transfer_id,
+            offset: sent,
+            data: buffer[..n].to_vec(),
+        }))?;
+        sent += n as u64;
+    }
+
+    // The server answers once it has verified the whole file
+    while stored.is_none() {
+        stored = apply_reply(next_reply(replies).await?, &mut acknowledged)?;
+    }
+
+    Ok(FileReport {
+        name,
+        size,
+        resumed_from: offset,
+        path: stored.unwrap_or_default(),
+    })
+}
+
+async fn next_reply(replies: &mut mpsc::UnboundedReceiver<FileTransfer>) -> Result<FileTransfer> {
+    tokio::time::timeout(REPLY_TIMEOUT, replies.recv())
+        .await
+        .map_err(|_| anyhow!("Server did not answer"))?
+        .ok_or_else(|| anyhow!("Connection closed"))
+}
+
+/// Returns the stored path once the server reports the file complete
+fn apply_reply(reply: FileTransfer, acknowledged: &mut u64) -> Result<Option<String>> {
+    match reply {
+        FileTransfer::Progress { received, .. } => {
+            *acknowledged = received.max(*acknowledged);
+            Ok(None)
+        }
+        FileTransfer::Complete { path, .. } => Ok(Some(path)),
+        FileTransfer::Failed { reason, .. } => bail!("Transfer failed: {}", reason),
+        other => {
+            warn!("Unexpected file transfer message: {:?}", other);
+            Ok(None)
+        }
+    }
+}
+
+/// Send a file to a connected profile
+///
+/// If the connection drops, the file is remembered and resumed when the
+/// profile connects again.
+pub async fn send_to_profile(state: Arc<Mutex<AppState>>, id: ProfileId, path: PathBuf) -> Result<FileReport> {
+    let transfer_id = NEXT_TRANSFER_ID.fetch_add(1, Ordering::Relaxed);
+    let (reply_sender, mut replies) = mpsc::unbounded_channel();
+    state.lock().file_replies.insert(transfer_id, reply_sender);
+
+    let result = send_file(
+        &path,
+        transfer_id,
+        |message| state.lock().send_bulk(id, InputEvent::FileTransfer(message)),
+        &mut replies,
+    )
+    .await;
+
+    let mut app_state = state.lock();
+    app_state.file_replies.remove(&transfer_id);
+    match &result {
+        Ok(report) => info!("Sent {} to profile {} ({})", report.name, id, report.path),
+        Err(e) if !app_state.is_connected(id) => {
+            warn!("Sending {} to profile {} interrupted: {}", path.display(), id, e);
+            app_state.unfinished_files.entry(id).or_default().push(path);
+        }
+        Err(e) => {
+            warn!("Sending {} to profile {} failed: {}", path.display(), id, e);
+            let _ = app_state.send_bulk(id, InputEvent::FileTransfer(FileTransfer::Cancel { transfer_id }));
+        }
+    }
+    result
+}
+
+/// Send files one after another, stopping at the first error
+pub async fn send_all(state: Arc<Mutex<AppState>>, id: ProfileId, paths: Vec<PathBuf>) -> Result<Vec<FileReport>> {
+    let mut reports = Vec::with_capacity(paths.len());
+    for path in paths {
+        reports.push(send_to_profile(state.clone(), id, path).await?);
+    }
+    Ok(reports)
+}
+
+/// Continue the transfers a dropped connection interrupted
+pub async fn resume_unfinished(state: Arc<Mutex<AppState>>, id: ProfileId) {
+    let paths = state.lock().unfinished_files.remove(&id).unwrap_or_default();
+    if paths.is_empty() {
+        return;
+    }
+    info!("Resuming {} interrupted file transfer(s) to profile {}", paths.len(), id);
+    if let Err(e) = send_all(state, id, paths).await {
+        warn!("Resumed file transfer failed: {}", e);
+    }
+}
+
+/// Connect to a server, send files and disconnect
+pub async fn send_to_server(
+    host: &str,
+    port: u16,
+    transport: CliTransport,
+    paths: &[PathBuf],
+) -> Result<Vec<FileReport>> {
+    let (reply_sender, mut replies) = mpsc::unbounded_channel();
+    let mut connection = network::connect_cli(host, port, transport, move |message| {
+        if let InputEvent::FileTransfer(reply) = message.event {
+            let _ = reply_sender.send(reply);
+        }
+    })
+    .await?;
+
+    let mut reports = Vec::with_capacity(paths.len());
+    for path in paths {
+        let transfer_id = NEXT_TRANSFER_ID.fetch_add(1, Ordering::Relaxed);
+        let report = send_file(
+            path,
+            transfer_id,
+            |message| connection.send_bulk(InputEvent::FileTransfer(message)),
+            &mut replies,
+        )
+        .await?;
+        reports.push(report);
+    }
+    connection.close().await;
+
+    Ok(reports)
+}
+
+/// `send` command line entry
+///
+/// Usage: `send --host HOST [--port PORT] [--tls|--quic] FILE...`.
+pub fn run_send_command(args: &[String]) -> Result<()> {
+    let mut host = None;
+    let mut port = DEFAULT_PORT;
+    let mut transport = CliTransport::default();
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--host" => host = Some(value()?.clone()),
+            "--port" => port = value()?.parse()?,
+            "--tls" => transport = CliTransport::Tls,
+            "--quic" => transport = CliTransport::Quic,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let host = host.ok_or_else(|| anyhow!("--host is required"))?;
+    if paths.is_empty() {
+        bail!("No files given");
+    }
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    for report in runtime.block_on(send_to_server(&host, port, transport, &paths))? {
+        let resumed = if report.resumed_from > 0 {
+            format!(", resumed at {} bytes", report.resumed_from)
+        } else {
+            String::new()
+        };
+        println!("{} ({} bytes{}) → {}", report.name, report.size, resumed, report.path);
+    }
+    Ok(())
+}
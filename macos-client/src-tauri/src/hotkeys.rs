@@ -0,0 +1,43 @@
+use samesame_protocol::Modifiers;
+
+/// Actions bound to global hotkeys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyAction {
+    /// Option+1: toggle between local input and the last target
+    ToggleMode,
+    /// Ctrl+Option+N: forward input to the profile bound to N
+    SelectTarget(u8),
+}
+
+/// Match a key press against the global hotkeys
+pub fn match_hotkey(key_code: u16, modifiers: &Modifiers) -> Option<HotkeyAction> {
+    if !modifiers.alt || modifiers.command || modifiers.shift {
+        return None;
+    }
+
+    let digit = digit_for_key_code(key_code)?;
+
+    if modifiers.control {
+        Some(HotkeyAction::SelectTarget(digit))
+    } else if digit == 1 {
+        Some(HotkeyAction::ToggleMode)
+    } else {
+        None
+    }
+}
+
+/// Map macOS key codes of the number row to their digit (1-9)
+fn digit_for_key_code(key_code: u16) -> Option<u8> {
+    match key_code {
+        0x12 => Some(1),
+        0x13 => Some(2),
+        0x14 => Some(3),
+        0x15 => Some(4),
+        0x17 => Some(5),
+        0x16 => Some(6),
+        0x1A => Some(7),
+        0x1C => Some(8),
+        0x19 => Some(9),
+        _ => None,
+    }
+}
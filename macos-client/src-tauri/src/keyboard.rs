@@ -0,0 +1,52 @@
+use core_foundation::base::{CFRelease, TCFType};
+use core_foundation::string::{CFString, CFStringRef};
+use core_graphics::event::CGEventFlags;
+use samesame_protocol::LockState;
+use std::ffi::c_void;
+
+/// kCGEventSourceStateCombinedSessionState
+const COMBINED_SESSION_STATE: i32 = 0;
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGEventSourceFlagsState(state_id: i32) -> u64;
+}
+
+#[link(name = "Carbon", kind = "framework")]
+extern "C" {
+    static kTISPropertyInputSourceID: CFStringRef;
+    fn TISCopyCurrentKeyboardInputSource() -> *mut c_void;
+    fn TISGetInputSourceProperty(source: *mut c_void, key: CFStringRef) -> *const c_void;
+}
+
+/// Lock keys as reported by modifier flags (Macs only have Caps Lock)
+pub fn lock_state_from_flags(flags: CGEventFlags) -> LockState {
+    LockState {
+        caps_lock: Some(flags.contains(CGEventFlags::CGEventFlagAlphaShift)),
+        num_lock: None,
+        scroll_lock: None,
+    }
+}
+
+/// Current state of the lock keys on this Mac
+pub fn lock_state() -> LockState {
+    let flags = unsafe { CGEventSourceFlagsState(COMBINED_SESSION_STATE) };
+    lock_state_from_flags(CGEventFlags::from_bits_truncate(flags))
+}
+
+/// ID of the active keyboard input source, e.g. "com.apple.keylayout.German"
+/// or "com.apple.inputmethod.Kotoeri.RomajiTyping.Japanese"
+pub fn current_input_source() -> Option<String> {
+    unsafe {
+        let source = TISCopyCurrentKeyboardInputSource();
+        if source.is_null() {
+            return None;
+        }
+
+        let id = TISGetInputSourceProperty(source, kTISPropertyInputSourceID) as CFStringRef;
+        let result = (!id.is_null()).then(|| CFString::wrap_under_get_rule(id).to_string());
+
+        CFRelease(source as *const c_void);
+        result
+    }
+}
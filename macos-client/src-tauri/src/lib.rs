@@ -1,53 +1,517 @@
 use parking_lot::Mutex;
+use serde::Serialize;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tauri::{AppHandle, State};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
 
 #[cfg(target_os = "macos")]
-mod event_tap_simple;
+mod displays;
 #[cfg(target_os = "macos")]
-use event_tap_simple as event_tap;
+mod event_tap;
+#[cfg(target_os = "macos")]
+mod keyboard;
 
+mod clipboard;
+mod discovery;
+mod file_transfer;
+mod hotkeys;
+mod media_keys;
 mod network;
+mod pairing;
+mod profiles;
+mod quic;
 mod state;
+mod tls;
+mod typing;
+
+use discovery::DiscoveredServer;
+use file_transfer::FileReport;
+use profiles::{ProfileId, ServerProfile};
+use samesame_protocol::arbitration::{Arbitration, ControlStatus};
+use samesame_protocol::clipboard::ClipboardSettings;
+use samesame_protocol::consumer::ConsumerKey;
+use samesame_protocol::datagram::PointerTransport;
+use samesame_protocol::keymap::TargetLayout;
+use samesame_protocol::layout::{ScreenLayout, SwitchOptions};
+use samesame_protocol::{InputEvent, LockState, PointerMode};
+use state::{AppState, BroadcastConfig, InputMode};
+use typing::TypingReport;
+
+pub use file_transfer::run_send_command;
+pub use pairing::run_pair_command;
+pub use typing::run_type_command;
 
-use state::{AppState, InputMode};
+/// How often the clipboard is checked for changes made on this Mac
+const CLIPBOARD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Profile as shown in the UI
+#[derive(Serialize)]
+struct ProfileStatus {
+    #[serde(flatten)]
+    profile: ServerProfile,
+    connected: bool,
+    active: bool,
+    /// Lock keys reported by the server
+    lock_state: Option<LockState>,
+    /// Pinned certificate fingerprint of a TLS server
+    fingerprint: Option<String>,
+    /// This Mac is paired with the server
+    paired: bool,
+    /// Who controls the server, while connected
+    control: Option<ControlStatus>,
+}
 
-/// Connect to Windows server
+/// Connect to a server, creating a profile for it if needed
 #[tauri::command]
 async fn connect_to_server(
     server_ip: String,
     port: u16,
+    tls: Option<bool>,
     state: State<'_, Arc<Mutex<AppState>>>,
 ) -> Result<String, String> {
-    let client = network::connect(&server_ip, port).await
-        .map_err(|e| format!("Failed to connect: {}", e))?;
+    let id = {
+        let mut app_state = state.lock();
+        match app_state.profiles.find_by_address(&server_ip, port) {
+            Some(profile) => profile.id,
+            None => {
+                let name = format!("{}:{}", server_ip, port);
+                let profile = app_state
+                    .profiles
+                    .add(name, server_ip.clone(), port, None, tls.unwrap_or_default(), false)
+                    .map_err(|e| e.to_string())?;
+                app_state.profiles.save().map_err(|e| e.to_string())?;
+                profile.id
+            }
+        }
+    };
 
-    let mut app_state = state.lock();
-    app_state.network_client = Some(client);
-    app_state.server_address = Some(format!("{}:{}", server_ip, port));
+    connect_profile(id, state).await?;
 
     Ok(format!("Connected to {}:{}", server_ip, port))
 }
 
-/// Disconnect from Windows server
+/// Disconnect from all servers
 #[tauri::command]
 fn disconnect_from_server(state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
     let mut app_state = state.lock();
-    app_state.network_client = None;
-    app_state.server_address = None;
+    let ids: Vec<ProfileId> = app_state.connections.keys().copied().collect();
+    for id in ids {
+        app_state.disconnect(id);
+    }
     Ok(())
 }
 
-/// Toggle input mode (macOS ↔ Windows)
+/// Add a server profile
 #[tauri::command]
-fn toggle_mode(state: State<'_, Arc<Mutex<AppState>>>) -> Result<String, String> {
+fn add_profile(
+    name: String,
+    host: String,
+    port: u16,
+    hotkey: Option<u8>,
+    tls: Option<bool>,
+    quic: Option<bool>,
+    state: State<'_, Arc<Mutex<AppState>>>,
+) -> Result<ServerProfile, String> {
     let mut app_state = state.lock();
+    let profile = app_state
+        .profiles
+        .add(name, host, port, hotkey, tls.unwrap_or_default(), quic.unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+    app_state.profiles.save().map_err(|e| e.to_string())?;
+    Ok(profile)
+}
 
-    app_state.mode = match app_state.mode {
-        InputMode::MacOS => InputMode::Windows,
-        InputMode::Windows => InputMode::MacOS,
+/// Servers announcing themselves on the local network
+#[tauri::command]
+async fn discover_servers(state: State<'_, Arc<Mutex<AppState>>>) -> Result<Vec<DiscoveredServer>, String> {
+    let mut servers = discovery::browse(discovery::BROWSE_TIME).await.map_err(|e| e.to_string())?;
+    let app_state = state.lock();
+    for server in &mut servers {
+        let announcement = &server.announcement;
+        server.profile = app_state
+            .profiles
+            .list()
+            .iter()
+            .find(|profile| {
+                (profile.port == announcement.port || Some(profile.port) == announcement.quic_port)
+                    && server.addresses.iter().any(|address| profile.host == address.to_string())
+            })
+            .map(|profile| profile.id);
+    }
+    Ok(servers)
+}
+
+/// Remove a server profile, closing its connection
+#[tauri::command]
+fn remove_profile(id: ProfileId, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    let mut app_state = state.lock();
+    app_state.disconnect(id);
+    if app_state.last_target == Some(id) {
+        app_state.last_target = None;
+    }
+    app_state
+        .profiles
+        .remove(id)
+        .ok_or_else(|| format!("Unknown profile {}", id))?;
+    app_state.profiles.save().map_err(|e| e.to_string())?;
+    app_state.reload_layout();
+    Ok(())
+}
+
+/// List server profiles with their connection status
+#[tauri::command]
+fn list_profiles(state: State<'_, Arc<Mutex<AppState>>>) -> Result<Vec<ProfileStatus>, String> {
+    let app_state = state.lock();
+    let profiles = app_state
+        .profiles
+        .list()
+        .iter()
+        .map(|profile| ProfileStatus {
+            connected: app_state.is_connected(profile.id),
+            active: app_state.mode == InputMode::Target(profile.id),
+            lock_state: app_state.remote_locks.get(&profile.id).copied(),
+            fingerprint: app_state.known_servers.get(&profile.address()).map(str::to_string),
+            paired: app_state.paired_servers.get(&profile.address()).is_some(),
+            control: app_state.control.get(&profile.id).cloned(),
+            profile: profile.clone(),
+        })
+        .collect();
+    Ok(profiles)
+}
+
+/// Open the connection of a profile (kept open until disconnected)
+#[tauri::command]
+async fn connect_profile(id: ProfileId, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    let (profile, credential) = {
+        let app_state = state.lock();
+        if app_state.is_connected(id) {
+            return Ok(());
+        }
+        let profile = app_state
+            .profiles
+            .get(id)
+            .cloned()
+            .ok_or_else(|| format!("Unknown profile {}", id))?;
+        let credential = app_state.paired_servers.get(&profile.address()).cloned();
+        (profile, credential)
     };
 
+    let app_state = state.inner().clone();
+    let on_message = move |message| app_state.lock().handle_server_message(id, message);
+    let client = if profile.quic {
+        network::connect_quic(&profile.host, profile.port, trust(&state, &profile), credential.as_ref(), on_message).await
+    } else if profile.tls {
+        network::connect_tls(&profile.host, profile.port, trust(&state, &profile), credential.as_ref(), on_message).await
+    } else {
+        network::connect(&profile.host, profile.port, credential.as_ref(), on_message).await
+    }
+    .map_err(|e| format!("Failed to connect to {}: {}", profile.address(), e))?;
+
+    let mut app_state = state.lock();
+    app_state.connections.insert(id, client);
+
+    // Tell the server about our monitors so it can map pointer positions
+    #[cfg(target_os = "macos")]
+    {
+        let screens = displays::screen_info();
+        app_state.local_screens = Some(screens.clone());
+        app_state.send_to(id, InputEvent::ScreenInfo(screens));
+
+        app_state.local_locks = keyboard::lock_state();
+        let locks = app_state.local_locks;
+        app_state.send_to(id, InputEvent::LockState(locks));
+    }
+    if profile.pointer_mode != PointerMode::default() {
+        app_state.send_to(id, InputEvent::SetPointerMode(profile.pointer_mode));
+    }
+    if profile.udp_pointer && !profile.quic {
+        app_state.send_to(id, InputEvent::PointerTransport(PointerTransport::Request));
+    }
+
+    // Continue file transfers the last connection dropped
+    if app_state.unfinished_files.contains_key(&id) {
+        tauri::async_runtime::spawn(file_transfer::resume_unfinished(state.inner().clone(), id));
+    }
+
+    Ok(())
+}
+
+/// Check a profile's TLS certificate against the pinned fingerprint
+fn trust(state: &State<'_, Arc<Mutex<AppState>>>, profile: &ServerProfile) -> impl FnOnce(&str) -> anyhow::Result<()> {
+    let app_state = state.inner().clone();
+    let address = profile.address();
+    move |fingerprint: &str| app_state.lock().known_servers.check(&address, fingerprint).map(|_| ())
+}
+
+/// Pair with a profile's server using the PIN the server shows
+#[tauri::command]
+async fn pair_profile(
+    id: ProfileId,
+    pin: String,
+    name: Option<String>,
+    state: State<'_, Arc<Mutex<AppState>>>,
+) -> Result<(), String> {
+    let profile = state
+        .lock()
+        .profiles
+        .get(id)
+        .cloned()
+        .ok_or_else(|| format!("Unknown profile {}", id))?;
+    let name = name.unwrap_or_else(pairing::device_name);
+
+    let trust = profile.tls.then(|| trust(&state, &profile));
+    let credential = network::pair(&profile.host, profile.port, trust, &name, &pin)
+        .await
+        .map_err(|e| format!("Failed to pair with {}: {}", profile.address(), e))?;
+
+    let mut app_state = state.lock();
+    app_state
+        .paired_servers
+        .insert(&profile.address(), credential)
+        .map_err(|e| e.to_string())?;
+    // Reconnect with the pairing
+    app_state.disconnect(id);
+    Ok(())
+}
+
+/// Forget the pairing with a profile's server
+#[tauri::command]
+fn forget_pairing(id: ProfileId, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    let mut app_state = state.lock();
+    let address = app_state
+        .profiles
+        .get(id)
+        .map(ServerProfile::address)
+        .ok_or_else(|| format!("Unknown profile {}", id))?;
+    app_state.paired_servers.forget(&address).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Choose how a profile's server applies mouse movement
+#[tauri::command]
+fn set_pointer_mode(
+    id: ProfileId,
+    mode: PointerMode,
+    state: State<'_, Arc<Mutex<AppState>>>,
+) -> Result<(), String> {
+    let mut app_state = state.lock();
+    let profile = app_state
+        .profiles
+        .get_mut(id)
+        .ok_or_else(|| format!("Unknown profile {}", id))?;
+    profile.pointer_mode = mode;
+    app_state.profiles.save().map_err(|e| e.to_string())?;
+
+    if app_state.is_connected(id) {
+        app_state.send_to(id, InputEvent::SetPointerMode(mode));
+    }
+    Ok(())
+}
+
+/// Send a profile's pointer events over UDP, from the next connection on
+#[tauri::command]
+fn set_udp_pointer(id: ProfileId, enabled: bool, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    let mut app_state = state.lock();
+    let profile = app_state
+        .profiles
+        .get_mut(id)
+        .ok_or_else(|| format!("Unknown profile {}", id))?;
+    profile.udp_pointer = enabled;
+    app_state.profiles.save().map_err(|e| e.to_string())
+}
+
+/// Forget the pinned certificate of a profile's server, after it legitimately changed
+#[tauri::command]
+fn forget_fingerprint(id: ProfileId, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    let mut app_state = state.lock();
+    let address = app_state
+        .profiles
+        .get(id)
+        .map(ServerProfile::address)
+        .ok_or_else(|| format!("Unknown profile {}", id))?;
+    app_state.known_servers.forget(&address).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Close the connection of a profile
+#[tauri::command]
+fn disconnect_profile(id: ProfileId, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    state.lock().disconnect(id);
+    Ok(())
+}
+
+/// Forward input to a profile, connecting first if needed
+#[tauri::command]
+async fn activate_profile(id: ProfileId, state: State<'_, Arc<Mutex<AppState>>>) -> Result<String, String> {
+    connect_profile(id, state.clone()).await?;
+
+    let mut app_state = state.lock();
+    app_state.activate(id);
+    Ok(format!("{:?}", app_state.mode))
+}
+
+/// Hand control of a profile's server to the next waiting client
+#[tauri::command]
+fn release_control(id: ProfileId, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    let mut app_state = state.lock();
+    if !app_state.is_connected(id) {
+        return Err(format!("Profile {} is not connected", id));
+    }
+    app_state.send_to(id, InputEvent::Arbitration(Arbitration::Release));
+    Ok(())
+}
+
+/// Configure broadcast mode (send input to several servers at once)
+#[tauri::command]
+fn set_broadcast(
+    enabled: bool,
+    targets: Vec<ProfileId>,
+    include_pointer: bool,
+    state: State<'_, Arc<Mutex<AppState>>>,
+) -> Result<(), String> {
+    let mut app_state = state.lock();
+    if let Some(id) = targets.iter().find(|id| app_state.profiles.get(**id).is_none()) {
+        return Err(format!("Unknown profile {}", id));
+    }
+
+    app_state.profiles.broadcast = BroadcastConfig {
+        enabled,
+        targets: targets.into_iter().collect(),
+        include_pointer,
+    };
+    app_state.profiles.save().map_err(|e| e.to_string())
+}
+
+/// Set the screen layout used for switching by pushing the cursor over a screen edge
+#[tauri::command]
+fn set_screen_layout(
+    layout: ScreenLayout<InputMode>,
+    options: SwitchOptions,
+    state: State<'_, Arc<Mutex<AppState>>>,
+) -> Result<(), String> {
+    let mut app_state = state.lock();
+    app_state.profiles.screen_layout = layout;
+    app_state.profiles.switch_options = options;
+    app_state.profiles.save().map_err(|e| e.to_string())?;
+    app_state.reload_layout();
+    Ok(())
+}
+
+/// Choose which media keys keep acting on the Mac while forwarding
+#[tauri::command]
+fn set_local_media_keys(keys: Vec<ConsumerKey>, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    let mut app_state = state.lock();
+    app_state.profiles.local_media_keys = keys.into_iter().collect();
+    app_state.profiles.save().map_err(|e| e.to_string())
+}
+
+/// Choose the input sources whose composed text is forwarded instead of raw keys
+#[tauri::command]
+fn set_text_input_sources(sources: Vec<String>, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    let mut app_state = state.lock();
+    app_state.profiles.text_input_sources = sources.into_iter().collect();
+    app_state.profiles.save().map_err(|e| e.to_string())
+}
+
+/// Configure clipboard synchronization
+#[tauri::command]
+fn set_clipboard_settings(settings: ClipboardSettings, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    let mut app_state = state.lock();
+    app_state.profiles.clipboard = settings;
+    app_state.reset_clipboard_sync();
+    app_state.profiles.save().map_err(|e| e.to_string())
+}
+
+/// ID of the active keyboard input source
+#[tauri::command]
+fn current_input_source() -> Option<String> {
+    #[cfg(target_os = "macos")]
+    {
+        keyboard::current_input_source()
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        None
+    }
+}
+
+/// Forward text composed in the app's text capture field
+#[tauri::command]
+fn send_text_input(text: String, composing: bool, state: State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    let mut app_state = state.lock();
+    if app_state.mode == InputMode::Local {
+        return Err("Not forwarding input".to_string());
+    }
+    if app_state.send_text(text, composing) == 0 {
+        return Err(format!("Target {:?} is not connected", app_state.mode));
+    }
+    Ok(())
+}
+
+/// Type text into the active target as key presses for the target's keyboard layout
+#[tauri::command]
+async fn type_text(
+    text: String,
+    layout: TargetLayout,
+    delay_ms: Option<u64>,
+    state: State<'_, Arc<Mutex<AppState>>>,
+) -> Result<TypingReport, String> {
+    if state.lock().mode == InputMode::Local {
+        return Err("Not forwarding input".to_string());
+    }
+    let delay_ms = delay_ms.unwrap_or(typing::DEFAULT_KEY_DELAY_MS);
+    typing::type_into_target(state.inner().clone(), &text, layout, delay_ms)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Profile files go to when none is given: the active target, else the last one
+fn file_target(app_state: &AppState) -> Option<ProfileId> {
+    match app_state.mode {
+        InputMode::Target(id) => Some(id),
+        InputMode::Local => app_state.last_target.filter(|id| app_state.is_connected(*id)),
+    }
+}
+
+/// Send files to a server
+#[tauri::command]
+async fn send_files(
+    paths: Vec<String>,
+    id: Option<ProfileId>,
+    state: State<'_, Arc<Mutex<AppState>>>,
+) -> Result<Vec<FileReport>, String> {
+    let id = id
+        .or_else(|| file_target(&state.lock()))
+        .ok_or_else(|| "No server to send files to".to_string())?;
+    if !state.lock().is_connected(id) {
+        return Err(format!("Profile {} is not connected", id));
+    }
+    let paths = paths.into_iter().map(PathBuf::from).collect();
+    file_transfer::send_all(state.inner().clone(), id, paths)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Send files dropped onto the window to the current target
+fn send_dropped_files(app: AppHandle, state: Arc<Mutex<AppState>>, paths: Vec<PathBuf>) {
+    let Some(id) = file_target(&state.lock()) else {
+        tracing::warn!("Dropped {} file(s), but no server is connected", paths.len());
+        let _ = app.emit("file-transfer", Err::<Vec<FileReport>, _>("No server to send files to"));
+        return;
+    };
+
+    tauri::async_runtime::spawn(async move {
+        let result = file_transfer::send_all(state, id, paths).await.map_err(|e| e.to_string());
+        let _ = app.emit("file-transfer", result);
+    });
+}
+
+/// Toggle input mode (local ↔ last target)
+#[tauri::command]
+fn toggle_mode(state: State<'_, Arc<Mutex<AppState>>>) -> Result<String, String> {
+    let mut app_state = state.lock();
+    app_state.toggle_mode();
     Ok(format!("{:?}", app_state.mode))
 }
 
@@ -55,7 +519,7 @@ fn toggle_mode(state: State<'_, Arc<Mutex<AppState>>>) -> Result<String, String>
 #[tauri::command]
 fn get_state(state: State<'_, Arc<Mutex<AppState>>>) -> Result<String, String> {
     let app_state = state.lock();
-    Ok(serde_json::to_string(&*app_state).map_err(|e| e.to_string())?)
+    serde_json::to_string(&*app_state).map_err(|e| e.to_string())
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -71,13 +535,71 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .manage(app_state.clone())
+        .on_window_event({
+            let app_state = app_state.clone();
+            move |window, event| {
+                if let tauri::WindowEvent::DragDrop(tauri::DragDropEvent::Drop { paths, .. }) = event {
+                    send_dropped_files(window.app_handle().clone(), app_state.clone(), paths.clone());
+                }
+            }
+        })
         .invoke_handler(tauri::generate_handler![
             connect_to_server,
             disconnect_from_server,
+            add_profile,
+            discover_servers,
+            remove_profile,
+            list_profiles,
+            connect_profile,
+            disconnect_profile,
+            activate_profile,
+            release_control,
+            set_pointer_mode,
+            set_udp_pointer,
+            forget_fingerprint,
+            pair_profile,
+            forget_pairing,
+            set_broadcast,
+            set_screen_layout,
+            set_local_media_keys,
+            set_text_input_sources,
+            set_clipboard_settings,
+            current_input_source,
+            send_text_input,
+            type_text,
+            send_files,
             toggle_mode,
             get_state,
         ])
         .setup(move |app| {
+            // Load saved server profiles, pinned certificates and pairings
+            let config_dir = app.path().app_config_dir()?;
+            match tls::KnownServers::load(config_dir.join(tls::KNOWN_SERVERS_FILE)) {
+                Ok(known_servers) => app_state.lock().known_servers = known_servers,
+                Err(e) => tracing::error!("Failed to load pinned certificates: {}", e),
+            }
+            match pairing::PairedServers::load(config_dir.join(pairing::PAIRED_SERVERS_FILE)) {
+                Ok(paired_servers) => app_state.lock().paired_servers = paired_servers,
+                Err(e) => tracing::error!("Failed to load pairings: {}", e),
+            }
+            let profiles_path = config_dir.join("profiles.json");
+            match profiles::ProfileStore::load(&profiles_path) {
+                Ok(store) => {
+                    let mut state = app_state.lock();
+                    state.profiles = store;
+                    state.reload_layout();
+                }
+                Err(e) => tracing::error!("Failed to load server profiles: {}", e),
+            }
+
+            // Watch the clipboard for changes to send to the servers
+            let state_clone = app_state.clone();
+            let woken = app_state.lock().clipboard_waker();
+            std::thread::spawn(move || loop {
+                let _ = woken.recv_timeout(CLIPBOARD_POLL_INTERVAL);
+                AppState::poll_clipboard(&state_clone);
+            });
+
             #[cfg(target_os = "macos")]
             {
                 // Start event tap on macOS
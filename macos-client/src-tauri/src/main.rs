@@ -2,5 +2,15 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
-    macos_client_lib::run()
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("type") => macos_client_lib::run_type_command(&args[1..]),
+        Some("send") => macos_client_lib::run_send_command(&args[1..]),
+        Some("pair") => macos_client_lib::run_pair_command(&args[1..]),
+        _ => return macos_client_lib::run(),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,54 @@
+use samesame_protocol::consumer::ConsumerKey;
+
+/// Event type of NX_SYSDEFINED events, which carry the media keys
+pub const NX_SYSDEFINED: u32 = 14;
+
+/// NSEvent subtype of system-defined events for the special function keys
+pub const NX_SUBTYPE_AUX_CONTROL_BUTTONS: i16 = 8;
+
+/// NX_KEYTYPE_* codes from IOKit's ev_keymap.h
+const NX_KEYTYPE_SOUND_UP: i64 = 0;
+const NX_KEYTYPE_SOUND_DOWN: i64 = 1;
+const NX_KEYTYPE_BRIGHTNESS_UP: i64 = 2;
+const NX_KEYTYPE_BRIGHTNESS_DOWN: i64 = 3;
+const NX_KEYTYPE_MUTE: i64 = 7;
+const NX_KEYTYPE_PLAY: i64 = 16;
+const NX_KEYTYPE_NEXT: i64 = 17;
+const NX_KEYTYPE_PREVIOUS: i64 = 18;
+const NX_KEYTYPE_FAST: i64 = 19;
+const NX_KEYTYPE_REWIND: i64 = 20;
+
+/// Key state in `data1` of a pressed / released aux control button
+const NX_KEYSTATE_DOWN: i64 = 0x0A;
+const NX_KEYSTATE_UP: i64 = 0x0B;
+
+/// Decode the `data1` field of an aux control button event
+///
+/// Bits 16-31 hold the key type, bits 8-15 the key state. Returns the key
+/// and whether it was pressed.
+pub fn decode_aux_button(data1: i64) -> Option<(ConsumerKey, bool)> {
+    let key_type = (data1 >> 16) & 0xFFFF;
+    let key_state = (data1 >> 8) & 0xFF;
+
+    let key = match key_type {
+        NX_KEYTYPE_SOUND_UP => ConsumerKey::VolumeUp,
+        NX_KEYTYPE_SOUND_DOWN => ConsumerKey::VolumeDown,
+        NX_KEYTYPE_BRIGHTNESS_UP => ConsumerKey::BrightnessUp,
+        NX_KEYTYPE_BRIGHTNESS_DOWN => ConsumerKey::BrightnessDown,
+        NX_KEYTYPE_MUTE => ConsumerKey::Mute,
+        NX_KEYTYPE_PLAY => ConsumerKey::PlayPause,
+        NX_KEYTYPE_NEXT => ConsumerKey::NextTrack,
+        NX_KEYTYPE_PREVIOUS => ConsumerKey::PreviousTrack,
+        NX_KEYTYPE_FAST => ConsumerKey::FastForward,
+        NX_KEYTYPE_REWIND => ConsumerKey::Rewind,
+        _ => return None,
+    };
+
+    let pressed = match key_state {
+        NX_KEYSTATE_DOWN => true,
+        NX_KEYSTATE_UP => false,
+        _ => return None,
+    };
+
+    Some((key, pressed))
+}
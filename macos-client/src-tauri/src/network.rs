@@ -1,37 +1,529 @@
-use anyhow::Result;
-use samesame_protocol::{InputEvent, Message};
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
-use tracing::{debug, error};
-
-/// Connect to Windows server
-pub async fn connect(server_ip: &str, port: u16) -> Result<TcpStream> {
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use samesame_protocol::datagram::{self as datagrams, DatagramCodec, PointerTransport, RESYNC_INTERVAL};
+use samesame_protocol::{FlowUpdate, InputEvent, Message, MuxConfig, MuxDecoder, MuxEncoder};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, error, info, warn};
+
+use crate::pairing::{self, PairedServers, PairingCredential};
+use crate::quic;
+use crate::tls::{self, KnownServers, Trust};
+
+/// Maximum number of messages queued per connection before events are dropped
+const SEND_QUEUE_SIZE: usize = 256;
+
+/// A write that takes longer than this marks the server as dead
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest time the pairing handshake may take
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An open connection to a server
+///
+/// Messages are queued and written by a background task, so sending never
+/// blocks the event tap, and a stalled server never delays other targets.
+/// Bulk transfers (clipboard, files) have a queue of their own. The writer
+/// multiplexes both onto the stream segment by segment, so input never waits
+/// for a bulk message to finish. Messages from the server are handled by a
+/// reader task.
+///
+/// Once the server offered the datagram transport, pointer moves and scroll
+/// deltas go over UDP instead of the queue.
+pub struct ServerConnection {
+    sender: mpsc::Sender<Message>,
+    bulk_sender: mpsc::UnboundedSender<Message>,
+    pointer: Arc<OnceLock<PointerChannel>>,
+    writer: JoinHandle<()>,
+    reader: JoinHandle<()>,
+    /// Sequence number of the last message sent on this connection
+    sequence: u64,
+}
+
+impl ServerConnection {
+    /// Queue an event for sending, numbered with this connection's sequence
+    pub fn send_event(&mut self, event: InputEvent) -> Result<()> {
+        self.sequence += 1;
+        let message = Message::new(self.sequence, event);
+
+        if let Some(pointer) = self.pointer.get().filter(|_| datagrams::is_datagram_event(&message.event)) {
+            return pointer.send(message);
+        }
+        match self.sender.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(anyhow!("Send queue full, dropping event")),
+            Err(TrySendError::Closed(_)) => Err(anyhow!("Connection closed")),
+        }
+    }
+
+    /// Queue part of a bulk transfer, sent after any pending input
+    pub fn send_bulk(&mut self, event: InputEvent) -> Result<()> {
+        self.sequence += 1;
+        self.bulk_sender
+            .send(Message::new(self.sequence, event))
+            .map_err(|_| anyhow!("Connection closed"))
+    }
+
+    /// Check if the connection has stopped (write error or closed by the server)
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed() || self.reader.is_finished()
+    }
+
+    /// Write everything still queued, then close the connection
+    pub async fn close(mut self) {
+        // Replacing the senders closes the queues, the writer stops once they are empty
+        let (closed, _) = mpsc::channel(1);
+        self.sender = closed;
+        let (closed, _) = mpsc::unbounded_channel();
+        self.bulk_sender = closed;
+        if let Err(e) = (&mut self.writer).await {
+            warn!("Writer task failed: {}", e);
+        }
+    }
+}
+
+impl Drop for ServerConnection {
+    fn drop(&mut self) {
+        self.writer.abort();
+        self.reader.abort();
+    }
+}
+
+/// Pointer events sent as datagrams, once the server offered it
+struct PointerChannel {
+    sink: PointerSink,
+    /// Last move sent as a datagram, repeated on the stream at the next resync
+    resync: Mutex<Option<Message>>,
+}
+
+enum PointerSink {
+    /// Authenticated datagrams to the UDP port the server offered
+    Udp { socket: UdpSocket, codec: DatagramCodec },
+    /// Datagrams of the QUIC connection, which protects them itself
+    Quic(quinn::Connection),
+}
+
+impl PointerChannel {
+    /// Open a socket to the port the server offered
+    async fn open(server_ip: IpAddr, port: u16, codec: DatagramCodec) -> Result<Self> {
+        let local: IpAddr = match server_ip {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+        socket.connect(SocketAddr::new(server_ip, port)).await?;
+        Ok(Self::new(PointerSink::Udp { socket, codec }))
+    }
+
+    fn new(sink: PointerSink) -> Self {
+        Self {
+            sink,
+            resync: Mutex::new(None),
+        }
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        let datagram = match &self.sink {
+            PointerSink::Udp { codec, .. } => codec.seal(&message)?,
+            PointerSink::Quic(_) => datagrams::encode_pointer(&message)?,
+        };
+        if let InputEvent::MouseMove(_) = message.event {
+            // Even if this datagram is lost, the resync carries the position
+            *self.resync.lock() = Some(message);
+        }
+        match &self.sink {
+            PointerSink::Udp { socket, .. } => socket.try_send(&datagram).map(drop).map_err(|e| anyhow!(e)),
+            PointerSink::Quic(connection) => connection.send_datagram(datagram.into()).map_err(|e| anyhow!(e)),
+        }
+        .map_err(|e| anyhow!("Failed to send pointer datagram: {}", e))
+    }
+
+    /// The last move as a resync message: same sequence, position only
+    fn take_resync(&self) -> Option<Message> {
+        let mut message = self.resync.lock().take()?;
+        if let InputEvent::MouseMove(move_event) = &mut message.event {
+            move_event.delta_x = 0.0;
+            move_event.delta_y = 0.0;
+        }
+        Some(message)
+    }
+}
+
+/// Connect to a server
+///
+/// Authenticates with `credential` if this Mac is paired with the server.
+/// `on_message` is called from the reader task for every message the server sends.
+pub async fn connect<F>(
+    server_ip: &str,
+    port: u16,
+    credential: Option<&PairingCredential>,
+    on_message: F,
+) -> Result<ServerConnection>
+where
+    F: FnMut(Message) + Send + 'static,
+{
     let addr = format!("{}:{}", server_ip, port);
-    let stream = TcpStream::connect(&addr).await?;
+    let stream = open_stream(&addr).await?;
+    let server = stream.peer_addr()?.ip();
+    let (mut read_half, mut write_half) = stream.into_split();
+    login(&mut read_half, &mut write_half, credential, &[]).await?;
+
+    tracing::info!("Connected to server at {}", addr);
+
+    Ok(start(read_half, write_half, on_message, addr, server))
+}
+
+/// Connect to a server over TLS
+///
+/// `trust` gets the fingerprint of the server's certificate and decides
+/// whether to go on; nothing is sent before it agreed.
+pub async fn connect_tls<T, F>(
+    server_ip: &str,
+    port: u16,
+    trust: T,
+    credential: Option<&PairingCredential>,
+    on_message: F,
+) -> Result<ServerConnection>
+where
+    T: FnOnce(&str) -> Result<()>,
+    F: FnMut(Message) + Send + 'static,
+{
+    let addr = format!("{}:{}", server_ip, port);
+    let stream = open_stream(&addr).await?;
+    let server = stream.peer_addr()?.ip();
+    let (stream, fingerprint) = tls::handshake(stream, server_ip).await?;
+    trust(&fingerprint)?;
+    let binding = tls::channel_binding(&stream)?;
+    let (mut read_half, mut write_half) = tokio::io::split(stream);
+    login(&mut read_half, &mut write_half, credential, &binding).await?;
+
+    tracing::info!("Connected to server at {} (TLS)", addr);
+
+    Ok(start(read_half, write_half, on_message, addr, server))
+}
+
+/// Connect to a server over QUIC
+///
+/// Trust works as in [`connect_tls`]. Bulk transfers get a stream of their
+/// own, and pointer events go as QUIC datagrams.
+pub async fn connect_quic<T, F>(
+    server_ip: &str,
+    port: u16,
+    trust: T,
+    credential: Option<&PairingCredential>,
+    on_message: F,
+) -> Result<ServerConnection>
+where
+    T: FnOnce(&str) -> Result<()>,
+    F: FnMut(Message) + Send + 'static,
+{
+    let addr = format!("{}:{}", server_ip, port);
+    let (connection, fingerprint) = tokio::time::timeout(HANDSHAKE_TIMEOUT, quic::connect(&addr, server_ip))
+        .await
+        .map_err(|_| anyhow!("Server did not answer the QUIC handshake"))??;
+    trust(&fingerprint)?;
+    let binding = quic::channel_binding(&connection)?;
+    let (mut main_send, mut main_recv, bulk) = quic::open_streams(&connection).await?;
+    login(&mut main_recv, &mut main_send, credential, &binding).await?;
+
+    tracing::info!("Connected to server at {} (QUIC)", addr);
+
+    let (read_half, write_half) = bulk.into_streams(main_recv, main_send);
+    let server = connection.remote_address().ip();
+    let client = start(read_half, write_half, on_message, addr, server);
+    if connection.max_datagram_size().is_some() {
+        let _ = client.pointer.set(PointerChannel::new(PointerSink::Quic(connection)));
+    }
+    Ok(client)
+}
+
+/// How a command line tool reaches the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CliTransport {
+    #[default]
+    Tcp,
+    Tls,
+    Quic,
+}
+
+/// Connect from a command line tool
+///
+/// Uses the pairings of the app, and checks TLS and QUIC servers against the
+/// fingerprints the app pinned.
+pub async fn connect_cli<F>(server_ip: &str, port: u16, transport: CliTransport, on_message: F) -> Result<ServerConnection>
+where
+    F: FnMut(Message) + Send + 'static,
+{
+    let address = format!("{}:{}", server_ip, port);
+    let paired_servers = PairedServers::load_default()?;
+    let credential = paired_servers.get(&address);
+    if transport == CliTransport::Tcp {
+        return connect(server_ip, port, credential, on_message).await;
+    }
+
+    let mut known_servers = KnownServers::load_default()?;
+    let trust = cli_trust(&mut known_servers, &address);
+    if transport == CliTransport::Quic {
+        return connect_quic(server_ip, port, trust, credential, on_message).await;
+    }
+    connect_tls(server_ip, port, trust, credential, on_message).await
+}
+
+/// Pin a server's certificate on first use from a command line tool
+fn cli_trust<'a>(known_servers: &'a mut KnownServers, address: &'a str) -> impl FnOnce(&str) -> Result<()> + 'a {
+    move |fingerprint: &str| {
+        if known_servers.check(address, fingerprint)? == Trust::FirstUse {
+            eprintln!("Pinned certificate of {}: {}", address, fingerprint);
+        }
+        Ok(())
+    }
+}
+
+/// Pair with a server using the PIN it shows, then disconnect
+///
+/// With `trust` the connection uses TLS, as in [`connect_tls`].
+pub async fn pair<T>(server_ip: &str, port: u16, trust: Option<T>, name: &str, pin: &str) -> Result<PairingCredential>
+where
+    T: FnOnce(&str) -> Result<()>,
+{
+    let addr = format!("{}:{}", server_ip, port);
+    let stream = open_stream(&addr).await?;
+    let handshake = async {
+        match trust {
+            Some(trust) => {
+                let (stream, fingerprint) = tls::handshake(stream, server_ip).await?;
+                trust(&fingerprint)?;
+                let binding = tls::channel_binding(&stream)?;
+                let (mut read_half, mut write_half) = tokio::io::split(stream);
+                pairing::pair(&mut read_half, &mut write_half, name, pin, &binding).await
+            }
+            None => {
+                let (mut read_half, mut write_half) = stream.into_split();
+                pairing::pair(&mut read_half, &mut write_half, name, pin, &[]).await
+            }
+        }
+    };
+    let credential = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+        .await
+        .map_err(|_| anyhow!("Server did not answer the pairing request"))??;
+
+    info!("Paired with {}", addr);
+    Ok(credential)
+}
+
+/// Pair from a command line tool, storing the credential where the app finds it
+pub async fn pair_cli(server_ip: &str, port: u16, use_tls: bool, name: &str, pin: &str) -> Result<()> {
+    let address = format!("{}:{}", server_ip, port);
+    let mut known_servers = KnownServers::load_default()?;
+    let trust = use_tls.then(|| cli_trust(&mut known_servers, &address));
+    let credential = pair(server_ip, port, trust, name, pin).await?;
+    PairedServers::load_default()?.insert(&address, credential)
+}
+
+/// Pairing handshake of a new connection, before any message is sent
+async fn login<R, W>(reader: &mut R, writer: &mut W, credential: Option<&PairingCredential>, binding: &[u8]) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, pairing::login(reader, writer, credential, binding))
+        .await
+        .map_err(|_| anyhow!("Server did not answer the handshake"))?
+}
+
+async fn open_stream(addr: &str) -> Result<TcpStream> {
+    let stream = TcpStream::connect(addr).await?;
 
     // Set TCP_NODELAY for low latency
     stream.set_nodelay(true)?;
 
-    tracing::info!("Connected to Windows server at {}", addr);
-
     Ok(stream)
 }
 
-/// Send an event to the Windows server
-pub async fn send_event(stream: &mut TcpStream, message: Message) -> Result<()> {
-    let bytes = message.to_bytes()?;
+/// Start the reader and writer tasks of a connection to `server`
+fn start<R, W, F>(read_half: R, write_half: W, on_message: F, addr: String, server: IpAddr) -> ServerConnection
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+    F: FnMut(Message) + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(SEND_QUEUE_SIZE);
+    let (bulk_sender, bulk_receiver) = mpsc::unbounded_channel();
+    let (flow_sender, flow_receiver) = mpsc::unbounded_channel();
+    let pointer = Arc::new(OnceLock::new());
+    let writer = tokio::spawn(write_loop(
+        write_half,
+        receiver,
+        bulk_receiver,
+        flow_receiver,
+        pointer.clone(),
+        addr.clone(),
+    ));
+    let reader = tokio::spawn(read_loop(read_half, on_message, flow_sender, pointer.clone(), server, addr));
 
-    // Write the serialized message
-    stream.write_all(&bytes).await?;
-    stream.flush().await?;
+    ServerConnection {
+        sender,
+        bulk_sender,
+        pointer,
+        writer,
+        reader,
+        sequence: 0,
+    }
+}
 
-    debug!("Sent event: {:?}", message.event);
+/// Read messages from the server until it closes the connection
+///
+/// Flow-control updates go to the writer through `flow`. A datagram offer
+/// opens the `pointer` channel to `server`.
+async fn read_loop<R: AsyncRead + Unpin, F: FnMut(Message)>(
+    mut stream: R,
+    mut on_message: F,
+    flow: mpsc::UnboundedSender<FlowUpdate>,
+    pointer: Arc<OnceLock<PointerChannel>>,
+    server: IpAddr,
+    addr: String,
+) {
+    let mut buffer = vec![0u8; 8192];
+    let mut decoder = MuxDecoder::new(MuxConfig::default());
 
-    Ok(())
+    loop {
+        let n = match stream.read(&mut buffer).await {
+            Ok(0) => {
+                info!("Server {} closed connection", addr);
+                break;
+            }
+            Ok(n) => n,
+            Err(e) => {
+                error!("Failed to read from {}: {}", addr, e);
+                break;
+            }
+        };
+
+        decoder.extend(&buffer[..n]);
+        loop {
+            match decoder.next_message() {
+                Ok(Some(Message {
+                    event: InputEvent::PointerTransport(transport),
+                    ..
+                })) => open_pointer_channel(transport, &pointer, server, &addr).await,
+                Ok(Some(message)) => on_message(message),
+                Ok(None) => break,
+                Err(e) => warn!("Failed to decode message from {}: {}", addr, e),
+            }
+        }
+        for update in decoder.take_flow_updates() {
+            let _ = flow.send(update);
+        }
+    }
+}
+
+/// Answer to our request for the datagram transport
+async fn open_pointer_channel(
+    transport: PointerTransport,
+    pointer: &OnceLock<PointerChannel>,
+    server: IpAddr,
+    addr: &str,
+) {
+    let offer = match transport {
+        PointerTransport::Offer(offer) => offer,
+        PointerTransport::Unavailable => {
+            info!("Server {} keeps pointer events on the stream", addr);
+            return;
+        }
+        PointerTransport::Request => return,
+    };
+    if pointer.get().is_some() {
+        return;
+    }
+    match PointerChannel::open(server, offer.port, DatagramCodec::from_offer(&offer)).await {
+        Ok(channel) => {
+            let _ = pointer.set(channel);
+            info!("Sending pointer events to {} over UDP port {}", addr, offer.port);
+        }
+        Err(e) => warn!("Failed to open UDP socket for {}, pointer events stay on the stream: {}", addr, e),
+    }
 }
 
-/// Send a ping to check connection
-pub async fn send_ping(stream: &mut TcpStream, sequence: u64) -> Result<()> {
-    let message = Message::new(sequence, InputEvent::Ping);
-    send_event(stream, message).await
+/// Write queued messages until the queues close or the stream fails
+///
+/// Everything queued is handed to the multiplexer before each segment, so
+/// input that arrives during a bulk transfer goes out next. While pointer
+/// events go as datagrams, the last move is repeated every
+/// [`RESYNC_INTERVAL`], so a lost datagram leaves the cursor off only briefly.
+async fn write_loop<W: AsyncWrite + Unpin>(
+    mut stream: W,
+    mut receiver: mpsc::Receiver<Message>,
+    mut bulk_receiver: mpsc::UnboundedReceiver<Message>,
+    mut flow: mpsc::UnboundedReceiver<FlowUpdate>,
+    pointer: Arc<OnceLock<PointerChannel>>,
+    addr: String,
+) {
+    let mut mux = MuxEncoder::new(MuxConfig::default());
+    let mut resync = tokio::time::interval(RESYNC_INTERVAL);
+    resync.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        // Checked before draining, so nothing queued before the close is lost
+        let closed = receiver.is_closed() && bulk_receiver.is_closed();
+        while let Ok(update) = flow.try_recv() {
+            mux.apply(update);
+        }
+        while let Ok(message) = receiver.try_recv() {
+            queue(&mut mux, message, &addr);
+        }
+        while let Ok(message) = bulk_receiver.try_recv() {
+            queue(&mut mux, message, &addr);
+        }
+
+        if let Some(segment) = mux.next_segment() {
+            match tokio::time::timeout(WRITE_TIMEOUT, write_segment(&mut stream, &segment)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    error!("Failed to send event to {}: {}", addr, e);
+                    break;
+                }
+                Err(_) => {
+                    error!("Server {} stopped accepting data, closing connection", addr);
+                    break;
+                }
+            }
+            continue;
+        }
+        if closed {
+            break;
+        }
+
+        // Nothing to send until a message or more window arrives
+        tokio::select! {
+            Some(update) = flow.recv() => mux.apply(update),
+            Some(message) = receiver.recv() => queue(&mut mux, message, &addr),
+            Some(message) = bulk_receiver.recv() => queue(&mut mux, message, &addr),
+            _ = resync.tick(), if pointer.get().is_some() => {
+                if let Some(message) = pointer.get().and_then(PointerChannel::take_resync) {
+                    queue(&mut mux, message, &addr);
+                }
+            }
+            else => break,
+        }
+    }
+}
+
+/// Hand a message to the multiplexer
+fn queue(mux: &mut MuxEncoder, message: Message, addr: &str) {
+    match mux.push(&message) {
+        Ok(channel) => debug!("Sending event on {:?} channel: {:?}", channel, message.event),
+        Err(e) => warn!("Dropping message to {}: {}", addr, e),
+    }
+}
+
+async fn write_segment<W: AsyncWrite + Unpin>(stream: &mut W, segment: &[u8]) -> Result<()> {
+    stream.write_all(segment).await?;
+    stream.flush().await?;
+    Ok(())
 }
@@ -0,0 +1,254 @@
+use anyhow::{anyhow, bail, Result};
+use samesame_protocol::pairing::{
+    self, challenge_proof, frame_length, hex, verify_challenge_proof, ClientFinish, ClientHello, ClientId, PairingKey,
+    Role, ServerHello, Spake2,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::info;
+
+use crate::network;
+use crate::tls::app_config_dir;
+
+/// File the pairing keys are stored in, next to the profiles
+pub const PAIRED_SERVERS_FILE: &str = "paired_servers.json";
+
+/// Port the server listens on
+const DEFAULT_PORT: u16 = 24800;
+
+/// What a client keeps from pairing with a server
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PairingCredential {
+    /// Id the server assigned to this client
+    #[serde(with = "hex")]
+    pub client_id: ClientId,
+    #[serde(with = "hex")]
+    key: PairingKey,
+}
+
+/// Pairing credentials by server
+#[derive(Default, Serialize, Deserialize)]
+pub struct PairedServers {
+    /// File the credentials are saved to (not persisted itself)
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Credential by server address (`host:port`)
+    servers: BTreeMap<String, PairingCredential>,
+}
+
+impl PairedServers {
+    /// Load credentials from `path`, starting empty if the file does not exist yet
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let mut paired = if path.exists() {
+            let data = fs::read_to_string(path)?;
+            serde_json::from_str(&data)?
+        } else {
+            Self::default()
+        };
+        paired.path = Some(path.to_path_buf());
+
+        Ok(paired)
+    }
+
+    /// Load the credentials the app uses, for the command line tools
+    pub fn load_default() -> Result<Self> {
+        Self::load(app_config_dir()?.join(PAIRED_SERVERS_FILE))
+    }
+
+    /// Write credentials back to the file they were loaded from
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, address: &str) -> Option<&PairingCredential> {
+        self.servers.get(address)
+    }
+
+    /// Store the credential of a new pairing, replacing an older one
+    pub fn insert(&mut self, address: &str, credential: PairingCredential) -> Result<()> {
+        self.servers.insert(address.to_string(), credential);
+        self.save()
+    }
+
+    /// Forget the pairing with a server
+    pub fn forget(&mut self, address: &str) -> Result<bool> {
+        let removed = self.servers.remove(address).is_some();
+        if removed {
+            self.save()?;
+            info!("Forgot pairing with {}", address);
+        }
+        Ok(removed)
+    }
+}
+
+/// Name this Mac pairs with, shown in the server's list of pairings
+pub fn device_name() -> String {
+    match std::env::var("USER") {
+        Ok(user) if !user.is_empty() => format!("{}'s Mac", user),
+        _ => "Mac".to_string(),
+    }
+}
+
+/// Introduce the client to the server before any message is sent
+///
+/// Authenticates with the stored pairing, or anonymously without one.
+/// `binding` ties the proofs to the TLS session (empty without TLS).
+pub async fn login(
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    credential: Option<&PairingCredential>,
+    binding: &[u8],
+) -> Result<()> {
+    let Some(credential) = credential else {
+        write_frame(writer, &ClientHello::Anonymous).await?;
+        return expect_accepted(reader).await;
+    };
+
+    let client_nonce = pairing::random_bytes()?;
+    write_frame(
+        writer,
+        &ClientHello::Paired {
+            client_id: credential.client_id,
+            nonce: client_nonce,
+        },
+    )
+    .await?;
+
+    let (server_nonce, proof) = match read_frame(reader).await? {
+        ServerHello::Challenge { nonce, proof } => (nonce, proof),
+        ServerHello::Rejected { reason } => bail!("Server refused the connection: {}", reason),
+        other => bail!("Unexpected handshake answer: {:?}", other),
+    };
+    // The server must know the key too, or it is not the server this Mac paired with
+    verify_challenge_proof(
+        &credential.key,
+        Role::Server,
+        &credential.client_id,
+        &client_nonce,
+        &server_nonce,
+        binding,
+        &proof,
+    )
+    .map_err(|_| anyhow!("Server could not prove the pairing, it is not the server this Mac paired with"))?;
+
+    let proof = challenge_proof(
+        &credential.key,
+        Role::Client,
+        &credential.client_id,
+        &client_nonce,
+        &server_nonce,
+        binding,
+    );
+    write_frame(writer, &ClientFinish { proof }).await?;
+    expect_accepted(reader).await
+}
+
+/// Pair with the server using the PIN it shows
+pub async fn pair(
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    name: &str,
+    pin: &str,
+    binding: &[u8],
+) -> Result<PairingCredential> {
+    let spake = Spake2::start(Role::Client, pin)?;
+    write_frame(
+        writer,
+        &ClientHello::Pair {
+            name: name.to_string(),
+            message: spake.message(),
+        },
+    )
+    .await?;
+
+    let (message, confirmation, client_id) = match read_frame(reader).await? {
+        ServerHello::PairReply {
+            message,
+            confirmation,
+            client_id,
+        } => (message, confirmation, client_id),
+        ServerHello::Rejected { reason } => bail!("Server refused pairing: {}", reason),
+        other => bail!("Unexpected handshake answer: {:?}", other),
+    };
+    let keys = spake.finish(&message, binding)?;
+    keys.verify(Role::Server, &confirmation)?;
+
+    write_frame(
+        writer,
+        &ClientFinish {
+            proof: keys.confirmation(Role::Client),
+        },
+    )
+    .await?;
+    expect_accepted(reader).await?;
+
+    Ok(PairingCredential { client_id, key: keys.key })
+}
+
+async fn expect_accepted(reader: &mut (impl AsyncRead + Unpin)) -> Result<()> {
+    match read_frame(reader).await? {
+        ServerHello::Accepted => Ok(()),
+        ServerHello::Rejected { reason } => bail!("Server refused the connection: {}", reason),
+        other => bail!("Unexpected handshake answer: {:?}", other),
+    }
+}
+
+async fn read_frame<T: DeserializeOwned>(reader: &mut (impl AsyncRead + Unpin)) -> Result<T> {
+    let mut prefix = [0u8; 4];
+    reader.read_exact(&mut prefix).await?;
+    let mut body = vec![0u8; frame_length(prefix)?];
+    reader.read_exact(&mut body).await?;
+    Ok(pairing::decode_frame(&body)?)
+}
+
+async fn write_frame<T: Serialize>(writer: &mut (impl AsyncWrite + Unpin), message: &T) -> Result<()> {
+    writer.write_all(&pairing::encode_frame(message)?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// `pair` command line entry
+///
+/// Usage: `pair --host HOST [--port PORT] [--tls] [--name NAME] PIN`.
+pub fn run_pair_command(args: &[String]) -> Result<()> {
+    let mut host = None;
+    let mut port = DEFAULT_PORT;
+    let mut tls = false;
+    let mut name = device_name();
+    let mut pin = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--host" => host = Some(value()?.clone()),
+            "--port" => port = value()?.parse()?,
+            "--tls" => tls = true,
+            "--name" => name = value()?.clone(),
+            _ => pin = Some(arg.clone()),
+        }
+    }
+
+    let host = host.ok_or_else(|| anyhow!("--host is required"))?;
+    let pin = pin.ok_or_else(|| anyhow!("No PIN given, use the one the server shows"))?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(network::pair_cli(&host, port, tls, &name, &pin))?;
+    println!("Paired with {}:{} as \"{}\"", host, port, name);
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use anyhow::{anyhow, Result};
+use samesame_protocol::clipboard::ClipboardSettings;
+use samesame_protocol::consumer::ConsumerKey;
+use samesame_protocol::layout::{ScreenLayout, SwitchOptions};
+use samesame_protocol::PointerMode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::state::{BroadcastConfig, InputMode};
+
+/// Identifier of a server profile
+pub type ProfileId = u32;
+
+/// A named server connection the client can forward input to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerProfile {
+    pub id: ProfileId,
+    /// Display name (e.g. "Windows Workstation")
+    pub name: String,
+    /// Server host name or IP
+    pub host: String,
+    /// Server port
+    pub port: u16,
+    /// Number key (1-9) that activates this target together with Ctrl+Option
+    pub hotkey: Option<u8>,
+    /// How the server applies mouse movement (absolute for desktop use, relative/game for 3D apps)
+    #[serde(default)]
+    pub pointer_mode: PointerMode,
+    /// Connect over TLS, pinning the server's certificate on first use
+    #[serde(default)]
+    pub tls: bool,
+    /// Send pointer moves and scroll deltas over UDP, if the server offers it
+    #[serde(default)]
+    pub udp_pointer: bool,
+    /// Connect over QUIC instead of TCP (always encrypted, pinned like TLS)
+    #[serde(default)]
+    pub quic: bool,
+}
+
+impl ServerProfile {
+    /// Server address (IP:PORT)
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// Server profiles and the screen layout between them, persisted as JSON
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProfileStore {
+    /// File the profiles are saved to (not persisted itself)
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    next_id: ProfileId,
+    profiles: Vec<ServerProfile>,
+    /// Arrangement of the local screen and the targets for edge switching
+    #[serde(default)]
+    pub screen_layout: ScreenLayout<InputMode>,
+    #[serde(default)]
+    pub switch_options: SwitchOptions,
+    /// Media keys that always act on the Mac, even while forwarding
+    #[serde(default)]
+    pub local_media_keys: BTreeSet<ConsumerKey>,
+    /// Input sources (e.g. Japanese IME, layouts with dead keys) whose composed
+    /// text is forwarded instead of the raw key presses
+    #[serde(default)]
+    pub text_input_sources: BTreeSet<String>,
+    /// Clipboard synchronization with the servers
+    #[serde(default)]
+    pub clipboard: ClipboardSettings,
+    /// Broadcast mode: further profiles that receive the input
+    #[serde(default)]
+    pub broadcast: BroadcastConfig,
+}
+
+impl ProfileStore {
+    /// Load profiles from `path`, starting empty if the file does not exist yet
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let mut store = if path.exists() {
+            let data = fs::read_to_string(path)?;
+            serde_json::from_str(&data)?
+        } else {
+            Self::default()
+        };
+        store.path = Some(path.to_path_buf());
+
+        info!("Loaded {} server profile(s) from {}", store.profiles.len(), path.display());
+
+        Ok(store)
+    }
+
+    /// Write profiles back to the file they were loaded from
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// Add a new profile and return it
+    pub fn add(
+        &mut self,
+        name: String,
+        host: String,
+        port: u16,
+        hotkey: Option<u8>,
+        tls: bool,
+        quic: bool,
+    ) -> Result<ServerProfile> {
+        if let Some(key) = hotkey {
+            if !(1..=9).contains(&key) {
+                return Err(anyhow!("Hotkey must be a number between 1 and 9"));
+            }
+            if let Some(existing) = self.find_by_hotkey(key) {
+                return Err(anyhow!("Hotkey {} is already used by '{}'", key, existing.name));
+            }
+        }
+
+        self.next_id += 1;
+        let profile = ServerProfile {
+            id: self.next_id,
+            name,
+            host,
+            port,
+            hotkey,
+            pointer_mode: PointerMode::default(),
+            tls,
+            udp_pointer: false,
+            quic,
+        };
+        self.profiles.push(profile.clone());
+
+        Ok(profile)
+    }
+
+    /// Remove a profile, returning it if it existed
+    pub fn remove(&mut self, id: ProfileId) -> Option<ServerProfile> {
+        let index = self.profiles.iter().position(|p| p.id == id)?;
+        self.screen_layout.remove_screen(&InputMode::Target(id));
+        self.broadcast.targets.remove(&id);
+        Some(self.profiles.remove(index))
+    }
+
+    pub fn get(&self, id: ProfileId) -> Option<&ServerProfile> {
+        self.profiles.iter().find(|p| p.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: ProfileId) -> Option<&mut ServerProfile> {
+        self.profiles.iter_mut().find(|p| p.id == id)
+    }
+
+    pub fn list(&self) -> &[ServerProfile] {
+        &self.profiles
+    }
+
+    /// Find the profile bound to Ctrl+Option+`key`
+    pub fn find_by_hotkey(&self, key: u8) -> Option<&ServerProfile> {
+        self.profiles.iter().find(|p| p.hotkey == Some(key))
+    }
+
+    /// Find a profile by host and port
+    pub fn find_by_address(&self, host: &str, port: u16) -> Option<&ServerProfile> {
+        self.profiles.iter().find(|p| p.host == host && p.port == port)
+    }
+}
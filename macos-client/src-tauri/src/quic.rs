@@ -0,0 +1,288 @@
+use anyhow::{anyhow, Context, Result};
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{Endpoint, RecvStream, SendStream, TransportConfig};
+use samesame_protocol::pairing::TLS_EXPORTER_LABEL;
+use samesame_protocol::quic::{
+    self as quic_protocol, QuicStream, SegmentSplitter, ALPN, BULK_STREAM_MARKER, IDLE_TIMEOUT, KEEP_ALIVE_INTERVAL,
+};
+use samesame_protocol::tls::certificate_fingerprint;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::{self, pki_types::CertificateDer};
+use tracing::warn;
+
+use crate::tls;
+
+/// Buffer between the mux and the streams
+const ROUTER_BUFFER: usize = 64 * 1024;
+
+/// The bulk stream of a connection, kept until the pairing handshake is done
+pub struct BulkStream {
+    send: SendStream,
+    recv: RecvStream,
+}
+
+/// Open a QUIC connection to `addr`, returns it with the server's certificate fingerprint
+///
+/// As with TLS over TCP, the certificate is not checked against any
+/// authority; the caller decides whether to trust the fingerprint before
+/// opening any stream. The local address is left to the system, so a laptop
+/// that changes networks keeps the connection.
+pub async fn connect(addr: &str, host: &str) -> Result<(quinn::Connection, String)> {
+    let server: SocketAddr = tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("Cannot resolve {}", addr))?;
+
+    let mut crypto = tls::pinning_config(&[&rustls::version::TLS13])?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicClientConfig::try_from(crypto).map_err(|e| anyhow!("Invalid QUIC TLS configuration: {}", e))?;
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    transport.max_idle_timeout(Some(IDLE_TIMEOUT.try_into()?));
+    let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+    config.transport_config(Arc::new(transport));
+
+    let local = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let endpoint = Endpoint::client(local)?;
+    let connection = endpoint
+        .connect_with(config, server, host)?
+        .await
+        .context("QUIC handshake failed")?;
+
+    let fingerprint = connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .and_then(|certificates| certificates.first().map(|certificate| certificate_fingerprint(certificate)))
+        .ok_or_else(|| anyhow!("Server sent no certificate"))?;
+
+    Ok((connection, fingerprint))
+}
+
+/// Keying material exported from the QUIC connection, binding the pairing handshake to it
+pub fn channel_binding(connection: &quinn::Connection) -> Result<Vec<u8>> {
+    let mut binding = vec![0u8; 32];
+    connection
+        .export_keying_material(&mut binding, TLS_EXPORTER_LABEL, b"")
+        .map_err(|_| anyhow!("Cannot export TLS keying material"))?;
+    Ok(binding)
+}
+
+/// Open the main and the bulk stream, returns the main stream for the pairing handshake
+pub async fn open_streams(connection: &quinn::Connection) -> Result<(SendStream, RecvStream, BulkStream)> {
+    let (main_send, main_recv) = connection.open_bi().await?;
+    let (mut send, recv) = connection.open_bi().await?;
+    send.write_all(&[BULK_STREAM_MARKER]).await?;
+    Ok((main_send, main_recv, BulkStream { send, recv }))
+}
+
+impl BulkStream {
+    /// Run the mux on the main and the bulk stream, returns the reader and writer for it
+    pub fn into_streams(self, main_recv: RecvStream, main_send: SendStream) -> (DuplexStream, DuplexStream) {
+        (merge_streams(main_recv, self.recv), split_streams(main_send, self.send))
+    }
+}
+
+/// Writer for the mux that sends bulk segments on the bulk stream
+fn split_streams(main: SendStream, bulk: SendStream) -> DuplexStream {
+    let (writer, mut routed) = tokio::io::duplex(ROUTER_BUFFER);
+    let main = forward(main);
+    let bulk = forward(bulk);
+
+    tokio::spawn(async move {
+        let mut splitter = SegmentSplitter::new();
+        let mut buffer = vec![0u8; ROUTER_BUFFER];
+        loop {
+            let n = match routed.read(&mut buffer).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => n,
+            };
+            splitter.extend(&buffer[..n]);
+            while let Ok(Some(segment)) = splitter.next_segment() {
+                let stream = match quic_protocol::stream_of(&segment) {
+                    QuicStream::Main => &main,
+                    QuicStream::Bulk => &bulk,
+                };
+                // Dropping `routed` makes the next write of the mux fail
+                if stream.send(segment).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    writer
+}
+
+/// Write segments to a stream until the queue closes, then finish the stream
+///
+/// The mux windows bound what can pile up in the queue.
+fn forward(mut stream: impl AsyncWrite + Unpin + Send + 'static) -> mpsc::UnboundedSender<Vec<u8>> {
+    let (queue, mut segments) = mpsc::unbounded_channel::<Vec<u8>>();
+    tokio::spawn(async move {
+        while let Some(segment) = segments.recv().await {
+            if stream.write_all(&segment).await.is_err() {
+                return;
+            }
+        }
+        let _ = stream.shutdown().await;
+    });
+    queue
+}
+
+/// Reader for the mux that interleaves the segments of both streams
+///
+/// Ends when the main stream ends.
+fn merge_streams(main: RecvStream, bulk: RecvStream) -> DuplexStream {
+    let (reader, writer) = tokio::io::duplex(ROUTER_BUFFER);
+    let writer = Arc::new(tokio::sync::Mutex::new(writer));
+    tokio::spawn(collect(main, writer.clone(), true));
+    tokio::spawn(collect(bulk, writer, false));
+    reader
+}
+
+/// Pass whole segments from a stream to the merged reader
+async fn collect(mut stream: impl AsyncRead + Unpin, merged: Arc<tokio::sync::Mutex<DuplexStream>>, main: bool) {
+    let mut splitter = SegmentSplitter::new();
+    let mut buffer = vec![0u8; 8192];
+    'read: loop {
+        let n = match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        splitter.extend(&buffer[..n]);
+        loop {
+            match splitter.next_segment() {
+                Ok(Some(segment)) => {
+                    if merged.lock().await.write_all(&segment).await.is_err() {
+                        break 'read;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Closing QUIC stream: {}", e);
+                    break 'read;
+                }
+            }
+        }
+    }
+    if main {
+        let _ = merged.lock().await.shutdown().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network;
+    use quinn::crypto::rustls::QuicServerConfig;
+    use samesame_protocol::clipboard::{self as clipboard_sync, ClipboardContent};
+    use samesame_protocol::datagram as datagrams;
+    use samesame_protocol::pairing::{self, ClientHello, ServerHello};
+    use samesame_protocol::{InputEvent, KeyboardEvent, Message, Modifiers, MouseMoveEvent, MuxConfig, MuxDecoder};
+    use std::time::Duration;
+    use tokio_rustls::rustls::crypto;
+    use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio_rustls::rustls::ServerConfig;
+
+    /// Longest wait for the other side
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// QUIC endpoint on loopback with a new self-signed certificate, and the certificate's fingerprint
+    fn server() -> (Endpoint, String) {
+        let generated = rcgen::generate_simple_self_signed(vec!["samesame".to_string()]).unwrap();
+        let certificate = generated.cert.der().clone();
+        let fingerprint = certificate_fingerprint(&certificate);
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(generated.signing_key.serialize_der()));
+        let mut crypto = ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], key)
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let config = quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto).unwrap()));
+
+        let endpoint = Endpoint::server(config, SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+        (endpoint, fingerprint)
+    }
+
+    /// First message arriving on a stream of the connection
+    async fn next_message(stream: &mut RecvStream) -> Message {
+        let mut decoder = MuxDecoder::new(MuxConfig::default());
+        let mut buffer = vec![0u8; 8192];
+        loop {
+            if let Some(message) = decoder.next_message().unwrap() {
+                return message;
+            }
+            let n = stream.read(&mut buffer).await.unwrap().expect("stream ended");
+            decoder.extend(&buffer[..n]);
+        }
+    }
+
+    /// Accept one client like the server does, returns what it sent on the main stream, the bulk stream and as datagram
+    async fn accept(endpoint: Endpoint) -> (Message, Message, Message) {
+        let connection = endpoint.accept().await.unwrap().await.unwrap();
+        let (mut main_send, mut main_recv) = connection.accept_bi().await.unwrap();
+        let (_bulk_send, mut bulk_recv) = connection.accept_bi().await.unwrap();
+        let mut marker = [0u8; 1];
+        bulk_recv.read_exact(&mut marker).await.unwrap();
+        assert_eq!(marker[0], BULK_STREAM_MARKER);
+
+        let mut prefix = [0u8; 4];
+        main_recv.read_exact(&mut prefix).await.unwrap();
+        let mut body = vec![0u8; pairing::frame_length(prefix).unwrap()];
+        main_recv.read_exact(&mut body).await.unwrap();
+        assert!(matches!(pairing::decode_frame(&body).unwrap(), ClientHello::Anonymous));
+        main_send.write_all(&pairing::encode_frame(&ServerHello::Accepted).unwrap()).await.unwrap();
+
+        let main = next_message(&mut main_recv).await;
+        let bulk = next_message(&mut bulk_recv).await;
+        let datagram = connection.read_datagram().await.unwrap();
+        (main, bulk, datagrams::decode_pointer(&datagram).unwrap())
+    }
+
+    #[tokio::test]
+    async fn input_bulk_and_pointer_take_their_own_paths() {
+        let (endpoint, fingerprint) = server();
+        let port = endpoint.local_addr().unwrap().port();
+        let server = tokio::spawn(accept(endpoint));
+
+        let trust = |seen: &str| {
+            assert_eq!(seen, fingerprint);
+            Ok(())
+        };
+        let mut connection = network::connect_quic("127.0.0.1", port, trust, None, |_| {}).await.unwrap();
+        connection
+            .send_event(InputEvent::Keyboard(KeyboardEvent {
+                key_code: 0x00,
+                character: Some('a'),
+                pressed: true,
+                modifiers: Modifiers::default(),
+                is_repeat: false,
+            }))
+            .unwrap();
+        for chunk in clipboard_sync::split(&ClipboardContent::text("copied"), 1).unwrap() {
+            connection.send_bulk(InputEvent::Clipboard(chunk)).unwrap();
+        }
+        connection
+            .send_event(InputEvent::MouseMove(MouseMoveEvent {
+                x: 0.25,
+                y: 0.75,
+                delta_x: 1.0,
+                delta_y: 1.0,
+                timestamp_us: 0,
+            }))
+            .unwrap();
+
+        let (main, bulk, datagram) = tokio::time::timeout(TIMEOUT, server).await.expect("server got nothing").unwrap();
+        assert!(matches!(main.event, InputEvent::Keyboard(event) if event.key_code == 0x00 && event.pressed));
+        assert!(matches!(bulk.event, InputEvent::Clipboard(chunk) if chunk.count == 1));
+        assert!(matches!(datagram.event, InputEvent::MouseMove(event) if event.x == 0.25 && event.y == 0.75));
+        connection.close().await;
+    }
+}
@@ -1,45 +1,593 @@
+use parking_lot::Mutex;
+use samesame_protocol::acceleration::{AccelerationCurve, PointerAccelerator};
+use samesame_protocol::arbitration::{Arbitration, ControlStatus};
+use samesame_protocol::clipboard::{self, Clipboard, ClipboardContent, ClipboardSync, ClipboardSyncMode};
+use samesame_protocol::consumer::ConsumerKey;
+use samesame_protocol::layout::{EdgeSwitcher, Transition};
+use samesame_protocol::mapping::ScreenInfo;
+use samesame_protocol::transfer::FileTransfer;
+use samesame_protocol::{
+    ConsumerControlEvent, EnterScreenEvent, EventKind, InputEvent, LockState, Message, MouseMoveEvent,
+    TextInputEvent,
+};
 use serde::{Deserialize, Serialize};
-use tokio::net::TcpStream;
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::clipboard::system_clipboard;
+use crate::hotkeys::HotkeyAction;
+use crate::network::ServerConnection;
+use crate::pairing::PairedServers;
+use crate::profiles::{ProfileId, ProfileStore};
+use crate::tls::KnownServers;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum InputMode {
     /// Inputs go to macOS (normal mode)
-    MacOS,
-    /// Inputs are forwarded to Windows (forwarding mode)
-    Windows,
+    Local,
+    /// Inputs are forwarded to the server of the given profile
+    Target(ProfileId),
+}
+
+/// Sends input to several servers at once, like synchronized terminal panes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BroadcastConfig {
+    pub enabled: bool,
+    /// Profiles that receive broadcast input in addition to the active target
+    pub targets: BTreeSet<ProfileId>,
+    /// Also broadcast pointer events and gestures (keyboard is always broadcast)
+    pub include_pointer: bool,
+}
+
+impl BroadcastConfig {
+    /// Check if an event of this kind is broadcast
+    fn applies_to(&self, kind: EventKind) -> bool {
+        match kind {
+            EventKind::Keyboard => self.enabled,
+            EventKind::Pointer | EventKind::Gesture => self.enabled && self.include_pointer,
+            EventKind::Control => false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct AppState {
     /// Current input mode
     pub mode: InputMode,
-    /// TCP connection to Windows server (if connected)
+    /// Configured server profiles
+    pub profiles: ProfileStore,
+    /// Open connections, keyed by profile
+    #[serde(skip)]
+    pub connections: HashMap<ProfileId, ServerConnection>,
+    /// Target the toggle hotkey switches back to
+    pub last_target: Option<ProfileId>,
+    /// Screen-edge switching, built from the stored layout
+    #[serde(skip)]
+    pub edge_switcher: Option<EdgeSwitcher<InputMode>>,
+    /// Virtual cursor position (pixels) on the active target screen
+    pub remote_cursor: (f64, f64),
+    /// Monitor topology of this Mac
+    pub local_screens: Option<ScreenInfo>,
+    /// Monitor topology reported by each connected server
+    pub remote_screens: HashMap<ProfileId, ScreenInfo>,
+    /// Servers that asked for unaccelerated pointer deltas
+    pub raw_deltas: BTreeSet<ProfileId>,
+    /// Lock keys on this Mac
+    pub local_locks: LockState,
+    /// Lock keys reported by each connected server
+    pub remote_locks: HashMap<ProfileId, LockState>,
+    /// Who controls each connected server
+    pub control: HashMap<ProfileId, ControlStatus>,
+    /// Undoes the macOS acceleration for servers that want raw deltas
+    #[serde(skip)]
+    delta_decelerator: Option<PointerAccelerator>,
+    #[serde(skip, default = "system_clipboard")]
+    clipboard: Arc<dyn Clipboard>,
+    /// Clipboard changes exchanged with each connected server
+    #[serde(skip)]
+    clipboard_sync: HashMap<ProfileId, ClipboardSync>,
+    /// Change count of the local clipboard when it was last checked
+    #[serde(skip)]
+    clipboard_change: Option<u64>,
+    /// Servers input moved to, whose clipboard the poller sends (sync on switch)
+    #[serde(skip)]
+    clipboard_switched: Vec<ProfileId>,
+    /// Wakes the clipboard poller, so a switch does not wait for the next poll
+    #[serde(skip)]
+    clipboard_wake: Option<std::sync::mpsc::Sender<()>>,
+    /// Server replies for running file transfers, keyed by transfer id
     #[serde(skip)]
-    pub network_client: Option<TcpStream>,
-    /// Server address (IP:PORT)
-    pub server_address: Option<String>,
-    /// Message sequence number
-    pub sequence: u64,
+    pub file_replies: HashMap<u64, mpsc::UnboundedSender<FileTransfer>>,
+    /// Files whose transfer a dropped connection interrupted, resumed on reconnect
+    #[serde(skip)]
+    pub unfinished_files: HashMap<ProfileId, Vec<PathBuf>>,
+    /// Pinned certificate fingerprints of TLS servers
+    #[serde(skip)]
+    pub known_servers: KnownServers,
+    /// Keys of the servers this Mac is paired with
+    #[serde(skip)]
+    pub paired_servers: PairedServers,
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
-            mode: InputMode::MacOS,
-            network_client: None,
-            server_address: None,
-            sequence: 0,
+            mode: InputMode::Local,
+            profiles: ProfileStore::default(),
+            connections: HashMap::new(),
+            last_target: None,
+            edge_switcher: None,
+            remote_cursor: (0.0, 0.0),
+            local_screens: None,
+            remote_screens: HashMap::new(),
+            raw_deltas: BTreeSet::new(),
+            local_locks: LockState::default(),
+            remote_locks: HashMap::new(),
+            control: HashMap::new(),
+            delta_decelerator: None,
+            clipboard: system_clipboard(),
+            clipboard_sync: HashMap::new(),
+            clipboard_change: None,
+            clipboard_switched: Vec::new(),
+            clipboard_wake: None,
+            file_replies: HashMap::new(),
+            unfinished_files: HashMap::new(),
+            known_servers: KnownServers::default(),
+            paired_servers: PairedServers::default(),
+        }
+    }
+
+    /// Rebuild the edge switcher after the stored layout changed
+    pub fn reload_layout(&mut self) {
+        let layout = &self.profiles.screen_layout;
+        self.edge_switcher = if layout.links.is_empty() {
+            None
+        } else {
+            Some(EdgeSwitcher::new(layout.clone(), self.profiles.switch_options.clone()))
+        };
+    }
+
+    /// Follow the pointer for screen-edge switching
+    ///
+    /// `local` is the Mac cursor position and `delta` the relative movement,
+    /// both in pixels. While a target is active the movement drives a virtual
+    /// cursor on that target's screen. Returns the transition if the pointer
+    /// crossed to another screen, so the caller can warp the local cursor.
+    pub fn track_pointer(
+        &mut self,
+        local: (f64, f64),
+        delta: (f64, f64),
+        now: Instant,
+    ) -> Option<Transition<InputMode>> {
+        let switcher = self.edge_switcher.as_mut()?;
+
+        let (x, y) = match self.mode {
+            InputMode::Local => local,
+            InputMode::Target(_) => {
+                let geometry = switcher.layout().geometry(&self.mode)?;
+                self.remote_cursor = (
+                    (self.remote_cursor.0 + delta.0).clamp(0.0, geometry.width - 1.0),
+                    (self.remote_cursor.1 + delta.1).clamp(0.0, geometry.height - 1.0),
+                );
+                self.remote_cursor
+            }
+        };
+
+        let transition = switcher.update(&self.mode, x, y, now)?;
+        if let InputMode::Target(id) = transition.to {
+            if !self.is_connected(id) {
+                return None;
+            }
+        }
+
+        self.switch_screen(&transition);
+        Some(transition)
+    }
+
+    /// Process a message received from the server of a profile
+    pub fn handle_server_message(&mut self, id: ProfileId, message: Message) {
+        match message.event {
+            InputEvent::CursorPosition(position) => {
+                // The server's cursor is authoritative for the virtual cursor
+                if self.mode != InputMode::Target(id) {
+                    return;
+                }
+                let Some(screens) = self.remote_screens.get(&id) else {
+                    return;
+                };
+                let Some(monitor) = screens.monitors.get(position.screen as usize) else {
+                    return;
+                };
+                let (x, y) = monitor.denormalize(position.x, position.y);
+                let bounds = screens.bounds();
+                self.remote_cursor = (x - bounds.x, y - bounds.y);
+                if let Some(edge) = position.edge {
+                    debug!("Remote cursor of profile {} reached {:?} edge", id, edge);
+                }
+            }
+            InputEvent::ScreenInfo(screens) => {
+                info!("Profile {} has {} monitor(s)", id, screens.monitors.len());
+
+                // Keep the edge-switching layout in sync with the real desktop size
+                let bounds = screens.bounds();
+                let screen = InputMode::Target(id);
+                if self.profiles.screen_layout.geometry(&screen).is_some() {
+                    self.profiles.screen_layout.set_screen(screen, bounds.width, bounds.height);
+                    self.reload_layout();
+                }
+
+                self.remote_screens.insert(id, screens);
+            }
+            InputEvent::RequestRawDeltas(enabled) => {
+                info!("Profile {} requested raw pointer deltas: {}", id, enabled);
+                if enabled {
+                    self.raw_deltas.insert(id);
+                } else {
+                    self.raw_deltas.remove(&id);
+                }
+            }
+            InputEvent::LockState(locks) => {
+                debug!("Profile {} lock keys: {:?}", id, locks);
+                self.remote_locks.insert(id, locks);
+            }
+            InputEvent::Clipboard(chunk) => {
+                let settings = self.profiles.clipboard.clone();
+                let sync = self.clipboard_sync.entry(id).or_insert_with(|| ClipboardSync::new(settings));
+                match clipboard::apply_incoming(sync, &*self.clipboard, chunk) {
+                    Ok(true) => debug!("Clipboard updated from profile {}", id),
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to apply clipboard from profile {}: {}", id, e),
+                }
+            }
+            InputEvent::FileTransfer(reply) => match self.file_replies.get(&reply.transfer_id()) {
+                Some(replies) => {
+                    let _ = replies.send(reply);
+                }
+                None => debug!("File transfer reply for a finished transfer: {:?}", reply),
+            },
+            InputEvent::Arbitration(Arbitration::Status(status)) => {
+                match (&status.controller, status.queue_position) {
+                    _ if status.in_control => info!("In control of profile {}", id),
+                    (Some(controller), Some(position)) => {
+                        info!("{} controls profile {}, waiting at position {}", controller, id, position)
+                    }
+                    (Some(controller), None) => info!("{} controls profile {}", controller, id),
+                    (None, _) => info!("Nobody controls profile {}", id),
+                }
+                self.control.insert(id, status);
+            }
+            InputEvent::Pong => debug!("Pong from profile {} (sequence {})", id, message.sequence),
+            event => warn!("Unexpected message from profile {}: {:?}", id, event),
+        }
+    }
+
+    /// Move input focus to the screen the pointer crossed onto
+    fn switch_screen(&mut self, transition: &Transition<InputMode>) {
+        if let InputMode::Target(_) = transition.from {
+            self.forward(InputEvent::LeaveScreen);
+        }
+
+        match transition.to {
+            InputMode::Local => self.mode = InputMode::Local,
+            InputMode::Target(id) => {
+                self.activate(id);
+                self.remote_cursor = (transition.entry_x, transition.entry_y);
+                let (x, y) = transition.entry_normalized;
+                self.forward(InputEvent::EnterScreen(EnterScreenEvent { x, y }));
+            }
         }
+
+        info!("Pointer moved to {:?} via {:?} edge", self.mode, transition.edge);
     }
 
-    /// Get next sequence number
-    pub fn next_sequence(&mut self) -> u64 {
-        self.sequence += 1;
-        self.sequence
+    /// Connection of the active target, if input should be forwarded
+    pub fn active_connection(&self) -> Option<&ServerConnection> {
+        match self.mode {
+            InputMode::Local => None,
+            InputMode::Target(id) => self.connections.get(&id),
+        }
+    }
+
+    /// Lock keys the active target last reported
+    pub fn active_locks(&self) -> Option<LockState> {
+        match self.mode {
+            InputMode::Local => None,
+            InputMode::Target(id) => self.remote_locks.get(&id).copied(),
+        }
     }
 
     /// Check if we should forward inputs
     pub fn should_forward(&self) -> bool {
-        self.mode == InputMode::Windows && self.network_client.is_some()
+        self.active_connection().is_some()
+    }
+
+    /// Check if a profile has an open connection
+    pub fn is_connected(&self, id: ProfileId) -> bool {
+        self.connections.get(&id).is_some_and(|c| !c.is_closed())
+    }
+
+    /// Send an event to the active target and, in broadcast mode, to all broadcast targets
+    ///
+    /// Every connection numbers its own messages. A target that fails is
+    /// disconnected without affecting the others. Returns the number of
+    /// targets the event was queued for.
+    pub fn forward(&mut self, event: InputEvent) -> usize {
+        let InputMode::Target(active) = self.mode else {
+            return 0;
+        };
+
+        let mut targets = BTreeSet::from([active]);
+        let broadcast = &self.profiles.broadcast;
+        if broadcast.applies_to(event.kind()) {
+            targets.extend(broadcast.targets.iter().copied());
+        }
+
+        // Computed once, the decelerator tracks movement timing
+        let raw_event = match &event {
+            InputEvent::MouseMove(move_event) if targets.iter().any(|id| self.raw_deltas.contains(id)) => {
+                Some(InputEvent::MouseMove(self.remove_acceleration(move_event)))
+            }
+            _ => None,
+        };
+
+        let mut sent = 0;
+        let mut failed = Vec::new();
+        for id in targets {
+            let Some(connection) = self.connections.get_mut(&id) else {
+                continue;
+            };
+            let event = match &raw_event {
+                Some(raw) if self.raw_deltas.contains(&id) => raw,
+                _ => &event,
+            };
+            match connection.send_event(event.clone()) {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    warn!("Failed to forward event to profile {}: {}", id, e);
+                    if connection.is_closed() {
+                        failed.push(id);
+                    }
+                }
+            }
+        }
+
+        for id in failed {
+            self.disconnect(id);
+        }
+
+        sent
+    }
+
+    /// Approximate the unaccelerated movement by inverting a macOS-like curve
+    fn remove_acceleration(&mut self, move_event: &MouseMoveEvent) -> MouseMoveEvent {
+        let decelerator = self
+            .delta_decelerator
+            .get_or_insert_with(|| PointerAccelerator::new(AccelerationCurve::MacOs));
+        let (delta_x, delta_y) =
+            decelerator.remove(move_event.delta_x, move_event.delta_y, move_event.timestamp_us);
+
+        MouseMoveEvent {
+            delta_x,
+            delta_y,
+            ..move_event.clone()
+        }
+    }
+
+    /// Forward a media key unless it should stay on the Mac, returns true if it was forwarded
+    pub fn handle_media_key(&mut self, key: ConsumerKey, pressed: bool) -> bool {
+        if self.mode == InputMode::Local || self.profiles.local_media_keys.contains(&key) {
+            return false;
+        }
+        self.forward(InputEvent::ConsumerControl(ConsumerControlEvent::new(key, pressed))) > 0
+    }
+
+    /// Record the Mac's lock keys and bring the targets in line when they change
+    pub fn update_local_locks(&mut self, locks: LockState) {
+        if locks == self.local_locks {
+            return;
+        }
+        self.local_locks = locks;
+        self.forward(InputEvent::LockState(locks));
+    }
+
+    /// Whether keys typed with this input source are forwarded as composed text
+    pub fn forwards_text(&self, input_source: Option<&str>) -> bool {
+        input_source.is_some_and(|source| self.profiles.text_input_sources.contains(source))
+    }
+
+    /// Forward composed text, returns the number of targets it was queued for
+    ///
+    /// Empty text is forwarded too: it erases the marked text on the target
+    /// when a composition is cancelled.
+    pub fn send_text(&mut self, text: String, composing: bool) -> usize {
+        self.forward(InputEvent::TextInput(TextInputEvent { text, composing }))
+    }
+
+    /// Send an event to one profile only
+    pub fn send_to(&mut self, id: ProfileId, event: InputEvent) {
+        let Some(connection) = self.connections.get_mut(&id) else {
+            return;
+        };
+        if let Err(e) = connection.send_event(event) {
+            warn!("Failed to send to profile {}: {}", id, e);
+        }
+    }
+
+    /// Queue part of a bulk transfer for one profile
+    pub fn send_bulk(&mut self, id: ProfileId, event: InputEvent) -> anyhow::Result<()> {
+        let connection = self
+            .connections
+            .get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("Profile {} is not connected", id))?;
+        connection.send_bulk(event)
+    }
+
+    /// Forward input to the given profile
+    pub fn activate(&mut self, id: ProfileId) {
+        self.mode = InputMode::Target(id);
+        self.last_target = Some(id);
+        self.enter_target(id);
+    }
+
+    /// Bring a server up to date when input moves to it
+    fn enter_target(&mut self, id: ProfileId) {
+        // Lock keys may have changed while another screen had the input
+        let locks = self.local_locks;
+        self.send_to(id, InputEvent::LockState(locks));
+        self.request_control(id);
+        self.clipboard_on_switch(id);
+    }
+
+    /// Ask the server to make this Mac its controller, or queue for it
+    fn request_control(&mut self, id: ProfileId) {
+        if !self.control.get(&id).is_some_and(|status| status.in_control) {
+            self.send_to(id, InputEvent::Arbitration(Arbitration::Request));
+        }
+    }
+
+    /// Toggle between local input and the last active target
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            InputMode::Target(_) => InputMode::Local,
+            InputMode::Local => match self.last_target.or_else(|| self.first_connected()) {
+                Some(id) => {
+                    self.last_target = Some(id);
+                    InputMode::Target(id)
+                }
+                None => InputMode::Local,
+            },
+        };
+        if let InputMode::Target(id) = self.mode {
+            self.enter_target(id);
+        }
+    }
+
+    /// Channel that wakes the clipboard poller before its next interval
+    pub fn clipboard_waker(&mut self) -> std::sync::mpsc::Receiver<()> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.clipboard_wake = Some(sender);
+        receiver
+    }
+
+    /// Send clipboard changes to all connected servers (continuous sync), or
+    /// the clipboard to the servers input moved to (sync on switch)
+    ///
+    /// The pasteboard is read without holding the state lock, which is only
+    /// taken to compare the change count and to queue the content.
+    pub fn poll_clipboard(state: &Mutex<AppState>) {
+        let (clipboard, switched) = {
+            let mut state = state.lock();
+            match state.profiles.clipboard.mode {
+                ClipboardSyncMode::Continuous => (state.clipboard.clone(), None),
+                ClipboardSyncMode::OnSwitch if !state.clipboard_switched.is_empty() => {
+                    (state.clipboard.clone(), Some(std::mem::take(&mut state.clipboard_switched)))
+                }
+                _ => return,
+            }
+        };
+
+        // Reading the whole clipboard is expensive, skip it while nothing changed
+        let change = clipboard.change_count();
+        if switched.is_none() && change.is_some() && change == state.lock().clipboard_change {
+            return;
+        }
+        let content = match clipboard.read() {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Failed to read clipboard: {}", e);
+                return;
+            }
+        };
+
+        let mut state = state.lock();
+        state.clipboard_change = change;
+        let ids = switched.unwrap_or_else(|| state.connections.keys().copied().collect());
+        state.queue_clipboard(&ids, content);
+    }
+
+    /// Take the clipboard along when input moves to a server (sync on switch)
+    ///
+    /// Called with the state locked, so the poller reads and sends it.
+    fn clipboard_on_switch(&mut self, id: ProfileId) {
+        if self.profiles.clipboard.mode != ClipboardSyncMode::OnSwitch {
+            return;
+        }
+        if !self.clipboard_switched.contains(&id) {
+            self.clipboard_switched.push(id);
+        }
+        if let Some(wake) = &self.clipboard_wake {
+            let _ = wake.send(());
+        }
+    }
+
+    /// Queue clipboard content for the given servers that don't have it yet
+    fn queue_clipboard(&mut self, ids: &[ProfileId], content: ClipboardContent) {
+        for &id in ids {
+            let Some(connection) = self.connections.get_mut(&id) else {
+                continue;
+            };
+            let settings = self.profiles.clipboard.clone();
+            let sync = self.clipboard_sync.entry(id).or_insert_with(|| ClipboardSync::new(settings));
+            let Some(chunks) = sync.outgoing(content.clone()) else {
+                continue;
+            };
+
+            debug!("Sending clipboard to profile {} in {} chunk(s)", id, chunks.len());
+            for chunk in chunks {
+                if let Err(e) = connection.send_bulk(InputEvent::Clipboard(chunk)) {
+                    warn!("Failed to send clipboard to profile {}: {}", id, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Start over with new clipboard settings
+    pub fn reset_clipboard_sync(&mut self) {
+        self.clipboard_sync.clear();
+        self.clipboard_change = None;
+    }
+
+    /// Close the connection of a profile, falling back to local input if it was active
+    pub fn disconnect(&mut self, id: ProfileId) {
+        self.connections.remove(&id);
+        self.remote_screens.remove(&id);
+        self.raw_deltas.remove(&id);
+        self.remote_locks.remove(&id);
+        self.control.remove(&id);
+        self.clipboard_sync.remove(&id);
+        if self.mode == InputMode::Target(id) {
+            self.mode = InputMode::Local;
+        }
+    }
+
+    /// Apply a global hotkey, returns false if it did not change anything
+    pub fn handle_hotkey(&mut self, action: HotkeyAction) -> bool {
+        match action {
+            HotkeyAction::ToggleMode => self.toggle_mode(),
+            HotkeyAction::SelectTarget(key) => {
+                let Some(profile) = self.profiles.find_by_hotkey(key) else {
+                    warn!("No server profile bound to hotkey {}", key);
+                    return false;
+                };
+                let id = profile.id;
+                if !self.is_connected(id) {
+                    warn!("Profile '{}' is not connected", profile.name);
+                }
+                self.activate(id);
+            }
+        }
+
+        info!("Switched to {:?}", self.mode);
+        true
+    }
+
+    /// Lowest profile id with an open connection
+    fn first_connected(&self) -> Option<ProfileId> {
+        self.connections.keys().min().copied()
     }
 }
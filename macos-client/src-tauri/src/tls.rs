@@ -0,0 +1,316 @@
+use anyhow::{anyhow, bail, Context, Result};
+use samesame_protocol::pairing::TLS_EXPORTER_LABEL;
+use samesame_protocol::tls::certificate_fingerprint;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{self, ClientConfig, DigitallySignedStruct, SignatureScheme, SupportedProtocolVersion};
+use tokio_rustls::TlsConnector;
+use tracing::{info, warn};
+
+/// File the pinned fingerprints are stored in, next to the profiles
+pub const KNOWN_SERVERS_FILE: &str = "known_servers.json";
+
+/// Config directory of the app when running from the command line
+///
+/// Matches the directory Tauri uses for the app on macOS.
+const APP_IDENTIFIER: &str = "com.ayysir.macos-client";
+
+/// Config directory of the app, for the command line tools
+pub fn app_config_dir() -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").ok_or_else(|| anyhow!("HOME is not set"))?;
+    Ok(PathBuf::from(home).join("Library/Application Support").join(APP_IDENTIFIER))
+}
+
+/// Certificate fingerprints of servers, pinned on first use
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KnownServers {
+    /// File the fingerprints are saved to (not persisted itself)
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Fingerprint by server address (`host:port`)
+    servers: BTreeMap<String, String>,
+}
+
+/// How a server's certificate was trusted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    /// First connection, the fingerprint was pinned now
+    FirstUse,
+    /// Matches the pinned fingerprint
+    Pinned,
+}
+
+impl KnownServers {
+    /// Load fingerprints from `path`, starting empty if the file does not exist yet
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let mut known = if path.exists() {
+            let data = fs::read_to_string(path)?;
+            serde_json::from_str(&data)?
+        } else {
+            Self::default()
+        };
+        known.path = Some(path.to_path_buf());
+
+        Ok(known)
+    }
+
+    /// Load the fingerprints the app uses, for the command line tools
+    pub fn load_default() -> Result<Self> {
+        Self::load(app_config_dir()?.join(KNOWN_SERVERS_FILE))
+    }
+
+    /// Write fingerprints back to the file they were loaded from
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, address: &str) -> Option<&str> {
+        self.servers.get(address).map(String::as_str)
+    }
+
+    /// Check a server's fingerprint, pinning it on first use
+    ///
+    /// A fingerprint that differs from the pinned one is an error: either the
+    /// server got a new certificate, or someone is intercepting the connection.
+    pub fn check(&mut self, address: &str, fingerprint: &str) -> Result<Trust> {
+        match self.servers.get(address) {
+            Some(pinned) if pinned == fingerprint => Ok(Trust::Pinned),
+            Some(pinned) => bail!(
+                "Certificate of {} changed! Pinned {}, the server presented {}. \
+                 If the server really got a new certificate, forget the old fingerprint and connect again.",
+                address,
+                pinned,
+                fingerprint
+            ),
+            None => {
+                self.servers.insert(address.to_string(), fingerprint.to_string());
+                self.save()?;
+                warn!("First connection to {}, pinned certificate {}", address, fingerprint);
+                Ok(Trust::FirstUse)
+            }
+        }
+    }
+
+    /// Forget the fingerprint of a server, so the next connection pins a new one
+    pub fn forget(&mut self, address: &str) -> Result<bool> {
+        let removed = self.servers.remove(address).is_some();
+        if removed {
+            self.save()?;
+            info!("Forgot certificate of {}", address);
+        }
+        Ok(removed)
+    }
+}
+
+/// TLS handshake with a server, returns the stream and the server's fingerprint
+///
+/// The certificate chain is not checked against any authority (servers use
+/// self-signed certificates), but the server must prove it holds the
+/// certificate's key. The caller decides whether to trust the fingerprint
+/// before sending anything.
+pub async fn handshake(stream: TcpStream, host: &str) -> Result<(TlsStream<TcpStream>, String)> {
+    let config = pinning_config(rustls::DEFAULT_VERSIONS)?;
+    let name = ServerName::try_from(host.to_string()).with_context(|| format!("Invalid server name {}", host))?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+        .context("TLS handshake failed")?;
+
+    let certificate = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+        .ok_or_else(|| anyhow!("Server sent no certificate"))?;
+    let fingerprint = certificate_fingerprint(certificate);
+
+    Ok((stream, fingerprint))
+}
+
+/// Client configuration that leaves trust to the fingerprint check, QUIC uses it too
+pub fn pinning_config(versions: &[&'static SupportedProtocolVersion]) -> Result<ClientConfig> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(versions)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertificate(provider)))
+        .with_no_client_auth();
+    Ok(config)
+}
+
+/// Keying material exported from the TLS session, binding the pairing handshake to it
+pub fn channel_binding(stream: &TlsStream<TcpStream>) -> Result<Vec<u8>> {
+    stream
+        .get_ref()
+        .1
+        .export_keying_material(vec![0u8; 32], TLS_EXPORTER_LABEL, None)
+        .map_err(|e| anyhow!("Cannot export TLS keying material: {}", e))
+}
+
+/// Accepts any certificate, leaving trust to the fingerprint check
+#[derive(Debug)]
+struct PinnedCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(message, certificate, signature, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(message, certificate, signature, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network;
+    use samesame_protocol::pairing::{self, ClientHello, ServerHello};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+    use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::TlsAcceptor;
+
+    /// Server with a new self-signed certificate for one connection
+    ///
+    /// Admits a client that says hello, and reports the bytes it received
+    /// after the TLS handshake.
+    async fn server() -> (SocketAddr, String, oneshot::Receiver<Vec<u8>>) {
+        let generated = rcgen::generate_simple_self_signed(vec!["samesame".to_string()]).unwrap();
+        let certificate = generated.cert.der().clone();
+        let fingerprint = certificate_fingerprint(&certificate);
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(generated.signing_key.serialize_der()));
+        let config = ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+            .with_protocol_versions(rustls::DEFAULT_VERSIONS)
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], key)
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (received, receiver) = oneshot::channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = TlsAcceptor::from(Arc::new(config)).accept(stream).await.unwrap();
+
+            let mut prefix = [0u8; 4];
+            if stream.read_exact(&mut prefix).await.is_err() {
+                let _ = received.send(Vec::new());
+                return;
+            }
+            let mut body = vec![0u8; pairing::frame_length(prefix).unwrap()];
+            stream.read_exact(&mut body).await.unwrap();
+            assert!(matches!(pairing::decode_frame(&body).unwrap(), ClientHello::Anonymous));
+            stream.write_all(&pairing::encode_frame(&ServerHello::Accepted).unwrap()).await.unwrap();
+            let _ = received.send([&prefix[..], &body].concat());
+
+            // Hold the connection until the client hangs up
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest).await;
+        });
+        (addr, fingerprint, receiver)
+    }
+
+    /// Connect like the app does, checking the fingerprint against `known`
+    async fn connect(addr: SocketAddr, known: &mut KnownServers) -> Result<Trust> {
+        let address = addr.to_string();
+        let mut trust = None;
+        let connection = network::connect_tls(
+            &addr.ip().to_string(),
+            addr.port(),
+            |fingerprint: &str| {
+                trust = Some(known.check(&address, fingerprint)?);
+                Ok(())
+            },
+            None,
+            |_| {},
+        )
+        .await?;
+        connection.close().await;
+        Ok(trust.expect("the fingerprint was checked"))
+    }
+
+    #[tokio::test]
+    async fn first_connection_pins_the_certificate() {
+        let mut known = KnownServers::default();
+        let (addr, fingerprint, received) = server().await;
+
+        assert_eq!(connect(addr, &mut known).await.unwrap(), Trust::FirstUse);
+        assert_eq!(known.get(&addr.to_string()), Some(fingerprint.as_str()));
+        assert!(!received.await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn matching_certificate_is_trusted() {
+        let mut known = KnownServers::default();
+        let (addr, fingerprint, received) = server().await;
+        known.check(&addr.to_string(), &fingerprint).unwrap();
+
+        assert_eq!(connect(addr, &mut known).await.unwrap(), Trust::Pinned);
+        assert!(!received.await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn changed_certificate_refuses_the_connection() {
+        let mut known = KnownServers::default();
+        let (addr, fingerprint, received) = server().await;
+        let pinned = "00:".repeat(31) + "00";
+        known.check(&addr.to_string(), &pinned).unwrap();
+
+        let error = connect(addr, &mut known).await.unwrap_err();
+        assert!(error.to_string().contains("changed"), "{}", error);
+        assert!(error.to_string().contains(&fingerprint), "{}", error);
+
+        // The pin stays, and the server got nothing after the TLS handshake
+        assert_eq!(known.get(&addr.to_string()), Some(pinned.as_str()));
+        assert!(received.await.unwrap().is_empty());
+    }
+}
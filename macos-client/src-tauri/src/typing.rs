@@ -0,0 +1,172 @@
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use samesame_protocol::arbitration::Arbitration;
+use samesame_protocol::keymap::{plan_typing, TargetLayout, TypingPlan};
+use samesame_protocol::{InputEvent, KeyboardEvent};
+use serde::Serialize;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use crate::network::{self, CliTransport};
+use crate::state::AppState;
+
+/// Port the server listens on
+const DEFAULT_PORT: u16 = 24800;
+
+/// Default pause between two typed characters
+pub const DEFAULT_KEY_DELAY_MS: u64 = 10;
+
+/// Shortest pause between two characters, keeps the send queue from overflowing
+const MIN_KEY_DELAY_MS: u64 = 1;
+
+/// How long to wait for the server to report its lock keys before typing
+const LOCK_STATE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Result of typing out a text
+#[derive(Debug, Clone, Serialize)]
+pub struct TypingReport {
+    /// Number of characters typed
+    pub typed: usize,
+    /// Characters the target layout has no key for (skipped)
+    pub untypable: Vec<char>,
+}
+
+impl TypingReport {
+    fn new(text: &str, plan: &TypingPlan) -> Self {
+        let typed = text
+            .chars()
+            .filter(|c| *c != '\r' && !plan.untypable.contains(c))
+            .count();
+        Self {
+            typed,
+            untypable: plan.untypable.clone(),
+        }
+    }
+}
+
+/// Type `text` into the active target as individual key presses
+pub async fn type_into_target(
+    state: Arc<Mutex<AppState>>,
+    text: &str,
+    layout: TargetLayout,
+    delay_ms: u64,
+) -> Result<TypingReport> {
+    let caps_lock = state.lock().active_locks().and_then(|locks| locks.caps_lock).unwrap_or(false);
+    let plan = plan_typing(text, layout, caps_lock);
+    let report = TypingReport::new(text, &plan);
+
+    replay(plan.events, delay_ms, |event| {
+        if state.lock().forward(InputEvent::Keyboard(event)) == 0 {
+            return Err(anyhow!("No target is connected"));
+        }
+        Ok(())
+    })
+    .await?;
+
+    Ok(report)
+}
+
+/// Connect to a server, type `text` and disconnect
+pub async fn type_to_server(
+    host: &str,
+    port: u16,
+    transport: CliTransport,
+    text: &str,
+    layout: TargetLayout,
+    delay_ms: u64,
+) -> Result<TypingReport> {
+    // The server reports its lock keys right after connecting
+    let (locks_sender, locks) = oneshot::channel();
+    let mut locks_sender = Some(locks_sender);
+    let mut connection = network::connect_cli(host, port, transport, move |message| {
+        if let InputEvent::LockState(locks) = message.event {
+            if let Some(sender) = locks_sender.take() {
+                let _ = sender.send(locks);
+            }
+        }
+    })
+    .await?;
+    connection.send_event(InputEvent::Arbitration(Arbitration::Request))?;
+
+    let caps_lock = match tokio::time::timeout(LOCK_STATE_TIMEOUT, locks).await {
+        Ok(Ok(locks)) => locks.caps_lock.unwrap_or(false),
+        _ => false,
+    };
+    let plan = plan_typing(text, layout, caps_lock);
+    let report = TypingReport::new(text, &plan);
+
+    replay(plan.events, delay_ms, |event| connection.send_event(InputEvent::Keyboard(event))).await?;
+    connection.close().await;
+
+    Ok(report)
+}
+
+/// Send key events, pausing after each released key
+async fn replay<F>(events: Vec<KeyboardEvent>, delay_ms: u64, mut send: F) -> Result<()>
+where
+    F: FnMut(KeyboardEvent) -> Result<()>,
+{
+    let delay = Duration::from_millis(delay_ms.max(MIN_KEY_DELAY_MS));
+    for event in events {
+        let released = !event.pressed;
+        send(event)?;
+        if released {
+            tokio::time::sleep(delay).await;
+        }
+    }
+    Ok(())
+}
+
+/// `type` command line entry
+///
+/// Usage: `type --host HOST [--port PORT] [--tls|--quic] [--layout us|german] [--delay MS] [TEXT]`.
+/// Without TEXT the text is read from standard input.
+pub fn run_type_command(args: &[String]) -> Result<()> {
+    let mut host = None;
+    let mut port = DEFAULT_PORT;
+    let mut transport = CliTransport::default();
+    let mut layout = TargetLayout::default();
+    let mut delay_ms = DEFAULT_KEY_DELAY_MS;
+    let mut text = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--host" => host = Some(value()?.clone()),
+            "--port" => port = value()?.parse()?,
+            "--tls" => transport = CliTransport::Tls,
+            "--quic" => transport = CliTransport::Quic,
+            "--layout" => {
+                let name = value()?;
+                layout = serde_json::from_value(serde_json::Value::String(name.clone()))
+                    .map_err(|_| anyhow!("Unknown layout {:?}, expected us or german", name))?;
+            }
+            "--delay" => delay_ms = value()?.parse()?,
+            _ if text.is_none() => text = Some(arg.clone()),
+            _ => return Err(anyhow!("Unexpected argument {:?}", arg)),
+        }
+    }
+
+    let host = host.ok_or_else(|| anyhow!("--host is required"))?;
+    let text = match text {
+        Some(text) => text,
+        None => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let report = runtime.block_on(type_to_server(&host, port, transport, &text, layout, delay_ms))?;
+
+    println!("Typed {} characters", report.typed);
+    if !report.untypable.is_empty() {
+        let skipped: String = report.untypable.iter().collect();
+        eprintln!("Skipped characters not on the {:?} layout: {}", layout, skipped);
+    }
+    Ok(())
+}
@@ -1,4 +1,5 @@
 const { invoke } = window.__TAURI__.core;
+const { listen } = window.__TAURI__.event;
 
 let serverIpEl;
 let portEl;
@@ -6,13 +7,15 @@ let statusEl;
 let modeEl;
 let connectBtn;
 let disconnectBtn;
+let profileListEl;
 
 async function connect() {
   const serverIp = serverIpEl.value;
   const port = parseInt(portEl.value);
+  const tls = document.querySelector("#use-tls").checked;
 
   try {
-    const result = await invoke("connect_to_server", { serverIp, port });
+    const result = await invoke("connect_to_server", { serverIp, port, tls });
     statusEl.textContent = `✅ ${result}`;
     statusEl.className = "status connected";
     connectBtn.disabled = true;
@@ -52,18 +55,337 @@ async function updateState() {
     const state = await invoke("get_state");
     const stateObj = JSON.parse(state);
 
-    if (stateObj.mode === "MacOS") {
+    if (stateObj.mode === "Local") {
       modeEl.textContent = "🖥️ macOS Mode";
       modeEl.className = "mode macos";
     } else {
-      modeEl.textContent = "🪟 Windows Mode";
+      modeEl.textContent = "🪟 Forwarding Mode";
       modeEl.className = "mode windows";
     }
+
+    const clipboardEl = document.querySelector("#clipboard-mode");
+    if (document.activeElement !== clipboardEl) {
+      clipboardEl.value = stateObj.profiles.clipboard.mode;
+    }
+
+    await updateProfiles();
   } catch (error) {
     console.error("Failed to get state:", error);
   }
 }
 
+async function updateProfiles() {
+  const profiles = await invoke("list_profiles");
+  profileListEl.replaceChildren();
+
+  for (const profile of profiles) {
+    const item = document.createElement("li");
+    item.className = profile.active ? "active" : "";
+
+    const label = document.createElement("span");
+    const hotkey = profile.hotkey ? ` [⌃⌥${profile.hotkey}]` : "";
+    const status = profile.connected ? "✅" : "⭕";
+    const capsLock = profile.lock_state?.caps_lock ? " ⇪" : "";
+    const tls = profile.quic ? " ⚡🔒" : profile.tls ? " 🔒" : "";
+    const paired = profile.paired ? " 🔑" : "";
+    label.textContent = `${status} ${profile.name} (${profile.host}:${profile.port})${tls}${paired}${hotkey}${capsLock}`;
+    item.appendChild(label);
+    if (profile.control) {
+      item.appendChild(controlLabel(profile.control));
+    }
+    if (profile.fingerprint) {
+      // Compare with the fingerprint the server prints on startup
+      const fingerprint = document.createElement("code");
+      fingerprint.className = "fingerprint";
+      fingerprint.textContent = profile.fingerprint;
+      item.appendChild(fingerprint);
+    }
+    item.appendChild(pointerModeSelect(profile));
+    if (!profile.quic) {
+      // QUIC sends pointer events as datagrams anyway
+      item.appendChild(udpPointerToggle(profile));
+    }
+
+    item.appendChild(profileButton("Activate", "btn-primary", () =>
+      invoke("activate_profile", { id: profile.id })));
+    if (profile.control?.in_control) {
+      item.appendChild(profileButton("Release Control", "btn-secondary", () =>
+        invoke("release_control", { id: profile.id })));
+    }
+    if (profile.connected) {
+      item.appendChild(profileButton("Disconnect", "btn-secondary", () =>
+        invoke("disconnect_profile", { id: profile.id })));
+    }
+    if (profile.paired) {
+      item.appendChild(profileButton("Forget Pairing", "btn-secondary", () =>
+        invoke("forget_pairing", { id: profile.id })));
+    } else {
+      item.appendChild(profileButton("Pair", "btn-secondary", () => pairProfile(profile)));
+    }
+    if (profile.fingerprint) {
+      item.appendChild(profileButton("Forget Certificate", "btn-secondary", () =>
+        invoke("forget_fingerprint", { id: profile.id })));
+    }
+    item.appendChild(profileButton("Remove", "btn-secondary", () =>
+      invoke("remove_profile", { id: profile.id })));
+
+    profileListEl.appendChild(item);
+  }
+}
+
+function controlLabel(control) {
+  // Only one client at a time controls a server, the others wait
+  const label = document.createElement("span");
+  label.className = "control";
+  if (control.in_control) {
+    label.textContent = "🎮 In control";
+  } else if (control.queue_position) {
+    label.textContent = `⏳ ${control.controller} in control, waiting (#${control.queue_position})`;
+  } else if (control.controller) {
+    label.textContent = `👀 ${control.controller} in control`;
+  } else {
+    label.textContent = "Nobody in control";
+  }
+  return label;
+}
+
+async function pairProfile(profile) {
+  // The server prints the PIN in its log
+  const pin = window.prompt(`PIN shown by ${profile.host}:${profile.port}`);
+  if (!pin) {
+    return;
+  }
+  await invoke("pair_profile", { id: profile.id, pin });
+  statusEl.textContent = `✅ Paired with ${profile.name}`;
+  statusEl.className = "status connected";
+}
+
+function pointerModeSelect(profile) {
+  const select = document.createElement("select");
+  for (const [mode, text] of [["Absolute", "Absolute"], ["Relative", "Relative"], ["Game", "Game (no acceleration)"]]) {
+    const option = document.createElement("option");
+    option.value = mode;
+    option.textContent = text;
+    option.selected = profile.pointer_mode === mode;
+    select.appendChild(option);
+  }
+  select.addEventListener("change", async () => {
+    try {
+      await invoke("set_pointer_mode", { id: profile.id, mode: select.value });
+    } catch (error) {
+      statusEl.textContent = `❌ ${error}`;
+      statusEl.className = "status error";
+    }
+  });
+  return select;
+}
+
+function udpPointerToggle(profile) {
+  // Takes effect on the next connection, the server must enable datagrams too
+  const label = document.createElement("label");
+  label.className = "udp-pointer";
+  const checkbox = document.createElement("input");
+  checkbox.type = "checkbox";
+  checkbox.checked = profile.udp_pointer;
+  checkbox.addEventListener("change", async () => {
+    try {
+      await invoke("set_udp_pointer", { id: profile.id, enabled: checkbox.checked });
+    } catch (error) {
+      statusEl.textContent = `❌ ${error}`;
+      statusEl.className = "status error";
+    }
+  });
+  label.append(checkbox, " Pointer over UDP");
+  return label;
+}
+
+function profileButton(text, kind, action) {
+  const button = document.createElement("button");
+  button.textContent = text;
+  button.className = `btn ${kind}`;
+  button.addEventListener("click", async () => {
+    try {
+      await action();
+    } catch (error) {
+      statusEl.textContent = `❌ ${error}`;
+      statusEl.className = "status error";
+    }
+    updateState();
+  });
+  return button;
+}
+
+async function addProfile() {
+  const name = document.querySelector("#profile-name").value;
+  const host = document.querySelector("#profile-host").value;
+  const port = parseInt(document.querySelector("#profile-port").value);
+  const hotkeyValue = document.querySelector("#profile-hotkey").value;
+  const hotkey = hotkeyValue ? parseInt(hotkeyValue) : null;
+  const tls = document.querySelector("#profile-tls").checked;
+  const quic = document.querySelector("#profile-quic").checked;
+
+  try {
+    await invoke("add_profile", { name: name || `${host}:${port}`, host, port, hotkey, tls, quic });
+    updateState();
+  } catch (error) {
+    statusEl.textContent = `❌ ${error}`;
+    statusEl.className = "status error";
+  }
+}
+
+async function discoverServers() {
+  const button = document.querySelector("#discover-btn");
+  const listEl = document.querySelector("#discovered-list");
+  button.disabled = true;
+  button.textContent = "Searching…";
+  try {
+    const servers = await invoke("discover_servers");
+    listEl.replaceChildren();
+    for (const server of servers) {
+      listEl.appendChild(discoveredServer(server));
+    }
+    if (servers.length === 0) {
+      const item = document.createElement("li");
+      item.textContent = "No servers found";
+      listEl.appendChild(item);
+    }
+  } catch (error) {
+    showError(error);
+  }
+  button.disabled = false;
+  button.textContent = "Search";
+}
+
+function discoveredServer(server) {
+  const item = document.createElement("li");
+  const host = server.addresses[0];
+  // QUIC is preferred when the server offers it
+  const port = server.quic_port ?? server.port;
+  const tls = server.quic_port ? " ⚡🔒" : server.tls ? " 🔒" : "";
+
+  const label = document.createElement("span");
+  label.textContent = `${server.name} (${host}:${port})${tls}`;
+  item.appendChild(label);
+  if (server.fingerprint) {
+    // Announcements are not authenticated, compare with the server's log
+    const fingerprint = document.createElement("code");
+    fingerprint.className = "fingerprint";
+    fingerprint.textContent = server.fingerprint;
+    item.appendChild(fingerprint);
+  }
+
+  if (!server.compatible) {
+    const note = document.createElement("span");
+    note.className = "control";
+    note.textContent = `Protocol version ${server.version}, update the app or the server`;
+    item.appendChild(note);
+  } else if (server.profile !== null) {
+    const note = document.createElement("span");
+    note.className = "control";
+    note.textContent = "Profile exists";
+    item.appendChild(note);
+  } else {
+    item.appendChild(profileButton("Add", "btn-primary", async () => {
+      await invoke("add_profile", {
+        name: server.name,
+        host,
+        port,
+        hotkey: null,
+        tls: server.tls,
+        quic: server.quic_port !== null,
+      });
+      item.remove();
+    }));
+  }
+  return item;
+}
+
+function showError(error) {
+  statusEl.textContent = `❌ ${error}`;
+  statusEl.className = "status error";
+}
+
+function sourceList(value) {
+  return value.split(",").map((s) => s.trim()).filter((s) => s.length > 0);
+}
+
+async function addCurrentSource() {
+  const source = await invoke("current_input_source");
+  const sourcesEl = document.querySelector("#text-sources");
+  const sources = sourceList(sourcesEl.value);
+  if (source && !sources.includes(source)) {
+    sources.push(source);
+  }
+  sourcesEl.value = sources.join(", ");
+}
+
+async function saveSources() {
+  const sources = sourceList(document.querySelector("#text-sources").value);
+  try {
+    await invoke("set_text_input_sources", { sources });
+  } catch (error) {
+    showError(error);
+  }
+}
+
+function sendText(text, composing) {
+  invoke("send_text_input", { text, composing }).catch(showError);
+}
+
+function setupTextCapture() {
+  const captureEl = document.querySelector("#text-capture");
+
+  // Marked text of an input method, replaced by the committed text at the end
+  captureEl.addEventListener("compositionupdate", (event) => sendText(event.data, true));
+  captureEl.addEventListener("compositionend", (event) => {
+    sendText(event.data, false);
+    captureEl.value = "";
+  });
+
+  // Plain typing without composition
+  captureEl.addEventListener("input", (event) => {
+    if (!event.isComposing && event.inputType === "insertText" && event.data) {
+      sendText(event.data, false);
+      captureEl.value = "";
+    }
+  });
+}
+
+async function saveClipboardMode() {
+  const state = JSON.parse(await invoke("get_state"));
+  const settings = { ...state.profiles.clipboard, mode: document.querySelector("#clipboard-mode").value };
+  try {
+    await invoke("set_clipboard_settings", { settings });
+  } catch (error) {
+    showError(error);
+  }
+}
+
+async function typeOut() {
+  const text = document.querySelector("#type-text").value;
+  const layout = document.querySelector("#type-layout").value;
+  try {
+    const report = await invoke("type_text", { text, layout });
+    statusEl.textContent = `✅ Typed ${report.typed} characters`;
+    statusEl.className = "status connected";
+    if (report.untypable.length > 0) {
+      showError(`Not on the ${layout} layout, skipped: ${report.untypable.join(" ")}`);
+    }
+  } catch (error) {
+    showError(error);
+  }
+}
+
+function showFileTransfer(result) {
+  if (result.Err !== undefined) {
+    showError(result.Err);
+    return;
+  }
+  const names = result.Ok.map((report) => report.name).join(", ");
+  statusEl.textContent = `✅ Sent ${names}`;
+  statusEl.className = "status connected";
+}
+
 window.addEventListener("DOMContentLoaded", () => {
   serverIpEl = document.querySelector("#server-ip");
   portEl = document.querySelector("#port");
@@ -71,9 +393,18 @@ window.addEventListener("DOMContentLoaded", () => {
   modeEl = document.querySelector("#mode");
   connectBtn = document.querySelector("#connect-btn");
   disconnectBtn = document.querySelector("#disconnect-btn");
+  profileListEl = document.querySelector("#profile-list");
 
   connectBtn.addEventListener("click", connect);
   disconnectBtn.addEventListener("click", disconnect);
+  document.querySelector("#add-profile-btn").addEventListener("click", addProfile);
+  document.querySelector("#discover-btn").addEventListener("click", discoverServers);
+  document.querySelector("#current-source-btn").addEventListener("click", addCurrentSource);
+  document.querySelector("#save-sources-btn").addEventListener("click", saveSources);
+  document.querySelector("#type-btn").addEventListener("click", typeOut);
+  document.querySelector("#clipboard-mode").addEventListener("change", saveClipboardMode);
+  setupTextCapture();
+  listen("file-transfer", (event) => showFileTransfer(event.payload));
 
   // Update state every 2 seconds
   setInterval(updateState, 2000);
@@ -0,0 +1,16 @@
+//! A decoded message must be valid and survive a round trip
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use samesame_protocol::Message;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(message) = Message::from_bytes(data) else {
+        return;
+    };
+    message.event.validate().expect("decoded event is valid");
+
+    let bytes = message.to_bytes().expect("decoded message encodes");
+    let again = Message::from_bytes(&bytes).expect("encoded message decodes");
+    assert_eq!(again.to_bytes().expect("decoded message encodes"), bytes);
+});
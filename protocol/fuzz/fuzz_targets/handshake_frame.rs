@@ -0,0 +1,19 @@
+//! Handshake frames from an unauthenticated peer never panic
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use samesame_protocol::pairing::{decode_frame, frame_length, ClientFinish, ClientHello, ServerHello};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((prefix, body)) = data.split_first_chunk::<4>() else {
+        return;
+    };
+    let Ok(length) = frame_length(*prefix) else {
+        return;
+    };
+    let body = &body[..length.min(body.len())];
+
+    let _ = decode_frame::<ClientHello>(body);
+    let _ = decode_frame::<ClientFinish>(body);
+    let _ = decode_frame::<ServerHello>(body);
+});
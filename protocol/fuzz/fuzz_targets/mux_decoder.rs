@@ -0,0 +1,26 @@
+//! Arbitrary bytes, split at arbitrary points, never panic or stall the decoder
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use samesame_protocol::{MuxConfig, MuxDecoder};
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte chooses how the stream is cut into reads
+    let Some((&read_size, stream)) = data.split_first() else {
+        return;
+    };
+    let mut decoder = MuxDecoder::new(MuxConfig::default());
+
+    for read in stream.chunks(usize::from(read_size).max(1)) {
+        decoder.extend(read);
+        loop {
+            match decoder.next_message() {
+                Ok(Some(message)) => message.event.validate().expect("decoded event is valid"),
+                Ok(None) => break,
+                // Like the server, carry on with the next segment
+                Err(_) => {}
+            }
+        }
+        decoder.take_flow_updates();
+    }
+});
@@ -0,0 +1,412 @@
+//! Pointer acceleration curves
+//!
+//! A curve maps pointer speed to output speed, both in desktop units per
+//! millisecond. Every curve is continuous and never decreasing, so a faster
+//! hand movement never moves the cursor less far. [`PointerAccelerator`]
+//! applies a curve to a stream of relative deltas using their timestamps, or
+//! removes it again to approximate the raw, unaccelerated movement.
+
+use serde::{Deserialize, Serialize};
+
+/// Time between two moves assumed when no usable timestamp is available (ms)
+const DEFAULT_INTERVAL_MS: f64 = 8.0;
+
+/// Bounds for the time between two moves (ms); longer pauses start a new movement
+const MIN_INTERVAL_MS: f64 = 1.0;
+const MAX_INTERVAL_MS: f64 = 50.0;
+
+/// Gain of the macOS-like curve for very slow and very fast movement
+const MACOS_MIN_GAIN: f64 = 0.5;
+const MACOS_MAX_GAIN: f64 = 3.0;
+/// Speed (units/ms) at which the macOS-like curve is halfway between both gains
+const MACOS_KNEE: f64 = 1.0;
+
+/// Shape of the Windows "Enhance pointer precision" default curve
+/// (SmoothMouseXCurve/YCurve), scaled so slow movement is close to 1:1
+const WINDOWS_CURVE: [CurvePoint; 4] = [
+    CurvePoint { speed: 0.43, output: 0.39 },
+    CurvePoint { speed: 1.25, output: 1.51 },
+    CurvePoint { speed: 3.86, output: 6.94 },
+    CurvePoint { speed: 40.0, output: 162.29 },
+];
+
+/// One point of a piecewise-linear curve
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CurvePoint {
+    /// Input speed in units per millisecond
+    pub speed: f64,
+    /// Output speed in units per millisecond
+    pub output: f64,
+}
+
+/// How relative pointer movement is accelerated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AccelerationCurve {
+    /// Constant factor, no acceleration
+    Linear { factor: f64 },
+    /// Smooth curve similar to the macOS default: precise when slow, fast when flicked
+    MacOs,
+    /// Curve shaped like Windows' "Enhance pointer precision"
+    Windows,
+    /// Piecewise-linear curve through the given points, starting at (0, 0)
+    ///
+    /// Beyond the last point the last segment's slope continues.
+    Custom { points: Vec<CurvePoint> },
+}
+
+impl Default for AccelerationCurve {
+    fn default() -> Self {
+        AccelerationCurve::Linear { factor: 1.0 }
+    }
+}
+
+impl AccelerationCurve {
+    /// Check that the curve is continuous and never decreasing
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            AccelerationCurve::Linear { factor } => {
+                if !factor.is_finite() || *factor <= 0.0 {
+                    return Err(format!("Linear factor must be positive, got {}", factor));
+                }
+            }
+            AccelerationCurve::MacOs | AccelerationCurve::Windows => {}
+            AccelerationCurve::Custom { points } => {
+                if points.is_empty() {
+                    return Err("Custom curve needs at least one point".to_string());
+                }
+                let mut previous = CurvePoint { speed: 0.0, output: 0.0 };
+                for point in points {
+                    if !point.speed.is_finite() || !point.output.is_finite() {
+                        return Err("Custom curve points must be finite".to_string());
+                    }
+                    if point.speed <= previous.speed {
+                        return Err(format!(
+                            "Custom curve speeds must increase, {} follows {}",
+                            point.speed, previous.speed
+                        ));
+                    }
+                    if point.output < previous.output {
+                        return Err(format!(
+                            "Custom curve outputs must not decrease, {} follows {}",
+                            point.output, previous.output
+                        ));
+                    }
+                    previous = *point;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Output speed for an input speed (both in units per millisecond)
+    pub fn output_speed(&self, speed: f64) -> f64 {
+        let speed = speed.max(0.0);
+        match self {
+            AccelerationCurve::Linear { factor } => speed * factor,
+            AccelerationCurve::MacOs => {
+                let squared = speed * speed;
+                let gain = MACOS_MIN_GAIN
+                    + (MACOS_MAX_GAIN - MACOS_MIN_GAIN) * squared / (squared + MACOS_KNEE * MACOS_KNEE);
+                speed * gain
+            }
+            AccelerationCurve::Windows => piecewise(&WINDOWS_CURVE, speed),
+            AccelerationCurve::Custom { points } => piecewise(points, speed),
+        }
+    }
+
+    /// Input speed that produces the given output speed (inverse of [`Self::output_speed`])
+    ///
+    /// Where the curve is flat the lowest matching input speed is returned.
+    pub fn input_speed(&self, output: f64) -> f64 {
+        if output <= 0.0 {
+            return 0.0;
+        }
+
+        // Find an upper bound, then bisect; the curve never decreases
+        let mut low = 0.0;
+        let mut high = 1.0;
+        while self.output_speed(high) < output {
+            low = high;
+            high *= 2.0;
+            if high > 1e9 {
+                return high;
+            }
+        }
+        for _ in 0..64 {
+            let middle = (low + high) / 2.0;
+            if self.output_speed(middle) < output {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        high
+    }
+}
+
+/// Linear interpolation through `points`, starting at the origin
+fn piecewise(points: &[CurvePoint], speed: f64) -> f64 {
+    let mut previous = CurvePoint { speed: 0.0, output: 0.0 };
+    for point in points {
+        if speed <= point.speed {
+            return interpolate(previous, *point, speed);
+        }
+        previous = *point;
+    }
+
+    // Continue the last segment
+    match points {
+        [.., before, last] => interpolate(*before, *last, speed),
+        [last] => interpolate(CurvePoint { speed: 0.0, output: 0.0 }, *last, speed),
+        [] => speed,
+    }
+}
+
+fn interpolate(a: CurvePoint, b: CurvePoint, speed: f64) -> f64 {
+    let slope = (b.output - a.output) / (b.speed - a.speed);
+    a.output + (speed - a.speed) * slope
+}
+
+/// Applies a curve to a stream of relative movements
+#[derive(Debug, Clone)]
+pub struct PointerAccelerator {
+    curve: AccelerationCurve,
+    /// Timestamp of the previous movement in microseconds
+    last_timestamp: Option<u64>,
+}
+
+impl PointerAccelerator {
+    pub fn new(curve: AccelerationCurve) -> Self {
+        Self {
+            curve,
+            last_timestamp: None,
+        }
+    }
+
+    pub fn curve(&self) -> &AccelerationCurve {
+        &self.curve
+    }
+
+    /// Accelerate a movement that happened at `timestamp_us`
+    pub fn apply(&mut self, dx: f64, dy: f64, timestamp_us: u64) -> (f64, f64) {
+        let interval = self.interval(timestamp_us);
+        let curve = &self.curve;
+        scale(dx, dy, interval, |speed| curve.output_speed(speed))
+    }
+
+    /// Undo the curve for a movement that happened at `timestamp_us`
+    pub fn remove(&mut self, dx: f64, dy: f64, timestamp_us: u64) -> (f64, f64) {
+        let interval = self.interval(timestamp_us);
+        let curve = &self.curve;
+        scale(dx, dy, interval, |speed| curve.input_speed(speed))
+    }
+
+    /// Milliseconds since the previous movement
+    fn interval(&mut self, timestamp_us: u64) -> f64 {
+        let previous = self.last_timestamp.replace(timestamp_us);
+        match previous {
+            Some(previous) if timestamp_us > previous => {
+                ((timestamp_us - previous) as f64 / 1000.0).clamp(MIN_INTERVAL_MS, MAX_INTERVAL_MS)
+            }
+            _ => DEFAULT_INTERVAL_MS,
+        }
+    }
+}
+
+/// Scale a movement so its speed becomes `map(speed)`
+fn scale(dx: f64, dy: f64, interval_ms: f64, map: impl Fn(f64) -> f64) -> (f64, f64) {
+    let distance = dx.hypot(dy);
+    if distance == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let speed = distance / interval_ms;
+    let factor = map(speed) / speed;
+    (dx * factor, dy * factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curves() -> Vec<AccelerationCurve> {
+        vec![
+            AccelerationCurve::Linear { factor: 1.0 },
+            AccelerationCurve::Linear { factor: 2.5 },
+            AccelerationCurve::MacOs,
+            AccelerationCurve::Windows,
+            AccelerationCurve::Custom {
+                points: vec![
+                    CurvePoint { speed: 0.5, output: 0.5 },
+                    CurvePoint { speed: 2.0, output: 2.0 },
+                    CurvePoint { speed: 4.0, output: 2.0 },
+                    CurvePoint { speed: 8.0, output: 20.0 },
+                ],
+            },
+        ]
+    }
+
+    /// Speeds from 0 to 100 units/ms, denser where the curves bend
+    fn speeds() -> impl Iterator<Item = f64> {
+        (0..=10_000).map(|i| i as f64 / 1000.0).chain((100..=1000).map(|i| i as f64 / 10.0))
+    }
+
+    #[test]
+    fn built_in_curves_are_valid() {
+        for curve in curves() {
+            assert_eq!(curve.validate(), Ok(()), "{:?}", curve);
+        }
+        let windows = AccelerationCurve::Custom {
+            points: WINDOWS_CURVE.to_vec(),
+        };
+        assert_eq!(windows.validate(), Ok(()));
+    }
+
+    #[test]
+    fn curves_start_at_zero() {
+        for curve in curves() {
+            assert_eq!(curve.output_speed(0.0), 0.0, "{:?}", curve);
+            assert_eq!(curve.output_speed(-1.0), 0.0, "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn curves_never_decrease() {
+        for curve in curves() {
+            let mut previous = 0.0;
+            for speed in speeds() {
+                let output = curve.output_speed(speed);
+                assert!(output >= previous, "{:?} decreases at {}: {} < {}", curve, speed, output, previous);
+                previous = output;
+            }
+        }
+    }
+
+    #[test]
+    fn curves_are_continuous() {
+        let epsilon = 1e-9;
+        for curve in curves() {
+            // Every breakpoint of the piecewise curves and the knee of the macOS one
+            let points: Vec<f64> = match &curve {
+                AccelerationCurve::Windows => WINDOWS_CURVE.iter().map(|point| point.speed).collect(),
+                AccelerationCurve::Custom { points } => points.iter().map(|point| point.speed).collect(),
+                _ => vec![MACOS_KNEE],
+            };
+            for speed in points.into_iter().chain(speeds()) {
+                let below = curve.output_speed(speed - epsilon);
+                let above = curve.output_speed(speed + epsilon);
+                assert!(
+                    (above - below).abs() < 1e-6,
+                    "{:?} jumps at {}: {} → {}",
+                    curve,
+                    speed,
+                    below,
+                    above
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn last_segment_continues() {
+        let curve = AccelerationCurve::Windows;
+        let last = WINDOWS_CURVE[3];
+        let before = WINDOWS_CURVE[2];
+        let slope = (last.output - before.output) / (last.speed - before.speed);
+        assert!((curve.output_speed(50.0) - (last.output + 10.0 * slope)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn macos_curve_is_precise_when_slow_and_fast_when_flicked() {
+        let curve = AccelerationCurve::MacOs;
+        assert!((curve.output_speed(0.01) / 0.01 - MACOS_MIN_GAIN).abs() < 0.01);
+        assert!((curve.output_speed(100.0) / 100.0 - MACOS_MAX_GAIN).abs() < 0.01);
+    }
+
+    #[test]
+    fn input_speed_inverts_the_curve() {
+        for curve in curves() {
+            for speed in [0.01, 0.3, 1.0, 1.25, 3.0, 9.0, 40.0, 75.0] {
+                let output = curve.output_speed(speed);
+                let input = curve.input_speed(output);
+                assert!((curve.output_speed(input) - output).abs() < 1e-6, "{:?} at {}", curve, speed);
+            }
+        }
+
+        // The custom curve is flat between 2 and 4, the lowest speed is returned
+        let flat = &curves()[4];
+        assert!((flat.input_speed(2.0) - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn invalid_curves_are_rejected() {
+        let point = |speed, output| CurvePoint { speed, output };
+        let invalid = [
+            AccelerationCurve::Linear { factor: 0.0 },
+            AccelerationCurve::Linear { factor: f64::NAN },
+            AccelerationCurve::Custom { points: Vec::new() },
+            AccelerationCurve::Custom {
+                points: vec![point(1.0, 1.0), point(1.0, 2.0)],
+            },
+            AccelerationCurve::Custom {
+                points: vec![point(1.0, 2.0), point(2.0, 1.0)],
+            },
+            AccelerationCurve::Custom {
+                points: vec![point(1.0, f64::INFINITY)],
+            },
+        ];
+        for curve in invalid {
+            assert!(curve.validate().is_err(), "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn accelerator_keeps_the_direction() {
+        let mut accelerator = PointerAccelerator::new(AccelerationCurve::MacOs);
+        accelerator.apply(0.0, 0.0, 0);
+
+        let (dx, dy) = accelerator.apply(30.0, -40.0, 8_000);
+        assert!(dx > 0.0 && dy < 0.0);
+        assert!((dx / dy - 30.0 / -40.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn faster_moves_travel_further() {
+        // The same distance covered in less time
+        let mut previous = 0.0;
+        for interval_us in [40_000, 20_000, 8_000, 4_000, 1_000] {
+            let mut accelerator = PointerAccelerator::new(AccelerationCurve::Windows);
+            accelerator.apply(0.0, 0.0, 100_000);
+            let (dx, _) = accelerator.apply(10.0, 0.0, 100_000 + interval_us);
+            assert!(dx >= previous, "{} µs: {} < {}", interval_us, dx, previous);
+            previous = dx;
+        }
+    }
+
+    #[test]
+    fn remove_undoes_apply() {
+        let mut forward = PointerAccelerator::new(AccelerationCurve::MacOs);
+        let mut backward = PointerAccelerator::new(AccelerationCurve::MacOs);
+
+        for (i, (dx, dy)) in [(3.0, 1.0), (12.0, -5.0), (0.5, 0.25), (40.0, 0.0)].into_iter().enumerate() {
+            let timestamp = i as u64 * 8_000;
+            let (ax, ay) = forward.apply(dx, dy, timestamp);
+            let (rx, ry) = backward.remove(ax, ay, timestamp);
+            assert!((rx - dx).abs() < 1e-6 && (ry - dy).abs() < 1e-6, "({}, {}) → ({}, {})", dx, dy, rx, ry);
+        }
+    }
+
+    #[test]
+    fn pauses_do_not_count_as_slow_movement() {
+        let mut accelerator = PointerAccelerator::new(AccelerationCurve::MacOs);
+        accelerator.apply(0.0, 0.0, 0);
+        let (after_pause, _) = accelerator.apply(10.0, 0.0, 5_000_000);
+
+        let mut accelerator = PointerAccelerator::new(AccelerationCurve::MacOs);
+        accelerator.apply(0.0, 0.0, 0);
+        let (at_max, _) = accelerator.apply(10.0, 0.0, (MAX_INTERVAL_MS * 1000.0) as u64);
+
+        assert!((after_pause - at_max).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,31 @@
+//! Control arbitration between clients
+//!
+//! Several clients may be connected to a server, but only one controls its
+//! keyboard and pointer at a time. Clients ask for control with
+//! [`Arbitration::Request`] and give it up with [`Arbitration::Release`];
+//! the others wait in a queue. The server hands control to the next client
+//! when the controller releases it, disconnects or stays idle while someone
+//! else is typing, and tells every client who is in control with
+//! [`Arbitration::Status`].
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Arbitration {
+    /// Become the controller, or queue for it (client → server)
+    Request,
+    /// Give up control or leave the queue (client → server)
+    Release,
+    /// Who is in control, sent to every client after a change (server → client)
+    Status(ControlStatus),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ControlStatus {
+    /// Name of the controlling client, `None` while nobody is in control
+    pub controller: Option<String>,
+    /// The receiving client is the controller
+    pub in_control: bool,
+    /// Place of the receiving client in the queue (1 = next), `None` if not waiting
+    pub queue_position: Option<u32>,
+}
@@ -0,0 +1,580 @@
+//! Clipboard synchronization
+//!
+//! Clipboard contents are sent as a series of [`ClipboardChunk`]s so a large
+//! image never holds up the input events queued behind it. Each side keeps a
+//! [`ClipboardSync`] per peer that remembers the last content it sent or
+//! received; content written from the peer is therefore never sent back,
+//! which would otherwise make two machines bounce it between each other.
+//!
+//! Platform clipboards implement [`Clipboard`]; [`MemoryClipboard`] keeps the
+//! content in memory for machines without a supported clipboard and for tests.
+
+use crate::validation::decode_bounded;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+
+/// Payload bytes per chunk
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Default limit for the content of one transfer
+pub const DEFAULT_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+/// Clipboard content in the formats that are synchronized
+///
+/// A clipboard usually offers several formats of the same content (e.g.
+/// plain text and HTML of a copied web page); all of them are carried.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ClipboardContent {
+    /// Plain text
+    pub text: Option<String>,
+    /// Rich text as an HTML fragment
+    pub html: Option<String>,
+    /// Image as PNG file data
+    pub png: Option<Vec<u8>>,
+}
+
+impl ClipboardContent {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: Some(text.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_none() && self.html.is_none() && self.png.is_none()
+    }
+
+    /// Total size of all formats in bytes
+    pub fn size(&self) -> usize {
+        self.text.as_ref().map_or(0, String::len)
+            + self.html.as_ref().map_or(0, String::len)
+            + self.png.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Fingerprint used to recognize content that was already synchronized
+    ///
+    /// FNV-1a, so both machines compute the same value independent of their
+    /// Rust version.
+    pub fn digest(&self) -> u64 {
+        let mut hash = Fnv1a::new();
+        for (tag, data) in [
+            (b't', self.text.as_ref().map(String::as_bytes)),
+            (b'h', self.html.as_ref().map(String::as_bytes)),
+            (b'p', self.png.as_deref()),
+        ] {
+            if let Some(data) = data {
+                hash.write(&[tag]);
+                hash.write(&(data.len() as u64).to_le_bytes());
+                hash.write(data);
+            }
+        }
+        hash.finish()
+    }
+
+    /// Drop formats until the content fits into `max_bytes`
+    ///
+    /// The image goes first, then the HTML; plain text is never dropped. Returns
+    /// `None` if nothing that fits is left.
+    pub fn limited(mut self, max_bytes: usize) -> Option<Self> {
+        if self.size() > max_bytes {
+            self.png = None;
+        }
+        if self.size() > max_bytes {
+            self.html = None;
+        }
+        if self.size() > max_bytes || self.is_empty() {
+            return None;
+        }
+        Some(self)
+    }
+}
+
+/// 64-bit FNV-1a hash
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Part of a clipboard transfer
+///
+/// The chunks of a transfer carry the bincode-encoded [`ClipboardContent`]
+/// and are sent in order. A new transfer replaces an unfinished one.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ClipboardChunk {
+    /// Identifies the transfer, chosen by the sender
+    pub transfer_id: u64,
+    /// Position of this chunk, starting at 0
+    pub index: u32,
+    /// Number of chunks in the transfer
+    pub count: u32,
+    pub data: Vec<u8>,
+}
+
+// Logged with every message; the payload would flood the log
+impl fmt::Debug for ClipboardChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClipboardChunk")
+            .field("transfer_id", &self.transfer_id)
+            .field("index", &self.index)
+            .field("count", &self.count)
+            .field("bytes", &self.data.len())
+            .finish()
+    }
+}
+
+/// Split content into chunks for sending
+pub fn split(content: &ClipboardContent, transfer_id: u64) -> Result<Vec<ClipboardChunk>, bincode::Error> {
+    let bytes = bincode::serialize(content)?;
+    let count = bytes.len().div_ceil(CHUNK_SIZE).max(1) as u32;
+    Ok((0..count)
+        .map(|index| {
+            let start = index as usize * CHUNK_SIZE;
+            let end = (start + CHUNK_SIZE).min(bytes.len());
+            ClipboardChunk {
+                transfer_id,
+                index,
+                count,
+                data: bytes[start..end].to_vec(),
+            }
+        })
+        .collect())
+}
+
+/// Puts received chunks back together
+#[derive(Debug)]
+pub struct ClipboardAssembler {
+    max_bytes: usize,
+    /// Transfer in progress and its chunk count
+    transfer: Option<(u64, u32)>,
+    data: Vec<u8>,
+    next_index: u32,
+}
+
+impl ClipboardAssembler {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            transfer: None,
+            data: Vec::new(),
+            next_index: 0,
+        }
+    }
+
+    /// Add a chunk, returns the content once the last chunk has arrived
+    ///
+    /// A chunk that does not continue the current transfer, or a transfer
+    /// larger than the limit, is an error; the transfer is then dropped.
+    pub fn push(&mut self, chunk: ClipboardChunk) -> Result<Option<ClipboardContent>, String> {
+        if chunk.index == 0 {
+            self.transfer = Some((chunk.transfer_id, chunk.count));
+            self.data.clear();
+            self.next_index = 0;
+        }
+
+        if self.transfer != Some((chunk.transfer_id, chunk.count)) || chunk.index != self.next_index {
+            self.reset();
+            return Err(format!(
+                "Unexpected chunk {}/{} of clipboard transfer {}",
+                chunk.index, chunk.count, chunk.transfer_id
+            ));
+        }
+
+        // Encoding adds a few length fields on top of the content itself
+        let limit = self.max_bytes.saturating_add(CHUNK_SIZE);
+        if chunk.count as usize > limit.div_ceil(CHUNK_SIZE) || self.data.len() + chunk.data.len() > limit {
+            self.reset();
+            return Err(format!(
+                "Clipboard transfer {} exceeds the limit of {} bytes",
+                chunk.transfer_id, self.max_bytes
+            ));
+        }
+
+        self.data.extend_from_slice(&chunk.data);
+        self.next_index += 1;
+        if self.next_index < chunk.count {
+            return Ok(None);
+        }
+
+        let result = decode_bounded::<ClipboardContent>(&self.data, self.data.len());
+        self.reset();
+        let content = result.map_err(|e| format!("Invalid clipboard content: {}", e))?;
+        if content.size() > self.max_bytes {
+            return Err(format!("Clipboard content exceeds the limit of {} bytes", self.max_bytes));
+        }
+        Ok(Some(content))
+    }
+
+    fn reset(&mut self) {
+        self.transfer = None;
+        self.data = Vec::new();
+        self.next_index = 0;
+    }
+}
+
+/// A system clipboard
+pub trait Clipboard: Send + Sync {
+    /// Current content, empty if the clipboard holds nothing that is synchronized
+    fn read(&self) -> Result<ClipboardContent, String>;
+
+    /// Replace the content
+    fn write(&self, content: &ClipboardContent) -> Result<(), String>;
+
+    /// Number that changes whenever the clipboard changes, if the platform has one
+    ///
+    /// Lets pollers skip reading an unchanged clipboard.
+    fn change_count(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Clipboard kept in memory
+#[derive(Debug, Default)]
+pub struct MemoryClipboard {
+    state: Mutex<(ClipboardContent, u64)>,
+}
+
+impl MemoryClipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Clipboard for MemoryClipboard {
+    fn read(&self) -> Result<ClipboardContent, String> {
+        Ok(self.state.lock().map_err(|e| e.to_string())?.0.clone())
+    }
+
+    fn write(&self, content: &ClipboardContent) -> Result<(), String> {
+        let mut state = self.state.lock().map_err(|e| e.to_string())?;
+        state.0 = content.clone();
+        state.1 += 1;
+        Ok(())
+    }
+
+    fn change_count(&self) -> Option<u64> {
+        self.state.lock().ok().map(|state| state.1)
+    }
+}
+
+/// When clipboard changes are sent to the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardSyncMode {
+    /// Never synchronize
+    Off,
+    /// Send every change as it happens
+    #[default]
+    Continuous,
+    /// Send the clipboard only when input moves to the other machine
+    OnSwitch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardSettings {
+    pub mode: ClipboardSyncMode,
+    /// Largest content sent or accepted; larger images and HTML are left out
+    pub max_bytes: usize,
+}
+
+impl Default for ClipboardSettings {
+    fn default() -> Self {
+        Self {
+            mode: ClipboardSyncMode::default(),
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+}
+
+/// Clipboard synchronization with one peer
+#[derive(Debug)]
+pub struct ClipboardSync {
+    settings: ClipboardSettings,
+    /// Digest of the content last sent to or received from the peer
+    last_digest: Option<u64>,
+    next_transfer: u64,
+    assembler: ClipboardAssembler,
+}
+
+impl ClipboardSync {
+    pub fn new(settings: ClipboardSettings) -> Self {
+        Self {
+            assembler: ClipboardAssembler::new(settings.max_bytes),
+            settings,
+            last_digest: None,
+            next_transfer: 0,
+        }
+    }
+
+    pub fn settings(&self) -> &ClipboardSettings {
+        &self.settings
+    }
+
+    /// Chunks to send for the local clipboard, if it changed since the last sync
+    pub fn outgoing(&mut self, content: ClipboardContent) -> Option<Vec<ClipboardChunk>> {
+        if self.settings.mode == ClipboardSyncMode::Off || content.is_empty() {
+            return None;
+        }
+
+        let digest = content.digest();
+        if self.last_digest == Some(digest) {
+            return None;
+        }
+        self.last_digest = Some(digest);
+
+        let content = content.limited(self.settings.max_bytes)?;
+        self.next_transfer += 1;
+        split(&content, self.next_transfer).ok()
+    }
+
+    /// Handle a chunk from the peer, returns the content to write once complete
+    ///
+    /// After writing, pass what the clipboard reads back to [`Self::written`].
+    pub fn incoming(&mut self, chunk: ClipboardChunk) -> Result<Option<ClipboardContent>, String> {
+        if self.settings.mode == ClipboardSyncMode::Off {
+            return Ok(None);
+        }
+        let content = self.assembler.push(chunk)?;
+        if let Some(content) = &content {
+            self.last_digest = Some(content.digest());
+        }
+        Ok(content)
+    }
+
+    /// Remember content written from the peer, as the clipboard reports it
+    ///
+    /// Platforms may store content slightly differently than it was written;
+    /// remembering the read-back version keeps it from being sent back.
+    pub fn written(&mut self, content: &ClipboardContent) {
+        self.last_digest = Some(content.digest());
+    }
+}
+
+/// Write content received from a peer and update its sync state
+pub fn apply_incoming(
+    sync: &mut ClipboardSync,
+    clipboard: &dyn Clipboard,
+    chunk: ClipboardChunk,
+) -> Result<bool, String> {
+    let Some(content) = sync.incoming(chunk)? else {
+        return Ok(false);
+    };
+    clipboard.write(&content)?;
+    if let Ok(stored) = clipboard.read() {
+        sync.written(&stored);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(max_bytes: usize) -> ClipboardSettings {
+        ClipboardSettings {
+            mode: ClipboardSyncMode::Continuous,
+            max_bytes,
+        }
+    }
+
+    /// Content with every format, `png_size` bytes of image
+    fn rich(png_size: usize) -> ClipboardContent {
+        ClipboardContent {
+            text: Some("hello".into()),
+            html: Some("<b>hello</b>".into()),
+            png: Some(vec![0x89; png_size]),
+        }
+    }
+
+    /// Send `content` from one side and apply it on the other
+    fn transfer(
+        from: &mut ClipboardSync,
+        to: &mut ClipboardSync,
+        clipboard: &dyn Clipboard,
+        content: ClipboardContent,
+    ) -> bool {
+        let Some(chunks) = from.outgoing(content) else {
+            return false;
+        };
+        let mut written = false;
+        for chunk in chunks {
+            written |= apply_incoming(to, clipboard, chunk).unwrap();
+        }
+        written
+    }
+
+    /// Clipboard that stores text with Windows line endings, like a platform converting on write
+    #[derive(Default)]
+    struct ConvertingClipboard(MemoryClipboard);
+
+    impl Clipboard for ConvertingClipboard {
+        fn read(&self) -> Result<ClipboardContent, String> {
+            self.0.read()
+        }
+
+        fn write(&self, content: &ClipboardContent) -> Result<(), String> {
+            let mut content = content.clone();
+            content.text = content.text.map(|text| text.replace('\n', "\r\n"));
+            self.0.write(&content)
+        }
+    }
+
+    #[test]
+    fn digest_is_stable_and_tells_formats_apart() {
+        // FNV-1a offset basis, the same on every machine
+        assert_eq!(ClipboardContent::default().digest(), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(rich(10).digest(), rich(10).digest());
+        assert_ne!(rich(10).digest(), rich(11).digest());
+
+        let html = ClipboardContent {
+            html: Some("hello".into()),
+            ..ClipboardContent::default()
+        };
+        assert_ne!(ClipboardContent::text("hello").digest(), html.digest());
+    }
+
+    #[test]
+    fn received_content_is_not_sent_back() {
+        let mut mac = ClipboardSync::new(settings(DEFAULT_MAX_BYTES));
+        let mut server = ClipboardSync::new(settings(DEFAULT_MAX_BYTES));
+        let mac_clipboard = MemoryClipboard::new();
+        let server_clipboard = MemoryClipboard::new();
+
+        assert!(transfer(&mut mac, &mut server, &server_clipboard, ClipboardContent::text("copied on the Mac")));
+        assert_eq!(server_clipboard.read().unwrap(), ClipboardContent::text("copied on the Mac"));
+
+        // The server's next poll sees the content it just wrote
+        assert!(server.outgoing(server_clipboard.read().unwrap()).is_none(), "echoed back");
+        assert!(mac.outgoing(ClipboardContent::text("copied on the Mac")).is_none(), "sent twice");
+
+        // A new copy on the server goes to the Mac, and stops there
+        assert!(transfer(&mut server, &mut mac, &mac_clipboard, ClipboardContent::text("copied on Windows")));
+        assert!(mac.outgoing(mac_clipboard.read().unwrap()).is_none(), "echoed back");
+    }
+
+    #[test]
+    fn content_converted_on_write_is_not_sent_back() {
+        let mut mac = ClipboardSync::new(settings(DEFAULT_MAX_BYTES));
+        let mut server = ClipboardSync::new(settings(DEFAULT_MAX_BYTES));
+        let server_clipboard = ConvertingClipboard::default();
+
+        assert!(transfer(&mut mac, &mut server, &server_clipboard, ClipboardContent::text("two\nlines")));
+        let stored = server_clipboard.read().unwrap();
+        assert_eq!(stored.text.as_deref(), Some("two\r\nlines"));
+        assert!(server.outgoing(stored).is_none());
+    }
+
+    #[test]
+    fn empty_content_and_sync_off_send_nothing() {
+        let mut sync = ClipboardSync::new(settings(DEFAULT_MAX_BYTES));
+        assert!(sync.outgoing(ClipboardContent::default()).is_none());
+
+        let mut off = ClipboardSync::new(ClipboardSettings {
+            mode: ClipboardSyncMode::Off,
+            ..settings(DEFAULT_MAX_BYTES)
+        });
+        assert!(off.outgoing(ClipboardContent::text("hello")).is_none());
+        let chunk = split(&ClipboardContent::text("hello"), 1).unwrap().remove(0);
+        assert_eq!(off.incoming(chunk), Ok(None));
+    }
+
+    #[test]
+    fn limited_drops_the_image_then_the_html() {
+        let content = rich(1000);
+        assert_eq!(content.clone().limited(content.size()), Some(content.clone()));
+
+        let without_image = content.clone().limited(100).unwrap();
+        assert_eq!(without_image.png, None);
+        assert!(without_image.html.is_some());
+
+        let text_only = content.clone().limited(10).unwrap();
+        assert_eq!(text_only, ClipboardContent::text("hello"));
+
+        assert_eq!(content.limited(4), None, "text is never cut");
+        assert_eq!(ClipboardContent::default().limited(100), None);
+    }
+
+    #[test]
+    fn large_content_is_reassembled_from_chunks() {
+        let content = rich(3 * CHUNK_SIZE + 17);
+        let chunks = split(&content, 5).unwrap();
+        assert_eq!(chunks.len(), 4);
+
+        let mut assembler = ClipboardAssembler::new(DEFAULT_MAX_BYTES);
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            assert_eq!(assembler.push(chunk.clone()), Ok(None));
+        }
+        assert_eq!(assembler.push(last.clone()), Ok(Some(content)));
+    }
+
+    #[test]
+    fn chunks_out_of_order_drop_the_transfer() {
+        let chunks = split(&rich(2 * CHUNK_SIZE), 1).unwrap();
+        let mut assembler = ClipboardAssembler::new(DEFAULT_MAX_BYTES);
+
+        // A transfer must start at its first chunk
+        assert!(assembler.push(chunks[1].clone()).is_err());
+
+        // A skipped chunk drops what came before
+        assembler.push(chunks[0].clone()).unwrap();
+        assert!(assembler.push(chunks[2].clone()).is_err());
+        assert!(assembler.push(chunks[1].clone()).is_err(), "the transfer is gone");
+
+        // Chunks of another transfer do not continue this one
+        let other = split(&rich(2 * CHUNK_SIZE), 2).unwrap();
+        assembler.push(chunks[0].clone()).unwrap();
+        assert!(assembler.push(other[1].clone()).is_err());
+    }
+
+    #[test]
+    fn new_transfer_replaces_an_unfinished_one() {
+        let first = split(&rich(2 * CHUNK_SIZE), 1).unwrap();
+        let second = split(&ClipboardContent::text("newer"), 2).unwrap();
+        let mut assembler = ClipboardAssembler::new(DEFAULT_MAX_BYTES);
+
+        assembler.push(first[0].clone()).unwrap();
+        assert_eq!(assembler.push(second[0].clone()), Ok(Some(ClipboardContent::text("newer"))));
+    }
+
+    #[test]
+    fn oversized_transfers_are_rejected() {
+        let limit = 2 * CHUNK_SIZE;
+        let mut assembler = ClipboardAssembler::new(limit);
+
+        // Announced as too many chunks
+        let mut chunk = split(&ClipboardContent::text("hello"), 1).unwrap().remove(0);
+        chunk.count = 100;
+        assert!(assembler.push(chunk).is_err());
+
+        // More data than the limit allows, whatever the count says
+        let chunks = split(&rich(4 * CHUNK_SIZE), 2).unwrap();
+        let results: Vec<_> = chunks.into_iter().map(|chunk| assembler.push(chunk)).collect();
+        assert!(results.iter().any(Result::is_err), "{:?}", results);
+        assert!(!results.iter().any(|result| matches!(result, Ok(Some(_)))));
+
+        // Content above the limit that still fits into the chunk allowance
+        let content = ClipboardContent::text("x".repeat(limit + 1));
+        let mut last = None;
+        for chunk in split(&content, 3).unwrap() {
+            last = Some(assembler.push(chunk));
+        }
+        assert!(last.unwrap().is_err());
+    }
+}
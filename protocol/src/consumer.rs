@@ -0,0 +1,61 @@
+//! Media, volume and brightness keys
+//!
+//! These keys are sent as usages of the USB HID consumer page (0x0C), which
+//! every platform understands in some form, rather than as key codes of the
+//! sending platform.
+
+use serde::{Deserialize, Serialize};
+
+/// A consumer control key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ConsumerKey {
+    PlayPause,
+    NextTrack,
+    PreviousTrack,
+    Stop,
+    FastForward,
+    Rewind,
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    BrightnessUp,
+    BrightnessDown,
+}
+
+impl ConsumerKey {
+    pub const ALL: [ConsumerKey; 11] = [
+        ConsumerKey::PlayPause,
+        ConsumerKey::NextTrack,
+        ConsumerKey::PreviousTrack,
+        ConsumerKey::Stop,
+        ConsumerKey::FastForward,
+        ConsumerKey::Rewind,
+        ConsumerKey::VolumeUp,
+        ConsumerKey::VolumeDown,
+        ConsumerKey::Mute,
+        ConsumerKey::BrightnessUp,
+        ConsumerKey::BrightnessDown,
+    ];
+
+    /// HID consumer page usage ID
+    pub fn usage(self) -> u16 {
+        match self {
+            ConsumerKey::PlayPause => 0xCD,
+            ConsumerKey::NextTrack => 0xB5,
+            ConsumerKey::PreviousTrack => 0xB6,
+            ConsumerKey::Stop => 0xB7,
+            ConsumerKey::FastForward => 0xB3,
+            ConsumerKey::Rewind => 0xB4,
+            ConsumerKey::VolumeUp => 0xE9,
+            ConsumerKey::VolumeDown => 0xEA,
+            ConsumerKey::Mute => 0xE2,
+            ConsumerKey::BrightnessUp => 0x6F,
+            ConsumerKey::BrightnessDown => 0x70,
+        }
+    }
+
+    /// Key for a HID consumer page usage ID, if it is one we know
+    pub fn from_usage(usage: u16) -> Option<ConsumerKey> {
+        ConsumerKey::ALL.into_iter().find(|key| key.usage() == usage)
+    }
+}
@@ -0,0 +1,302 @@
+//! Pointer events over UDP
+//!
+//! Over a lossy link, TCP holds every later byte back until a lost segment
+//! has been retransmitted, so the remote cursor stutters even though a lost
+//! mouse move is worthless a few milliseconds later. With the hybrid
+//! transport, mouse moves and scroll deltas travel as datagrams instead,
+//! while keys, buttons and everything else stay on the reliable stream.
+//!
+//! The client asks for it with [`PointerTransport::Request`] on the stream.
+//! A server that supports it answers with a [`DatagramOffer`]: the UDP port,
+//! a session id and a key that authenticates the datagrams of this
+//! connection. Every datagram carries a [`Message`] numbered from the same
+//! sequence as the stream. The receiver only applies pointer messages newer
+//! than the last one (latest wins), so late and duplicated datagrams are
+//! dropped. Moves lost on the way are made good by a resync: every
+//! [`RESYNC_INTERVAL`] the client repeats its last datagram move on the
+//! stream, with the same sequence number and no delta.
+//!
+//! A datagram is the session id (`u64` little endian), the bincode-encoded
+//! message and a truncated HMAC-SHA256 over both. Datagrams are
+//! authenticated but not encrypted.
+
+use crate::validation::decode_bounded;
+use crate::{InputEvent, Message};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt;
+use std::time::Duration;
+
+/// Largest datagram sent or accepted
+pub const MAX_DATAGRAM: usize = 512;
+
+/// Bytes of the authentication tag at the end of a datagram
+pub const TAG_SIZE: usize = 16;
+
+/// How often the last datagram move is repeated on the stream
+pub const RESYNC_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Key authenticating the datagrams of one connection
+pub type DatagramKey = [u8; 32];
+
+/// Negotiation of the datagram transport on the stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PointerTransport {
+    /// Send pointer events as datagrams from now on (client → server)
+    Request,
+    /// Where to send them (server → client)
+    Offer(DatagramOffer),
+    /// The server keeps pointer events on the stream (server → client)
+    Unavailable,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DatagramOffer {
+    /// UDP port on the server's address
+    pub port: u16,
+    /// Identifies the connection in every datagram
+    pub session: u64,
+    pub key: DatagramKey,
+}
+
+// Logged with every message; the key stays out of the log
+impl fmt::Debug for DatagramOffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatagramOffer")
+            .field("port", &self.port)
+            .field("session", &self.session)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Events that go as datagrams when the transport is active
+pub fn is_datagram_event(event: &InputEvent) -> bool {
+    matches!(event, InputEvent::MouseMove(_) | InputEvent::MouseScroll(_))
+}
+
+/// Why a datagram was dropped
+#[derive(Debug)]
+pub enum DatagramError {
+    /// Shorter than a header and tag, or longer than [`MAX_DATAGRAM`]
+    Size(usize),
+    /// The tag does not match, the datagram was forged or corrupted
+    Forged,
+    /// Not a pointer event
+    NotPointer,
+    Codec(bincode::Error),
+    Invalid(crate::ValidationError),
+}
+
+impl fmt::Display for DatagramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatagramError::Size(size) => write!(f, "Datagram of {} bytes has an invalid size", size),
+            DatagramError::Forged => write!(f, "Datagram failed authentication"),
+            DatagramError::NotPointer => write!(f, "Datagram does not carry a pointer event"),
+            DatagramError::Codec(e) => write!(f, "Invalid datagram: {}", e),
+            DatagramError::Invalid(e) => write!(f, "Invalid datagram: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DatagramError {}
+
+/// Encode a pointer message for a datagram, without session or tag
+///
+/// QUIC datagrams carry this as is, the connection protects them.
+pub fn encode_pointer(message: &Message) -> Result<Vec<u8>, DatagramError> {
+    if !is_datagram_event(&message.event) {
+        return Err(DatagramError::NotPointer);
+    }
+    message.to_bytes().map_err(DatagramError::Codec)
+}
+
+/// Decode and check a pointer message from a datagram
+pub fn decode_pointer(bytes: &[u8]) -> Result<Message, DatagramError> {
+    if bytes.len() > MAX_DATAGRAM {
+        return Err(DatagramError::Size(bytes.len()));
+    }
+    let message: Message = decode_bounded(bytes, MAX_DATAGRAM).map_err(DatagramError::Codec)?;
+    if !is_datagram_event(&message.event) {
+        return Err(DatagramError::NotPointer);
+    }
+    message.event.validate().map_err(DatagramError::Invalid)?;
+    Ok(message)
+}
+
+/// Session id of a datagram, to find its key
+pub fn session_of(datagram: &[u8]) -> Option<u64> {
+    let id = datagram.get(..8)?;
+    Some(u64::from_le_bytes(id.try_into().ok()?))
+}
+
+/// Seals and opens the datagrams of one connection
+#[derive(Clone)]
+pub struct DatagramCodec {
+    session: u64,
+    key: DatagramKey,
+}
+
+impl DatagramCodec {
+    pub fn new(session: u64, key: DatagramKey) -> Self {
+        Self { session, key }
+    }
+
+    pub fn from_offer(offer: &DatagramOffer) -> Self {
+        Self::new(offer.session, offer.key)
+    }
+
+    /// Encode and authenticate a pointer message
+    pub fn seal(&self, message: &Message) -> Result<Vec<u8>, DatagramError> {
+        let mut datagram = self.session.to_le_bytes().to_vec();
+        datagram.extend(encode_pointer(message)?);
+        let tag = self.tag(&datagram);
+        datagram.extend_from_slice(&tag);
+        if datagram.len() > MAX_DATAGRAM {
+            return Err(DatagramError::Size(datagram.len()));
+        }
+        Ok(datagram)
+    }
+
+    /// Check and decode a datagram of this session
+    pub fn open(&self, datagram: &[u8]) -> Result<Message, DatagramError> {
+        if datagram.len() < 8 + TAG_SIZE || datagram.len() > MAX_DATAGRAM {
+            return Err(DatagramError::Size(datagram.len()));
+        }
+        let (body, tag) = datagram.split_at(datagram.len() - TAG_SIZE);
+        if session_of(body) != Some(self.session) || !self.verify(body, tag) {
+            return Err(DatagramError::Forged);
+        }
+        decode_pointer(&body[8..])
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any length")
+    }
+
+    fn tag(&self, body: &[u8]) -> [u8; TAG_SIZE] {
+        let mut mac = self.mac();
+        mac.update(body);
+        let full = mac.finalize().into_bytes();
+        let mut tag = [0u8; TAG_SIZE];
+        tag.copy_from_slice(&full[..TAG_SIZE]);
+        tag
+    }
+
+    fn verify(&self, body: &[u8], tag: &[u8]) -> bool {
+        let mut mac = self.mac();
+        mac.update(body);
+        mac.verify_truncated_left(tag).is_ok()
+    }
+}
+
+/// Drops a share of datagrams, to try packet loss on a loopback connection
+///
+/// Deterministic for a given seed, so a lossy run can be repeated.
+#[derive(Debug, Clone)]
+pub struct LossSimulator {
+    rate: f64,
+    state: u64,
+}
+
+impl LossSimulator {
+    /// Drop about `rate` (0.0 to 1.0) of all datagrams
+    pub fn new(rate: f64, seed: u64) -> Self {
+        Self {
+            rate: rate.clamp(0.0, 1.0),
+            state: seed | 1,
+        }
+    }
+
+    /// Whether the next datagram is lost
+    pub fn drop_next(&mut self) -> bool {
+        if self.rate <= 0.0 {
+            return false;
+        }
+        // xorshift64*, plenty for picking datagrams to drop
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11;
+        (value as f64 / (1u64 << 53) as f64) < self.rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KeyboardEvent, Modifiers, MouseMoveEvent};
+
+    fn mouse_move(x: f64) -> Message {
+        Message::new(
+            7,
+            InputEvent::MouseMove(MouseMoveEvent {
+                x,
+                y: 0.5,
+                delta_x: 1.0,
+                delta_y: 0.0,
+                timestamp_us: 0,
+            }),
+        )
+    }
+
+    #[test]
+    fn sealed_datagrams_open_with_the_same_key() {
+        let codec = DatagramCodec::new(3, [9; 32]);
+        let datagram = codec.seal(&mouse_move(0.25)).unwrap();
+        assert_eq!(session_of(&datagram), Some(3));
+
+        let message = codec.open(&datagram).unwrap();
+        assert_eq!(message.sequence, 7);
+        assert!(matches!(message.event, InputEvent::MouseMove(move_event) if move_event.x == 0.25));
+    }
+
+    #[test]
+    fn other_keys_and_changed_bytes_are_forged() {
+        let codec = DatagramCodec::new(3, [9; 32]);
+        let datagram = codec.seal(&mouse_move(0.25)).unwrap();
+
+        assert!(matches!(DatagramCodec::new(3, [8; 32]).open(&datagram), Err(DatagramError::Forged)));
+        assert!(matches!(DatagramCodec::new(4, [9; 32]).open(&datagram), Err(DatagramError::Forged)));
+        let mut changed = datagram.clone();
+        changed[12] ^= 1;
+        assert!(matches!(codec.open(&changed), Err(DatagramError::Forged)));
+        assert!(matches!(codec.open(&datagram[..8 + TAG_SIZE - 1]), Err(DatagramError::Size(_))));
+    }
+
+    #[test]
+    fn only_pointer_events_are_sealed() {
+        let codec = DatagramCodec::new(3, [9; 32]);
+        let key = Message::new(
+            1,
+            InputEvent::Keyboard(KeyboardEvent {
+                key_code: 0,
+                character: None,
+                pressed: true,
+                modifiers: Modifiers::default(),
+                is_repeat: false,
+            }),
+        );
+        assert!(matches!(codec.seal(&key), Err(DatagramError::NotPointer)));
+    }
+
+    #[test]
+    fn invalid_moves_are_rejected() {
+        let codec = DatagramCodec::new(3, [9; 32]);
+        let datagram = codec.seal(&mouse_move(f64::NAN)).unwrap();
+        assert!(matches!(codec.open(&datagram), Err(DatagramError::Invalid(_))));
+    }
+
+    #[test]
+    fn loss_simulator_drops_about_its_rate() {
+        let mut loss = LossSimulator::new(0.3, 42);
+        let dropped = (0..10_000).filter(|_| loss.drop_next()).count();
+        assert!((2_700..3_300).contains(&dropped), "{} dropped", dropped);
+
+        let mut again = LossSimulator::new(0.3, 42);
+        let mut loss = LossSimulator::new(0.3, 42);
+        assert!((0..100).all(|_| loss.drop_next() == again.drop_next()), "same seed, same drops");
+        assert!(!(0..100).any(|_| LossSimulator::new(0.0, 1).drop_next()));
+    }
+}
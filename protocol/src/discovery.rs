@@ -0,0 +1,168 @@
+//! Finding servers on the local network
+//!
+//! A server announces itself over mDNS/DNS-SD as an instance of
+//! [`SERVICE_TYPE`]. The instance name is the name shown to users, the SRV
+//! record carries the TCP port and the TXT record the rest of the
+//! [`Announcement`]: protocol version, whether TCP uses TLS, certificate
+//! fingerprint and QUIC port. Clients browse for the service type and list
+//! what they find, so nobody has to look up the server's address.
+//!
+//! Announcements are unauthenticated, anyone on the network can send one. The
+//! fingerprint is only shown, so users can compare it with the one the server
+//! prints; the client still asks before trusting a certificate.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// DNS-SD service type of the server
+pub const SERVICE_TYPE: &str = "_samesame._tcp.local.";
+
+/// Version of the protocol spoken on the connection
+///
+/// Raised when a change makes old clients and servers unable to talk.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Longest server name, the limit of a DNS label
+pub const MAX_NAME_LENGTH: usize = 63;
+
+/// TXT key of the protocol version
+pub const KEY_VERSION: &str = "version";
+
+/// TXT key of the TLS flag, `1` when TCP connections use TLS
+pub const KEY_TLS: &str = "tls";
+
+/// TXT key of the certificate fingerprint, missing without TLS and QUIC
+pub const KEY_FINGERPRINT: &str = "fingerprint";
+
+/// TXT key of the QUIC port, missing without QUIC
+pub const KEY_QUIC_PORT: &str = "quic";
+
+/// What a server tells the local network about itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Announcement {
+    /// Name shown to users
+    pub name: String,
+    /// TCP port
+    pub port: u16,
+    /// Protocol version of the server
+    pub version: u16,
+    /// TCP connections use TLS
+    pub tls: bool,
+    /// Certificate fingerprint, set when the server uses TLS or QUIC
+    pub fingerprint: Option<String>,
+    /// UDP port for QUIC, set when the server accepts QUIC
+    pub quic_port: Option<u16>,
+}
+
+/// Why an announcement was ignored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnnouncementError {
+    /// The name is empty, too long or contains control characters
+    InvalidName,
+    /// The port is zero
+    InvalidPort,
+    /// A TXT value is missing or malformed
+    InvalidProperty(&'static str),
+}
+
+impl fmt::Display for AnnouncementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnouncementError::InvalidName => write!(f, "Invalid server name"),
+            AnnouncementError::InvalidPort => write!(f, "Invalid port"),
+            AnnouncementError::InvalidProperty(key) => write!(f, "Invalid TXT property {}", key),
+        }
+    }
+}
+
+impl std::error::Error for AnnouncementError {}
+
+impl Announcement {
+    /// Announcement of a server speaking this protocol version
+    pub fn new(name: impl Into<String>, port: u16, tls: bool, fingerprint: Option<String>, quic_port: Option<u16>) -> Self {
+        Self {
+            name: name.into(),
+            port,
+            version: PROTOCOL_VERSION,
+            tls,
+            fingerprint,
+            quic_port,
+        }
+    }
+
+    /// Key/value pairs of the TXT record
+    pub fn properties(&self) -> Vec<(&'static str, String)> {
+        let mut properties = vec![
+            (KEY_VERSION, self.version.to_string()),
+            (KEY_TLS, u8::from(self.tls).to_string()),
+        ];
+        if let Some(fingerprint) = &self.fingerprint {
+            properties.push((KEY_FINGERPRINT, fingerprint.clone()));
+        }
+        if let Some(port) = self.quic_port {
+            properties.push((KEY_QUIC_PORT, port.to_string()));
+        }
+        properties
+    }
+
+    /// Read an announcement received from the network
+    ///
+    /// `property` looks up a TXT value by key. Everything is checked, a
+    /// malformed announcement is rejected as a whole.
+    pub fn from_properties<'a>(
+        name: &str,
+        port: u16,
+        property: impl Fn(&str) -> Option<&'a str>,
+    ) -> Result<Self, AnnouncementError> {
+        if name.is_empty() || name.len() > MAX_NAME_LENGTH || name.chars().any(char::is_control) {
+            return Err(AnnouncementError::InvalidName);
+        }
+        if port == 0 {
+            return Err(AnnouncementError::InvalidPort);
+        }
+
+        let version = property(KEY_VERSION)
+            .and_then(|value| value.parse().ok())
+            .ok_or(AnnouncementError::InvalidProperty(KEY_VERSION))?;
+        let tls = match property(KEY_TLS) {
+            Some("1") => true,
+            Some("0") | None => false,
+            Some(_) => return Err(AnnouncementError::InvalidProperty(KEY_TLS)),
+        };
+        let fingerprint = match property(KEY_FINGERPRINT) {
+            Some(value) if is_fingerprint(value) => Some(value.to_string()),
+            Some(_) => return Err(AnnouncementError::InvalidProperty(KEY_FINGERPRINT)),
+            None => None,
+        };
+        let quic_port = match property(KEY_QUIC_PORT) {
+            Some(value) => match value.parse() {
+                Ok(port) if port != 0 => Some(port),
+                _ => return Err(AnnouncementError::InvalidProperty(KEY_QUIC_PORT)),
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            port,
+            version,
+            tls,
+            fingerprint,
+            quic_port,
+        })
+    }
+
+    /// Whether this client can talk to the server
+    pub fn is_compatible(&self) -> bool {
+        self.version == PROTOCOL_VERSION
+    }
+}
+
+/// Whether `value` has the format of [`certificate_fingerprint`](crate::tls::certificate_fingerprint)
+fn is_fingerprint(value: &str) -> bool {
+    let bytes: Vec<&str> = value.split(':').collect();
+    bytes.len() == 32
+        && bytes
+            .iter()
+            .all(|byte| byte.len() == 2 && byte.chars().all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c)))
+}
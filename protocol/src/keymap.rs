@@ -0,0 +1,478 @@
+//! Key codes and keyboard layouts
+//!
+//! Keyboard events carry macOS key codes, which the server translates into
+//! Windows virtual keys with [`windows_vk`]. For typing out text the other
+//! direction is needed: [`plan_typing`] looks up which virtual key and
+//! modifiers produce a character on the target's keyboard layout and turns
+//! that back into key events the server will replay faithfully.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{KeyboardEvent, Modifiers};
+
+/// macOS key code → Windows virtual key
+const MAC_TO_WINDOWS_VK: &[(u16, u16)] = &[
+    // Letters A-Z
+    (0x00, 0x41), // A
+    (0x0B, 0x42), // B
+    (0x08, 0x43), // C
+    (0x02, 0x44), // D
+    (0x0E, 0x45), // E
+    (0x03, 0x46), // F
+    (0x05, 0x47), // G
+    (0x04, 0x48), // H
+    (0x22, 0x49), // I
+    (0x26, 0x4A), // J
+    (0x28, 0x4B), // K
+    (0x25, 0x4C), // L
+    (0x2E, 0x4D), // M
+    (0x2D, 0x4E), // N
+    (0x1F, 0x4F), // O
+    (0x23, 0x50), // P
+    (0x0C, 0x51), // Q
+    (0x0F, 0x52), // R
+    (0x01, 0x53), // S
+    (0x11, 0x54), // T
+    (0x20, 0x55), // U
+    (0x09, 0x56), // V
+    (0x0D, 0x57), // W
+    (0x07, 0x58), // X
+    (0x10, 0x59), // Y
+    (0x06, 0x5A), // Z
+    // Numbers 0-9
+    (0x1D, 0x30), // 0
+    (0x12, 0x31), // 1
+    (0x13, 0x32), // 2
+    (0x14, 0x33), // 3
+    (0x15, 0x34), // 4
+    (0x17, 0x35), // 5
+    (0x16, 0x36), // 6
+    (0x1A, 0x37), // 7
+    (0x1C, 0x38), // 8
+    (0x19, 0x39), // 9
+    // Special keys
+    (0x24, VK_RETURN),
+    (0x30, VK_TAB),
+    (0x31, VK_SPACE),
+    (0x33, 0x08), // Delete/Backspace
+    (0x35, 0x1B), // Escape
+    (0x75, 0x2E), // Forward Delete
+    (0x73, 0x24), // Home
+    (0x77, 0x23), // End
+    (0x74, 0x21), // Page Up
+    (0x79, 0x22), // Page Down
+    // Arrow keys
+    (0x7B, 0x25), // Left
+    (0x7C, 0x27), // Right
+    (0x7E, 0x26), // Up
+    (0x7D, 0x28), // Down
+    // Function keys F1-F12
+    (0x7A, 0x70),
+    (0x78, 0x71),
+    (0x63, 0x72),
+    (0x76, 0x73),
+    (0x60, 0x74),
+    (0x61, 0x75),
+    (0x62, 0x76),
+    (0x64, 0x77),
+    (0x65, 0x78),
+    (0x6D, 0x79),
+    (0x67, 0x7A),
+    (0x6F, 0x7B),
+    // Punctuation and symbols (German layout specific)
+    (0x27, VK_OEM_7),      // ' (Ä on German keyboard)
+    (0x29, VK_OEM_3),      // ` (Ö on German keyboard)
+    (0x21, VK_OEM_4),      // [ (Ü on German keyboard)
+    (0x2B, VK_OEM_COMMA),  // ,
+    (0x2F, VK_OEM_PERIOD), // .
+    (0x2C, VK_OEM_2),      // /
+    (0x18, VK_OEM_PLUS),   // =
+    (0x1B, VK_OEM_MINUS),  // -
+];
+
+/// Windows virtual key → macOS key code for typing out text, besides letters and digits
+///
+/// Lists only keys [`MAC_TO_WINDOWS_VK`] translates back to the same virtual
+/// key; characters on other keys cannot be typed.
+const TYPING_KEYS: &[(u16, u16)] = &[
+    (VK_RETURN, 0x24),
+    (VK_TAB, 0x30),
+    (VK_SPACE, 0x31),
+    (VK_OEM_7, 0x27),
+    (VK_OEM_3, 0x29),
+    (VK_OEM_4, 0x21),
+    (VK_OEM_COMMA, 0x2B),
+    (VK_OEM_PERIOD, 0x2F),
+    (VK_OEM_2, 0x2C),
+    (VK_OEM_PLUS, 0x18),
+    (VK_OEM_MINUS, 0x1B),
+];
+
+const VK_TAB: u16 = 0x09;
+const VK_RETURN: u16 = 0x0D;
+const VK_SPACE: u16 = 0x20;
+const VK_OEM_1: u16 = 0xBA;
+const VK_OEM_PLUS: u16 = 0xBB;
+const VK_OEM_COMMA: u16 = 0xBC;
+const VK_OEM_MINUS: u16 = 0xBD;
+const VK_OEM_PERIOD: u16 = 0xBE;
+const VK_OEM_2: u16 = 0xBF;
+const VK_OEM_3: u16 = 0xC0;
+const VK_OEM_4: u16 = 0xDB;
+const VK_OEM_5: u16 = 0xDC;
+const VK_OEM_6: u16 = 0xDD;
+const VK_OEM_7: u16 = 0xDE;
+const VK_OEM_102: u16 = 0xE2;
+
+/// Windows virtual key for a macOS key code
+pub fn windows_vk(mac_key_code: u16) -> Option<u16> {
+    MAC_TO_WINDOWS_VK
+        .iter()
+        .find(|(mac, _)| *mac == mac_key_code)
+        .map(|(_, vk)| *vk)
+}
+
+/// macOS key code the server translates into `vk`
+pub fn mac_key_code(vk: u16) -> Option<u16> {
+    match vk {
+        // Letters and digits
+        0x41..=0x5A | 0x30..=0x39 => MAC_TO_WINDOWS_VK
+            .iter()
+            .find(|(_, windows)| *windows == vk)
+            .map(|(mac, _)| *mac),
+        _ => TYPING_KEYS
+            .iter()
+            .find(|(windows, _)| *windows == vk)
+            .map(|(_, mac)| *mac),
+    }
+}
+
+/// Keyboard layout configured on the target machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetLayout {
+    /// US English (QWERTY)
+    Us,
+    /// German (QWERTZ)
+    #[default]
+    German,
+}
+
+/// Shift state needed for a character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Plain,
+    Shift,
+    /// AltGr, which Windows treats as Ctrl+Alt
+    AltGr,
+}
+
+/// Character → (virtual key, level, is dead key)
+type LayoutEntry = (char, u16, Level, bool);
+
+const US_SYMBOLS: &[LayoutEntry] = &[
+    ('!', 0x31, Level::Shift, false),
+    ('@', 0x32, Level::Shift, false),
+    ('#', 0x33, Level::Shift, false),
+    ('$', 0x34, Level::Shift, false),
+    ('%', 0x35, Level::Shift, false),
+    ('^', 0x36, Level::Shift, false),
+    ('&', 0x37, Level::Shift, false),
+    ('*', 0x38, Level::Shift, false),
+    ('(', 0x39, Level::Shift, false),
+    (')', 0x30, Level::Shift, false),
+    ('`', VK_OEM_3, Level::Plain, false),
+    ('~', VK_OEM_3, Level::Shift, false),
+    ('-', VK_OEM_MINUS, Level::Plain, false),
+    ('_', VK_OEM_MINUS, Level::Shift, false),
+    ('=', VK_OEM_PLUS, Level::Plain, false),
+    ('+', VK_OEM_PLUS, Level::Shift, false),
+    ('[', VK_OEM_4, Level::Plain, false),
+    ('{', VK_OEM_4, Level::Shift, false),
+    (']', VK_OEM_6, Level::Plain, false),
+    ('}', VK_OEM_6, Level::Shift, false),
+    ('\\', VK_OEM_5, Level::Plain, false),
+    ('|', VK_OEM_5, Level::Shift, false),
+    (';', VK_OEM_1, Level::Plain, false),
+    (':', VK_OEM_1, Level::Shift, false),
+    ('\'', VK_OEM_7, Level::Plain, false),
+    ('"', VK_OEM_7, Level::Shift, false),
+    (',', VK_OEM_COMMA, Level::Plain, false),
+    ('<', VK_OEM_COMMA, Level::Shift, false),
+    ('.', VK_OEM_PERIOD, Level::Plain, false),
+    ('>', VK_OEM_PERIOD, Level::Shift, false),
+    ('/', VK_OEM_2, Level::Plain, false),
+    ('?', VK_OEM_2, Level::Shift, false),
+];
+
+const GERMAN_SYMBOLS: &[LayoutEntry] = &[
+    ('!', 0x31, Level::Shift, false),
+    ('"', 0x32, Level::Shift, false),
+    ('§', 0x33, Level::Shift, false),
+    ('$', 0x34, Level::Shift, false),
+    ('%', 0x35, Level::Shift, false),
+    ('&', 0x36, Level::Shift, false),
+    ('/', 0x37, Level::Shift, false),
+    ('(', 0x38, Level::Shift, false),
+    (')', 0x39, Level::Shift, false),
+    ('=', 0x30, Level::Shift, false),
+    ('²', 0x32, Level::AltGr, false),
+    ('³', 0x33, Level::AltGr, false),
+    ('{', 0x37, Level::AltGr, false),
+    ('[', 0x38, Level::AltGr, false),
+    (']', 0x39, Level::AltGr, false),
+    ('}', 0x30, Level::AltGr, false),
+    ('@', 0x51, Level::AltGr, false),
+    ('€', 0x45, Level::AltGr, false),
+    ('µ', 0x4D, Level::AltGr, false),
+    ('ß', VK_OEM_4, Level::Plain, false),
+    ('?', VK_OEM_4, Level::Shift, false),
+    ('\\', VK_OEM_4, Level::AltGr, false),
+    ('´', VK_OEM_6, Level::Plain, true),
+    ('`', VK_OEM_6, Level::Shift, true),
+    ('^', VK_OEM_5, Level::Plain, true),
+    ('°', VK_OEM_5, Level::Shift, false),
+    ('ü', VK_OEM_1, Level::Plain, false),
+    ('Ü', VK_OEM_1, Level::Shift, false),
+    ('+', VK_OEM_PLUS, Level::Plain, false),
+    ('*', VK_OEM_PLUS, Level::Shift, false),
+    ('~', VK_OEM_PLUS, Level::AltGr, false),
+    ('ö', VK_OEM_3, Level::Plain, false),
+    ('Ö', VK_OEM_3, Level::Shift, false),
+    ('ä', VK_OEM_7, Level::Plain, false),
+    ('Ä', VK_OEM_7, Level::Shift, false),
+    ('#', VK_OEM_2, Level::Plain, false),
+    ('\'', VK_OEM_2, Level::Shift, false),
+    ('<', VK_OEM_102, Level::Plain, false),
+    ('>', VK_OEM_102, Level::Shift, false),
+    ('|', VK_OEM_102, Level::AltGr, false),
+    (',', VK_OEM_COMMA, Level::Plain, false),
+    (';', VK_OEM_COMMA, Level::Shift, false),
+    ('.', VK_OEM_PERIOD, Level::Plain, false),
+    (':', VK_OEM_PERIOD, Level::Shift, false),
+    ('-', VK_OEM_MINUS, Level::Plain, false),
+    ('_', VK_OEM_MINUS, Level::Shift, false),
+];
+
+impl TargetLayout {
+    fn symbols(self) -> &'static [LayoutEntry] {
+        match self {
+            TargetLayout::Us => US_SYMBOLS,
+            TargetLayout::German => GERMAN_SYMBOLS,
+        }
+    }
+
+    /// Virtual key, level and dead-key flag that produce `c`
+    fn lookup(self, c: char) -> Option<(u16, Level, bool)> {
+        match c {
+            'a'..='z' => Some((c.to_ascii_uppercase() as u16, Level::Plain, false)),
+            'A'..='Z' => Some((c as u16, Level::Shift, false)),
+            '0'..='9' => Some((c as u16, Level::Plain, false)),
+            ' ' => Some((VK_SPACE, Level::Plain, false)),
+            '\n' => Some((VK_RETURN, Level::Plain, false)),
+            '\t' => Some((VK_TAB, Level::Plain, false)),
+            _ => self
+                .symbols()
+                .iter()
+                .find(|(symbol, ..)| *symbol == c)
+                .map(|&(_, vk, level, dead)| (vk, level, dead)),
+        }
+    }
+
+    /// Whether Caps Lock switches `c` on key `vk` to its other case
+    ///
+    /// That is the case when the other case sits on the same key, like ä/Ä
+    /// but not ß, whose upper case is "SS".
+    fn caps_lock_applies(self, c: char, vk: u16) -> bool {
+        let other: Vec<char> = match c.is_lowercase() {
+            true => c.to_uppercase().collect(),
+            false => c.to_lowercase().collect(),
+        };
+        match other[..] {
+            [other] if other != c => self.lookup(other).is_some_and(|(other_vk, ..)| other_vk == vk),
+            _ => false,
+        }
+    }
+}
+
+/// A key press with the modifiers held for it
+#[derive(Debug, Clone, Copy)]
+pub struct KeyStroke {
+    /// macOS key code, as sent in [`KeyboardEvent`]
+    pub key_code: u16,
+    pub modifiers: Modifiers,
+}
+
+/// Key strokes that type `c` on `layout`, `None` if the layout has no key for it
+///
+/// Dead keys (e.g. ^ on German keyboards) are followed by a space so the
+/// character itself appears. With `caps_lock` on the target, Shift is
+/// inverted for letters Caps Lock affects.
+pub fn keystrokes(layout: TargetLayout, c: char, caps_lock: bool) -> Option<Vec<KeyStroke>> {
+    let (vk, mut level, dead) = layout.lookup(c)?;
+    if caps_lock && layout.caps_lock_applies(c, vk) {
+        level = match level {
+            Level::Plain => Level::Shift,
+            Level::Shift => Level::Plain,
+            Level::AltGr => Level::AltGr,
+        };
+    }
+
+    // Modifiers travel as Mac modifiers: Cmd becomes Ctrl, Option becomes Alt
+    let modifiers = match level {
+        Level::Plain => Modifiers::default(),
+        Level::Shift => Modifiers {
+            shift: true,
+            ..Modifiers::default()
+        },
+        Level::AltGr => Modifiers {
+            command: true,
+            alt: true,
+            ..Modifiers::default()
+        },
+    };
+
+    let mut strokes = vec![KeyStroke {
+        key_code: mac_key_code(vk)?,
+        modifiers,
+    }];
+    if dead {
+        strokes.push(KeyStroke {
+            key_code: mac_key_code(VK_SPACE)?,
+            modifiers: Modifiers::default(),
+        });
+    }
+    Some(strokes)
+}
+
+/// Key events that type a text, and what could not be typed
+#[derive(Debug, Clone, Default)]
+pub struct TypingPlan {
+    /// Press and release events, in order
+    pub events: Vec<KeyboardEvent>,
+    /// Characters the layout has no key for (skipped)
+    pub untypable: Vec<char>,
+}
+
+/// Turn `text` into key events for the given target layout and Caps Lock state
+pub fn plan_typing(text: &str, layout: TargetLayout, caps_lock: bool) -> TypingPlan {
+    let mut plan = TypingPlan::default();
+
+    // "\r\n" is one line break
+    for c in text.replace("\r\n", "\n").chars() {
+        let Some(strokes) = keystrokes(layout, c, caps_lock) else {
+            if !plan.untypable.contains(&c) {
+                plan.untypable.push(c);
+            }
+            continue;
+        };
+
+        for stroke in strokes {
+            for pressed in [true, false] {
+                plan.events.push(KeyboardEvent {
+                    key_code: stroke.key_code,
+                    character: Some(c),
+                    pressed,
+                    modifiers: stroke.modifiers,
+                    is_repeat: false,
+                });
+            }
+        }
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(layout: TargetLayout, c: char, caps_lock: bool) -> Vec<(u16, bool)> {
+        keystrokes(layout, c, caps_lock)
+            .expect("typable")
+            .iter()
+            .map(|stroke| (windows_vk(stroke.key_code).expect("mapped key code"), stroke.modifiers.shift))
+            .collect()
+    }
+
+    #[test]
+    fn typing_keys_round_trip_through_the_server_mapping() {
+        for &(vk, mac) in TYPING_KEYS {
+            assert_eq!(windows_vk(mac), Some(vk), "key code 0x{:02X}", mac);
+        }
+        for layout in [TargetLayout::Us, TargetLayout::German] {
+            for &(symbol, vk, ..) in layout.symbols() {
+                if let Some(mac) = mac_key_code(vk) {
+                    assert_eq!(windows_vk(mac), Some(vk), "{:?} on {:?}", symbol, layout);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn forwarding_leaves_unlisted_keys_unmapped() {
+        for mac in [0x2A, 0x0A, 0x32, 0x1E] {
+            assert_eq!(windows_vk(mac), None, "key code 0x{:02X}", mac);
+        }
+    }
+
+    #[test]
+    fn characters_on_unreachable_keys_are_untypable() {
+        let plan = plan_typing("ü<a>", TargetLayout::German, false);
+        assert_eq!(plan.untypable, vec!['ü', '<', '>']);
+        assert_eq!(plan.events.len(), 2);
+
+        let plan = plan_typing("a;b]", TargetLayout::Us, false);
+        assert_eq!(plan.untypable, vec![';', ']']);
+    }
+
+    #[test]
+    fn plans_shift_and_altgr() {
+        assert_eq!(typed(TargetLayout::German, 'z', false), vec![(0x5A, false)]);
+        assert_eq!(typed(TargetLayout::German, 'Ä', false), vec![(VK_OEM_7, true)]);
+        assert_eq!(typed(TargetLayout::Us, '?', false), vec![(VK_OEM_2, true)]);
+
+        let at = keystrokes(TargetLayout::German, '@', false).unwrap();
+        assert_eq!(windows_vk(at[0].key_code), Some(0x51));
+        assert!(at[0].modifiers.command && at[0].modifiers.alt && !at[0].modifiers.shift);
+    }
+
+    #[test]
+    fn caps_lock_inverts_shift_for_letters_only() {
+        assert_eq!(typed(TargetLayout::German, 'a', true), vec![(0x41, true)]);
+        assert_eq!(typed(TargetLayout::German, 'A', true), vec![(0x41, false)]);
+        assert_eq!(typed(TargetLayout::German, 'ä', true), vec![(VK_OEM_7, true)]);
+        assert_eq!(typed(TargetLayout::German, 'Ö', true), vec![(VK_OEM_3, false)]);
+
+        // ß has no single upper case on its key, digits and symbols ignore Caps Lock
+        assert_eq!(typed(TargetLayout::German, 'ß', true), vec![(VK_OEM_4, false)]);
+        assert_eq!(typed(TargetLayout::German, '1', true), vec![(0x31, false)]);
+        assert_eq!(typed(TargetLayout::German, '!', true), vec![(0x31, true)]);
+        assert_eq!(typed(TargetLayout::Us, '-', true), vec![(VK_OEM_MINUS, false)]);
+
+        let at = keystrokes(TargetLayout::German, '€', true).unwrap();
+        assert!(!at[0].modifiers.shift);
+    }
+
+    #[test]
+    fn plan_presses_and_releases_each_key() {
+        let plan = plan_typing("Hi\r\n", TargetLayout::Us, false);
+        let keys: Vec<(u16, bool, bool)> = plan
+            .events
+            .iter()
+            .map(|event| (windows_vk(event.key_code).unwrap(), event.pressed, event.modifiers.shift))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (0x48, true, true),
+                (0x48, false, true),
+                (0x49, true, false),
+                (0x49, false, false),
+                (VK_RETURN, true, false),
+                (VK_RETURN, false, false),
+            ]
+        );
+        assert!(plan.untypable.is_empty());
+    }
+}
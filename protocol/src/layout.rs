@@ -0,0 +1,416 @@
+//! Screen layout and edge switching
+//!
+//! Describes which edge of which screen neighbours which other screen and
+//! decides, from the cursor position, when the pointer crosses over. Screens
+//! are identified by any id type, so the client can use its own notion of
+//! targets.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Edge of a screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Edge {
+    pub fn opposite(self) -> Edge {
+        match self {
+            Edge::Left => Edge::Right,
+            Edge::Right => Edge::Left,
+            Edge::Top => Edge::Bottom,
+            Edge::Bottom => Edge::Top,
+        }
+    }
+}
+
+/// Size of a screen in pixels
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenGeometry<S> {
+    pub id: S,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl<S> ScreenGeometry<S> {
+    /// Length of the given edge in pixels
+    fn edge_length(&self, edge: Edge) -> f64 {
+        match edge {
+            Edge::Left | Edge::Right => self.height,
+            Edge::Top | Edge::Bottom => self.width,
+        }
+    }
+}
+
+/// `edge` of screen `from` leads to screen `to`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EdgeLink<S> {
+    pub from: S,
+    pub edge: Edge,
+    pub to: S,
+}
+
+/// Arrangement of screens and the edges connecting them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenLayout<S> {
+    pub screens: Vec<ScreenGeometry<S>>,
+    pub links: Vec<EdgeLink<S>>,
+}
+
+impl<S> Default for ScreenLayout<S> {
+    fn default() -> Self {
+        Self {
+            screens: Vec::new(),
+            links: Vec::new(),
+        }
+    }
+}
+
+impl<S: Clone + PartialEq> ScreenLayout<S> {
+    /// Add a screen or update its size
+    pub fn set_screen(&mut self, id: S, width: f64, height: f64) {
+        match self.screens.iter_mut().find(|s| s.id == id) {
+            Some(screen) => {
+                screen.width = width;
+                screen.height = height;
+            }
+            None => self.screens.push(ScreenGeometry { id, width, height }),
+        }
+    }
+
+    /// Remove a screen and every link touching it
+    pub fn remove_screen(&mut self, id: &S) {
+        self.screens.retain(|s| &s.id != id);
+        self.links.retain(|l| &l.from != id && &l.to != id);
+    }
+
+    /// Place `b` next to `edge` of `a`; the reverse link is added as well
+    pub fn link(&mut self, a: S, edge: Edge, b: S) {
+        let reverse = edge.opposite();
+        self.links.retain(|l| {
+            let replaced = (l.from == a && l.edge == edge) || (l.from == b && l.edge == reverse);
+            !replaced
+        });
+        self.links.push(EdgeLink {
+            from: b.clone(),
+            edge: reverse,
+            to: a.clone(),
+        });
+        self.links.push(EdgeLink { from: a, edge, to: b });
+    }
+
+    pub fn geometry(&self, id: &S) -> Option<&ScreenGeometry<S>> {
+        self.screens.iter().find(|s| &s.id == id)
+    }
+
+    /// Screen that lies beyond `edge` of `from`
+    pub fn neighbour(&self, from: &S, edge: Edge) -> Option<&S> {
+        self.links
+            .iter()
+            .find(|l| &l.from == from && l.edge == edge)
+            .map(|l| &l.to)
+    }
+}
+
+/// Tuning for edge switching
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SwitchOptions {
+    /// Time the cursor has to push against an edge before switching (0 = immediately)
+    pub dwell_ms: u64,
+    /// Size of the corner areas (pixels) where no switch happens, so hot corners keep working
+    pub corner_size: f64,
+}
+
+/// The pointer moved from one screen to another
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition<S> {
+    pub from: S,
+    pub to: S,
+    /// Edge of `from` that was crossed
+    pub edge: Edge,
+    /// Entry point on `to` in pixels
+    pub entry_x: f64,
+    pub entry_y: f64,
+    /// Entry point on `to`, normalized to 0.0-1.0
+    pub entry_normalized: (f64, f64),
+}
+
+/// Decides when the pointer crosses a linked screen edge
+#[derive(Debug, Clone)]
+pub struct EdgeSwitcher<S> {
+    layout: ScreenLayout<S>,
+    options: SwitchOptions,
+    /// Edge the cursor is currently pushing against and since when
+    pending: Option<(S, Edge, Instant)>,
+}
+
+impl<S: Clone + PartialEq> EdgeSwitcher<S> {
+    pub fn new(layout: ScreenLayout<S>, options: SwitchOptions) -> Self {
+        Self {
+            layout,
+            options,
+            pending: None,
+        }
+    }
+
+    pub fn layout(&self) -> &ScreenLayout<S> {
+        &self.layout
+    }
+
+    pub fn options(&self) -> &SwitchOptions {
+        &self.options
+    }
+
+    /// Feed a cursor position (pixels) on screen `current`
+    ///
+    /// Positions at or beyond a screen edge count as touching that edge.
+    /// Returns a transition once the cursor crosses a linked edge.
+    pub fn update(&mut self, current: &S, x: f64, y: f64, now: Instant) -> Option<Transition<S>> {
+        let Some(geometry) = self.layout.geometry(current) else {
+            self.pending = None;
+            return None;
+        };
+
+        let Some((edge, along)) = touched_edge(geometry, x, y) else {
+            self.pending = None;
+            return None;
+        };
+
+        let length = geometry.edge_length(edge);
+        if along < self.options.corner_size || along > length - 1.0 - self.options.corner_size {
+            self.pending = None;
+            return None;
+        }
+
+        let Some(to) = self.layout.neighbour(current, edge).cloned() else {
+            self.pending = None;
+            return None;
+        };
+        let target = self.layout.geometry(&to)?;
+
+        // Dwell: the cursor has to stay at the same edge for a while
+        let dwell = Duration::from_millis(self.options.dwell_ms);
+        let since = match &self.pending {
+            Some((screen, pending_edge, since)) if screen == current && *pending_edge == edge => *since,
+            _ => {
+                self.pending = Some((current.clone(), edge, now));
+                now
+            }
+        };
+        if now.duration_since(since) < dwell {
+            return None;
+        }
+        self.pending = None;
+
+        let (entry_x, entry_y) = entry_point(geometry, edge, along, target);
+        Some(Transition {
+            from: current.clone(),
+            to,
+            edge,
+            entry_x,
+            entry_y,
+            entry_normalized: (
+                entry_x / (target.width - 1.0).max(1.0),
+                entry_y / (target.height - 1.0).max(1.0),
+            ),
+        })
+    }
+}
+
+/// Edge the position touches and the offset along that edge
+fn touched_edge<S>(geometry: &ScreenGeometry<S>, x: f64, y: f64) -> Option<(Edge, f64)> {
+    let max_x = geometry.width - 1.0;
+    let max_y = geometry.height - 1.0;
+
+    if x <= 0.0 {
+        Some((Edge::Left, y.clamp(0.0, max_y)))
+    } else if x >= max_x {
+        Some((Edge::Right, y.clamp(0.0, max_y)))
+    } else if y <= 0.0 {
+        Some((Edge::Top, x.clamp(0.0, max_x)))
+    } else if y >= max_y {
+        Some((Edge::Bottom, x.clamp(0.0, max_x)))
+    } else {
+        None
+    }
+}
+
+/// Map an exit point on `edge` of `from` to the entry point on `to`
+///
+/// The position along the edge is scaled proportionally, so leaving at the
+/// middle of a 1440 px high screen enters at the middle of a 1080 px one. The
+/// entry point lies one pixel inside the target screen so the cursor does not
+/// immediately touch the edge it came through.
+pub fn entry_point<S>(
+    from: &ScreenGeometry<S>,
+    edge: Edge,
+    along: f64,
+    to: &ScreenGeometry<S>,
+) -> (f64, f64) {
+    let fraction = along / (from.edge_length(edge) - 1.0).max(1.0);
+    let target_along = fraction.clamp(0.0, 1.0) * (to.edge_length(edge) - 1.0).max(0.0);
+
+    match edge {
+        Edge::Right => (1.0, target_along),
+        Edge::Left => (to.width - 2.0, target_along),
+        Edge::Bottom => (target_along, 1.0),
+        Edge::Top => (target_along, to.height - 2.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2560x1440 Mac with a 1920x1080 target on its right
+    fn layout() -> ScreenLayout<&'static str> {
+        let mut layout = ScreenLayout::default();
+        layout.set_screen("mac", 2560.0, 1440.0);
+        layout.set_screen("pc", 1920.0, 1080.0);
+        layout.link("mac", Edge::Right, "pc");
+        layout
+    }
+
+    /// Feed a cursor path on `screen`, one point per millisecond, until it switches
+    ///
+    /// Like the client, which moves the cursor to the other screen on the first transition.
+    fn follow(
+        switcher: &mut EdgeSwitcher<&'static str>,
+        screen: &'static str,
+        path: &[(f64, f64)],
+        start: Instant,
+    ) -> Option<Transition<&'static str>> {
+        path.iter()
+            .enumerate()
+            .find_map(|(i, &(x, y))| switcher.update(&screen, x, y, start + Duration::from_millis(i as u64)))
+    }
+
+    /// Points from `from` to `to` in `steps` equal steps
+    fn line(from: (f64, f64), to: (f64, f64), steps: usize) -> Vec<(f64, f64)> {
+        (0..=steps)
+            .map(|i| {
+                let t = i as f64 / steps as f64;
+                (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crossing_a_linked_edge_switches() {
+        let mut switcher = EdgeSwitcher::new(layout(), SwitchOptions::default());
+        let path = line((1000.0, 720.0), (2700.0, 720.0), 17);
+
+        let transition = follow(&mut switcher, "mac", &path, Instant::now()).expect("crossed a linked edge");
+
+        assert_eq!((transition.from, transition.to, transition.edge), ("mac", "pc", Edge::Right));
+        // The middle of the 1440 px edge enters the middle of the 1080 px one, one pixel inside
+        assert_eq!(transition.entry_x, 1.0);
+        assert!((transition.entry_y - 539.5).abs() < 0.5, "entry_y = {}", transition.entry_y);
+        assert!((transition.entry_normalized.1 - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn moving_inside_the_screen_never_switches() {
+        let mut switcher = EdgeSwitcher::new(layout(), SwitchOptions::default());
+        let mut path = line((1.0, 1.0), (2558.0, 1.0), 50);
+        path.extend(line((2558.0, 1.0), (2558.0, 1438.0), 50));
+        path.extend(line((2558.0, 1438.0), (1.0, 720.0), 50));
+
+        assert!(follow(&mut switcher, "mac", &path, Instant::now()).is_none());
+    }
+
+    #[test]
+    fn unlinked_edges_are_walls() {
+        let mut switcher = EdgeSwitcher::new(layout(), SwitchOptions::default());
+        let mut path = line((1280.0, 720.0), (-100.0, 720.0), 20);
+        path.extend(line((1280.0, 720.0), (1280.0, -100.0), 20));
+        path.extend(line((1280.0, 720.0), (1280.0, 1600.0), 20));
+
+        assert!(follow(&mut switcher, "mac", &path, Instant::now()).is_none());
+    }
+
+    #[test]
+    fn corners_are_kept_for_hot_corners() {
+        let options = SwitchOptions {
+            dwell_ms: 0,
+            corner_size: 50.0,
+        };
+        let mut switcher = EdgeSwitcher::new(layout(), options);
+
+        // Into the top right corner and the bottom right corner
+        let start = Instant::now();
+        assert!(follow(&mut switcher, "mac", &line((2000.0, 300.0), (2600.0, 10.0), 20), start).is_none());
+        assert!(follow(&mut switcher, "mac", &line((2000.0, 1100.0), (2600.0, 1430.0), 20), start).is_none());
+
+        // Sliding down the edge out of the corner switches at the first point outside it
+        let transition = follow(&mut switcher, "mac", &line((2559.0, 0.0), (2559.0, 100.0), 100), start).unwrap();
+        let fraction = 50.0 / 1439.0;
+        assert!((transition.entry_y - fraction * 1079.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn dwell_requires_pushing_against_the_edge() {
+        let options = SwitchOptions {
+            dwell_ms: 100,
+            corner_size: 0.0,
+        };
+        let mut switcher = EdgeSwitcher::new(layout(), options);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Touch the edge briefly, leave, come back: the dwell starts again
+        assert!(switcher.update(&"mac", 2559.0, 500.0, at(0)).is_none());
+        assert!(switcher.update(&"mac", 2559.0, 510.0, at(60)).is_none());
+        assert!(switcher.update(&"mac", 2500.0, 510.0, at(90)).is_none());
+        assert!(switcher.update(&"mac", 2559.0, 520.0, at(120)).is_none());
+        assert!(switcher.update(&"mac", 2559.0, 520.0, at(200)).is_none());
+
+        let transition = switcher.update(&"mac", 2559.0, 520.0, at(220)).expect("dwell elapsed");
+        assert_eq!(transition.to, "pc");
+    }
+
+    #[test]
+    fn round_trip_returns_to_the_same_height() {
+        let mut switcher = EdgeSwitcher::new(layout(), SwitchOptions::default());
+        let start = Instant::now();
+
+        let there = follow(&mut switcher, "mac", &line((2000.0, 360.0), (2600.0, 360.0), 6), start).unwrap();
+        let entry = (there.entry_x, there.entry_y);
+
+        // Straight back left on the target
+        let back = follow(&mut switcher, "pc", &line(entry, (-50.0, entry.1), 10), start).unwrap();
+        assert_eq!((back.from, back.to, back.edge), ("pc", "mac", Edge::Left));
+        assert_eq!(back.entry_x, 2558.0);
+        assert!((back.entry_y - 360.0).abs() < 1.0, "entry_y = {}", back.entry_y);
+    }
+
+    #[test]
+    fn gap_between_screens_of_different_height() {
+        // A short target beside a tall screen: the whole edge maps onto it, there is no dead zone
+        let mut layout = ScreenLayout::default();
+        layout.set_screen("tall", 1080.0, 1920.0);
+        layout.set_screen("short", 1920.0, 1080.0);
+        layout.link("tall", Edge::Right, "short");
+        let mut switcher = EdgeSwitcher::new(layout, SwitchOptions::default());
+        let start = Instant::now();
+
+        for y in [0.0, 100.0, 960.0, 1800.0, 1919.0] {
+            let transition = follow(&mut switcher, "tall", &[(1079.0, y)], start);
+            let entry_y = transition.unwrap_or_else(|| panic!("no switch at y = {}", y)).entry_y;
+            assert!((0.0..=1079.0).contains(&entry_y), "entry_y = {}", entry_y);
+        }
+    }
+
+    #[test]
+    fn removed_screens_are_not_entered() {
+        let mut layout = layout();
+        layout.remove_screen(&"pc");
+        let mut switcher = EdgeSwitcher::new(layout, SwitchOptions::default());
+
+        assert!(follow(&mut switcher, "mac", &line((2000.0, 720.0), (2600.0, 720.0), 6), Instant::now()).is_none());
+    }
+}
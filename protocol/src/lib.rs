@@ -1,6 +1,25 @@
 use serde::{Deserialize, Serialize};
 
-/// All event types that can be sent from macOS to Windows
+pub mod acceleration;
+pub mod arbitration;
+pub mod clipboard;
+pub mod consumer;
+pub mod datagram;
+pub mod discovery;
+pub mod keymap;
+pub mod layout;
+pub mod mapping;
+pub mod mux;
+pub mod pairing;
+pub mod quic;
+pub mod tls;
+pub mod transfer;
+pub mod validation;
+
+pub use mux::{Channel, FlowUpdate, MuxConfig, MuxDecoder, MuxEncoder, MuxError};
+pub use validation::{DecodeError, ValidationError};
+
+/// All event types that can be sent from macOS to Windows (and the few sent back)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum InputEvent {
     /// Keyboard key press/release
@@ -17,6 +36,82 @@ pub enum InputEvent {
     Ping,
     /// Pong response
     Pong,
+    /// The pointer crossed onto the receiving screen
+    EnterScreen(EnterScreenEvent),
+    /// The pointer left the receiving screen
+    LeaveScreen,
+    /// Cursor position reported by the server (server → client)
+    CursorPosition(CursorPositionEvent),
+    /// Monitor topology of the sender, exchanged on connect
+    ScreenInfo(mapping::ScreenInfo),
+    /// How the server should apply mouse movement
+    SetPointerMode(PointerMode),
+    /// Server asks the client for unaccelerated deltas (it applies its own curve)
+    RequestRawDeltas(bool),
+    /// Media, volume or brightness key
+    ConsumerControl(ConsumerControlEvent),
+    /// Lock keys: the wanted state (client → server) or the actual state (server → client)
+    LockState(LockState),
+    /// Text composed on the sender (dead keys, input methods), typed as Unicode
+    TextInput(TextInputEvent),
+    /// Part of a clipboard transfer, in both directions
+    Clipboard(clipboard::ClipboardChunk),
+    /// File transfer from client to server, with the server's replies
+    FileTransfer(transfer::FileTransfer),
+    /// Who controls the server when several clients are connected
+    Arbitration(arbitration::Arbitration),
+    /// Negotiation of pointer events over UDP
+    PointerTransport(datagram::PointerTransport),
+}
+
+/// Coarse classification of events, used for routing decisions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EventKind {
+    /// Key presses and releases
+    Keyboard,
+    /// Mouse movement, buttons and scrolling
+    Pointer,
+    /// Trackpad gestures
+    Gesture,
+    /// Connection housekeeping and feedback (ping/pong, cursor reports, screen info, clipboard, files)
+    Control,
+}
+
+impl InputEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            InputEvent::Keyboard(_)
+            | InputEvent::ConsumerControl(_)
+            | InputEvent::LockState(_)
+            | InputEvent::TextInput(_) => EventKind::Keyboard,
+            InputEvent::MouseMove(_)
+            | InputEvent::MouseButton(_)
+            | InputEvent::MouseScroll(_)
+            | InputEvent::EnterScreen(_)
+            | InputEvent::LeaveScreen => EventKind::Pointer,
+            InputEvent::Gesture(_) => EventKind::Gesture,
+            InputEvent::Ping
+            | InputEvent::Pong
+            | InputEvent::CursorPosition(_)
+            | InputEvent::ScreenInfo(_)
+            | InputEvent::SetPointerMode(_)
+            | InputEvent::RequestRawDeltas(_)
+            | InputEvent::Clipboard(_)
+            | InputEvent::FileTransfer(_)
+            | InputEvent::Arbitration(_)
+            | InputEvent::PointerTransport(_) => EventKind::Control,
+        }
+    }
+
+    /// Release of a key or button, which must always get through so nothing stays held
+    pub fn is_release(&self) -> bool {
+        match self {
+            InputEvent::Keyboard(key_event) => !key_event.pressed,
+            InputEvent::MouseButton(button_event) => !button_event.pressed,
+            InputEvent::ConsumerControl(consumer_event) => !consumer_event.pressed,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,18 +124,40 @@ pub struct KeyboardEvent {
     pub pressed: bool,
     /// Modifier keys state
     pub modifiers: Modifiers,
+    /// Key-down generated by autorepeat while the key is held
+    pub is_repeat: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MouseMoveEvent {
-    /// Absolute X position (0.0 to 1.0 normalized)
+    /// Absolute X position (0.0 to 1.0 normalized to the sender's virtual desktop)
     pub x: f64,
-    /// Absolute Y position (0.0 to 1.0 normalized)
+    /// Absolute Y position (0.0 to 1.0 normalized to the sender's virtual desktop)
     pub y: f64,
-    /// Relative X movement
+    /// Relative X movement in the sender's desktop units (sub-pixel precision)
     pub delta_x: f64,
-    /// Relative Y movement
+    /// Relative Y movement in the sender's desktop units (sub-pixel precision)
     pub delta_y: f64,
+    /// Sender's monotonic clock in microseconds, used to compute pointer speed
+    pub timestamp_us: u64,
+}
+
+/// How mouse movement is applied on the receiving side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PointerMode {
+    /// Place the cursor at the absolute position (desktop use)
+    #[default]
+    Absolute,
+    /// Move the cursor by the relative deltas (pointer lock, 3D tools)
+    Relative,
+    /// Relative movement with pointer acceleration disabled (games)
+    Game,
+}
+
+impl PointerMode {
+    pub fn is_relative(self) -> bool {
+        matches!(self, PointerMode::Relative | PointerMode::Game)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,7 +166,7 @@ pub struct MouseButtonEvent {
     pub pressed: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MouseButton {
     Left,
     Right,
@@ -67,6 +184,91 @@ pub struct MouseScrollEvent {
     pub delta_y: f64,
     /// Is this a pixel-based scroll (trackpad) or line-based (mouse wheel)
     pub is_pixel_based: bool,
+    /// Phase of the finger-driven part of a trackpad scroll
+    pub phase: ScrollPhase,
+    /// Phase of the inertia that follows lifting the fingers
+    pub momentum_phase: MomentumPhase,
+}
+
+/// Phase of a trackpad scroll gesture (`None` for mouse wheels)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ScrollPhase {
+    #[default]
+    None,
+    /// Fingers touched the trackpad, scrolling may follow
+    MayBegin,
+    Began,
+    Changed,
+    Ended,
+    Cancelled,
+}
+
+/// Phase of scroll inertia (momentum) after the fingers were lifted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MomentumPhase {
+    /// Not a momentum event
+    #[default]
+    None,
+    Began,
+    Changed,
+    Ended,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsumerControlEvent {
+    /// HID consumer page usage ID (see [`consumer::ConsumerKey`])
+    pub usage: u16,
+    /// Is the key pressed (true) or released (false)
+    pub pressed: bool,
+}
+
+impl ConsumerControlEvent {
+    pub fn new(key: consumer::ConsumerKey, pressed: bool) -> Self {
+        Self {
+            usage: key.usage(),
+            pressed,
+        }
+    }
+
+    pub fn key(&self) -> Option<consumer::ConsumerKey> {
+        consumer::ConsumerKey::from_usage(self.usage)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextInputEvent {
+    pub text: String,
+    /// `true` while the text is still being composed (marked text of an input
+    /// method); only committed text (`false`) is typed on the receiver
+    pub composing: bool,
+}
+
+/// State of the lock keys; `None` means the sender has no such key (e.g. Num Lock on a Mac)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct LockState {
+    pub caps_lock: Option<bool>,
+    pub num_lock: Option<bool>,
+    pub scroll_lock: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnterScreenEvent {
+    /// Entry X position (0.0 to 1.0 normalized)
+    pub x: f64,
+    /// Entry Y position (0.0 to 1.0 normalized)
+    pub y: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CursorPositionEvent {
+    /// X position on the screen the cursor is on (0.0 to 1.0 normalized)
+    pub x: f64,
+    /// Y position on the screen the cursor is on (0.0 to 1.0 normalized)
+    pub y: f64,
+    /// Index of the monitor the cursor is on
+    pub screen: u32,
+    /// Edge of the virtual desktop the cursor touches, if any
+    pub edge: Option<layout::Edge>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -138,8 +340,16 @@ impl Message {
         bincode::serialize(self)
     }
 
-    /// Deserialize message from bytes using bincode
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, bincode::Error> {
-        bincode::deserialize(bytes)
+    /// Deserialize and validate a message received from the peer
+    ///
+    /// Decoding is bounded by [`validation::MAX_MESSAGE_SIZE`], see [`validation`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let limit = validation::MAX_MESSAGE_SIZE;
+        if bytes.len() > limit {
+            return Err(DecodeError::TooLarge { size: bytes.len(), limit });
+        }
+        let message: Self = validation::decode_bounded(bytes, limit)?;
+        message.event.validate()?;
+        Ok(message)
     }
 }
@@ -0,0 +1,390 @@
+//! Monitor topology and pointer coordinate mapping
+//!
+//! Both sides describe their monitors with a [`ScreenInfo`]. Positions are
+//! given in desktop coordinates, i.e. the units the platform uses to place
+//! the cursor: points on macOS, physical pixels on Windows (per-monitor DPI
+//! aware). [`PointerMapper`] converts positions and movements between the
+//! client's and the server's desktops, monitor by monitor, so mixed
+//! resolutions and scale factors land the pointer in the right place.
+
+use serde::{Deserialize, Serialize};
+
+/// Units of desktop coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CoordinateSpace {
+    /// Logical (scaled) pixels, e.g. macOS points
+    Logical,
+    /// Physical pixels, e.g. Windows with per-monitor DPI awareness
+    Physical,
+}
+
+/// One monitor in desktop coordinates
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MonitorInfo {
+    /// Platform identifier of the monitor
+    pub id: u32,
+    /// Top-left corner
+    pub x: f64,
+    pub y: f64,
+    /// Size in desktop coordinates
+    pub width: f64,
+    pub height: f64,
+    /// Physical pixels per logical pixel (2.0 for Retina, 1.5 for 150% scaling)
+    pub scale_factor: f64,
+    pub primary: bool,
+}
+
+impl MonitorInfo {
+    fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Squared distance from a point to this monitor (0 if inside)
+    fn distance_squared(&self, x: f64, y: f64) -> f64 {
+        let dx = (self.x - x).max(x - (self.x + self.width - 1.0)).max(0.0);
+        let dy = (self.y - y).max(y - (self.y + self.height - 1.0)).max(0.0);
+        dx * dx + dy * dy
+    }
+
+    /// Point on this monitor from coordinates normalized to the monitor (0.0-1.0)
+    pub fn denormalize(&self, u: f64, v: f64) -> (f64, f64) {
+        (
+            self.x + u.clamp(0.0, 1.0) * (self.width - 1.0).max(0.0),
+            self.y + v.clamp(0.0, 1.0) * (self.height - 1.0).max(0.0),
+        )
+    }
+
+    /// Coordinates normalized to this monitor (0.0-1.0), clamped to its area
+    pub fn normalize(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            ((x - self.x) / (self.width - 1.0).max(1.0)).clamp(0.0, 1.0),
+            ((y - self.y) / (self.height - 1.0).max(1.0)).clamp(0.0, 1.0),
+        )
+    }
+}
+
+/// Bounding box of all monitors
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DesktopBounds {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Monitor topology of one machine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScreenInfo {
+    pub coordinate_space: CoordinateSpace,
+    pub monitors: Vec<MonitorInfo>,
+}
+
+impl ScreenInfo {
+    /// Bounding box of all monitors (the virtual desktop)
+    pub fn bounds(&self) -> DesktopBounds {
+        if self.monitors.is_empty() {
+            return DesktopBounds {
+                x: 0.0,
+                y: 0.0,
+                width: 1.0,
+                height: 1.0,
+            };
+        }
+
+        let left = self.monitors.iter().map(|m| m.x).fold(f64::INFINITY, f64::min);
+        let top = self.monitors.iter().map(|m| m.y).fold(f64::INFINITY, f64::min);
+        let right = self.monitors.iter().map(|m| m.x + m.width).fold(f64::NEG_INFINITY, f64::max);
+        let bottom = self.monitors.iter().map(|m| m.y + m.height).fold(f64::NEG_INFINITY, f64::max);
+
+        DesktopBounds {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    /// Index of the monitor containing the point, or the nearest one
+    pub fn monitor_index_at(&self, x: f64, y: f64) -> Option<usize> {
+        if let Some(index) = self.monitors.iter().position(|m| m.contains(x, y)) {
+            return Some(index);
+        }
+        (0..self.monitors.len()).min_by(|&a, &b| {
+            let da = self.monitors[a].distance_squared(x, y);
+            let db = self.monitors[b].distance_squared(x, y);
+            da.total_cmp(&db)
+        })
+    }
+
+    /// Coordinates normalized to the virtual desktop (0.0-1.0)
+    pub fn normalize(&self, x: f64, y: f64) -> (f64, f64) {
+        let b = self.bounds();
+        (
+            ((x - b.x) / (b.width - 1.0).max(1.0)).clamp(0.0, 1.0),
+            ((y - b.y) / (b.height - 1.0).max(1.0)).clamp(0.0, 1.0),
+        )
+    }
+
+    /// Desktop coordinates from coordinates normalized to the virtual desktop
+    pub fn denormalize(&self, nx: f64, ny: f64) -> (f64, f64) {
+        let b = self.bounds();
+        (
+            b.x + nx.clamp(0.0, 1.0) * (b.width - 1.0).max(0.0),
+            b.y + ny.clamp(0.0, 1.0) * (b.height - 1.0).max(0.0),
+        )
+    }
+
+    /// Logical pixels per desktop unit on a monitor
+    fn logical_per_unit(&self, monitor: &MonitorInfo) -> f64 {
+        match self.coordinate_space {
+            CoordinateSpace::Logical => 1.0,
+            CoordinateSpace::Physical => 1.0 / monitor.scale_factor,
+        }
+    }
+}
+
+/// Converts pointer positions and movements from a source to a target desktop
+///
+/// Source monitors are paired with target monitors in left-to-right,
+/// top-to-bottom order. A position on a source monitor maps to the same
+/// relative position on its paired target monitor; movement is converted
+/// through logical pixels, so a 10 pt move on a Retina display becomes a
+/// 15 px move on a 150% scaled Windows monitor.
+#[derive(Debug, Clone)]
+pub struct PointerMapper {
+    source: ScreenInfo,
+    target: ScreenInfo,
+    /// Target monitor index for every source monitor index
+    pairs: Vec<usize>,
+}
+
+impl PointerMapper {
+    pub fn new(source: ScreenInfo, target: ScreenInfo) -> Self {
+        let source_order = spatial_order(&source);
+        let target_order = spatial_order(&target);
+
+        let mut pairs = vec![0; source.monitors.len()];
+        if !target_order.is_empty() {
+            for (rank, &source_index) in source_order.iter().enumerate() {
+                pairs[source_index] = target_order[rank.min(target_order.len() - 1)];
+            }
+        }
+
+        Self {
+            source,
+            target,
+            pairs,
+        }
+    }
+
+    pub fn source(&self) -> &ScreenInfo {
+        &self.source
+    }
+
+    pub fn target(&self) -> &ScreenInfo {
+        &self.target
+    }
+
+    /// Map a point in source desktop coordinates to target desktop coordinates
+    pub fn map_point(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let source_index = self.source.monitor_index_at(x, y)?;
+        let target_monitor = self.target.monitors.get(self.pairs[source_index])?;
+
+        let (u, v) = self.source.monitors[source_index].normalize(x, y);
+        Some(target_monitor.denormalize(u, v))
+    }
+
+    /// Map a point normalized to the source desktop to one normalized to the target desktop
+    pub fn map_normalized(&self, nx: f64, ny: f64) -> Option<(f64, f64)> {
+        let (x, y) = self.source.denormalize(nx, ny);
+        let (tx, ty) = self.map_point(x, y)?;
+        Some(self.target.normalize(tx, ty))
+    }
+
+    /// Convert a movement on the source monitor at (x, y) into target desktop units
+    pub fn map_delta(&self, x: f64, y: f64, dx: f64, dy: f64) -> (f64, f64) {
+        let Some(source_index) = self.source.monitor_index_at(x, y) else {
+            return (dx, dy);
+        };
+        let Some(target_monitor) = self.target.monitors.get(self.pairs[source_index]) else {
+            return (dx, dy);
+        };
+        let source_monitor = &self.source.monitors[source_index];
+
+        // Source units → logical pixels → target units
+        let to_logical = self.source.logical_per_unit(source_monitor);
+        let to_target = 1.0 / self.target.logical_per_unit(target_monitor);
+        (dx * to_logical * to_target, dy * to_logical * to_target)
+    }
+}
+
+/// Monitor indices sorted left-to-right, then top-to-bottom
+fn spatial_order(info: &ScreenInfo) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..info.monitors.len()).collect();
+    order.sort_by(|&a, &b| {
+        let ma = &info.monitors[a];
+        let mb = &info.monitors[b];
+        ma.x.total_cmp(&mb.x).then(ma.y.total_cmp(&mb.y))
+    });
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(id: u32, x: f64, y: f64, width: f64, height: f64, scale_factor: f64) -> MonitorInfo {
+        MonitorInfo {
+            id,
+            x,
+            y,
+            width,
+            height,
+            scale_factor,
+            primary: x == 0.0 && y == 0.0,
+        }
+    }
+
+    fn assert_near(actual: (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 0.01 && (actual.1 - expected.1).abs() < 0.01,
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    /// Retina MacBook (1440x900 pt) with a 1080p display above left of it
+    fn retina_mac() -> ScreenInfo {
+        ScreenInfo {
+            coordinate_space: CoordinateSpace::Logical,
+            monitors: vec![
+                monitor(1, 0.0, 0.0, 1440.0, 900.0, 2.0),
+                monitor(2, -1920.0, -1080.0, 1920.0, 1080.0, 1.0),
+            ],
+        }
+    }
+
+    /// Windows at 100%: a 1440p primary and a 1080p monitor left of it, lower
+    fn windows_100() -> ScreenInfo {
+        ScreenInfo {
+            coordinate_space: CoordinateSpace::Physical,
+            monitors: vec![
+                monitor(10, 0.0, 0.0, 2560.0, 1440.0, 1.0),
+                monitor(11, -1920.0, 360.0, 1920.0, 1080.0, 1.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn monitors_pair_left_to_right() {
+        let mapper = PointerMapper::new(retina_mac(), windows_100());
+
+        // The display at the negative origin is leftmost on both sides
+        assert_near(mapper.map_point(-1920.0, -1080.0).unwrap(), (-1920.0, 360.0));
+        assert_near(mapper.map_point(-1.0, -1.0).unwrap(), (-1.0, 1439.0));
+        assert_near(mapper.map_point(0.0, 0.0).unwrap(), (0.0, 0.0));
+        assert_near(mapper.map_point(1439.0, 899.0).unwrap(), (2559.0, 1439.0));
+    }
+
+    #[test]
+    fn retina_positions_keep_their_relative_place() {
+        let mapper = PointerMapper::new(retina_mac(), windows_100());
+
+        // Middle of the built-in display, in points, lands in the middle of the primary monitor
+        assert_near(mapper.map_point(719.5, 449.5).unwrap(), (1279.5, 719.5));
+        // A third across the external display
+        assert_near(mapper.map_point(-1920.0 + 1919.0 / 3.0, -540.0).unwrap(), (-1920.0 + 1919.0 / 3.0, 900.0));
+    }
+
+    #[test]
+    fn retina_points_move_as_logical_pixels() {
+        let mapper = PointerMapper::new(retina_mac(), windows_100());
+
+        // 10 pt on Retina are 10 logical pixels, 10 physical pixels at 100%
+        assert_near(mapper.map_delta(700.0, 400.0, 10.0, -4.0), (10.0, -4.0));
+        assert_near(mapper.map_delta(-1000.0, -500.0, 10.0, -4.0), (10.0, -4.0));
+    }
+
+    #[test]
+    fn retina_to_150_percent_scales_movement() {
+        let target = ScreenInfo {
+            coordinate_space: CoordinateSpace::Physical,
+            monitors: vec![monitor(10, 0.0, 0.0, 3840.0, 2160.0, 1.5)],
+        };
+        let mapper = PointerMapper::new(retina_mac(), target);
+
+        assert_near(mapper.map_delta(700.0, 400.0, 10.0, 2.0), (15.0, 3.0));
+    }
+
+    #[test]
+    fn scaled_150_percent_source_to_100_percent() {
+        // A 4K monitor at 150% left of and above a 1080p one at 100%
+        let source = ScreenInfo {
+            coordinate_space: CoordinateSpace::Physical,
+            monitors: vec![
+                monitor(1, -3840.0, -720.0, 3840.0, 2160.0, 1.5),
+                monitor(2, 0.0, 0.0, 1920.0, 1080.0, 1.0),
+            ],
+        };
+        let mapper = PointerMapper::new(source, windows_100());
+
+        // 15 physical pixels at 150% are 10 logical pixels
+        assert_near(mapper.map_delta(-2000.0, 0.0, 15.0, -30.0), (10.0, -20.0));
+        assert_near(mapper.map_delta(100.0, 100.0, 15.0, -30.0), (15.0, -30.0));
+
+        // Corners of the scaled monitor land on the corners of its partner
+        assert_near(mapper.map_point(-3840.0, -720.0).unwrap(), (-1920.0, 360.0));
+        assert_near(mapper.map_point(-1.0, 1439.0).unwrap(), (-1.0, 1439.0));
+    }
+
+    #[test]
+    fn normalized_positions_span_negative_origins() {
+        let mapper = PointerMapper::new(retina_mac(), windows_100());
+        let source = mapper.source().bounds();
+        assert_eq!((source.x, source.y, source.width, source.height), (-1920.0, -1080.0, 3360.0, 1980.0));
+
+        // Top-left of the source desktop is the top-left of the external display
+        let (nx, ny) = mapper.map_normalized(0.0, 0.0).unwrap();
+        assert_near(mapper.target().denormalize(nx, ny), (-1920.0, 360.0));
+
+        // Bottom-right is the bottom-right of the built-in display
+        let (nx, ny) = mapper.map_normalized(1.0, 1.0).unwrap();
+        assert_near(mapper.target().denormalize(nx, ny), (2559.0, 1439.0));
+    }
+
+    #[test]
+    fn points_in_gaps_use_the_nearest_monitor() {
+        let mapper = PointerMapper::new(retina_mac(), windows_100());
+
+        // Below the external display and left of the built-in one, nothing is there
+        let (x, y) = mapper.map_point(-100.0, 500.0).unwrap();
+        let target = mapper.target();
+        let index = target.monitor_index_at(x, y).unwrap();
+        assert!(target.monitors[index].contains(x, y), "({}, {}) is off screen", x, y);
+    }
+
+    #[test]
+    fn extra_source_monitors_share_the_last_target() {
+        let target = ScreenInfo {
+            coordinate_space: CoordinateSpace::Physical,
+            monitors: vec![monitor(10, 0.0, 0.0, 1920.0, 1080.0, 1.0)],
+        };
+        let mapper = PointerMapper::new(retina_mac(), target);
+
+        assert_near(mapper.map_point(-1920.0, -1080.0).unwrap(), (0.0, 0.0));
+        assert_near(mapper.map_point(1439.0, 899.0).unwrap(), (1919.0, 1079.0));
+    }
+
+    #[test]
+    fn no_target_monitors_maps_nothing() {
+        let target = ScreenInfo {
+            coordinate_space: CoordinateSpace::Physical,
+            monitors: Vec::new(),
+        };
+        let mapper = PointerMapper::new(retina_mac(), target);
+
+        assert_eq!(mapper.map_point(100.0, 100.0), None);
+        assert_eq!(mapper.map_delta(100.0, 100.0, 3.0, 4.0), (3.0, 4.0));
+    }
+}
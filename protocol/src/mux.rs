@@ -0,0 +1,606 @@
+//! Channel multiplexing on a single byte stream
+//!
+//! Every message travels on one of three logical [`Channel`]s: control
+//! (connection housekeeping and feedback), input (keys, pointer, gestures) and
+//! bulk (clipboard and file data). Messages are encoded with bincode and cut
+//! into segments no larger than their channel allows. The writer always sends
+//! the next segment of the most important channel that has data waiting, so a
+//! keystroke queued during a large transfer goes out after at most one bulk
+//! segment instead of after the whole transfer.
+//!
+//! Each channel also has a flow-control window: the writer may only have that
+//! many payload bytes outstanding until the reader grants more. This keeps bulk
+//! data from filling the socket buffers, where input would otherwise queue up
+//! behind it no matter how the writer orders its segments.
+//!
+//! On the wire a segment is a 4-byte little-endian length followed by a tag
+//! byte and the payload. The tag's low bits hold the channel, [`TAG_END`]
+//! marks the last segment of a message and [`TAG_WINDOW`] a window update,
+//! whose payload is the granted byte count as a little-endian `u32`. Both
+//! sides must use the same [`MuxConfig`].
+
+use crate::validation::MAX_MESSAGE_SIZE;
+use crate::{DecodeError, EventKind, InputEvent, Message};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Size of the length prefix in bytes
+pub const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Tag bit on the last segment of a message
+pub const TAG_END: u8 = 0x80;
+
+/// Tag bit on window updates
+pub const TAG_WINDOW: u8 = 0x40;
+
+const TAG_CHANNEL_MASK: u8 = 0x0f;
+
+/// Logical channel of a message, most important first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Channel {
+    /// Connection housekeeping and feedback (ping/pong, screen info, cursor reports)
+    Control,
+    /// Keyboard, pointer and gesture events
+    Input,
+    /// Clipboard and file transfers
+    Bulk,
+}
+
+impl Channel {
+    /// All channels in the order they are served
+    pub const ALL: [Channel; 3] = [Channel::Control, Channel::Input, Channel::Bulk];
+
+    /// Channel an event is sent on
+    pub fn of(event: &InputEvent) -> Self {
+        match event {
+            InputEvent::Clipboard(_) | InputEvent::FileTransfer(_) => Channel::Bulk,
+            event if event.kind() == EventKind::Control => Channel::Control,
+            _ => Channel::Input,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Option<Self> {
+        match tag & TAG_CHANNEL_MASK {
+            0 => Some(Channel::Control),
+            1 => Some(Channel::Input),
+            2 => Some(Channel::Bulk),
+            _ => None,
+        }
+    }
+}
+
+/// Size limits and flow-control window of a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelLimits {
+    /// Largest payload of one segment
+    pub max_segment: usize,
+    /// Largest encoded message
+    pub max_message: usize,
+    /// Payload bytes the writer may send before the reader grants more
+    pub window: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MuxConfig {
+    pub control: ChannelLimits,
+    pub input: ChannelLimits,
+    pub bulk: ChannelLimits,
+}
+
+impl MuxConfig {
+    pub fn limits(&self, channel: Channel) -> &ChannelLimits {
+        match channel {
+            Channel::Control => &self.control,
+            Channel::Input => &self.input,
+            Channel::Bulk => &self.bulk,
+        }
+    }
+
+    /// Largest segment payload of any channel
+    fn max_segment(&self) -> usize {
+        Channel::ALL
+            .iter()
+            .map(|channel| self.limits(*channel).max_segment)
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+impl Default for MuxConfig {
+    fn default() -> Self {
+        Self {
+            control: ChannelLimits {
+                max_segment: 16 * 1024,
+                max_message: MAX_MESSAGE_SIZE,
+                window: 256 * 1024,
+            },
+            input: ChannelLimits {
+                max_segment: 16 * 1024,
+                max_message: 64 * 1024,
+                window: 256 * 1024,
+            },
+            // Small window: bulk bytes in flight are what input has to wait behind
+            bulk: ChannelLimits {
+                max_segment: 16 * 1024,
+                max_message: 256 * 1024,
+                window: 128 * 1024,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MuxError {
+    /// A message is larger than its channel allows
+    MessageTooLarge { channel: Channel, size: usize, limit: usize },
+    /// A segment is larger than its channel allows
+    SegmentTooLarge { channel: Channel, size: usize, limit: usize },
+    /// The peer sent more than its window allows
+    WindowExceeded(Channel),
+    /// A segment with an unknown tag or a malformed window update
+    InvalidSegment(u8),
+    /// A message could not be encoded
+    Codec(bincode::Error),
+    /// A received message is malformed or has invalid values
+    Decode(DecodeError),
+}
+
+impl fmt::Display for MuxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MuxError::MessageTooLarge { channel, size, limit } => {
+                write!(f, "{:?} message of {} bytes exceeds the limit of {}", channel, size, limit)
+            }
+            MuxError::SegmentTooLarge { channel, size, limit } => {
+                write!(f, "{:?} segment of {} bytes exceeds the limit of {}", channel, size, limit)
+            }
+            MuxError::WindowExceeded(channel) => write!(f, "Peer exceeded the {:?} window", channel),
+            MuxError::InvalidSegment(tag) => write!(f, "Invalid segment with tag {:#04x}", tag),
+            MuxError::Codec(e) => write!(f, "Cannot encode message: {}", e),
+            MuxError::Decode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MuxError {}
+
+/// Flow-control bookkeeping the reading side hands to the writing side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowUpdate {
+    /// The peer allows this many more bytes on the channel
+    Credit(Channel, u32),
+    /// Grant the peer this many more bytes on the channel
+    Grant(Channel, u32),
+}
+
+/// Message being sent, with the number of bytes already sent
+struct Outgoing {
+    data: Vec<u8>,
+    sent: usize,
+}
+
+/// Turns messages into segments, in priority order and within the windows
+pub struct MuxEncoder {
+    config: MuxConfig,
+    queues: [VecDeque<Outgoing>; 3],
+    /// Payload bytes each channel may still send
+    credit: [u64; 3],
+    /// Window updates owed to the peer
+    grants: [u32; 3],
+}
+
+impl MuxEncoder {
+    pub fn new(config: MuxConfig) -> Self {
+        let credit = Channel::ALL.map(|channel| u64::from(config.limits(channel).window));
+        Self {
+            config,
+            queues: Default::default(),
+            credit,
+            grants: [0; 3],
+        }
+    }
+
+    /// Queue a message, returns the channel it goes out on
+    pub fn push(&mut self, message: &Message) -> Result<Channel, MuxError> {
+        let channel = Channel::of(&message.event);
+        let data = message.to_bytes().map_err(MuxError::Codec)?;
+        let limit = self.config.limits(channel).max_message;
+        if data.len() > limit {
+            return Err(MuxError::MessageTooLarge {
+                channel,
+                size: data.len(),
+                limit,
+            });
+        }
+        self.queues[channel.index()].push_back(Outgoing { data, sent: 0 });
+        Ok(channel)
+    }
+
+    pub fn apply(&mut self, update: FlowUpdate) {
+        match update {
+            FlowUpdate::Credit(channel, bytes) => {
+                let credit = &mut self.credit[channel.index()];
+                *credit = credit.saturating_add(u64::from(bytes));
+            }
+            FlowUpdate::Grant(channel, bytes) => {
+                let grant = &mut self.grants[channel.index()];
+                *grant = grant.saturating_add(bytes);
+            }
+        }
+    }
+
+    /// Next segment to write, `None` if nothing may be sent right now
+    pub fn next_segment(&mut self) -> Option<Vec<u8>> {
+        // Window updates first, the peer may be waiting for them
+        for channel in Channel::ALL {
+            let grant = std::mem::take(&mut self.grants[channel.index()]);
+            if grant > 0 {
+                return Some(segment(channel as u8 | TAG_WINDOW, &grant.to_le_bytes()));
+            }
+        }
+
+        for channel in Channel::ALL {
+            let index = channel.index();
+            let credit = self.credit[index];
+            let Some(message) = self.queues[index].front_mut() else {
+                continue;
+            };
+            if credit == 0 {
+                continue;
+            }
+
+            let max_segment = self.config.limits(channel).max_segment;
+            let len = (message.data.len() - message.sent).min(max_segment).min(credit as usize);
+            let start = message.sent;
+            message.sent += len;
+            self.credit[index] -= len as u64;
+
+            let end = message.sent == message.data.len();
+            let tag = channel as u8 | if end { TAG_END } else { 0 };
+            let segment = segment(tag, &message.data[start..message.sent]);
+            if end {
+                self.queues[index].pop_front();
+            }
+            return Some(segment);
+        }
+        None
+    }
+
+    /// Bytes still queued on a channel
+    pub fn queued_bytes(&self, channel: Channel) -> usize {
+        self.queues[channel.index()]
+            .iter()
+            .map(|message| message.data.len() - message.sent)
+            .sum()
+    }
+}
+
+fn segment(tag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(LENGTH_PREFIX_SIZE + 1 + payload.len());
+    segment.extend_from_slice(&(payload.len() as u32 + 1).to_le_bytes());
+    segment.push(tag);
+    segment.extend_from_slice(payload);
+    segment
+}
+
+/// Reassembles messages from a byte stream
+///
+/// TCP may split or merge writes arbitrarily, so the reader feeds whatever it
+/// reads into the decoder and takes out complete messages. A segment that
+/// breaks a limit is consumed before the error is returned, and the rest of
+/// its message is dropped, so the stream stays in sync and decoding can
+/// continue.
+pub struct MuxDecoder {
+    config: MuxConfig,
+    buffer: Vec<u8>,
+    /// Bytes of an oversized segment still to be dropped
+    skip: usize,
+    /// Message being reassembled on each channel
+    partial: [Vec<u8>; 3],
+    /// Channels whose current message is dropped up to its last segment
+    discarding: [bool; 3],
+    /// Payload bytes the peer may still send on each channel
+    remaining: [u64; 3],
+    /// Payload bytes received since the last window update
+    unacknowledged: [u32; 3],
+    updates: Vec<FlowUpdate>,
+}
+
+impl MuxDecoder {
+    pub fn new(config: MuxConfig) -> Self {
+        let remaining = Channel::ALL.map(|channel| u64::from(config.limits(channel).window));
+        Self {
+            config,
+            buffer: Vec::new(),
+            skip: 0,
+            partial: Default::default(),
+            discarding: [false; 3],
+            remaining,
+            unacknowledged: [0; 3],
+            updates: Vec::new(),
+        }
+    }
+
+    /// Append bytes read from the stream
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Take the next complete message, if one has been received
+    pub fn next_message(&mut self) -> Result<Option<Message>, MuxError> {
+        loop {
+            if self.skip > 0 {
+                let n = self.skip.min(self.buffer.len());
+                self.buffer.drain(..n);
+                self.skip -= n;
+                if self.skip > 0 {
+                    return Ok(None);
+                }
+            }
+
+            if self.buffer.len() < LENGTH_PREFIX_SIZE {
+                return Ok(None);
+            }
+            let mut prefix = [0u8; LENGTH_PREFIX_SIZE];
+            prefix.copy_from_slice(&self.buffer[..LENGTH_PREFIX_SIZE]);
+            let length = u32::from_le_bytes(prefix) as usize;
+
+            // Never buffer a segment no channel allows
+            let limit = self.config.max_segment();
+            if length > limit + 1 {
+                // The tag tells which channel's message to drop
+                let Some(&tag) = self.buffer.get(LENGTH_PREFIX_SIZE) else {
+                    return Ok(None);
+                };
+                // Skipped even with an unknown tag, or the same error would come back forever
+                self.skip = LENGTH_PREFIX_SIZE + length;
+                let channel = Channel::from_tag(tag).ok_or(MuxError::InvalidSegment(tag))?;
+                self.discard(channel, tag & TAG_END != 0);
+                return Err(MuxError::SegmentTooLarge {
+                    channel,
+                    size: length - 1,
+                    limit,
+                });
+            }
+
+            let frame_end = LENGTH_PREFIX_SIZE + length;
+            if self.buffer.len() < frame_end {
+                return Ok(None);
+            }
+            let frame: Vec<u8> = self.buffer.drain(..frame_end).skip(LENGTH_PREFIX_SIZE).collect();
+            if let Some(message) = self.segment(&frame)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// Flow-control updates collected while decoding, for the writing side
+    pub fn take_flow_updates(&mut self) -> Vec<FlowUpdate> {
+        std::mem::take(&mut self.updates)
+    }
+
+    fn segment(&mut self, frame: &[u8]) -> Result<Option<Message>, MuxError> {
+        let (&tag, payload) = frame.split_first().ok_or(MuxError::InvalidSegment(0))?;
+        let channel = Channel::from_tag(tag).ok_or(MuxError::InvalidSegment(tag))?;
+        let index = channel.index();
+
+        if tag & TAG_WINDOW != 0 {
+            let bytes = payload.try_into().map_err(|_| MuxError::InvalidSegment(tag))?;
+            self.updates.push(FlowUpdate::Credit(channel, u32::from_le_bytes(bytes)));
+            return Ok(None);
+        }
+
+        let limits = *self.config.limits(channel);
+        let end = tag & TAG_END != 0;
+        if payload.len() as u64 > self.remaining[index] {
+            self.discard(channel, end);
+            return Err(MuxError::WindowExceeded(channel));
+        }
+        self.remaining[index] -= payload.len() as u64;
+        self.unacknowledged[index] += payload.len() as u32;
+        if self.unacknowledged[index] >= limits.window / 2 {
+            let grant = std::mem::take(&mut self.unacknowledged[index]);
+            self.remaining[index] += u64::from(grant);
+            self.updates.push(FlowUpdate::Grant(channel, grant));
+        }
+
+        if self.discarding[index] {
+            self.discarding[index] = !end;
+            return Ok(None);
+        }
+        if payload.len() > limits.max_segment {
+            self.discard(channel, end);
+            return Err(MuxError::SegmentTooLarge {
+                channel,
+                size: payload.len(),
+                limit: limits.max_segment,
+            });
+        }
+        let size = self.partial[index].len() + payload.len();
+        if size > limits.max_message {
+            self.discard(channel, end);
+            return Err(MuxError::MessageTooLarge {
+                channel,
+                size,
+                limit: limits.max_message,
+            });
+        }
+
+        self.partial[index].extend_from_slice(payload);
+        if !end {
+            return Ok(None);
+        }
+        let data = std::mem::take(&mut self.partial[index]);
+        Message::from_bytes(&data).map(Some).map_err(MuxError::Decode)
+    }
+
+    /// Drop the message in progress on a channel, up to its last segment
+    fn discard(&mut self, channel: Channel, end: bool) {
+        self.partial[channel.index()].clear();
+        self.discarding[channel.index()] = !end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard::ClipboardChunk;
+    use crate::{KeyboardEvent, Modifiers};
+
+    /// Clipboard transfer of `count` chunks
+    fn bulk(count: u32, chunk_size: usize) -> Vec<Message> {
+        (0..count)
+            .map(|index| {
+                Message::new(
+                    u64::from(index),
+                    InputEvent::Clipboard(ClipboardChunk {
+                        transfer_id: 1,
+                        index,
+                        count,
+                        data: vec![index as u8; chunk_size],
+                    }),
+                )
+            })
+            .collect()
+    }
+
+    fn key(sequence: u64) -> Message {
+        Message::new(
+            sequence,
+            InputEvent::Keyboard(KeyboardEvent {
+                key_code: 0x00,
+                character: Some('a'),
+                pressed: true,
+                modifiers: Modifiers::default(),
+                is_repeat: false,
+            }),
+        )
+    }
+
+    /// Channel of a data segment, `None` for window updates
+    fn data_channel(segment: &[u8]) -> Option<Channel> {
+        let tag = segment[LENGTH_PREFIX_SIZE];
+        (tag & TAG_WINDOW == 0).then(|| Channel::from_tag(tag).unwrap())
+    }
+
+    /// Both directions of a connection: `sender` writes to `receiver`, whose
+    /// window updates travel back
+    struct Link {
+        sender: MuxEncoder,
+        sender_decoder: MuxDecoder,
+        receiver: MuxDecoder,
+        receiver_encoder: MuxEncoder,
+    }
+
+    impl Link {
+        fn new() -> Self {
+            Self {
+                sender: MuxEncoder::new(MuxConfig::default()),
+                sender_decoder: MuxDecoder::new(MuxConfig::default()),
+                receiver: MuxDecoder::new(MuxConfig::default()),
+                receiver_encoder: MuxEncoder::new(MuxConfig::default()),
+            }
+        }
+
+        /// Deliver one segment, with the messages it completes and its channel
+        fn step(&mut self) -> Option<(Option<Channel>, Vec<Message>)> {
+            let segment = match self.sender.next_segment() {
+                Some(segment) => segment,
+                None => {
+                    self.return_windows();
+                    self.sender.next_segment()?
+                }
+            };
+
+            self.receiver.extend(&segment);
+            let mut messages = Vec::new();
+            while let Some(message) = self.receiver.next_message().unwrap() {
+                messages.push(message);
+            }
+            Some((data_channel(&segment), messages))
+        }
+
+        /// Send the receiver's window updates back to the sender
+        fn return_windows(&mut self) {
+            for update in self.receiver.take_flow_updates() {
+                self.receiver_encoder.apply(update);
+            }
+            while let Some(segment) = self.receiver_encoder.next_segment() {
+                self.sender_decoder.extend(&segment);
+                while self.sender_decoder.next_message().unwrap().is_some() {}
+            }
+            for update in self.sender_decoder.take_flow_updates() {
+                self.sender.apply(update);
+            }
+        }
+    }
+
+    #[test]
+    fn input_overtakes_a_large_transfer() {
+        let mut link = Link::new();
+        // 16 chunks of 200 KiB, several windows worth of bulk data
+        let transfer = bulk(16, 200 * 1024);
+        for message in &transfer {
+            assert_eq!(link.sender.push(message).unwrap(), Channel::Bulk);
+        }
+
+        let mut received_bulk = 0;
+        let mut keys_sent = 0;
+        let mut keys_received = 0;
+        let mut step = 0;
+        while let Some((_, messages)) = link.step() {
+            received_bulk += messages.len();
+            step += 1;
+
+            // A key press every 13 segments, at varying offsets into the chunks
+            if step % 13 != 0 {
+                continue;
+            }
+            keys_sent += 1;
+            link.sender.push(&key(1000 + keys_sent)).unwrap();
+
+            let mut bulk_segments = 0;
+            loop {
+                let (channel, messages) = link.step().expect("the key press is sent");
+                if channel == Some(Channel::Bulk) {
+                    bulk_segments += 1;
+                }
+                let key_arrived = messages.iter().any(|message| matches!(message.event, InputEvent::Keyboard(_)));
+                received_bulk += messages.len() - usize::from(key_arrived);
+                if key_arrived {
+                    keys_received += 1;
+                    break;
+                }
+            }
+            assert!(bulk_segments <= 1, "{} bulk segments went out before the key press", bulk_segments);
+        }
+
+        assert!(keys_sent > 10);
+        assert_eq!(keys_received, keys_sent);
+        assert_eq!(received_bulk, transfer.len());
+        assert_eq!(link.sender.queued_bytes(Channel::Bulk), 0);
+    }
+
+    #[test]
+    fn bulk_waits_for_window_updates() {
+        let mut encoder = MuxEncoder::new(MuxConfig::default());
+        for message in bulk(4, 200 * 1024) {
+            encoder.push(&message).unwrap();
+        }
+
+        let mut sent = 0;
+        while let Some(segment) = encoder.next_segment() {
+            sent += segment.len() - LENGTH_PREFIX_SIZE - 1;
+        }
+        assert_eq!(sent, MuxConfig::default().bulk.window as usize);
+
+        // Input still goes out while bulk is stalled
+        encoder.push(&key(1)).unwrap();
+        let segment = encoder.next_segment().unwrap();
+        assert_eq!(data_channel(&segment), Some(Channel::Input));
+    }
+}
@@ -0,0 +1,579 @@
+//! Device pairing with a PIN
+//!
+//! The server shows a short PIN; the user types it into the client. Both
+//! sides then run SPAKE2 (RFC 9382) over edwards25519 with the PIN as the
+//! password. An eavesdropper learns nothing about the PIN, and an active
+//! attacker gets a single guess per attempt. Both sides derive the same
+//! 32-byte pairing key from the exchange and store it with the client's id.
+//!
+//! Later connections prove knowledge of the pairing key in a mutual
+//! challenge-response instead of using the PIN again.
+//!
+//! The handshake runs before the multiplexed message stream starts (see
+//! [`crate::mux`]), as length-prefixed bincode frames. When the connection is
+//! encrypted, TLS exporter bytes are mixed into every proof, so a
+//! man-in-the-middle cannot relay a handshake into a connection of its own.
+
+use crate::validation::decode_bounded;
+use curve25519_dalek::constants::ED25519_BASEPOINT_POINT;
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+
+/// Digits of the PIN the server shows
+pub const PIN_DIGITS: usize = 6;
+
+/// Largest handshake frame either side accepts
+pub const MAX_HANDSHAKE_FRAME: usize = 1024;
+
+/// TLS exporter label for the channel binding
+pub const TLS_EXPORTER_LABEL: &[u8] = b"EXPORTER-samesame-pairing";
+
+/// Identifies a paired client on the server
+pub type ClientId = [u8; 16];
+
+/// Long-term secret shared by a client and a server after pairing
+pub type PairingKey = [u8; 32];
+
+/// Random challenge of the challenge-response
+pub type Nonce = [u8; 32];
+
+/// SPAKE2 message or proof
+pub type Tag = [u8; 32];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// First handshake frame, client → server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ClientHello {
+    /// No pairing; only servers that do not require pairing accept it
+    Anonymous,
+    /// Authenticate with an earlier pairing
+    Paired { client_id: ClientId, nonce: Nonce },
+    /// Pair now, with the SPAKE2 message for the PIN the user entered
+    Pair { name: String, message: Tag },
+}
+
+/// Server answer to [`ClientHello`] and [`ClientFinish`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ServerHello {
+    /// Authenticated, the message stream starts
+    Accepted,
+    /// Refused, the server closes the connection
+    Rejected { reason: String },
+    /// Proof that the server knows the pairing key, with a challenge for the client
+    Challenge { nonce: Nonce, proof: Tag },
+    /// SPAKE2 answer with the server's key confirmation and the id it assigned
+    PairReply {
+        message: Tag,
+        confirmation: Tag,
+        client_id: ClientId,
+    },
+}
+
+/// Client's proof, answering [`ServerHello::Challenge`] or [`ServerHello::PairReply`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientFinish {
+    pub proof: Tag,
+}
+
+/// Errors of the pairing handshake
+#[derive(Debug)]
+pub enum PairingError {
+    /// The peer's SPAKE2 message is not a usable point
+    InvalidMessage,
+    /// Key confirmation failed: the PIN was wrong or someone interfered
+    WrongPin,
+    /// A challenge-response proof did not match the pairing key
+    InvalidProof,
+    /// A frame exceeds [`MAX_HANDSHAKE_FRAME`]
+    FrameTooLarge(usize),
+    /// A frame could not be encoded or decoded
+    Codec(bincode::Error),
+    /// The operating system could not provide random bytes
+    Random(getrandom::Error),
+}
+
+impl fmt::Display for PairingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PairingError::InvalidMessage => write!(f, "Invalid pairing message"),
+            PairingError::WrongPin => write!(f, "Wrong PIN"),
+            PairingError::InvalidProof => write!(f, "Pairing key does not match"),
+            PairingError::FrameTooLarge(size) => {
+                write!(f, "Handshake frame of {} bytes exceeds the limit of {}", size, MAX_HANDSHAKE_FRAME)
+            }
+            PairingError::Codec(e) => write!(f, "Invalid handshake frame: {}", e),
+            PairingError::Random(e) => write!(f, "No random numbers: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PairingError {}
+
+/// Encode a handshake frame: `u32` little-endian length, then the bincode body
+pub fn encode_frame<T: Serialize>(message: &T) -> Result<Vec<u8>, PairingError> {
+    let body = bincode::serialize(message).map_err(PairingError::Codec)?;
+    if body.len() > MAX_HANDSHAKE_FRAME {
+        return Err(PairingError::FrameTooLarge(body.len()));
+    }
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&body);
+    Ok(frame)
+}
+
+/// Check the length prefix of a frame before reading its body
+pub fn frame_length(prefix: [u8; 4]) -> Result<usize, PairingError> {
+    let length = u32::from_le_bytes(prefix) as usize;
+    if length > MAX_HANDSHAKE_FRAME {
+        return Err(PairingError::FrameTooLarge(length));
+    }
+    Ok(length)
+}
+
+/// Decode the body of a handshake frame
+pub fn decode_frame<T: DeserializeOwned>(body: &[u8]) -> Result<T, PairingError> {
+    decode_bounded(body, MAX_HANDSHAKE_FRAME).map_err(PairingError::Codec)
+}
+
+/// Bytes from the operating system's random number generator
+pub fn random_bytes<const N: usize>() -> Result<[u8; N], PairingError> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).map_err(PairingError::Random)?;
+    Ok(bytes)
+}
+
+/// A new uniformly random PIN of [`PIN_DIGITS`] digits
+pub fn generate_pin() -> Result<String, PairingError> {
+    let limit = 10u64.pow(PIN_DIGITS as u32);
+    // Rejection sampling keeps every PIN equally likely
+    loop {
+        let value = u64::from_le_bytes(random_bytes()?);
+        if value < u64::MAX - u64::MAX % limit {
+            return Ok(format!("{:0width$}", value % limit, width = PIN_DIGITS));
+        }
+    }
+}
+
+/// Side of the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+impl Role {
+    fn label(self) -> &'static [u8] {
+        match self {
+            Role::Client => b"samesame client",
+            Role::Server => b"samesame server",
+        }
+    }
+}
+
+/// One side of a SPAKE2 exchange
+///
+/// The client plays party A, the server party B.
+pub struct Spake2 {
+    role: Role,
+    password: Scalar,
+    secret: Scalar,
+    message: EdwardsPoint,
+}
+
+/// Keys both sides derive from a SPAKE2 exchange
+pub struct PairingKeys {
+    /// Long-term key the pairing is stored with
+    pub key: PairingKey,
+    client_confirmation: [u8; 16],
+    server_confirmation: [u8; 16],
+    transcript: [u8; 32],
+}
+
+impl Spake2 {
+    /// Start an exchange for `pin`
+    pub fn start(role: Role, pin: &str) -> Result<Self, PairingError> {
+        Ok(Self::with_secret(role, pin, Scalar::from_bytes_mod_order_wide(&random_bytes()?)))
+    }
+
+    /// Start an exchange with a given ephemeral secret, for known-answer tests
+    fn with_secret(role: Role, pin: &str, secret: Scalar) -> Self {
+        let password = Scalar::from_bytes_mod_order_wide(
+            &Sha512::new()
+                .chain_update(b"samesame pin")
+                .chain_update(pin.trim())
+                .finalize()
+                .into(),
+        );
+        let blind = match role {
+            Role::Client => point_m(),
+            Role::Server => point_n(),
+        };
+        let message = EdwardsPoint::mul_base(&secret) + blind * password;
+        Self {
+            role,
+            password,
+            secret,
+            message,
+        }
+    }
+
+    /// Message to send to the peer
+    pub fn message(&self) -> Tag {
+        self.message.compress().to_bytes()
+    }
+
+    /// Combine with the peer's message
+    ///
+    /// Succeeds even with a wrong PIN; the key confirmation in
+    /// [`PairingKeys`] is what detects a mismatch.
+    pub fn finish(self, peer_message: &Tag, binding: &[u8]) -> Result<PairingKeys, PairingError> {
+        let peer = CompressedEdwardsY(*peer_message)
+            .decompress()
+            .filter(|point| !point.is_small_order())
+            .ok_or(PairingError::InvalidMessage)?;
+        let peer_blind = match self.role {
+            Role::Client => point_n(),
+            Role::Server => point_m(),
+        };
+        let shared = ((peer - peer_blind * self.password) * self.secret).mul_by_cofactor();
+        if shared.is_identity() {
+            return Err(PairingError::InvalidMessage);
+        }
+
+        let (client_message, server_message) = match self.role {
+            Role::Client => (self.message.compress(), peer.compress()),
+            Role::Server => (peer.compress(), self.message.compress()),
+        };
+        let mut transcript = Sha256::new();
+        for field in [
+            Role::Client.label(),
+            Role::Server.label(),
+            client_message.as_bytes(),
+            server_message.as_bytes(),
+            shared.compress().as_bytes(),
+            self.password.as_bytes(),
+            binding,
+        ] {
+            transcript.update((field.len() as u64).to_le_bytes());
+            transcript.update(field);
+        }
+        let transcript: [u8; 32] = transcript.finalize().into();
+
+        // Ke || Ka as in RFC 9382, with the long-term key derived from Ke
+        let (encryption_key, authentication_key) = transcript.split_at(16);
+        let mut key = [0u8; 32];
+        expand(encryption_key, b"samesame pairing key", &mut key);
+        let mut confirmation = [0u8; 32];
+        expand(authentication_key, b"ConfirmationKeys", &mut confirmation);
+        let mut client_confirmation = [0u8; 16];
+        let mut server_confirmation = [0u8; 16];
+        client_confirmation.copy_from_slice(&confirmation[..16]);
+        server_confirmation.copy_from_slice(&confirmation[16..]);
+
+        Ok(PairingKeys {
+            key,
+            client_confirmation,
+            server_confirmation,
+            transcript,
+        })
+    }
+}
+
+impl PairingKeys {
+    /// Key confirmation sent by `role`
+    pub fn confirmation(&self, role: Role) -> Tag {
+        self.confirmation_mac(role).finalize().into_bytes().into()
+    }
+
+    /// Check the peer's key confirmation in constant time
+    pub fn verify(&self, role: Role, confirmation: &Tag) -> Result<(), PairingError> {
+        self.confirmation_mac(role)
+            .verify_slice(confirmation)
+            .map_err(|_| PairingError::WrongPin)
+    }
+
+    fn confirmation_mac(&self, role: Role) -> HmacSha256 {
+        let key = match role {
+            Role::Client => &self.client_confirmation,
+            Role::Server => &self.server_confirmation,
+        };
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(&self.transcript);
+        mac
+    }
+}
+
+/// Proof of the pairing key in the challenge-response of a paired connection
+pub fn challenge_proof(
+    key: &PairingKey,
+    role: Role,
+    client_id: &ClientId,
+    client_nonce: &Nonce,
+    server_nonce: &Nonce,
+    binding: &[u8],
+) -> Tag {
+    challenge_mac(key, role, client_id, client_nonce, server_nonce, binding)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+/// Check a challenge-response proof in constant time
+pub fn verify_challenge_proof(
+    key: &PairingKey,
+    role: Role,
+    client_id: &ClientId,
+    client_nonce: &Nonce,
+    server_nonce: &Nonce,
+    binding: &[u8],
+    proof: &Tag,
+) -> Result<(), PairingError> {
+    challenge_mac(key, role, client_id, client_nonce, server_nonce, binding)
+        .verify_slice(proof)
+        .map_err(|_| PairingError::InvalidProof)
+}
+
+fn challenge_mac(
+    key: &PairingKey,
+    role: Role,
+    client_id: &ClientId,
+    client_nonce: &Nonce,
+    server_nonce: &Nonce,
+    binding: &[u8],
+) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(role.label());
+    mac.update(client_id);
+    mac.update(client_nonce);
+    mac.update(server_nonce);
+    mac.update(&(binding.len() as u64).to_le_bytes());
+    mac.update(binding);
+    mac
+}
+
+fn expand(secret: &[u8], info: &[u8], output: &mut [u8]) {
+    Hkdf::<Sha256>::new(None, secret)
+        .expand(info, output)
+        .expect("output fits HKDF-SHA256");
+}
+
+/// SPAKE2 blinding point of the client
+fn point_m() -> EdwardsPoint {
+    hash_to_point(b"samesame SPAKE2 M")
+}
+
+/// SPAKE2 blinding point of the server
+fn point_n() -> EdwardsPoint {
+    hash_to_point(b"samesame SPAKE2 N")
+}
+
+/// Point with a discrete logarithm nobody knows
+///
+/// Hashes the seed with a counter until the hash decodes as a point, then
+/// clears the cofactor. Security of SPAKE2 rests on nobody knowing how M and N
+/// relate to the base point, which a hash guarantees.
+fn hash_to_point(seed: &[u8]) -> EdwardsPoint {
+    (0u32..)
+        .find_map(|counter| {
+            let hash: [u8; 32] = Sha256::new().chain_update(seed).chain_update(counter.to_le_bytes()).finalize().into();
+            CompressedEdwardsY(hash)
+                .decompress()
+                .map(|point| point.mul_by_cofactor())
+                .filter(|point| !point.is_identity() && *point != ED25519_BASEPOINT_POINT)
+        })
+        .expect("a hash decodes as a point within a few tries")
+}
+
+/// Byte arrays as lowercase hex, for ids and keys in JSON files
+///
+/// Use with `#[serde(with = "samesame_protocol::pairing::hex")]`.
+pub mod hex {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn encode(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn decode<const N: usize>(text: &str) -> Option<[u8; N]> {
+        if text.len() != 2 * N || !text.is_ascii() {
+            return None;
+        }
+        let mut bytes = [0u8; N];
+        for (byte, pair) in bytes.iter_mut().zip(text.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(bytes)
+    }
+
+    /// Bytes of any length, `None` if `text` is not hex
+    pub fn decode_vec(text: &str) -> Option<Vec<u8>> {
+        if !text.len().is_multiple_of(2) || !text.is_ascii() {
+            return None;
+        }
+        text.as_bytes()
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+            .collect()
+    }
+
+    pub fn serialize<S: Serializer, const N: usize>(bytes: &[u8; N], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error> {
+        let text = String::deserialize(deserializer)?;
+        decode(&text).ok_or_else(|| de::Error::custom(format!("expected {} hex digits", 2 * N)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINDING: &[u8] = b"binding";
+
+    fn secret(byte: u8) -> Scalar {
+        Scalar::from_bytes_mod_order([byte; 32])
+    }
+
+    fn decode(text: &str) -> [u8; 32] {
+        hex::decode(text).unwrap()
+    }
+
+    /// Both sides of an exchange with random secrets
+    fn exchange(client_pin: &str, server_pin: &str, client_binding: &[u8], server_binding: &[u8]) -> (PairingKeys, PairingKeys) {
+        let client = Spake2::start(Role::Client, client_pin).unwrap();
+        let server = Spake2::start(Role::Server, server_pin).unwrap();
+        let (client_message, server_message) = (client.message(), server.message());
+        (
+            client.finish(&server_message, client_binding).unwrap(),
+            server.finish(&client_message, server_binding).unwrap(),
+        )
+    }
+
+    // Known answers: a change to any of these breaks pairing with older peers
+    #[test]
+    fn blinding_points_are_fixed() {
+        assert_eq!(
+            hex::encode(point_m().compress().as_bytes()),
+            "5cb154f5e84d64577412342ac65f2e5a638e1c35a9e384ab3818bfb4f3157456"
+        );
+        assert_eq!(
+            hex::encode(point_n().compress().as_bytes()),
+            "30a9fd0ebf8d046ba97a4eb06d648e20c24ddd68895e528f30ac83d449fee230"
+        );
+        assert_ne!(point_m(), point_n());
+    }
+
+    #[test]
+    fn exchange_matches_known_answers() {
+        let client = Spake2::with_secret(Role::Client, "123456", secret(1));
+        let server = Spake2::with_secret(Role::Server, "123456", secret(2));
+        let (client_message, server_message) = (client.message(), server.message());
+        assert_eq!(
+            hex::encode(&client_message),
+            "f64c03fd0f17b2da15171082f49292c461c8548952aab06de80ae4e41c62df8e"
+        );
+        assert_eq!(
+            hex::encode(&server_message),
+            "7ce3e803f1deae4a421111e03f6123b80d595bc4e803f0ae93aac7243c1f7676"
+        );
+
+        let client_keys = client.finish(&server_message, BINDING).unwrap();
+        let server_keys = server.finish(&client_message, BINDING).unwrap();
+        let key = "1bd01f5bbdeb4b3796c0327ade312b5302adc5d4437e9e15292afd5f7f7dabab";
+        assert_eq!(hex::encode(&client_keys.key), key);
+        assert_eq!(hex::encode(&server_keys.key), key);
+        assert_eq!(
+            client_keys.confirmation(Role::Client),
+            decode("4660d8354ee686087dd681fd97534bc8b07dbd0773a93e337afe052870785bce")
+        );
+        assert_eq!(
+            server_keys.confirmation(Role::Server),
+            decode("5ff15d36b0d185a5f66d8ed967191e63b84b75e53c989edde4cd0041da6aabef")
+        );
+    }
+
+    #[test]
+    fn challenge_proof_matches_known_answer() {
+        let proof = challenge_proof(&[7; 32], Role::Server, &[1; 16], &[2; 32], &[3; 32], BINDING);
+        assert_eq!(proof, decode("d68acbf5c3cddbde8c2a04900f5fb799431b6fd703aec0f74d1a8f16dc2a3567"));
+    }
+
+    #[test]
+    fn same_pin_derives_the_same_key() {
+        let (client, server) = exchange("042917", "042917", BINDING, BINDING);
+        assert_eq!(client.key, server.key);
+        server.verify(Role::Client, &client.confirmation(Role::Client)).unwrap();
+        client.verify(Role::Server, &server.confirmation(Role::Server)).unwrap();
+
+        // Each side's confirmation only counts for its own role
+        assert!(server.verify(Role::Client, &server.confirmation(Role::Server)).is_err());
+    }
+
+    #[test]
+    fn surrounding_whitespace_of_the_pin_is_ignored() {
+        let (client, server) = exchange(" 042917\n", "042917", BINDING, BINDING);
+        assert_eq!(client.key, server.key);
+    }
+
+    #[test]
+    fn wrong_pin_fails_key_confirmation() {
+        let (client, server) = exchange("042918", "042917", BINDING, BINDING);
+        assert_ne!(client.key, server.key);
+        assert!(matches!(
+            server.verify(Role::Client, &client.confirmation(Role::Client)),
+            Err(PairingError::WrongPin)
+        ));
+        assert!(matches!(
+            client.verify(Role::Server, &server.confirmation(Role::Server)),
+            Err(PairingError::WrongPin)
+        ));
+    }
+
+    #[test]
+    fn different_channel_binding_fails_key_confirmation() {
+        let (client, server) = exchange("042917", "042917", b"one connection", b"another connection");
+        assert_ne!(client.key, server.key);
+        assert!(server.verify(Role::Client, &client.confirmation(Role::Client)).is_err());
+    }
+
+    #[test]
+    fn invalid_peer_messages_are_rejected() {
+        // The identity, and the point of order 2 (y = -1)
+        let identity = decode("0100000000000000000000000000000000000000000000000000000000000000");
+        let order_two = decode("ecffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f");
+        for message in [identity, order_two] {
+            let server = Spake2::start(Role::Server, "042917").unwrap();
+            assert!(matches!(server.finish(&message, BINDING), Err(PairingError::InvalidMessage)));
+        }
+    }
+
+    #[test]
+    fn challenge_proof_depends_on_every_input() {
+        let proof = challenge_proof(&[7; 32], Role::Client, &[1; 16], &[2; 32], &[3; 32], BINDING);
+        verify_challenge_proof(&[7; 32], Role::Client, &[1; 16], &[2; 32], &[3; 32], BINDING, &proof).unwrap();
+
+        assert!(verify_challenge_proof(&[8; 32], Role::Client, &[1; 16], &[2; 32], &[3; 32], BINDING, &proof).is_err());
+        assert!(verify_challenge_proof(&[7; 32], Role::Server, &[1; 16], &[2; 32], &[3; 32], BINDING, &proof).is_err());
+        assert!(verify_challenge_proof(&[7; 32], Role::Client, &[1; 16], &[9; 32], &[3; 32], BINDING, &proof).is_err());
+        assert!(verify_challenge_proof(&[7; 32], Role::Client, &[1; 16], &[2; 32], &[3; 32], b"other", &proof).is_err());
+    }
+
+    #[test]
+    fn pins_have_the_configured_digits() {
+        for _ in 0..100 {
+            let pin = generate_pin().unwrap();
+            assert_eq!(pin.len(), PIN_DIGITS);
+            assert!(pin.bytes().all(|byte| byte.is_ascii_digit()));
+        }
+    }
+}
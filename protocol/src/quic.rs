@@ -0,0 +1,95 @@
+//! Pieces of the QUIC transport both sides need
+//!
+//! Over QUIC a connection carries two bidirectional streams. The client opens
+//! the main stream first and the bulk stream second, and writes
+//! [`BULK_STREAM_MARKER`] on the bulk stream right away, so the server sees
+//! both before the pairing handshake starts on the main stream. After the
+//! handshake the mux runs on top of both: segments of bulk messages go on the
+//! bulk stream, everything else (window updates included) on the main stream.
+//! QUIC retransmits each stream on its own, so a lost bulk packet no longer
+//! holds up input the way it does on TCP.
+//!
+//! The readers take whole segments from each stream and interleave them, so
+//! the [`MuxDecoder`](crate::MuxDecoder) sees the same byte stream it would
+//! see on TCP. Pointer moves and scroll deltas go as QUIC datagrams, encoded
+//! with [`encode_pointer`](crate::datagram::encode_pointer); QUIC already
+//! encrypts and authenticates them.
+
+use crate::mux::{Channel, MuxError, LENGTH_PREFIX_SIZE, TAG_WINDOW};
+use std::time::Duration;
+
+/// Application protocol negotiated in the TLS handshake
+pub const ALPN: &[u8] = b"samesame/1";
+
+/// First byte the client writes on the bulk stream
+pub const BULK_STREAM_MARKER: u8 = 0xB5;
+
+/// How often the client pings an idle connection, keeping NAT mappings open
+/// and noticing a changed network quickly
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A connection without any packet for this long is closed
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest segment payload accepted from a stream
+///
+/// Larger than any segment a [`MuxEncoder`](crate::MuxEncoder) writes with
+/// the default limits.
+pub const MAX_SEGMENT: usize = 64 * 1024;
+
+/// One of the two streams of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuicStream {
+    Main,
+    Bulk,
+}
+
+/// Stream a mux segment is sent on
+pub fn stream_of(segment: &[u8]) -> QuicStream {
+    match segment.get(LENGTH_PREFIX_SIZE) {
+        Some(&tag) if tag & TAG_WINDOW == 0 && Channel::from_tag(tag) == Some(Channel::Bulk) => QuicStream::Bulk,
+        _ => QuicStream::Main,
+    }
+}
+
+/// Cuts a byte stream of mux segments into whole segments
+///
+/// Used on both ends of a stream: to route what the mux writes, and to hand
+/// the reader whole segments only, so segments from two streams never mix.
+#[derive(Debug, Default)]
+pub struct SegmentSplitter {
+    buffer: Vec<u8>,
+}
+
+impl SegmentSplitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Take the next complete segment, length prefix and tag included
+    pub fn next_segment(&mut self) -> Result<Option<Vec<u8>>, MuxError> {
+        let Some(prefix) = self.buffer.get(..LENGTH_PREFIX_SIZE) else {
+            return Ok(None);
+        };
+        let length = u32::from_le_bytes(prefix.try_into().expect("prefix has four bytes")) as usize;
+        if length == 0 {
+            return Err(MuxError::InvalidSegment(0));
+        }
+        if length > MAX_SEGMENT + 1 {
+            // The stream cannot be resynchronized, the caller closes it
+            let tag = self.buffer.get(LENGTH_PREFIX_SIZE).copied().unwrap_or_default();
+            return Err(MuxError::InvalidSegment(tag));
+        }
+
+        let end = LENGTH_PREFIX_SIZE + length;
+        if self.buffer.len() < end {
+            return Ok(None);
+        }
+        let rest = self.buffer.split_off(end);
+        Ok(Some(std::mem::replace(&mut self.buffer, rest)))
+    }
+}
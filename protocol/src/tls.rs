@@ -0,0 +1,20 @@
+//! Pieces of the TLS transport both sides need
+//!
+//! Servers use self-signed certificates, so there is no authority vouching
+//! for them. Instead the client pins the certificate's fingerprint the first
+//! time it connects, and the user can compare it with the one the server
+//! prints.
+
+use sha2::{Digest, Sha256};
+
+/// Fingerprint of a DER-encoded certificate, as shown to users
+///
+/// SHA-256 as colon-separated uppercase hex, the format browsers and
+/// `openssl x509 -fingerprint -sha256` print.
+pub fn certificate_fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
@@ -0,0 +1,138 @@
+//! File transfer
+//!
+//! The sender offers a file with its size and SHA-256 hash. The receiver
+//! answers with the offset to start from: zero for a new file, or the length
+//! of a partial file left by an interrupted transfer of the same content, so a
+//! transfer resumes after a reconnect. Chunks then stream in order, and the
+//! receiver acknowledges progress so the sender never has more than
+//! [`SEND_WINDOW`] bytes in flight. Once all bytes have arrived the receiver
+//! checks the hash and reports where the file was stored.
+//!
+//! File data travels on the bulk channel (see [`crate::mux`]), so a transfer
+//! never delays keystrokes.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// File bytes per chunk
+pub const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Bytes the receiver may let arrive before it acknowledges them
+pub const ACK_INTERVAL: u64 = 1024 * 1024;
+
+/// Unacknowledged bytes the sender may have in flight
+pub const SEND_WINDOW: u64 = 4 * ACK_INTERVAL;
+
+/// SHA-256 of the file content
+pub type FileHash = [u8; 32];
+
+/// Messages of a file transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FileTransfer {
+    /// A file the sender wants to transfer (client → server)
+    Offer(FileOffer),
+    /// Accepted; the sender continues at `offset` (server → client)
+    Accept { transfer_id: u64, offset: u64 },
+    /// Refused by the receiver's policy (server → client)
+    Reject { transfer_id: u64, reason: String },
+    /// File content (client → server)
+    Chunk(FileChunk),
+    /// Bytes received so far (server → client)
+    Progress { transfer_id: u64, received: u64 },
+    /// Stored and verified at `path` (server → client)
+    Complete { transfer_id: u64, path: String },
+    /// Failed on the receiving side, e.g. hash mismatch or disk error (server → client)
+    Failed { transfer_id: u64, reason: String },
+    /// The sender gives up; the partial file is kept for a later resume
+    Cancel { transfer_id: u64 },
+}
+
+impl FileTransfer {
+    pub fn transfer_id(&self) -> u64 {
+        match self {
+            FileTransfer::Offer(offer) => offer.transfer_id,
+            FileTransfer::Chunk(chunk) => chunk.transfer_id,
+            FileTransfer::Accept { transfer_id, .. }
+            | FileTransfer::Reject { transfer_id, .. }
+            | FileTransfer::Progress { transfer_id, .. }
+            | FileTransfer::Complete { transfer_id, .. }
+            | FileTransfer::Failed { transfer_id, .. }
+            | FileTransfer::Cancel { transfer_id } => *transfer_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileOffer {
+    /// Identifies the transfer on this connection, chosen by the sender
+    pub transfer_id: u64,
+    /// File name without any directory
+    pub name: String,
+    pub size: u64,
+    pub sha256: FileHash,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FileChunk {
+    pub transfer_id: u64,
+    /// Position of the data in the file
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+// Logged with every message; the payload would flood the log
+impl fmt::Debug for FileChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileChunk")
+            .field("transfer_id", &self.transfer_id)
+            .field("offset", &self.offset)
+            .field("bytes", &self.data.len())
+            .finish()
+    }
+}
+
+/// SHA-256 of everything `reader` returns
+pub fn sha256_reader(mut reader: impl Read) -> io::Result<FileHash> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; FILE_CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// SHA-256 of a file
+pub fn sha256_file(path: impl AsRef<Path>) -> io::Result<FileHash> {
+    sha256_reader(File::open(path)?)
+}
+
+/// Incremental SHA-256 for data that arrives in pieces
+#[derive(Clone, Default)]
+pub struct FileHasher(Sha256);
+
+impl FileHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> FileHash {
+        self.0.finalize().into()
+    }
+}
+
+/// Hash as lowercase hex, for file names and logs
+pub fn hash_hex(hash: &FileHash) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
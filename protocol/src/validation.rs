@@ -0,0 +1,330 @@
+//! Bounded decoding and validation of messages from the peer
+//!
+//! Everything received is untrusted. Decoding never reads more than an
+//! explicit limit, and a decoded [`InputEvent`] is checked before it
+//! is used: floats must be finite, normalized coordinates within `0.0..=1.0`,
+//! deltas and scales of a plausible magnitude and key codes in the macOS
+//! range. A message that fails is rejected with a [`DecodeError`] that says
+//! which field was wrong.
+//!
+//! The wire format is the one of `bincode::serialize` (fixed-size integers,
+//! little endian), so bounded decoding reads what either side writes.
+
+use crate::mapping::ScreenInfo;
+use crate::{GestureType, InputEvent};
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+/// Largest encoded message accepted from the peer
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Highest macOS virtual key code
+pub const MAX_KEY_CODE: u16 = 0x7F;
+
+/// Largest pointer movement of one event, in desktop pixels
+pub const MAX_POINTER_DELTA: f64 = 10_000.0;
+
+/// Largest scroll delta of one event, in pixels or lines
+pub const MAX_SCROLL_DELTA: f64 = 10_000.0;
+
+/// Largest pinch magnification of one gesture event
+pub const MAX_ZOOM: f64 = 100.0;
+
+/// Most monitors a peer may report
+pub const MAX_MONITORS: usize = 64;
+
+/// Largest monitor position or size, in desktop pixels
+pub const MAX_DESKTOP_COORDINATE: f64 = 1_000_000.0;
+
+/// Largest monitor scale factor
+pub const MAX_SCALE_FACTOR: f64 = 16.0;
+
+/// Decode `bytes` without reading more than `limit` bytes
+///
+/// Trailing bytes after the value are an error.
+pub fn decode_bounded<T: DeserializeOwned>(bytes: &[u8], limit: usize) -> Result<T, bincode::Error> {
+    // bincode drops `with_limit` when decoding from a slice, the slice is the limit
+    if bytes.len() > limit {
+        return Err(Box::new(bincode::ErrorKind::SizeLimit));
+    }
+    bincode::DefaultOptions::new().with_fixint_encoding().deserialize(bytes)
+}
+
+/// Why a received message was rejected
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The message is larger than [`MAX_MESSAGE_SIZE`]
+    TooLarge { size: usize, limit: usize },
+    /// The bytes are not a valid encoding
+    Codec(bincode::Error),
+    /// The message decoded but a value is out of range
+    Invalid(ValidationError),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooLarge { size, limit } => {
+                write!(f, "Message of {} bytes exceeds the limit of {}", size, limit)
+            }
+            DecodeError::Codec(e) => write!(f, "Invalid message: {}", e),
+            DecodeError::Invalid(e) => write!(f, "Invalid message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<bincode::Error> for DecodeError {
+    fn from(error: bincode::Error) -> Self {
+        DecodeError::Codec(error)
+    }
+}
+
+impl From<ValidationError> for DecodeError {
+    fn from(error: ValidationError) -> Self {
+        DecodeError::Invalid(error)
+    }
+}
+
+/// A value in a decoded event that cannot be used
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// A float is NaN or infinite
+    NotFinite(&'static str),
+    /// A value lies outside its allowed range
+    OutOfRange { field: &'static str, value: f64 },
+    /// Not a macOS virtual key code
+    KeyCode(u16),
+    /// More monitors than [`MAX_MONITORS`]
+    TooManyMonitors(usize),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::NotFinite(field) => write!(f, "{} is not a finite number", field),
+            ValidationError::OutOfRange { field, value } => write!(f, "{} of {} is out of range", field, value),
+            ValidationError::KeyCode(code) => write!(f, "Unknown key code {:#04x}", code),
+            ValidationError::TooManyMonitors(count) => {
+                write!(f, "{} monitors exceed the limit of {}", count, MAX_MONITORS)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Check that `value` is finite and within `min..=max`
+fn check(field: &'static str, value: f64, min: f64, max: f64) -> Result<(), ValidationError> {
+    if !value.is_finite() {
+        return Err(ValidationError::NotFinite(field));
+    }
+    if value < min || value > max {
+        return Err(ValidationError::OutOfRange { field, value });
+    }
+    Ok(())
+}
+
+fn check_normalized(field: &'static str, value: f64) -> Result<(), ValidationError> {
+    check(field, value, 0.0, 1.0)
+}
+
+impl InputEvent {
+    /// Check the values of an event received from the peer
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        match self {
+            InputEvent::Keyboard(key_event) if key_event.key_code > MAX_KEY_CODE => {
+                return Err(ValidationError::KeyCode(key_event.key_code));
+            }
+            InputEvent::MouseMove(move_event) => {
+                check_normalized("x", move_event.x)?;
+                check_normalized("y", move_event.y)?;
+                check("delta_x", move_event.delta_x, -MAX_POINTER_DELTA, MAX_POINTER_DELTA)?;
+                check("delta_y", move_event.delta_y, -MAX_POINTER_DELTA, MAX_POINTER_DELTA)?;
+            }
+            InputEvent::MouseScroll(scroll_event) => {
+                check("delta_x", scroll_event.delta_x, -MAX_SCROLL_DELTA, MAX_SCROLL_DELTA)?;
+                check("delta_y", scroll_event.delta_y, -MAX_SCROLL_DELTA, MAX_SCROLL_DELTA)?;
+            }
+            InputEvent::Gesture(gesture) => match gesture.gesture_type {
+                GestureType::ZoomIn(scale) | GestureType::ZoomOut(scale) => check("zoom", scale, 0.0, MAX_ZOOM)?,
+                _ => {}
+            },
+            InputEvent::EnterScreen(enter_event) => {
+                check_normalized("x", enter_event.x)?;
+                check_normalized("y", enter_event.y)?;
+            }
+            InputEvent::CursorPosition(position) => {
+                check_normalized("x", position.x)?;
+                check_normalized("y", position.y)?;
+            }
+            InputEvent::ScreenInfo(screens) => validate_screens(screens)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn validate_screens(screens: &ScreenInfo) -> Result<(), ValidationError> {
+    if screens.monitors.len() > MAX_MONITORS {
+        return Err(ValidationError::TooManyMonitors(screens.monitors.len()));
+    }
+    for monitor in &screens.monitors {
+        check("monitor x", monitor.x, -MAX_DESKTOP_COORDINATE, MAX_DESKTOP_COORDINATE)?;
+        check("monitor y", monitor.y, -MAX_DESKTOP_COORDINATE, MAX_DESKTOP_COORDINATE)?;
+        check("monitor width", monitor.width, 1.0, MAX_DESKTOP_COORDINATE)?;
+        check("monitor height", monitor.height, 1.0, MAX_DESKTOP_COORDINATE)?;
+        check("scale factor", monitor.scale_factor, 0.1, MAX_SCALE_FACTOR)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapping::{CoordinateSpace, MonitorInfo};
+    use crate::{
+        GestureEvent, KeyboardEvent, Message, Modifiers, MomentumPhase, MouseMoveEvent, MouseScrollEvent, ScrollPhase,
+        TextInputEvent,
+    };
+
+    fn mouse_move(x: f64, y: f64, delta_x: f64, delta_y: f64) -> InputEvent {
+        InputEvent::MouseMove(MouseMoveEvent {
+            x,
+            y,
+            delta_x,
+            delta_y,
+            timestamp_us: 0,
+        })
+    }
+
+    fn scroll(delta_y: f64) -> InputEvent {
+        InputEvent::MouseScroll(MouseScrollEvent {
+            delta_x: 0.0,
+            delta_y,
+            is_pixel_based: true,
+            phase: ScrollPhase::None,
+            momentum_phase: MomentumPhase::None,
+        })
+    }
+
+    fn key(key_code: u16) -> InputEvent {
+        InputEvent::Keyboard(KeyboardEvent {
+            key_code,
+            character: None,
+            pressed: true,
+            modifiers: Modifiers::default(),
+            is_repeat: false,
+        })
+    }
+
+    fn monitor(width: f64, scale_factor: f64) -> MonitorInfo {
+        MonitorInfo {
+            id: 1,
+            x: 0.0,
+            y: 0.0,
+            width,
+            height: 1080.0,
+            scale_factor,
+            primary: true,
+        }
+    }
+
+    fn screens(monitors: Vec<MonitorInfo>) -> InputEvent {
+        InputEvent::ScreenInfo(ScreenInfo {
+            coordinate_space: CoordinateSpace::Logical,
+            monitors,
+        })
+    }
+
+    #[test]
+    fn values_within_the_limit_decode() {
+        let bytes = bincode::serialize(&vec![7u8; 100]).unwrap();
+        let decoded: Vec<u8> = decode_bounded(&bytes, bytes.len()).unwrap();
+        assert_eq!(decoded, vec![7u8; 100]);
+        let result = decode_bounded::<Vec<u8>>(&bytes, bytes.len() - 1);
+        assert!(matches!(result.map_err(|e| *e), Err(bincode::ErrorKind::SizeLimit)));
+    }
+
+    #[test]
+    fn length_prefixes_beyond_the_input_are_rejected() {
+        // A string claiming u64::MAX bytes, followed by nothing
+        let bytes = u64::MAX.to_le_bytes();
+        assert!(decode_bounded::<String>(&bytes, MAX_MESSAGE_SIZE).is_err());
+        assert!(decode_bounded::<Vec<u64>>(&bytes, MAX_MESSAGE_SIZE).is_err());
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let mut bytes = bincode::serialize(&42u32).unwrap();
+        bytes.push(0);
+        assert!(decode_bounded::<u32>(&bytes, 64).is_err());
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected_unread() {
+        let bytes = vec![0u8; MAX_MESSAGE_SIZE + 1];
+        let result = Message::from_bytes(&bytes);
+        assert!(matches!(result, Err(DecodeError::TooLarge { size, limit }) if size == MAX_MESSAGE_SIZE + 1 && limit == MAX_MESSAGE_SIZE));
+    }
+
+    #[test]
+    fn messages_are_validated_after_decoding() {
+        let bytes = Message::new(1, key(0x80)).to_bytes().unwrap();
+        assert!(matches!(Message::from_bytes(&bytes), Err(DecodeError::Invalid(ValidationError::KeyCode(0x80)))));
+
+        let text = InputEvent::TextInput(TextInputEvent {
+            text: "ok".into(),
+            composing: false,
+        });
+        let bytes = Message::new(2, text).to_bytes().unwrap();
+        assert!(Message::from_bytes(&bytes).is_ok());
+    }
+
+    #[test]
+    fn non_finite_floats_are_rejected() {
+        assert_eq!(mouse_move(f64::NAN, 0.5, 0.0, 0.0).validate(), Err(ValidationError::NotFinite("x")));
+        assert_eq!(mouse_move(0.5, 0.5, f64::INFINITY, 0.0).validate(), Err(ValidationError::NotFinite("delta_x")));
+        assert_eq!(scroll(f64::NEG_INFINITY).validate(), Err(ValidationError::NotFinite("delta_y")));
+        let zoom = InputEvent::Gesture(GestureEvent {
+            gesture_type: GestureType::ZoomIn(f64::NAN),
+        });
+        assert_eq!(zoom.validate(), Err(ValidationError::NotFinite("zoom")));
+        assert_eq!(screens(vec![monitor(f64::NAN, 1.0)]).validate(), Err(ValidationError::NotFinite("monitor width")));
+    }
+
+    #[test]
+    fn values_out_of_range_are_rejected() {
+        assert_eq!(mouse_move(1.5, 0.5, 0.0, 0.0).validate(), Err(ValidationError::OutOfRange { field: "x", value: 1.5 }));
+        assert!(matches!(
+            mouse_move(0.5, 0.5, 0.0, -MAX_POINTER_DELTA - 1.0).validate(),
+            Err(ValidationError::OutOfRange { field: "delta_y", .. })
+        ));
+        assert!(matches!(scroll(MAX_SCROLL_DELTA * 2.0).validate(), Err(ValidationError::OutOfRange { field: "delta_y", .. })));
+        assert!(matches!(
+            screens(vec![monitor(1920.0, 0.0)]).validate(),
+            Err(ValidationError::OutOfRange { field: "scale factor", .. })
+        ));
+
+        // The bounds themselves are fine
+        assert!(mouse_move(0.0, 1.0, MAX_POINTER_DELTA, -MAX_POINTER_DELTA).validate().is_ok());
+    }
+
+    #[test]
+    fn key_codes_above_the_macos_range_are_rejected() {
+        assert!(key(MAX_KEY_CODE).validate().is_ok());
+        assert_eq!(key(MAX_KEY_CODE + 1).validate(), Err(ValidationError::KeyCode(MAX_KEY_CODE + 1)));
+        assert_eq!(key(u16::MAX).validate(), Err(ValidationError::KeyCode(u16::MAX)));
+    }
+
+    #[test]
+    fn too_many_monitors_are_rejected() {
+        assert!(screens(vec![monitor(1920.0, 1.0); MAX_MONITORS]).validate().is_ok());
+        assert_eq!(
+            screens(vec![monitor(1920.0, 1.0); MAX_MONITORS + 1]).validate(),
+            Err(ValidationError::TooManyMonitors(MAX_MONITORS + 1))
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use samesame_protocol::{Channel, MuxConfig};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Who may connect and how much they may send
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessSettings {
+    /// Addresses that may connect, like `192.168.1.0/24` (all if empty)
+    pub allow: Vec<IpNet>,
+    /// Addresses that may never connect, checked before `allow`
+    pub deny: Vec<IpNet>,
+    /// Connections open at the same time, handshakes included (0 = unlimited)
+    pub max_connections: usize,
+    /// Largest message accepted from a client
+    pub max_message_bytes: usize,
+    /// Invalid messages after which a client is disconnected (0 = never)
+    pub max_invalid_messages: u32,
+    /// Messages a client may send per second
+    pub rate: RateSettings,
+}
+
+impl Default for AccessSettings {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: Vec::new(),
+            max_connections: 16,
+            max_message_bytes: 256 * 1024,
+            max_invalid_messages: 16,
+            rate: RateSettings::default(),
+        }
+    }
+}
+
+/// Token bucket for messages of one connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateSettings {
+    /// Sustained messages per second (0 = unlimited)
+    pub messages_per_sec: u32,
+    /// Messages that may arrive at once after a quiet period
+    pub burst: u32,
+}
+
+impl Default for RateSettings {
+    fn default() -> Self {
+        // Gaming mice report at 1000 Hz, with room for keys and gestures on top
+        Self {
+            messages_per_sec: 2000,
+            burst: 4000,
+        }
+    }
+}
+
+/// IP network, written like `192.168.1.0/24`, `fd00::/8` or a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack socket show up as ::ffff:a.b.c.d
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_matches(u32::from(net), u32::from(ip), self.prefix, 32),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => prefix_matches(u128::from(net), u128::from(ip), self.prefix, 128),
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches<T>(net: T, ip: T, prefix: u8, bits: u8) -> bool
+where
+    T: Into<u128>,
+{
+    if prefix == 0 {
+        return true;
+    }
+    let shift = u32::from(bits - prefix);
+    (net.into() ^ ip.into()) >> shift == 0
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (text, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| format!("Invalid address in {}", text))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .ok_or_else(|| format!("Invalid prefix length in {}", text))?,
+            None => bits,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for IpNet {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<IpNet> for String {
+    fn from(net: IpNet) -> Self {
+        net.to_string()
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Why a connection was turned away before the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// The address is denied or not allowed
+    Address,
+    /// `max_connections` are open already
+    TooManyConnections,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::Address => write!(f, "address not allowed"),
+            Refusal::TooManyConnections => write!(f, "too many open connections"),
+        }
+    }
+}
+
+/// Connection limits shared by all connections
+pub struct AccessControl {
+    settings: AccessSettings,
+    connections: AtomicUsize,
+    pub metrics: Metrics,
+}
+
+impl AccessControl {
+    pub fn new(settings: AccessSettings) -> Self {
+        Self {
+            settings,
+            connections: AtomicUsize::new(0),
+            metrics: Metrics::default(),
+        }
+    }
+
+    pub fn settings(&self) -> &AccessSettings {
+        &self.settings
+    }
+
+    /// Check a new connection, which counts until the guard is dropped
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionGuard, Refusal> {
+        let denied = self.settings.deny.iter().any(|net| net.contains(ip));
+        let allowed = self.settings.allow.is_empty() || self.settings.allow.iter().any(|net| net.contains(ip));
+        if denied || !allowed {
+            self.metrics.refused_addresses.fetch_add(1, Ordering::Relaxed);
+            return Err(Refusal::Address);
+        }
+
+        let limit = self.settings.max_connections;
+        let reserved = self
+            .connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                (limit == 0 || open < limit).then_some(open + 1)
+            });
+        if reserved.is_err() {
+            self.metrics.refused_connections.fetch_add(1, Ordering::Relaxed);
+            return Err(Refusal::TooManyConnections);
+        }
+
+        Ok(ConnectionGuard { access: self.clone() })
+    }
+
+    /// Mux limits for reading from clients, with `max_message_bytes` applied
+    pub fn mux_config(&self) -> MuxConfig {
+        let mut config = MuxConfig::default();
+        for channel in Channel::ALL {
+            let limits = match channel {
+                Channel::Control => &mut config.control,
+                Channel::Input => &mut config.input,
+                Channel::Bulk => &mut config.bulk,
+            };
+            limits.max_message = limits.max_message.min(self.settings.max_message_bytes);
+        }
+        config
+    }
+
+    pub fn rate_limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.settings.rate.clone())
+    }
+}
+
+/// An open connection, counted until dropped
+pub struct ConnectionGuard {
+    access: Arc<AccessControl>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.access.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// How often each limit triggered since the server started
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub refused_addresses: AtomicU64,
+    pub refused_connections: AtomicU64,
+    pub rate_limited: AtomicU64,
+    pub invalid_messages: AtomicU64,
+    pub disconnected: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub refused_addresses: u64,
+    pub refused_connections: u64,
+    pub rate_limited: u64,
+    pub invalid_messages: u64,
+    pub disconnected: u64,
+}
+
+impl Metrics {
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            refused_addresses: self.refused_addresses.load(Ordering::Relaxed),
+            refused_connections: self.refused_connections.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            invalid_messages: self.invalid_messages.load(Ordering::Relaxed),
+            disconnected: self.disconnected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} address(es) refused, {} connection(s) over the limit, {} message(s) rate-limited, \
+             {} invalid message(s), {} client(s) disconnected",
+            self.refused_addresses, self.refused_connections, self.rate_limited, self.invalid_messages, self.disconnected
+        )
+    }
+}
+
+/// Token bucket limiting the messages of one connection
+///
+/// The bucket holds up to `burst` tokens and refills at `messages_per_sec`;
+/// every message takes one. Time is passed in by the caller, so the limiter
+/// runs on any clock.
+#[derive(Debug)]
+pub struct RateLimiter {
+    settings: RateSettings,
+    tokens: f64,
+    last: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateSettings) -> Self {
+        Self {
+            tokens: f64::from(settings.burst.max(1)),
+            settings,
+            last: None,
+        }
+    }
+
+    /// Take a token for a message arriving at `now`, false if the bucket is empty
+    pub fn allow(&mut self, now: Instant) -> bool {
+        if self.settings.messages_per_sec == 0 {
+            return true;
+        }
+
+        if let Some(last) = self.last {
+            let elapsed = now.saturating_duration_since(last).as_secs_f64();
+            let capacity = f64::from(self.settings.burst.max(1));
+            self.tokens = (self.tokens + elapsed * f64::from(self.settings.messages_per_sec)).min(capacity);
+        }
+        self.last = Some(now);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn net(text: &str) -> IpNet {
+        text.parse().unwrap()
+    }
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    fn limiter(messages_per_sec: u32, burst: u32) -> RateLimiter {
+        RateLimiter::new(RateSettings { messages_per_sec, burst })
+    }
+
+    #[test]
+    fn burst_passes_then_the_bucket_is_empty() {
+        let mut limiter = limiter(10, 5);
+        let now = Instant::now();
+
+        assert!((0..5).all(|_| limiter.allow(now)));
+        assert!(!limiter.allow(now));
+        assert!(!limiter.allow(now + Duration::from_millis(50)), "half a token is not enough");
+    }
+
+    #[test]
+    fn bucket_refills_at_the_rate_up_to_the_burst() {
+        let mut limiter = limiter(10, 5);
+        let start = Instant::now();
+        assert!((0..5).all(|_| limiter.allow(start)));
+
+        // 0.3 s bring back three tokens
+        let later = start + Duration::from_millis(300);
+        assert!((0..3).all(|_| limiter.allow(later)));
+        assert!(!limiter.allow(later));
+
+        // A long pause fills the bucket no further than the burst
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!((0..10).filter(|_| limiter.allow(much_later)).count(), 5);
+    }
+
+    #[test]
+    fn sustained_rate_is_kept() {
+        let mut limiter = limiter(100, 1);
+        let start = Instant::now();
+
+        // Twice the allowed rate for a second, half of it passes
+        let passed = (0..200).filter(|i| limiter.allow(start + Duration::from_millis(i * 5))).count();
+        assert!((100..=101).contains(&passed), "{} passed", passed);
+    }
+
+    #[test]
+    fn zero_rate_is_unlimited() {
+        let mut limiter = limiter(0, 1);
+        let now = Instant::now();
+        assert!((0..10_000).all(|_| limiter.allow(now)));
+    }
+
+    #[test]
+    fn prefixes_match_their_network() {
+        let lan = net("192.168.1.0/24");
+        assert!(lan.contains(ip("192.168.1.1")));
+        assert!(lan.contains(ip("192.168.1.255")));
+        assert!(!lan.contains(ip("192.168.2.1")));
+
+        // IPv4 clients of a dual-stack socket
+        assert!(lan.contains(ip("::ffff:192.168.1.7")));
+    }
+
+    #[test]
+    fn zero_prefix_matches_its_whole_family() {
+        let any_v4 = net("0.0.0.0/0");
+        assert!(any_v4.contains(ip("10.0.0.1")));
+        assert!(any_v4.contains(ip("255.255.255.255")));
+        assert!(!any_v4.contains(ip("::1")));
+
+        let any_v6 = net("::/0");
+        assert!(any_v6.contains(ip("fe80::1")));
+        assert!(!any_v6.contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn full_prefix_matches_one_address() {
+        let host = net("10.0.0.5/32");
+        assert!(host.contains(ip("10.0.0.5")));
+        assert!(!host.contains(ip("10.0.0.4")));
+        assert_eq!(net("10.0.0.5"), host, "an address alone is a /32");
+        assert_eq!(net("::1"), net("::1/128"));
+    }
+
+    #[test]
+    fn ipv6_prefixes_match() {
+        let ula = net("fd00::/8");
+        assert!(ula.contains(ip("fd12:3456::1")));
+        assert!(!ula.contains(ip("fe80::1")));
+
+        let subnet = net("2001:db8:1:2::/64");
+        assert!(subnet.contains(ip("2001:db8:1:2:ffff::1")));
+        assert!(!subnet.contains(ip("2001:db8:1:3::1")));
+    }
+
+    #[test]
+    fn invalid_networks_are_rejected() {
+        for text in ["192.168.1.0/33", "::/129", "192.168.1/24", "10.0.0.0/", "host/8"] {
+            assert!(text.parse::<IpNet>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let access = Arc::new(AccessControl::new(AccessSettings {
+            allow: vec![net("192.168.1.0/24")],
+            deny: vec![net("192.168.1.66")],
+            ..AccessSettings::default()
+        }));
+
+        assert!(access.admit(ip("192.168.1.10")).is_ok());
+        assert!(matches!(access.admit(ip("192.168.1.66")), Err(Refusal::Address)));
+        assert!(matches!(access.admit(ip("10.0.0.1")), Err(Refusal::Address)));
+        assert_eq!(access.metrics.snapshot().refused_addresses, 2);
+    }
+
+    #[test]
+    fn connections_count_until_dropped() {
+        let access = Arc::new(AccessControl::new(AccessSettings {
+            max_connections: 1,
+            ..AccessSettings::default()
+        }));
+
+        let first = access.admit(ip("127.0.0.1")).unwrap();
+        assert!(matches!(access.admit(ip("127.0.0.1")), Err(Refusal::TooManyConnections)));
+        drop(first);
+        assert!(access.admit(ip("127.0.0.1")).is_ok());
+    }
+}
@@ -0,0 +1,352 @@
+use parking_lot::Mutex;
+use samesame_protocol::arbitration::{Arbitration, ControlStatus};
+use samesame_protocol::{InputEvent, Message};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::outgoing::Outgoing;
+use crate::policy::HeldKeys;
+
+/// Handing control between clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArbitrationSettings {
+    /// Seconds without input after which a waiting client may take over (0 = never)
+    pub idle_handoff_secs: u64,
+}
+
+impl Default for ArbitrationSettings {
+    fn default() -> Self {
+        Self { idle_handoff_secs: 30 }
+    }
+}
+
+/// Session handle in the arbiter
+pub type MemberId = u64;
+
+/// Decides which client controls this machine
+///
+/// One session at a time is the controller, the others wait in a queue in
+/// the order they asked. Control passes to the next one when the controller
+/// releases it or disconnects. A waiting client that sends input or asks
+/// again takes over from a controller that has been idle for
+/// `idle_handoff_secs`, so a Mac left alone does not block the others. Time
+/// is passed in by the caller, so the arbiter runs on any clock.
+pub struct Arbiter {
+    settings: ArbitrationSettings,
+    state: Mutex<ArbiterState>,
+}
+
+#[derive(Default)]
+struct ArbiterState {
+    next_id: MemberId,
+    members: BTreeMap<MemberId, Member>,
+    controller: Option<MemberId>,
+    /// Time of the controller's last input
+    last_input: Option<Instant>,
+    queue: VecDeque<MemberId>,
+}
+
+struct Member {
+    name: String,
+    sender: Outgoing,
+}
+
+impl Arbiter {
+    pub fn new(settings: ArbitrationSettings) -> Self {
+        Self {
+            settings,
+            state: Mutex::new(ArbiterState::default()),
+        }
+    }
+
+    /// Add a new session, which asks for control right away
+    ///
+    /// Status updates are sent through `sender`.
+    pub fn join(&self, name: String, sender: Outgoing, now: Instant) -> MemberId {
+        let mut state = self.state.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.members.insert(id, Member { name, sender });
+        self.request_locked(&mut state, id, now);
+        state.notify();
+        id
+    }
+
+    /// Remove a session that disconnected, handing control on
+    pub fn leave(&self, id: MemberId, now: Instant) {
+        let mut state = self.state.lock();
+        state.queue.retain(|queued| *queued != id);
+        if state.controller == Some(id) {
+            state.hand_off(now);
+        }
+        state.members.remove(&id);
+        state.notify();
+    }
+
+    /// Become the controller, or queue for it
+    pub fn request(&self, id: MemberId, now: Instant) {
+        let mut state = self.state.lock();
+        self.request_locked(&mut state, id, now);
+        state.notify();
+    }
+
+    /// Give up control, or stop waiting for it
+    pub fn release(&self, id: MemberId, now: Instant) {
+        let mut state = self.state.lock();
+        state.queue.retain(|queued| *queued != id);
+        if state.controller == Some(id) {
+            state.hand_off(now);
+        }
+        state.notify();
+    }
+
+    /// Whether input from the session may be injected, `held` is what the session holds down
+    ///
+    /// Releases of what the session itself pressed always pass, so nothing
+    /// stays held when control moves on. Other releases never pass, they
+    /// could lift keys another session holds.
+    pub fn allow_input(&self, id: MemberId, event: &InputEvent, held: &HeldKeys, now: Instant) -> bool {
+        if event.is_release() {
+            return held.is_held_release(event);
+        }
+
+        let mut state = self.state.lock();
+        if state.controller == Some(id) {
+            state.last_input = Some(now);
+            return true;
+        }
+        if !self.can_take_over(&state, now) {
+            return false;
+        }
+        state.take_control(id, now);
+        state.notify();
+        true
+    }
+
+    fn request_locked(&self, state: &mut ArbiterState, id: MemberId, now: Instant) {
+        if state.controller == Some(id) {
+            return;
+        }
+        if self.can_take_over(state, now) {
+            state.take_control(id, now);
+        } else if !state.queue.contains(&id) {
+            state.queue.push_back(id);
+        }
+    }
+
+    /// Nobody is in control, or the controller has been idle long enough
+    fn can_take_over(&self, state: &ArbiterState, now: Instant) -> bool {
+        if state.controller.is_none() {
+            return true;
+        }
+        let idle_handoff = Duration::from_secs(self.settings.idle_handoff_secs);
+        !idle_handoff.is_zero() && state.last_input.is_some_and(|last| now.duration_since(last) >= idle_handoff)
+    }
+}
+
+impl ArbiterState {
+    /// Make `id` the controller, the previous one waits at the end of the queue
+    fn take_control(&mut self, id: MemberId, now: Instant) {
+        self.queue.retain(|queued| *queued != id);
+        if let Some(previous) = self.controller.replace(id) {
+            self.queue.push_back(previous);
+        }
+        self.last_input = Some(now);
+        info!("{} is now in control", self.name(id));
+    }
+
+    /// Pass control to the first waiting session, if any
+    fn hand_off(&mut self, now: Instant) {
+        self.controller = None;
+        self.last_input = None;
+        match self.queue.pop_front() {
+            Some(next) => self.take_control(next, now),
+            None => info!("Nobody is in control"),
+        }
+    }
+
+    fn name(&self, id: MemberId) -> &str {
+        self.members.get(&id).map_or("?", |member| member.name.as_str())
+    }
+
+    /// Tell every session who is in control and where it stands
+    fn notify(&self) {
+        let controller = self.controller.map(|id| self.name(id).to_string());
+        for (id, member) in &self.members {
+            let status = ControlStatus {
+                controller: controller.clone(),
+                in_control: self.controller == Some(*id),
+                queue_position: self
+                    .queue
+                    .iter()
+                    .position(|queued| queued == id)
+                    .map(|position| position as u32 + 1),
+            };
+            member.sender.send(Message::new(0, InputEvent::Arbitration(Arbitration::Status(status))));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outgoing::Overflow;
+    use samesame_protocol::{KeyboardEvent, Modifiers};
+    use tokio::sync::mpsc;
+
+    /// A joined session and the status updates it receives
+    struct Client {
+        id: MemberId,
+        receiver: mpsc::Receiver<Message>,
+    }
+
+    impl Client {
+        fn join(arbiter: &Arbiter, name: &str, now: Instant) -> Self {
+            let (sender, receiver) = Outgoing::channel(16, &Overflow::default());
+            let id = arbiter.join(name.into(), sender, now);
+            Self { id, receiver }
+        }
+
+        /// Newest status sent to this session
+        fn status(&mut self) -> ControlStatus {
+            let mut newest = None;
+            while let Ok(message) = self.receiver.try_recv() {
+                if let InputEvent::Arbitration(Arbitration::Status(status)) = message.event {
+                    newest = Some(status);
+                }
+            }
+            newest.expect("no status update")
+        }
+    }
+
+    fn key(pressed: bool) -> InputEvent {
+        InputEvent::Keyboard(KeyboardEvent {
+            key_code: 0x00,
+            character: None,
+            pressed,
+            modifiers: Modifiers::default(),
+            is_repeat: false,
+        })
+    }
+
+    fn arbiter(idle_handoff_secs: u64) -> Arbiter {
+        Arbiter::new(ArbitrationSettings { idle_handoff_secs })
+    }
+
+    #[test]
+    fn first_session_controls_and_the_next_waits() {
+        let arbiter = arbiter(30);
+        let now = Instant::now();
+        let mut first = Client::join(&arbiter, "first", now);
+        let mut second = Client::join(&arbiter, "second", now);
+
+        let status = first.status();
+        assert!(status.in_control);
+        assert_eq!(status.queue_position, None);
+        let status = second.status();
+        assert!(!status.in_control);
+        assert_eq!(status.controller.as_deref(), Some("first"));
+        assert_eq!(status.queue_position, Some(1));
+
+        assert!(arbiter.allow_input(first.id, &key(true), &HeldKeys::default(), now));
+        assert!(!arbiter.allow_input(second.id, &key(true), &HeldKeys::default(), now));
+        assert!(!arbiter.allow_input(second.id, &key(false), &HeldKeys::default(), now));
+    }
+
+    #[test]
+    fn only_releases_of_held_keys_pass_without_control() {
+        let arbiter = arbiter(30);
+        let now = Instant::now();
+        let first = Client::join(&arbiter, "first", now);
+        let second = Client::join(&arbiter, "second", now);
+
+        let mut held = HeldKeys::default();
+        held.update(&key(true));
+        arbiter.release(first.id, now);
+        assert!(!arbiter.allow_input(first.id, &key(true), &held, now));
+        assert!(arbiter.allow_input(first.id, &key(false), &held, now));
+        assert!(!arbiter.allow_input(first.id, &key(false), &HeldKeys::default(), now));
+        assert!(arbiter.allow_input(second.id, &key(true), &HeldKeys::default(), now));
+    }
+
+    #[test]
+    fn release_hands_control_to_the_queue_in_order() {
+        let arbiter = arbiter(30);
+        let now = Instant::now();
+        let first = Client::join(&arbiter, "first", now);
+        let mut second = Client::join(&arbiter, "second", now);
+        let mut third = Client::join(&arbiter, "third", now);
+        assert_eq!(third.status().queue_position, Some(2));
+
+        arbiter.release(first.id, now);
+        assert!(second.status().in_control);
+        assert_eq!(third.status().queue_position, Some(1));
+        assert!(!arbiter.allow_input(first.id, &key(true), &HeldKeys::default(), now));
+
+        // Asking again puts the first session at the end
+        arbiter.request(first.id, now);
+        arbiter.leave(second.id, now);
+        assert!(third.status().in_control);
+        assert!(arbiter.allow_input(third.id, &key(true), &HeldKeys::default(), now));
+    }
+
+    #[test]
+    fn request_from_the_controller_keeps_control() {
+        let arbiter = arbiter(30);
+        let now = Instant::now();
+        let mut first = Client::join(&arbiter, "first", now);
+        let mut second = Client::join(&arbiter, "second", now);
+
+        arbiter.request(first.id, now);
+        arbiter.request(second.id, now);
+        assert!(first.status().in_control);
+        assert_eq!(second.status().queue_position, Some(1));
+    }
+
+    #[test]
+    fn idle_controller_is_taken_over() {
+        let arbiter = arbiter(30);
+        let start = Instant::now();
+        let mut first = Client::join(&arbiter, "first", start);
+        let mut second = Client::join(&arbiter, "second", start);
+
+        // Input keeps the controller active
+        let later = start + Duration::from_secs(20);
+        assert!(arbiter.allow_input(first.id, &key(true), &HeldKeys::default(), later));
+        assert!(!arbiter.allow_input(second.id, &key(true), &HeldKeys::default(), later + Duration::from_secs(29)));
+
+        // Idle long enough, input from a waiting session takes over
+        assert!(arbiter.allow_input(second.id, &key(true), &HeldKeys::default(), later + Duration::from_secs(30)));
+        assert!(second.status().in_control);
+        assert_eq!(first.status().queue_position, Some(1));
+    }
+
+    #[test]
+    fn request_takes_over_an_idle_controller() {
+        let arbiter = arbiter(30);
+        let start = Instant::now();
+        let mut first = Client::join(&arbiter, "first", start);
+        let mut second = Client::join(&arbiter, "second", start);
+
+        arbiter.request(second.id, start + Duration::from_secs(10));
+        assert!(first.status().in_control);
+        arbiter.request(second.id, start + Duration::from_secs(30));
+        assert!(second.status().in_control);
+        assert!(!first.status().in_control);
+    }
+
+    #[test]
+    fn zero_idle_handoff_never_takes_over() {
+        let arbiter = arbiter(0);
+        let start = Instant::now();
+        let first = Client::join(&arbiter, "first", start);
+        let second = Client::join(&arbiter, "second", start);
+
+        let much_later = start + Duration::from_secs(24 * 60 * 60);
+        assert!(!arbiter.allow_input(second.id, &key(true), &HeldKeys::default(), much_later));
+        assert!(arbiter.allow_input(first.id, &key(true), &HeldKeys::default(), much_later));
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use samesame_protocol::mapping::{CoordinateSpace, MonitorInfo, ScreenInfo};
+use samesame_protocol::{CursorPositionEvent, InputEvent, LockState};
+use tracing::debug;
+
+use crate::lock_keys::LockKey;
+use crate::scroll::WheelDelta;
+use crate::text_input::TextKeystroke;
+
+/// Platform layer that injects input and reports the cursor
+pub trait InputBackend: Send + Sync {
+    /// Inject an input event
+    fn simulate(&self, event: &InputEvent) -> Result<()>;
+
+    /// Move the cursor by whole pixels
+    fn move_relative(&self, dx: i32, dy: i32) -> Result<()>;
+
+    /// Enable or disable pointer acceleration for relative moves
+    fn set_pointer_acceleration(&self, enabled: bool) -> Result<()>;
+
+    /// Turn the mouse wheel
+    fn scroll(&self, delta: WheelDelta) -> Result<()>;
+
+    /// Type text through the Unicode input path
+    fn type_text(&self, keystrokes: &[TextKeystroke]) -> Result<()>;
+
+    /// Current state of the lock keys
+    fn lock_state(&self) -> LockState;
+
+    /// Press and release a lock key
+    fn toggle_lock(&self, key: LockKey) -> Result<()>;
+
+    /// Current cursor position, if it can be determined
+    fn cursor_position(&self) -> Option<CursorPositionEvent>;
+
+    /// Monitor topology of this machine
+    fn screen_info(&self) -> ScreenInfo;
+}
+
+/// Injects input through the Windows SendInput API
+#[cfg(windows)]
+pub struct WindowsBackend;
+
+#[cfg(windows)]
+impl InputBackend for WindowsBackend {
+    fn simulate(&self, event: &InputEvent) -> Result<()> {
+        crate::input_simulator::simulate_event(event)
+    }
+
+    fn move_relative(&self, dx: i32, dy: i32) -> Result<()> {
+        crate::input_simulator::move_relative(dx, dy)
+    }
+
+    fn set_pointer_acceleration(&self, enabled: bool) -> Result<()> {
+        crate::input_simulator::set_pointer_acceleration(enabled)
+    }
+
+    fn scroll(&self, delta: WheelDelta) -> Result<()> {
+        crate::input_simulator::scroll(delta)
+    }
+
+    fn type_text(&self, keystrokes: &[TextKeystroke]) -> Result<()> {
+        crate::input_simulator::type_text(keystrokes)
+    }
+
+    fn lock_state(&self) -> LockState {
+        crate::input_simulator::lock_state()
+    }
+
+    fn toggle_lock(&self, key: LockKey) -> Result<()> {
+        crate::input_simulator::toggle_lock(key)
+    }
+
+    fn cursor_position(&self) -> Option<CursorPositionEvent> {
+        crate::input_simulator::cursor_position()
+    }
+
+    fn screen_info(&self) -> ScreenInfo {
+        crate::input_simulator::screen_info()
+    }
+}
+
+/// Backend that records events instead of injecting them
+///
+/// Used where no real input injection is available and to drive the server
+/// in tests, e.g. by setting the cursor position it reports.
+pub struct MockBackend {
+    events: Mutex<Vec<InputEvent>>,
+    relative_moves: Mutex<Vec<(i32, i32)>>,
+    acceleration: Mutex<bool>,
+    scrolls: Mutex<Vec<WheelDelta>>,
+    typed: Mutex<Vec<TextKeystroke>>,
+    locks: Mutex<LockState>,
+    cursor: Mutex<Option<CursorPositionEvent>>,
+    screens: Mutex<ScreenInfo>,
+}
+
+impl MockBackend {
+    /// Mock with a single 1920×1080 monitor
+    pub fn new() -> Self {
+        Self {
+            events: Mutex::new(Vec::new()),
+            relative_moves: Mutex::new(Vec::new()),
+            acceleration: Mutex::new(true),
+            scrolls: Mutex::new(Vec::new()),
+            typed: Mutex::new(Vec::new()),
+            locks: Mutex::new(LockState {
+                caps_lock: Some(false),
+                num_lock: Some(true),
+                scroll_lock: Some(false),
+            }),
+            cursor: Mutex::new(None),
+            screens: Mutex::new(ScreenInfo {
+                coordinate_space: CoordinateSpace::Physical,
+                monitors: vec![MonitorInfo {
+                    id: 0,
+                    x: 0.0,
+                    y: 0.0,
+                    width: 1920.0,
+                    height: 1080.0,
+                    scale_factor: 1.0,
+                    primary: true,
+                }],
+            }),
+        }
+    }
+
+    /// Set the monitor topology reported to clients
+    pub fn set_screen_info(&self, screens: ScreenInfo) {
+        *self.screens.lock() = screens;
+    }
+
+    /// Set the cursor position reported to clients
+    pub fn set_cursor_position(&self, position: Option<CursorPositionEvent>) {
+        *self.cursor.lock() = position;
+    }
+
+    /// Take all events received so far
+    pub fn take_events(&self) -> Vec<InputEvent> {
+        std::mem::take(&mut *self.events.lock())
+    }
+
+    /// Take all relative moves received so far
+    pub fn take_relative_moves(&self) -> Vec<(i32, i32)> {
+        std::mem::take(&mut *self.relative_moves.lock())
+    }
+
+    /// Take all wheel movement received so far
+    pub fn take_scrolls(&self) -> Vec<WheelDelta> {
+        std::mem::take(&mut *self.scrolls.lock())
+    }
+
+    /// Take all text keystrokes received so far
+    pub fn take_typed(&self) -> Vec<TextKeystroke> {
+        std::mem::take(&mut *self.typed.lock())
+    }
+
+    /// Set the lock state, e.g. as if a lock key was pressed on the server
+    pub fn set_lock_state(&self, locks: LockState) {
+        *self.locks.lock() = locks;
+    }
+
+    /// Whether pointer acceleration is currently enabled
+    pub fn pointer_acceleration(&self) -> bool {
+        *self.acceleration.lock()
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputBackend for MockBackend {
+    fn simulate(&self, event: &InputEvent) -> Result<()> {
+        debug!("Mock backend received: {:?}", event);
+        self.events.lock().push(event.clone());
+        Ok(())
+    }
+
+    fn move_relative(&self, dx: i32, dy: i32) -> Result<()> {
+        self.relative_moves.lock().push((dx, dy));
+        Ok(())
+    }
+
+    fn set_pointer_acceleration(&self, enabled: bool) -> Result<()> {
+        debug!("Mock backend pointer acceleration: {}", enabled);
+        *self.acceleration.lock() = enabled;
+        Ok(())
+    }
+
+    fn scroll(&self, delta: WheelDelta) -> Result<()> {
+        self.scrolls.lock().push(delta);
+        Ok(())
+    }
+
+    fn type_text(&self, keystrokes: &[TextKeystroke]) -> Result<()> {
+        self.typed.lock().extend_from_slice(keystrokes);
+        Ok(())
+    }
+
+    fn lock_state(&self) -> LockState {
+        *self.locks.lock()
+    }
+
+    fn toggle_lock(&self, key: LockKey) -> Result<()> {
+        let mut locks = self.locks.lock();
+        let state = match key {
+            LockKey::Caps => &mut locks.caps_lock,
+            LockKey::Num => &mut locks.num_lock,
+            LockKey::Scroll => &mut locks.scroll_lock,
+        };
+        *state = state.map(|on| !on);
+        Ok(())
+    }
+
+    fn cursor_position(&self) -> Option<CursorPositionEvent> {
+        self.cursor.lock().clone()
+    }
+
+    fn screen_info(&self) -> ScreenInfo {
+        self.screens.lock().clone()
+    }
+}
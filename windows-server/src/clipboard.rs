@@ -0,0 +1,199 @@
+/// Start and end markers of the fragment inside a CF_HTML document
+const FRAGMENT_START: &str = "<!--StartFragment-->";
+const FRAGMENT_END: &str = "<!--EndFragment-->";
+
+/// Wrap an HTML fragment in the CF_HTML clipboard format
+///
+/// The header holds byte offsets into the UTF-8 data, padded to a fixed
+/// width so the header length doesn't depend on them.
+pub fn encode_cf_html(fragment: &str) -> String {
+    let header = |start_html: usize, end_html: usize, start_fragment: usize, end_fragment: usize| {
+        format!(
+            "Version:0.9\r\nStartHTML:{:010}\r\nEndHTML:{:010}\r\nStartFragment:{:010}\r\nEndFragment:{:010}\r\n",
+            start_html, end_html, start_fragment, end_fragment
+        )
+    };
+    let prefix = format!("<html><body>{}", FRAGMENT_START);
+    let suffix = format!("{}</body></html>", FRAGMENT_END);
+
+    let start_html = header(0, 0, 0, 0).len();
+    let start_fragment = start_html + prefix.len();
+    let end_fragment = start_fragment + fragment.len();
+    let end_html = end_fragment + suffix.len();
+
+    format!(
+        "{}{}{}{}",
+        header(start_html, end_html, start_fragment, end_fragment),
+        prefix,
+        fragment,
+        suffix
+    )
+}
+
+/// Extract the HTML fragment from CF_HTML clipboard data
+pub fn decode_cf_html(data: &str) -> Option<String> {
+    let offset = |name: &str| -> Option<usize> {
+        let line = data.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))?;
+        line.trim().parse().ok()
+    };
+
+    let fragment = match (offset("StartFragment"), offset("EndFragment")) {
+        (Some(start), Some(end)) if start <= end => data.get(start..end),
+        _ => None,
+    };
+
+    // Fall back to the markers when the offsets are missing or broken
+    let fragment = fragment.or_else(|| {
+        let start = data.find(FRAGMENT_START)? + FRAGMENT_START.len();
+        let end = data[start..].find(FRAGMENT_END)? + start;
+        data.get(start..end)
+    })?;
+    Some(fragment.to_string())
+}
+
+#[cfg(windows)]
+pub use windows_clipboard::WindowsClipboard;
+
+#[cfg(windows)]
+mod windows_clipboard {
+    use super::*;
+    use samesame_protocol::clipboard::{Clipboard, ClipboardContent};
+    use std::thread;
+    use std::time::Duration;
+    use windows::core::w;
+    use windows::Win32::Foundation::{HANDLE, HGLOBAL};
+    use windows::Win32::System::DataExchange::{
+        CloseClipboard, EmptyClipboard, GetClipboardData, GetClipboardSequenceNumber,
+        IsClipboardFormatAvailable, OpenClipboard, RegisterClipboardFormatW, SetClipboardData,
+    };
+    use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE};
+
+    /// Standard clipboard format for UTF-16 text
+    const CF_UNICODETEXT: u32 = 13;
+
+    /// Attempts to open the clipboard while another application holds it
+    const OPEN_ATTEMPTS: u32 = 5;
+    const OPEN_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+    /// The Windows clipboard
+    pub struct WindowsClipboard {
+        html_format: u32,
+        png_format: u32,
+    }
+
+    impl WindowsClipboard {
+        pub fn new() -> Self {
+            unsafe {
+                Self {
+                    html_format: RegisterClipboardFormatW(w!("HTML Format")),
+                    png_format: RegisterClipboardFormatW(w!("PNG")),
+                }
+            }
+        }
+    }
+
+    impl Default for WindowsClipboard {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Keeps the clipboard open, closes it when dropped
+    struct OpenGuard;
+
+    impl OpenGuard {
+        fn open() -> Result<Self, String> {
+            let mut attempt = 1;
+            loop {
+                match unsafe { OpenClipboard(None) } {
+                    Ok(()) => return Ok(Self),
+                    Err(e) if attempt >= OPEN_ATTEMPTS => return Err(format!("OpenClipboard failed: {}", e)),
+                    Err(_) => {
+                        attempt += 1;
+                        thread::sleep(OPEN_RETRY_DELAY);
+                    }
+                }
+            }
+        }
+    }
+
+    impl Drop for OpenGuard {
+        fn drop(&mut self) {
+            unsafe {
+                let _ = CloseClipboard();
+            }
+        }
+    }
+
+    /// Copy the data of a clipboard format, `None` if it is not on the clipboard
+    unsafe fn get_data(format: u32) -> Option<Vec<u8>> {
+        IsClipboardFormatAvailable(format).ok()?;
+        let handle = GetClipboardData(format).ok()?;
+        let memory = HGLOBAL(handle.0);
+
+        let pointer = GlobalLock(memory) as *const u8;
+        if pointer.is_null() {
+            return None;
+        }
+        let data = std::slice::from_raw_parts(pointer, GlobalSize(memory)).to_vec();
+        let _ = GlobalUnlock(memory);
+        Some(data)
+    }
+
+    /// Put data on the (open and emptied) clipboard; the system takes ownership
+    unsafe fn set_data(format: u32, data: &[u8]) -> Result<(), String> {
+        let memory = GlobalAlloc(GMEM_MOVEABLE, data.len()).map_err(|e| format!("GlobalAlloc failed: {}", e))?;
+        let pointer = GlobalLock(memory) as *mut u8;
+        if pointer.is_null() {
+            return Err("GlobalLock failed".to_string());
+        }
+        std::ptr::copy_nonoverlapping(data.as_ptr(), pointer, data.len());
+        let _ = GlobalUnlock(memory);
+
+        SetClipboardData(format, HANDLE(memory.0)).map_err(|e| format!("SetClipboardData failed: {}", e))?;
+        Ok(())
+    }
+
+    impl Clipboard for WindowsClipboard {
+        fn read(&self) -> Result<ClipboardContent, String> {
+            let _guard = OpenGuard::open()?;
+            unsafe {
+                let text = get_data(CF_UNICODETEXT).map(|data| {
+                    let units: Vec<u16> = data.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+                    let end = units.iter().position(|&unit| unit == 0).unwrap_or(units.len());
+                    String::from_utf16_lossy(&units[..end])
+                });
+                let html = get_data(self.html_format).and_then(|data| {
+                    let end = data.iter().position(|&byte| byte == 0).unwrap_or(data.len());
+                    decode_cf_html(&String::from_utf8_lossy(&data[..end]))
+                });
+                let png = get_data(self.png_format);
+                Ok(ClipboardContent { text, html, png })
+            }
+        }
+
+        fn write(&self, content: &ClipboardContent) -> Result<(), String> {
+            let _guard = OpenGuard::open()?;
+            unsafe {
+                EmptyClipboard().map_err(|e| format!("EmptyClipboard failed: {}", e))?;
+                if let Some(text) = &content.text {
+                    let data: Vec<u8> = text.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect();
+                    set_data(CF_UNICODETEXT, &data)?;
+                }
+                if let Some(html) = &content.html {
+                    let mut data = encode_cf_html(html).into_bytes();
+                    data.push(0);
+                    set_data(self.html_format, &data)?;
+                }
+                if let Some(png) = &content.png {
+                    set_data(self.png_format, png)?;
+                }
+            }
+            Ok(())
+        }
+
+        fn change_count(&self) -> Option<u64> {
+            Some(u64::from(unsafe { GetClipboardSequenceNumber() }))
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::{anyhow, Result};
+use samesame_protocol::acceleration::AccelerationCurve;
+use samesame_protocol::clipboard::ClipboardSettings;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::access::AccessSettings;
+use crate::arbiter::ArbitrationSettings;
+use crate::datagram::DatagramSettings;
+use crate::discovery::DiscoverySettings;
+use crate::file_receiver::ReceiveSettings;
+use crate::key_repeat::RepeatSettings;
+use crate::pairing::PairingSettings;
+use crate::policy::PolicySettings;
+use crate::quic::QuicSettings;
+use crate::scroll::ScrollSettings;
+use crate::tls::TlsSettings;
+
+/// Environment variable naming the config file
+const CONFIG_ENV: &str = "SAMESAME_CONFIG";
+
+/// Config file used when the environment variable is not set
+const DEFAULT_CONFIG_FILE: &str = "samesame-server.json";
+
+/// Server settings, read from a JSON file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Acceleration applied to relative pointer movement
+    pub acceleration: AccelerationCurve,
+    /// Ask clients for unaccelerated deltas, so only `acceleration` applies
+    pub raw_deltas: bool,
+    /// Conversion of scroll deltas into wheel input
+    pub scroll: ScrollSettings,
+    /// Whether key repeats come from the client or are generated here
+    pub key_repeat: RepeatSettings,
+    /// Clipboard synchronization with clients
+    pub clipboard: ClipboardSettings,
+    /// Files clients may send and where they are stored
+    pub files: ReceiveSettings,
+    /// Encryption of client connections
+    pub tls: TlsSettings,
+    /// Which clients may connect
+    pub pairing: PairingSettings,
+    /// What each client may do once connected
+    pub policies: PolicySettings,
+    /// Handing control between several connected clients
+    pub arbitration: ArbitrationSettings,
+    /// Address lists, connection and message limits
+    pub access: AccessSettings,
+    /// Pointer events over UDP
+    pub datagrams: DatagramSettings,
+    /// QUIC transport, next to TCP
+    pub quic: QuicSettings,
+    /// Announcing the server on the local network
+    pub discovery: DiscoverySettings,
+}
+
+impl ServerConfig {
+    /// Load the config from `$SAMESAME_CONFIG` or `samesame-server.json`
+    pub fn load_default() -> Result<Self> {
+        let path = std::env::var_os(CONFIG_ENV)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE));
+        Self::load(path)
+    }
+
+    /// Load the config from `path`, using defaults if the file does not exist
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = fs::read_to_string(path)?;
+        let config: Self = serde_json::from_str(&data)?;
+        config
+            .acceleration
+            .validate()
+            .map_err(|e| anyhow!("Invalid acceleration curve in {}: {}", path.display(), e))?;
+
+        info!("Loaded config from {}", path.display());
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,38 @@
+use samesame_protocol::CursorPositionEvent;
+use std::time::{Duration, Instant};
+
+/// Decides which cursor positions are reported to the client
+///
+/// Positions are throttled to one report per interval, except that reaching
+/// (or leaving) an edge of the virtual desktop is reported immediately so the
+/// client can switch screens without delay.
+pub struct CursorReporter {
+    interval: Duration,
+    last: Option<(CursorPositionEvent, Instant)>,
+}
+
+impl CursorReporter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    /// Feed the current position, returns it if it should be reported now
+    pub fn update(&mut self, position: CursorPositionEvent, now: Instant) -> Option<CursorPositionEvent> {
+        let report = match &self.last {
+            None => true,
+            Some((last, _)) if *last == position => false,
+            Some((last, _)) if last.edge != position.edge => true,
+            Some((_, sent_at)) => now.duration_since(*sent_at) >= self.interval,
+        };
+
+        if report {
+            self.last = Some((position.clone(), now));
+            Some(position)
+        } else {
+            None
+        }
+    }
+}
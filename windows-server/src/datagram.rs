@@ -0,0 +1,188 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use samesame_protocol::datagram::{self as datagrams, DatagramCodec, DatagramOffer, LossSimulator, MAX_DATAGRAM};
+use samesame_protocol::pairing;
+use samesame_protocol::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Pointer events over UDP
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatagramSettings {
+    /// Offer the UDP transport to clients that ask for it
+    pub enabled: bool,
+    /// UDP port, the same number as the TCP port by default
+    pub port: u16,
+    /// Share of datagrams dropped on purpose, to try packet loss (0.0 to 1.0)
+    pub simulated_loss: f64,
+}
+
+impl Default for DatagramSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 24800,
+            simulated_loss: 0.0,
+        }
+    }
+}
+
+/// Receives the pointer datagrams of all connections on one UDP socket
+///
+/// Each connection that asked for the transport has a route with its own
+/// key. A datagram is only passed on if it authenticates with the key of its
+/// session and comes from the address of that connection.
+pub struct DatagramReceiver {
+    port: u16,
+    routes: Mutex<HashMap<u64, Route>>,
+}
+
+struct Route {
+    codec: DatagramCodec,
+    peer: IpAddr,
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl DatagramReceiver {
+    /// Bind the socket and start receiving
+    pub async fn bind(settings: &DatagramSettings) -> Result<Arc<Self>> {
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], settings.port))).await?;
+        let receiver = Arc::new(Self {
+            port: socket.local_addr()?.port(),
+            routes: Mutex::new(HashMap::new()),
+        });
+        info!("Accepting pointer datagrams on UDP port {}", receiver.port);
+
+        let mut loss = None;
+        if settings.simulated_loss > 0.0 {
+            warn!("Dropping {:.0}% of pointer datagrams on purpose", settings.simulated_loss * 100.0);
+            let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+            loss = Some(LossSimulator::new(settings.simulated_loss, seed));
+        }
+        tokio::spawn(receive_loop(socket, receiver.clone(), loss));
+
+        Ok(receiver)
+    }
+
+    /// Open a route for a connection from `peer`, returns the offer for the client
+    pub fn register(self: &Arc<Self>, peer: IpAddr) -> Result<(DatagramOffer, DatagramRoute)> {
+        let offer = DatagramOffer {
+            port: self.port,
+            session: u64::from_le_bytes(pairing::random_bytes()?),
+            key: pairing::random_bytes()?,
+        };
+        let (sender, messages) = mpsc::unbounded_channel();
+        self.routes.lock().insert(
+            offer.session,
+            Route {
+                codec: DatagramCodec::from_offer(&offer),
+                peer: peer.to_canonical(),
+                sender,
+            },
+        );
+
+        let route = DatagramRoute {
+            registration: Some((self.clone(), offer.session)),
+            messages,
+        };
+        Ok((offer, route))
+    }
+
+    /// Pass a datagram to its connection
+    fn dispatch(&self, datagram: &[u8], from: SocketAddr) {
+        let Some(session) = datagrams::session_of(datagram) else {
+            return;
+        };
+        let routes = self.routes.lock();
+        let Some(route) = routes.get(&session) else {
+            debug!("Datagram from {} for unknown session", from);
+            return;
+        };
+        if route.peer != from.ip().to_canonical() {
+            debug!("Datagram from {} for a session of {}", from, route.peer);
+            return;
+        }
+        match route.codec.open(datagram) {
+            Ok(message) => {
+                let _ = route.sender.send(message);
+            }
+            Err(e) => debug!("Dropped datagram from {}: {}", from, e),
+        }
+    }
+}
+
+async fn receive_loop(socket: UdpSocket, receiver: Arc<DatagramReceiver>, mut loss: Option<LossSimulator>) {
+    let mut buffer = [0u8; MAX_DATAGRAM + 1];
+
+    loop {
+        let (n, from) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                // Windows reports ICMP errors of earlier sends here, the socket stays usable
+                debug!("Failed to receive datagram: {}", e);
+                continue;
+            }
+        };
+        if loss.as_mut().is_some_and(LossSimulator::drop_next) {
+            continue;
+        }
+        receiver.dispatch(&buffer[..n], from);
+    }
+}
+
+/// Pointer messages of one connection, until dropped
+pub struct DatagramRoute {
+    /// Receiver and session the route is registered under (UDP only)
+    registration: Option<(Arc<DatagramReceiver>, u64)>,
+    messages: mpsc::UnboundedReceiver<Message>,
+}
+
+impl DatagramRoute {
+    /// Pointer messages from the datagrams of a QUIC connection
+    ///
+    /// QUIC encrypts and authenticates them, so they carry just the message.
+    pub fn quic(connection: quinn::Connection) -> Self {
+        let (sender, messages) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let datagram = tokio::select! {
+                    datagram = connection.read_datagram() => datagram,
+                    _ = sender.closed() => return,
+                };
+                let Ok(datagram) = datagram else {
+                    return;
+                };
+                match datagrams::decode_pointer(&datagram) {
+                    Ok(message) => {
+                        let _ = sender.send(message);
+                    }
+                    Err(e) => debug!("Dropped datagram from {}: {}", connection.remote_address(), e),
+                }
+            }
+        });
+
+        Self {
+            registration: None,
+            messages,
+        }
+    }
+
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.messages.recv().await
+    }
+}
+
+impl Drop for DatagramRoute {
+    fn drop(&mut self) {
+        if let Some((receiver, session)) = &self.registration {
+            receiver.routes.lock().remove(session);
+        }
+    }
+}
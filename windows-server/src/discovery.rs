@@ -0,0 +1,100 @@
+use anyhow::Result;
+use mdns_sd::{IfKind, ServiceDaemon, ServiceInfo};
+use samesame_protocol::discovery::{Announcement, MAX_NAME_LENGTH, SERVICE_TYPE};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::info;
+
+/// Name used when neither the config nor the environment has one
+const FALLBACK_NAME: &str = "SameSame Server";
+
+/// Announcing the server on the local network
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscoverySettings {
+    /// Announce the server over mDNS, so clients can list it
+    pub enabled: bool,
+    /// Name shown to clients, the computer name if not set
+    pub name: Option<String>,
+}
+
+impl Default for DiscoverySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            name: None,
+        }
+    }
+}
+
+/// Longest wait for the goodbye to go out when the announcer is dropped
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Keeps the server announced until dropped
+pub struct Announcer {
+    daemon: ServiceDaemon,
+    /// Full DNS-SD name of the announced instance
+    fullname: String,
+}
+
+impl Drop for Announcer {
+    fn drop(&mut self) {
+        // Shutting down alone sends nothing; the goodbye of the unregister lets
+        // clients drop the server right away
+        if let Ok(status) = self.daemon.unregister(&self.fullname) {
+            let _ = status.recv_timeout(GOODBYE_TIMEOUT);
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Start answering mDNS queries for the server
+///
+/// Announced on every interface, loopback included, so a client on the same
+/// machine finds it too.
+pub fn announce(announcement: Announcement) -> Result<Announcer> {
+    let host = format!("{}-samesame.local.", host_label(&announcement.name));
+    let properties = announcement.properties();
+    let service = ServiceInfo::new(SERVICE_TYPE, &announcement.name, &host, (), announcement.port, &properties[..])?
+        .enable_addr_auto();
+
+    let fullname = service.get_fullname().to_string();
+
+    let daemon = ServiceDaemon::new()?;
+    daemon.enable_interface(IfKind::LoopbackV4)?;
+    daemon.register(service)?;
+    info!("Announcing \"{}\" on the local network", announcement.name);
+    Ok(Announcer { daemon, fullname })
+}
+
+/// Name to announce: the configured one, else the computer name
+pub fn server_name(settings: &DiscoverySettings) -> String {
+    let name = settings
+        .name
+        .clone()
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.chars().filter(|c| !c.is_control()).collect::<String>())
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| FALLBACK_NAME.to_string());
+
+    // Cut at a character boundary
+    let mut end = name.len().min(MAX_NAME_LENGTH);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    name[..end].to_string()
+}
+
+/// DNS label for the host record, derived from the server name
+fn host_label(name: &str) -> String {
+    let label: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let label = label.trim_matches('-');
+    match label.is_empty() {
+        true => "server".to_string(),
+        false => label.chars().take(MAX_NAME_LENGTH - "-samesame".len()).collect(),
+    }
+}
@@ -0,0 +1,543 @@
+use parking_lot::Mutex;
+use samesame_protocol::transfer::{
+    hash_hex, FileChunk, FileHasher, FileOffer, FileTransfer, ACK_INTERVAL, FILE_CHUNK_SIZE,
+};
+use samesame_protocol::{InputEvent, Message};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::outgoing::Outgoing;
+
+/// Directory inside the receive directory that holds unfinished files
+const PARTIAL_DIR: &str = ".partial";
+
+/// Unfinished files not written to for this long are deleted
+const PARTIAL_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Failed transfers remembered, so chunks still in flight for them are dropped silently
+const MAX_FAILED: usize = 64;
+
+/// Partial files a transfer is writing to, in any session
+static OPEN_PARTIALS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Names Windows reserves for devices, in any directory and with any extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Which files the server accepts and where they go
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReceiveSettings {
+    /// Accept files at all
+    pub enabled: bool,
+    /// Directory received files are stored in
+    pub directory: PathBuf,
+    /// Largest file accepted
+    pub max_file_bytes: u64,
+    /// Replace existing files instead of adding " (1)", " (2)", ... to the name
+    pub overwrite: bool,
+}
+
+impl Default for ReceiveSettings {
+    fn default() -> Self {
+        let directory = std::env::var_os("USERPROFILE")
+            .map(|home| PathBuf::from(home).join("Downloads").join("SameSame"))
+            .unwrap_or_else(|| PathBuf::from("received"));
+        Self {
+            enabled: true,
+            directory,
+            max_file_bytes: 4 * 1024 * 1024 * 1024,
+            overwrite: false,
+        }
+    }
+}
+
+/// A file being received
+struct Incoming {
+    offer: FileOffer,
+    /// Sanitized name the file is stored under
+    name: String,
+    part_path: PathBuf,
+    /// Keeps other transfers of the same content out of the partial file
+    _claim: PartialClaim,
+    file: File,
+    hasher: FileHasher,
+    received: u64,
+    /// Bytes reported to the sender with the last progress message
+    acknowledged: u64,
+}
+
+/// Receives the files of one client
+///
+/// Data is written to `<directory>/.partial/<sha256>.part` first, so an
+/// interrupted transfer of the same content continues where it stopped, even
+/// over a new connection. Only verified files are moved to the directory.
+pub struct FileReceiver {
+    settings: ReceiveSettings,
+    incoming: HashMap<u64, Incoming>,
+    /// Latest transfers that failed; chunks still in flight for them are dropped silently
+    failed: VecDeque<u64>,
+}
+
+impl FileReceiver {
+    pub fn new(settings: ReceiveSettings) -> Self {
+        Self {
+            settings,
+            incoming: HashMap::new(),
+            failed: VecDeque::new(),
+        }
+    }
+
+    /// Delete partial files of transfers that were abandoned long ago
+    pub fn remove_expired_partials(&self) {
+        let Ok(entries) = fs::read_dir(self.settings.directory.join(PARTIAL_DIR)) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let expired = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age > PARTIAL_EXPIRY));
+            if !expired || path.extension().is_none_or(|extension| extension != "part") {
+                continue;
+            }
+            // A transfer that is still writing keeps its file
+            let Some(_claim) = PartialClaim::acquire(&path) else {
+                continue;
+            };
+            match fs::remove_file(&path) {
+                Ok(()) => debug!("Removed expired partial file {}", path.display()),
+                Err(e) => warn!("Failed to remove expired partial file {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    /// Handle a message from the client, returns the replies
+    pub fn handle(&mut self, message: FileTransfer) -> Vec<FileTransfer> {
+        match message {
+            FileTransfer::Offer(offer) => self.offer(offer),
+            FileTransfer::Chunk(chunk) => self.chunk(chunk).into_iter().collect(),
+            FileTransfer::Cancel { transfer_id } => {
+                if let Some(incoming) = self.incoming.remove(&transfer_id) {
+                    info!("Transfer of {} cancelled at {} bytes", incoming.name, incoming.received);
+                }
+                Vec::new()
+            }
+            other => {
+                warn!("Unexpected file transfer message: {:?}", other);
+                Vec::new()
+            }
+        }
+    }
+
+    fn offer(&mut self, offer: FileOffer) -> Vec<FileTransfer> {
+        let transfer_id = offer.transfer_id;
+        let reject = |reason: String| vec![FileTransfer::Reject { transfer_id, reason }];
+
+        if !self.settings.enabled {
+            return reject("Receiving files is disabled".to_string());
+        }
+        let Some(name) = sanitize_file_name(&offer.name) else {
+            return reject(format!("Invalid file name {:?}", offer.name));
+        };
+        if offer.size > self.settings.max_file_bytes {
+            return reject(format!(
+                "{} has {} bytes, the limit is {}",
+                name, offer.size, self.settings.max_file_bytes
+            ));
+        }
+
+        let incoming = match self.open_partial(offer, name) {
+            Ok(incoming) => incoming,
+            Err(e) => return reject(format!("Cannot store file: {}", e)),
+        };
+        let offset = incoming.received;
+        if offset > 0 {
+            info!("Resuming {} at {} of {} bytes", incoming.name, offset, incoming.offer.size);
+        } else {
+            info!("Receiving {} ({} bytes)", incoming.name, incoming.offer.size);
+        }
+
+        let mut replies = vec![FileTransfer::Accept { transfer_id, offset }];
+        if offset == incoming.offer.size {
+            // Everything arrived before the connection dropped
+            replies.push(self.finish(incoming));
+        } else {
+            self.incoming.insert(transfer_id, incoming);
+        }
+        replies
+    }
+
+    /// Open the partial file for an offer and hash what it already holds
+    fn open_partial(&self, offer: FileOffer, name: String) -> io::Result<Incoming> {
+        let partial_dir = self.settings.directory.join(PARTIAL_DIR);
+        fs::create_dir_all(&partial_dir)?;
+        let part_path = partial_dir.join(format!("{}.part", hash_hex(&offer.sha256)));
+        let claim = PartialClaim::acquire(&part_path)
+            .ok_or_else(|| io::Error::other("the same file is already being received"))?;
+
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&part_path)?;
+        let mut received = file.metadata()?.len();
+        if received > offer.size {
+            file.set_len(0)?;
+            received = 0;
+        }
+
+        let mut hasher = FileHasher::new();
+        let mut buffer = vec![0u8; FILE_CHUNK_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        let mut existing = (&mut file).take(received);
+        loop {
+            let n = existing.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+        }
+        file.seek(SeekFrom::Start(received))?;
+
+        Ok(Incoming {
+            offer,
+            name,
+            part_path,
+            _claim: claim,
+            file,
+            hasher,
+            received,
+            acknowledged: received,
+        })
+    }
+
+    fn chunk(&mut self, chunk: FileChunk) -> Option<FileTransfer> {
+        let transfer_id = chunk.transfer_id;
+        let Some(incoming) = self.incoming.get_mut(&transfer_id) else {
+            if self.failed.contains(&transfer_id) {
+                return None;
+            }
+            return Some(FileTransfer::Failed {
+                transfer_id,
+                reason: "Unknown transfer".to_string(),
+            });
+        };
+
+        let end = chunk.offset + chunk.data.len() as u64;
+        let error = if chunk.offset != incoming.received {
+            Some(format!("Expected data at {}, got {}", incoming.received, chunk.offset))
+        } else if end > incoming.offer.size {
+            Some(format!("Data beyond the announced size of {} bytes", incoming.offer.size))
+        } else {
+            incoming.file.write_all(&chunk.data).err().map(|e| format!("Write failed: {}", e))
+        };
+        if let Some(reason) = error {
+            // The partial file stays for a later resume
+            self.incoming.remove(&transfer_id);
+            if self.failed.len() == MAX_FAILED {
+                self.failed.pop_front();
+            }
+            self.failed.push_back(transfer_id);
+            return Some(FileTransfer::Failed { transfer_id, reason });
+        }
+
+        incoming.hasher.update(&chunk.data);
+        incoming.received = end;
+
+        if incoming.received == incoming.offer.size {
+            let incoming = self.incoming.remove(&transfer_id)?;
+            return Some(self.finish(incoming));
+        }
+        if incoming.received - incoming.acknowledged >= ACK_INTERVAL {
+            incoming.acknowledged = incoming.received;
+            return Some(FileTransfer::Progress {
+                transfer_id,
+                received: incoming.received,
+            });
+        }
+        None
+    }
+
+    /// Verify a complete file and move it into the receive directory
+    fn finish(&self, incoming: Incoming) -> FileTransfer {
+        let transfer_id = incoming.offer.transfer_id;
+        let Incoming {
+            offer,
+            name,
+            part_path,
+            file,
+            hasher,
+            ..
+        } = incoming;
+        drop(file);
+
+        if hasher.finish() != offer.sha256 {
+            warn!("Received {} does not match its hash, discarding it", name);
+            let _ = fs::remove_file(&part_path);
+            return FileTransfer::Failed {
+                transfer_id,
+                reason: "File content does not match its hash".to_string(),
+            };
+        }
+
+        let target = target_path(&self.settings.directory, &name, self.settings.overwrite);
+        if let Err(e) = fs::rename(&part_path, &target) {
+            return FileTransfer::Failed {
+                transfer_id,
+                reason: format!("Cannot move file into place: {}", e),
+            };
+        }
+
+        info!("Received {}", target.display());
+        FileTransfer::Complete {
+            transfer_id,
+            path: target.display().to_string(),
+        }
+    }
+}
+
+/// Exclusive use of a partial file, released on drop
+struct PartialClaim(PathBuf);
+
+impl PartialClaim {
+    /// Claim `path`, `None` if another transfer holds it
+    fn acquire(path: &Path) -> Option<Self> {
+        let mut open = OPEN_PARTIALS.lock();
+        if open.iter().any(|claimed| claimed == path) {
+            return None;
+        }
+        open.push(path.to_path_buf());
+        Some(Self(path.to_path_buf()))
+    }
+}
+
+impl Drop for PartialClaim {
+    fn drop(&mut self) {
+        OPEN_PARTIALS.lock().retain(|claimed| *claimed != self.0);
+    }
+}
+
+/// Hands a client's file transfers to a [`FileReceiver`] in the background
+///
+/// Writing and hashing happen on the blocking thread pool, so a slow disk
+/// never holds up the input of the session. The task starts with the first
+/// transfer message and ends once the worker is dropped and its queue is empty.
+pub struct FileWorker {
+    settings: ReceiveSettings,
+    /// Where replies to the client go
+    replies: Outgoing,
+    /// Messages for the running task, with the sequence to answer with
+    queue: Option<mpsc::UnboundedSender<(u64, FileTransfer)>>,
+}
+
+impl FileWorker {
+    pub fn new(settings: ReceiveSettings, replies: Outgoing) -> Self {
+        Self {
+            settings,
+            replies,
+            queue: None,
+        }
+    }
+
+    /// Queue a message from the client, starting the task if needed
+    pub fn handle(&mut self, sequence: u64, message: FileTransfer) {
+        let queue = self.queue.get_or_insert_with(|| {
+            let (queue, messages) = mpsc::unbounded_channel();
+            tokio::spawn(receive_files(self.settings.clone(), messages, self.replies.clone()));
+            queue
+        });
+        let _ = queue.send((sequence, message));
+    }
+}
+
+/// Run the receiver for queued messages and send its replies
+async fn receive_files(
+    settings: ReceiveSettings,
+    mut messages: mpsc::UnboundedReceiver<(u64, FileTransfer)>,
+    replies: Outgoing,
+) {
+    let receiver = FileReceiver::new(settings);
+    let Some((mut receiver, ())) = blocking(receiver, |receiver| receiver.remove_expired_partials()).await else {
+        return;
+    };
+
+    while let Some((sequence, message)) = messages.recv().await {
+        let Some((handled, answers)) = blocking(receiver, |receiver| receiver.handle(message)).await else {
+            return;
+        };
+        receiver = handled;
+        for answer in answers {
+            if !replies.send(Message::new(sequence, InputEvent::FileTransfer(answer))) {
+                return;
+            }
+        }
+    }
+}
+
+/// Run `work` on the blocking pool, `None` if it panicked
+async fn blocking<T: Send + 'static>(
+    mut receiver: FileReceiver,
+    work: impl FnOnce(&mut FileReceiver) -> T + Send + 'static,
+) -> Option<(FileReceiver, T)> {
+    let result = tokio::task::spawn_blocking(move || {
+        let output = work(&mut receiver);
+        (receiver, output)
+    })
+    .await;
+    result.inspect_err(|e| warn!("File receiver failed: {}", e)).ok()
+}
+
+/// A file name that is safe to create on Windows, `None` if nothing usable is left
+///
+/// Directories are stripped, so a name can never leave the receive directory.
+pub fn sanitize_file_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_control() || "<>:\"|?*".contains(c) { '_' } else { c })
+        .collect();
+
+    // Windows drops trailing dots and spaces, which would change the name
+    let name = name.trim().trim_end_matches(['.', ' ']);
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved)) {
+        return Some(format!("_{}", name));
+    }
+    Some(name.to_string())
+}
+
+/// Path for a received file, numbering the name if it is taken
+fn target_path(directory: &Path, name: &str, overwrite: bool) -> PathBuf {
+    let path = directory.join(name);
+    if overwrite || !path.exists() {
+        return path;
+    }
+
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (name, None),
+    };
+    (1..)
+        .map(|n| match extension {
+            Some(extension) => directory.join(format!("{} ({}).{}", stem, n, extension)),
+            None => directory.join(format!("{} ({})", stem, n)),
+        })
+        .find(|path| !path.exists())
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    /// Receive directory of one test, emptied first
+    fn settings(test: &str) -> ReceiveSettings {
+        let directory = std::env::temp_dir().join(format!("samesame-files-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        ReceiveSettings {
+            directory,
+            ..ReceiveSettings::default()
+        }
+    }
+
+    fn offer(transfer_id: u64, content: &[u8]) -> FileTransfer {
+        let mut hasher = FileHasher::new();
+        hasher.update(content);
+        FileTransfer::Offer(FileOffer {
+            transfer_id,
+            name: "notes.txt".to_string(),
+            size: content.len() as u64,
+            sha256: hasher.finish(),
+        })
+    }
+
+    fn chunk(transfer_id: u64, offset: u64, data: &[u8]) -> FileTransfer {
+        FileTransfer::Chunk(FileChunk {
+            transfer_id,
+            offset,
+            data: data.to_vec(),
+        })
+    }
+
+    #[test]
+    fn interrupted_transfer_resumes_in_a_new_receiver() {
+        let settings = settings("resume");
+        let content = b"first half, second half";
+        let mut receiver = FileReceiver::new(settings.clone());
+        receiver.handle(offer(1, content));
+        assert!(receiver.handle(chunk(1, 0, &content[..11])).is_empty());
+        drop(receiver);
+
+        let mut receiver = FileReceiver::new(settings.clone());
+        let replies = receiver.handle(offer(7, content));
+        assert!(matches!(replies[..], [FileTransfer::Accept { transfer_id: 7, offset: 11 }]));
+        let replies = receiver.handle(chunk(7, 11, &content[11..]));
+        assert!(matches!(replies[..], [FileTransfer::Complete { transfer_id: 7, .. }]));
+        assert_eq!(fs::read(settings.directory.join("notes.txt")).unwrap(), content);
+        fs::remove_dir_all(&settings.directory).unwrap();
+    }
+
+    #[test]
+    fn same_content_is_received_by_one_transfer_at_a_time() {
+        let settings = settings("claim");
+        let content = b"shared content";
+        let mut first = FileReceiver::new(settings.clone());
+        let mut second = FileReceiver::new(settings.clone());
+        assert!(matches!(first.handle(offer(1, content))[..], [FileTransfer::Accept { .. }]));
+        assert!(matches!(second.handle(offer(1, content))[..], [FileTransfer::Reject { .. }]));
+
+        first.handle(FileTransfer::Cancel { transfer_id: 1 });
+        assert!(matches!(second.handle(offer(1, content))[..], [FileTransfer::Accept { .. }]));
+        fs::remove_dir_all(&settings.directory).unwrap();
+    }
+
+    #[test]
+    fn failed_transfers_are_remembered_up_to_the_limit() {
+        let settings = settings("failed");
+        let mut receiver = FileReceiver::new(settings.clone());
+        for transfer_id in 0..=MAX_FAILED as u64 {
+            receiver.handle(offer(transfer_id, &transfer_id.to_le_bytes()));
+            let replies = receiver.handle(chunk(transfer_id, 1, b"x"));
+            assert!(matches!(replies[..], [FileTransfer::Failed { .. }]));
+            // Late chunks of a failed transfer get no answer
+            assert!(receiver.handle(chunk(transfer_id, 2, b"x")).is_empty());
+        }
+        assert_eq!(receiver.failed.len(), MAX_FAILED);
+        assert!(matches!(receiver.handle(chunk(0, 2, b"x"))[..], [FileTransfer::Failed { .. }]));
+        fs::remove_dir_all(&settings.directory).unwrap();
+    }
+
+    #[test]
+    fn only_expired_partial_files_are_removed() {
+        let settings = settings("expiry");
+        let partial_dir = settings.directory.join(PARTIAL_DIR);
+        fs::create_dir_all(&partial_dir).unwrap();
+        let old = SystemTime::now() - PARTIAL_EXPIRY - Duration::from_secs(60);
+        for name in ["old.part", "claimed.part", "old.txt"] {
+            File::create(partial_dir.join(name)).unwrap().set_modified(old).unwrap();
+        }
+        fs::write(partial_dir.join("recent.part"), b"data").unwrap();
+
+        let receiver = FileReceiver::new(settings.clone());
+        let claim = PartialClaim::acquire(&partial_dir.join("claimed.part")).unwrap();
+        receiver.remove_expired_partials();
+        drop(claim);
+
+        assert!(!partial_dir.join("old.part").exists());
+        assert!(partial_dir.join("claimed.part").exists());
+        assert!(partial_dir.join("old.txt").exists());
+        assert!(partial_dir.join("recent.part").exists());
+        fs::remove_dir_all(&settings.directory).unwrap();
+    }
+}
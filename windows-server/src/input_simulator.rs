@@ -1,14 +1,35 @@
 use anyhow::{Result, anyhow};
 use samesame_protocol::{
     InputEvent, KeyboardEvent, MouseMoveEvent, MouseButtonEvent, MouseScrollEvent,
-    GestureEvent, GestureType, MouseButton,
+    GestureEvent, GestureType, MouseButton, EnterScreenEvent, CursorPositionEvent,
+    ConsumerControlEvent, LockState, Modifiers,
 };
+use samesame_protocol::consumer::ConsumerKey;
+use samesame_protocol::layout::Edge;
+use samesame_protocol::mapping::{CoordinateSpace, MonitorInfo, ScreenInfo};
+use parking_lot::Mutex;
 use std::mem;
 use tracing::{debug, warn};
 
+use crate::lock_keys::LockKey;
+use crate::scroll::{ScrollSettings, ScrollTranslator, WheelDelta};
+use crate::text_input::TextKeystroke;
+
+/// Mouse parameters (threshold1, threshold2, acceleration) before acceleration was disabled
+#[cfg(windows)]
+static SAVED_MOUSE_PARAMS: Mutex<Option<[i32; 3]>> = Mutex::new(None);
+
 #[cfg(windows)]
 use windows::Win32::{
-    Foundation::POINT,
+    Foundation::{BOOL, LPARAM, POINT, RECT},
+    Graphics::Gdi::{
+        EnumDisplayMonitors, GetMonitorInfoW, MonitorFromPoint, HDC, HMONITOR, MONITORINFO,
+        MONITOR_DEFAULTTONEAREST,
+    },
+    UI::HiDpi::{
+        GetDpiForMonitor, SetProcessDpiAwarenessContext, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
+        MDT_EFFECTIVE_DPI,
+    },
     UI::Input::KeyboardAndMouse::*,
     UI::WindowsAndMessaging::*,
 };
@@ -21,16 +42,190 @@ pub fn simulate_event(event: &InputEvent) -> Result<()> {
         InputEvent::MouseButton(btn_event) => simulate_mouse_button(btn_event),
         InputEvent::MouseScroll(scroll_event) => simulate_mouse_scroll(scroll_event),
         InputEvent::Gesture(gesture_event) => simulate_gesture(gesture_event),
-        InputEvent::Ping | InputEvent::Pong => Ok(()),
+        InputEvent::EnterScreen(enter_event) => simulate_enter_screen(enter_event),
+        InputEvent::LeaveScreen => {
+            debug!("Pointer left this screen");
+            Ok(())
+        }
+        InputEvent::Ping
+        | InputEvent::Pong
+        | InputEvent::CursorPosition(_)
+        | InputEvent::ScreenInfo(_)
+        | InputEvent::SetPointerMode(_)
+        | InputEvent::RequestRawDeltas(_)
+        | InputEvent::Clipboard(_)
+        | InputEvent::FileTransfer(_)
+        | InputEvent::Arbitration(_)
+        | InputEvent::PointerTransport(_) => Ok(()),
+        InputEvent::ConsumerControl(consumer_event) => simulate_consumer_control(consumer_event),
+        InputEvent::LockState(_) => Ok(()),
+        InputEvent::TextInput(_) => {
+            // Typed by the session, which keeps track of the marked text
+            debug!("Text input outside a session");
+            Ok(())
+        }
+    }
+}
+
+/// Current cursor position, relative to the monitor it is on
+#[cfg(windows)]
+pub fn cursor_position() -> Option<CursorPositionEvent> {
+    unsafe {
+        let mut point = POINT::default();
+        GetCursorPos(&mut point).ok()?;
+
+        let monitor = MonitorFromPoint(point, MONITOR_DEFAULTTONEAREST);
+        let mut info = MONITORINFO {
+            cbSize: mem::size_of::<MONITORINFO>() as u32,
+            ..Default::default()
+        };
+        if !GetMonitorInfoW(monitor, &mut info).as_bool() {
+            return None;
+        }
+        let rect = info.rcMonitor;
+        let width = (rect.right - rect.left - 1).max(1) as f64;
+        let height = (rect.bottom - rect.top - 1).max(1) as f64;
+
+        Some(CursorPositionEvent {
+            x: (point.x - rect.left) as f64 / width,
+            y: (point.y - rect.top) as f64 / height,
+            screen: monitor_index(monitor),
+            edge: virtual_desktop_edge(point),
+        })
+    }
+}
+
+/// Edge of the virtual desktop (all monitors) the point touches
+#[cfg(windows)]
+fn virtual_desktop_edge(point: POINT) -> Option<Edge> {
+    unsafe {
+        let left = GetSystemMetrics(SM_XVIRTUALSCREEN);
+        let top = GetSystemMetrics(SM_YVIRTUALSCREEN);
+        let right = left + GetSystemMetrics(SM_CXVIRTUALSCREEN) - 1;
+        let bottom = top + GetSystemMetrics(SM_CYVIRTUALSCREEN) - 1;
+
+        if point.x <= left {
+            Some(Edge::Left)
+        } else if point.x >= right {
+            Some(Edge::Right)
+        } else if point.y <= top {
+            Some(Edge::Top)
+        } else if point.y >= bottom {
+            Some(Edge::Bottom)
+        } else {
+            None
+        }
+    }
+}
+
+/// Index of a monitor in display enumeration order
+#[cfg(windows)]
+fn monitor_index(monitor: HMONITOR) -> u32 {
+    enumerate_monitors()
+        .iter()
+        .position(|m| *m == monitor)
+        .unwrap_or(0) as u32
+}
+
+/// All monitors in display enumeration order
+#[cfg(windows)]
+fn enumerate_monitors() -> Vec<HMONITOR> {
+    unsafe extern "system" fn collect(monitor: HMONITOR, _: HDC, _: *mut RECT, data: LPARAM) -> BOOL {
+        let monitors = &mut *(data.0 as *mut Vec<HMONITOR>);
+        monitors.push(monitor);
+        BOOL::from(true)
     }
+
+    let mut monitors: Vec<HMONITOR> = Vec::new();
+    unsafe {
+        let _ = EnumDisplayMonitors(
+            HDC::default(),
+            None,
+            Some(collect),
+            LPARAM(&mut monitors as *mut Vec<HMONITOR> as isize),
+        );
+    }
+    monitors
+}
+
+/// Use physical pixels for all cursor APIs, so mixed-DPI setups map correctly
+#[cfg(windows)]
+pub fn enable_dpi_awareness() {
+    unsafe {
+        if let Err(e) = SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) {
+            warn!("Failed to enable per-monitor DPI awareness: {}", e);
+        }
+    }
+}
+
+/// Monitor topology of this machine in physical pixels
+#[cfg(windows)]
+pub fn screen_info() -> ScreenInfo {
+    let monitors = enumerate_monitors()
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, monitor)| unsafe {
+            let mut info = MONITORINFO {
+                cbSize: mem::size_of::<MONITORINFO>() as u32,
+                ..Default::default()
+            };
+            if !GetMonitorInfoW(monitor, &mut info).as_bool() {
+                return None;
+            }
+
+            let mut dpi_x = 96;
+            let mut dpi_y = 96;
+            if GetDpiForMonitor(monitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y).is_err() {
+                dpi_x = 96;
+            }
+
+            let rect = info.rcMonitor;
+            Some(MonitorInfo {
+                id: index as u32,
+                x: rect.left as f64,
+                y: rect.top as f64,
+                width: (rect.right - rect.left) as f64,
+                height: (rect.bottom - rect.top) as f64,
+                scale_factor: dpi_x as f64 / 96.0,
+                primary: info.dwFlags & MONITORINFOF_PRIMARY != 0,
+            })
+        })
+        .collect();
+
+    ScreenInfo {
+        coordinate_space: CoordinateSpace::Physical,
+        monitors,
+    }
+}
+
+/// Place the cursor at the entry point when the pointer crosses over
+#[cfg(windows)]
+fn simulate_enter_screen(event: &EnterScreenEvent) -> Result<()> {
+    debug!("Pointer entered this screen at ({:.3}, {:.3})", event.x, event.y);
+
+    simulate_mouse_move(&MouseMoveEvent {
+        x: event.x,
+        y: event.y,
+        delta_x: 0.0,
+        delta_y: 0.0,
+        timestamp_us: 0,
+    })
 }
 
 #[cfg(windows)]
 fn simulate_keyboard(event: &KeyboardEvent) -> Result<()> {
+    // Unknown codes are dropped: passed through they land on keys like the Windows key
+    let vk_code = samesame_protocol::keymap::windows_vk(event.key_code)
+        .ok_or_else(|| anyhow!("Unknown macOS key code: 0x{:02X}", event.key_code))?;
     let mut inputs = Vec::new();
 
-    // Map macOS modifiers to Windows modifiers
-    let win_mods = event.modifiers.to_windows();
+    // Map macOS modifiers to Windows modifiers; on repeats they are still held
+    // from the first key-down, pressing them again would confuse shortcuts
+    let win_mods = if event.is_repeat {
+        Modifiers::default().to_windows()
+    } else {
+        event.modifiers.to_windows()
+    };
 
     // Press modifiers first
     if win_mods.ctrl {
@@ -46,9 +241,6 @@ fn simulate_keyboard(event: &KeyboardEvent) -> Result<()> {
         inputs.push(create_key_input(VK_LWIN.0 as u16, true, false));
     }
 
-    // Map the actual key
-    let vk_code = map_macos_to_windows_key(event.key_code);
-
     if event.pressed {
         inputs.push(create_key_input(vk_code, true, false));
     } else {
@@ -81,6 +273,116 @@ fn simulate_keyboard(event: &KeyboardEvent) -> Result<()> {
     Ok(())
 }
 
+/// Press or release a media / volume key
+#[cfg(windows)]
+fn simulate_consumer_control(event: &ConsumerControlEvent) -> Result<()> {
+    let Some(key) = event.key() else {
+        return Err(anyhow!("Unknown consumer usage 0x{:02X}", event.usage));
+    };
+
+    let vk = match key {
+        ConsumerKey::PlayPause => VK_MEDIA_PLAY_PAUSE,
+        ConsumerKey::NextTrack | ConsumerKey::FastForward => VK_MEDIA_NEXT_TRACK,
+        ConsumerKey::PreviousTrack | ConsumerKey::Rewind => VK_MEDIA_PREV_TRACK,
+        ConsumerKey::Stop => VK_MEDIA_STOP,
+        ConsumerKey::VolumeUp => VK_VOLUME_UP,
+        ConsumerKey::VolumeDown => VK_VOLUME_DOWN,
+        ConsumerKey::Mute => VK_VOLUME_MUTE,
+        ConsumerKey::BrightnessUp | ConsumerKey::BrightnessDown => {
+            // Windows has no virtual key for brightness
+            debug!("Ignoring {:?}, not supported on Windows", key);
+            return Ok(());
+        }
+    };
+
+    let mut flags = KEYEVENTF_EXTENDEDKEY;
+    if !event.pressed {
+        flags |= KEYEVENTF_KEYUP;
+    }
+
+    let input = INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: vk,
+                wScan: 0,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    };
+
+    send_inputs(&[input])
+}
+
+/// Type text through the Unicode input path
+#[cfg(windows)]
+pub fn type_text(keystrokes: &[TextKeystroke]) -> Result<()> {
+    let mut inputs = Vec::new();
+    for &keystroke in keystrokes {
+        let (down, up) = match keystroke {
+            TextKeystroke::Unicode(unit) => (create_unicode_input(unit, true), create_unicode_input(unit, false)),
+            TextKeystroke::Enter => (create_key_input(VK_RETURN.0, true, false), create_key_input(VK_RETURN.0, false, false)),
+            TextKeystroke::Tab => (create_key_input(VK_TAB.0, true, false), create_key_input(VK_TAB.0, false, false)),
+            TextKeystroke::Backspace => (create_key_input(VK_BACK.0, true, false), create_key_input(VK_BACK.0, false, false)),
+        };
+        inputs.push(down);
+        inputs.push(up);
+    }
+
+    if inputs.is_empty() {
+        return Ok(());
+    }
+    send_inputs(&inputs)
+}
+
+/// Key event for one UTF-16 code unit
+#[cfg(windows)]
+fn create_unicode_input(unit: u16, is_press: bool) -> INPUT {
+    let mut flags = KEYEVENTF_UNICODE;
+    if !is_press {
+        flags |= KEYEVENTF_KEYUP;
+    }
+
+    INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: VIRTUAL_KEY(0),
+                wScan: unit,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    }
+}
+
+/// Current state of the lock keys
+#[cfg(windows)]
+pub fn lock_state() -> LockState {
+    // The low bit of GetKeyState is the toggle state
+    let toggled = |vk: VIRTUAL_KEY| unsafe { GetKeyState(vk.0 as i32) & 1 != 0 };
+    LockState {
+        caps_lock: Some(toggled(VK_CAPITAL)),
+        num_lock: Some(toggled(VK_NUMLOCK)),
+        scroll_lock: Some(toggled(VK_SCROLL)),
+    }
+}
+
+/// Press and release a lock key
+#[cfg(windows)]
+pub fn toggle_lock(key: LockKey) -> Result<()> {
+    let vk = match key {
+        LockKey::Caps => VK_CAPITAL,
+        LockKey::Num => VK_NUMLOCK,
+        LockKey::Scroll => VK_SCROLL,
+    };
+
+    send_inputs(&[create_key_input(vk.0, true, false), create_key_input(vk.0, false, false)])
+}
+
 #[cfg(windows)]
 fn create_key_input(vk_code: u16, is_press: bool, is_unicode: bool) -> INPUT {
     let mut flags = KEYBD_EVENT_FLAGS(0);
@@ -107,30 +409,81 @@ fn create_key_input(vk_code: u16, is_press: bool, is_unicode: bool) -> INPUT {
 
 #[cfg(windows)]
 fn simulate_mouse_move(event: &MouseMoveEvent) -> Result<()> {
-    unsafe {
-        // Get screen dimensions
-        let screen_width = GetSystemMetrics(SM_CXSCREEN);
-        let screen_height = GetSystemMetrics(SM_CYSCREEN);
+    // Coordinates are normalized to the virtual desktop (all monitors), which is
+    // exactly what MOUSEEVENTF_VIRTUALDESK maps 0-65535 onto
+    let abs_x = (event.x * 65535.0) as i32;
+    let abs_y = (event.y * 65535.0) as i32;
+
+    let input = INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0 {
+            mi: MOUSEINPUT {
+                dx: abs_x,
+                dy: abs_y,
+                mouseData: 0,
+                dwFlags: MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK,
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    };
 
-        // Convert normalized coordinates (0.0-1.0) to absolute screen coordinates
-        let abs_x = (event.x * 65535.0) as i32;
-        let abs_y = (event.y * 65535.0) as i32;
+    send_inputs(&[input])?;
 
-        let input = INPUT {
-            r#type: INPUT_MOUSE,
-            Anonymous: INPUT_0 {
-                mi: MOUSEINPUT {
-                    dx: abs_x,
-                    dy: abs_y,
-                    mouseData: 0,
-                    dwFlags: MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK,
-                    time: 0,
-                    dwExtraInfo: 0,
-                },
+    Ok(())
+}
+
+/// Move the cursor by whole pixels
+///
+/// Relative moves go through the system's pointer speed and acceleration
+/// settings, just like a physical mouse.
+#[cfg(windows)]
+pub fn move_relative(dx: i32, dy: i32) -> Result<()> {
+    let input = INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0 {
+            mi: MOUSEINPUT {
+                dx,
+                dy,
+                mouseData: 0,
+                dwFlags: MOUSEEVENTF_MOVE,
+                time: 0,
+                dwExtraInfo: 0,
             },
-        };
+        },
+    };
 
-        send_inputs(&[input])?;
+    send_inputs(&[input])
+}
+
+/// Turn "Enhance pointer precision" off, or back to the user's setting
+///
+/// The original parameters are remembered when acceleration is first
+/// disabled and restored when it is enabled again.
+#[cfg(windows)]
+pub fn set_pointer_acceleration(enabled: bool) -> Result<()> {
+    let mut saved = SAVED_MOUSE_PARAMS.lock();
+
+    unsafe {
+        if enabled {
+            let Some(mut params) = saved.take() else {
+                return Ok(());
+            };
+            SystemParametersInfoW(SPI_SETMOUSE, 0, Some(params.as_mut_ptr().cast()), SPIF_SENDCHANGE)?;
+        } else {
+            let mut params = [0i32; 3];
+            SystemParametersInfoW(
+                SPI_GETMOUSE,
+                0,
+                Some(params.as_mut_ptr().cast()),
+                SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+            )?;
+            if saved.is_none() {
+                *saved = Some(params);
+            }
+            params[2] = 0;
+            SystemParametersInfoW(SPI_SETMOUSE, 0, Some(params.as_mut_ptr().cast()), SPIF_SENDCHANGE)?;
+        }
     }
 
     Ok(())
@@ -181,51 +534,44 @@ fn simulate_mouse_button(event: &MouseButtonEvent) -> Result<()> {
     Ok(())
 }
 
+/// Scroll without accumulation across events (sessions use their own translator)
 #[cfg(windows)]
 fn simulate_mouse_scroll(event: &MouseScrollEvent) -> Result<()> {
-    // Windows expects scroll in units of WHEEL_DELTA (120)
-    // macOS trackpad gives pixel-based scrolling, we need to convert
-    let delta_multiplier = if event.is_pixel_based { 1.0 } else { 120.0 };
-
-    // Vertical scroll (invert for Windows - macOS natural scrolling is opposite)
-    if event.delta_y.abs() > 0.01 {
-        let delta = (-event.delta_y * delta_multiplier) as i32;
-        let input = INPUT {
-            r#type: INPUT_MOUSE,
-            Anonymous: INPUT_0 {
-                mi: MOUSEINPUT {
-                    dx: 0,
-                    dy: 0,
-                    mouseData: delta as u32,
-                    dwFlags: MOUSEEVENTF_WHEEL,
-                    time: 0,
-                    dwExtraInfo: 0,
-                },
-            },
-        };
-        send_inputs(&[input])?;
-    }
+    let delta = ScrollTranslator::new(ScrollSettings::default()).translate(event);
+    scroll(delta)
+}
+
+/// Turn the wheel by the given WHEEL_DELTA units (high-resolution, any amount)
+#[cfg(windows)]
+pub fn scroll(delta: WheelDelta) -> Result<()> {
+    let mut inputs = Vec::new();
 
-    // Horizontal scroll
-    if event.delta_x.abs() > 0.01 {
-        let delta = (event.delta_x * delta_multiplier) as i32;
-        let input = INPUT {
+    for (amount, flag) in [
+        (delta.vertical, MOUSEEVENTF_WHEEL),
+        (delta.horizontal, MOUSEEVENTF_HWHEEL),
+    ] {
+        if amount == 0 {
+            continue;
+        }
+        inputs.push(INPUT {
             r#type: INPUT_MOUSE,
             Anonymous: INPUT_0 {
                 mi: MOUSEINPUT {
                     dx: 0,
                     dy: 0,
-                    mouseData: delta as u32,
-                    dwFlags: MOUSEEVENTF_HWHEEL,
+                    mouseData: amount as u32,
+                    dwFlags: flag,
                     time: 0,
                     dwExtraInfo: 0,
                 },
             },
-        };
-        send_inputs(&[input])?;
+        });
     }
 
-    Ok(())
+    if inputs.is_empty() {
+        return Ok(());
+    }
+    send_inputs(&inputs)
 }
 
 #[cfg(windows)]
@@ -298,102 +644,6 @@ fn send_inputs(inputs: &[INPUT]) -> Result<()> {
     Ok(())
 }
 
-/// Map macOS virtual key codes to Windows virtual key codes
-/// This is a simplified mapping - full mapping would need a comprehensive table
-#[cfg(windows)]
-fn map_macos_to_windows_key(macos_key: u16) -> u16 {
-    // macOS key codes: https://eastmanreference.com/complete-list-of-applescript-key-codes
-    match macos_key {
-        // Letters A-Z (macOS: 0-11, 12-35)
-        0x00 => 0x41,  // A
-        0x0B => 0x42,  // B
-        0x08 => 0x43,  // C
-        0x02 => 0x44,  // D
-        0x0E => 0x45,  // E
-        0x03 => 0x46,  // F
-        0x05 => 0x47,  // G
-        0x04 => 0x48,  // H
-        0x22 => 0x49,  // I
-        0x26 => 0x4A,  // J
-        0x28 => 0x4B,  // K
-        0x25 => 0x4C,  // L
-        0x2E => 0x4D,  // M
-        0x2D => 0x4E,  // N
-        0x1F => 0x4F,  // O
-        0x23 => 0x50,  // P
-        0x0C => 0x51,  // Q
-        0x0F => 0x52,  // R
-        0x01 => 0x53,  // S
-        0x11 => 0x54,  // T
-        0x20 => 0x55,  // U
-        0x09 => 0x56,  // V
-        0x0D => 0x57,  // W
-        0x07 => 0x58,  // X
-        0x10 => 0x59,  // Y
-        0x06 => 0x5A,  // Z
-
-        // Numbers 0-9
-        0x1D => 0x30,  // 0
-        0x12 => 0x31,  // 1
-        0x13 => 0x32,  // 2
-        0x14 => 0x33,  // 3
-        0x15 => 0x34,  // 4
-        0x17 => 0x35,  // 5
-        0x16 => 0x36,  // 6
-        0x1A => 0x37,  // 7
-        0x1C => 0x38,  // 8
-        0x19 => 0x39,  // 9
-
-        // Special keys
-        0x24 => VK_RETURN.0 as u16,     // Return
-        0x30 => VK_TAB.0 as u16,        // Tab
-        0x31 => VK_SPACE.0 as u16,      // Space
-        0x33 => VK_BACK.0 as u16,       // Delete/Backspace
-        0x35 => VK_ESCAPE.0 as u16,     // Escape
-        0x75 => VK_DELETE.0 as u16,     // Forward Delete
-        0x73 => VK_HOME.0 as u16,       // Home
-        0x77 => VK_END.0 as u16,        // End
-        0x74 => VK_PRIOR.0 as u16,      // Page Up
-        0x79 => VK_NEXT.0 as u16,       // Page Down
-
-        // Arrow keys
-        0x7B => VK_LEFT.0 as u16,       // Left Arrow
-        0x7C => VK_RIGHT.0 as u16,      // Right Arrow
-        0x7E => VK_UP.0 as u16,         // Up Arrow
-        0x7D => VK_DOWN.0 as u16,       // Down Arrow
-
-        // Function keys
-        0x7A => VK_F1.0 as u16,
-        0x78 => VK_F2.0 as u16,
-        0x63 => VK_F3.0 as u16,
-        0x76 => VK_F4.0 as u16,
-        0x60 => VK_F5.0 as u16,
-        0x61 => VK_F6.0 as u16,
-        0x62 => VK_F7.0 as u16,
-        0x64 => VK_F8.0 as u16,
-        0x65 => VK_F9.0 as u16,
-        0x6D => VK_F10.0 as u16,
-        0x67 => VK_F11.0 as u16,
-        0x6F => VK_F12.0 as u16,
-
-        // Punctuation and symbols (German layout specific)
-        0x27 => VK_OEM_7.0 as u16,      // ' (Ä on German keyboard)
-        0x29 => VK_OEM_3.0 as u16,      // ` (Ö on German keyboard)
-        0x21 => VK_OEM_4.0 as u16,      // [ (Ü on German keyboard)
-        0x2B => VK_OEM_COMMA.0 as u16,  // ,
-        0x2F => VK_OEM_PERIOD.0 as u16, // .
-        0x2C => VK_OEM_2.0 as u16,      // /
-        0x18 => VK_OEM_PLUS.0 as u16,   // =
-        0x1B => VK_OEM_MINUS.0 as u16,  // -
-
-        // Default: pass through
-        _ => {
-            warn!("Unknown macOS key code: 0x{:02X}, passing through", macos_key);
-            macos_key
-        }
-    }
-}
-
 #[cfg(not(windows))]
 pub fn simulate_event(_event: &InputEvent) -> Result<()> {
     Err(anyhow!("Input simulation is only supported on Windows"))
@@ -0,0 +1,275 @@
+use samesame_protocol::KeyboardEvent;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Where key repeats come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepeatMode {
+    /// Inject the Mac's autorepeat key-downs as they arrive
+    #[default]
+    Forward,
+    /// Ignore the Mac's repeats and generate them here, so a laggy link
+    /// cannot keep a key repeating after it was released
+    Server,
+}
+
+/// Key repeat settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepeatSettings {
+    pub mode: RepeatMode,
+    /// Time a key has to be held before it starts repeating
+    pub delay_ms: u64,
+    /// Time between two repeats
+    pub interval_ms: u64,
+}
+
+impl Default for RepeatSettings {
+    fn default() -> Self {
+        Self {
+            mode: RepeatMode::Forward,
+            delay_ms: 500,
+            interval_ms: 33,
+        }
+    }
+}
+
+/// Source of the current time
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Tracks the held key and produces server-side repeats
+///
+/// Time comes from the clock the generator was created with, so tests can
+/// run it on a clock they advance by hand.
+pub struct RepeatGenerator {
+    settings: RepeatSettings,
+    clock: Arc<dyn Clock>,
+    /// Last pressed key and when it repeats next
+    held: Option<(KeyboardEvent, Instant)>,
+}
+
+impl RepeatGenerator {
+    pub fn new(settings: RepeatSettings) -> Self {
+        Self::with_clock(settings, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(settings: RepeatSettings, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            settings,
+            clock,
+            held: None,
+        }
+    }
+
+    /// Feed a key event from the client, returns whether it should be injected
+    pub fn handle(&mut self, event: &KeyboardEvent) -> bool {
+        if event.is_repeat {
+            return self.settings.mode == RepeatMode::Forward;
+        }
+
+        if event.pressed {
+            // Only the most recently pressed key repeats, like on a real keyboard
+            let next = self.clock.now() + Duration::from_millis(self.settings.delay_ms);
+            self.held = Some((event.clone(), next));
+        } else if self.held.as_ref().is_some_and(|(held, _)| held.key_code == event.key_code) {
+            self.held = None;
+        }
+        true
+    }
+
+    /// When the next repeat is due, if one is pending
+    pub fn next_repeat(&self) -> Option<Instant> {
+        if self.settings.mode != RepeatMode::Server {
+            return None;
+        }
+        self.held.as_ref().map(|(_, next)| *next)
+    }
+
+    /// Repeat to inject now, if one is due
+    ///
+    /// At most one repeat is produced per call; after a stall the next one
+    /// is scheduled from now instead of catching up with a burst.
+    pub fn poll(&mut self) -> Option<KeyboardEvent> {
+        if self.settings.mode != RepeatMode::Server {
+            return None;
+        }
+
+        let now = self.clock.now();
+        let (held, next) = self.held.as_mut()?;
+        if now < *next {
+            return None;
+        }
+        *next = now + Duration::from_millis(self.settings.interval_ms.max(1));
+
+        Some(KeyboardEvent {
+            is_repeat: true,
+            ..held.clone()
+        })
+    }
+
+    /// Stop repeating, returns the key that was still held
+    pub fn cancel(&mut self) -> Option<KeyboardEvent> {
+        self.held.take().map(|(held, _)| held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use samesame_protocol::Modifiers;
+
+    /// Clock that only moves when told to
+    struct ManualClock(Mutex<Instant>);
+
+    impl ManualClock {
+        fn new() -> Arc<Self> {
+            Arc::new(Self(Mutex::new(Instant::now())))
+        }
+
+        fn advance(&self, ms: u64) {
+            *self.0.lock() += Duration::from_millis(ms);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock()
+        }
+    }
+
+    fn key(key_code: u16, pressed: bool, is_repeat: bool) -> KeyboardEvent {
+        KeyboardEvent {
+            key_code,
+            character: None,
+            pressed,
+            modifiers: Modifiers::default(),
+            is_repeat,
+        }
+    }
+
+    fn server_repeats(clock: &Arc<ManualClock>) -> RepeatGenerator {
+        let settings = RepeatSettings {
+            mode: RepeatMode::Server,
+            delay_ms: 500,
+            interval_ms: 33,
+        };
+        RepeatGenerator::with_clock(settings, clock.clone())
+    }
+
+    #[test]
+    fn forward_mode_passes_mac_repeats() {
+        let clock = ManualClock::new();
+        let mut repeats = RepeatGenerator::with_clock(RepeatSettings::default(), clock.clone());
+
+        assert!(repeats.handle(&key(0x00, true, false)));
+        assert!(repeats.handle(&key(0x00, true, true)));
+        clock.advance(5000);
+        assert_eq!(repeats.next_repeat(), None);
+        assert!(repeats.poll().is_none());
+    }
+
+    #[test]
+    fn server_mode_drops_mac_repeats() {
+        let clock = ManualClock::new();
+        let mut repeats = server_repeats(&clock);
+
+        assert!(repeats.handle(&key(0x00, true, false)));
+        assert!(!repeats.handle(&key(0x00, true, true)));
+        assert!(repeats.handle(&key(0x00, false, false)));
+    }
+
+    #[test]
+    fn repeats_start_after_the_delay_at_the_interval() {
+        let clock = ManualClock::new();
+        let mut repeats = server_repeats(&clock);
+        let pressed_at = clock.now();
+
+        repeats.handle(&key(0x00, true, false));
+        assert_eq!(repeats.next_repeat(), Some(pressed_at + Duration::from_millis(500)));
+
+        clock.advance(499);
+        assert!(repeats.poll().is_none());
+        clock.advance(1);
+        let repeat = repeats.poll().expect("first repeat after the delay");
+        assert!(repeat.is_repeat && repeat.pressed);
+        assert_eq!(repeat.key_code, 0x00);
+
+        // Then one per interval
+        assert!(repeats.poll().is_none());
+        clock.advance(32);
+        assert!(repeats.poll().is_none());
+        clock.advance(1);
+        assert!(repeats.poll().is_some());
+        assert_eq!(repeats.next_repeat(), Some(pressed_at + Duration::from_millis(566)));
+    }
+
+    #[test]
+    fn release_stops_repeating() {
+        let clock = ManualClock::new();
+        let mut repeats = server_repeats(&clock);
+
+        repeats.handle(&key(0x00, true, false));
+        clock.advance(600);
+        assert!(repeats.poll().is_some());
+
+        repeats.handle(&key(0x00, false, false));
+        assert_eq!(repeats.next_repeat(), None);
+        clock.advance(1000);
+        assert!(repeats.poll().is_none());
+    }
+
+    #[test]
+    fn only_the_newest_key_repeats() {
+        let clock = ManualClock::new();
+        let mut repeats = server_repeats(&clock);
+
+        repeats.handle(&key(0x00, true, false));
+        clock.advance(200);
+        repeats.handle(&key(0x01, true, false));
+
+        // Releasing the older key keeps the newer one repeating, from its own press
+        repeats.handle(&key(0x00, false, false));
+        clock.advance(499);
+        assert!(repeats.poll().is_none());
+        clock.advance(1);
+        assert_eq!(repeats.poll().map(|repeat| repeat.key_code), Some(0x01));
+    }
+
+    #[test]
+    fn stalls_do_not_cause_bursts() {
+        let clock = ManualClock::new();
+        let mut repeats = server_repeats(&clock);
+
+        repeats.handle(&key(0x00, true, false));
+        clock.advance(2000);
+        assert!(repeats.poll().is_some());
+        assert!(repeats.poll().is_none(), "missed repeats are not caught up");
+        assert_eq!(repeats.next_repeat(), Some(clock.now() + Duration::from_millis(33)));
+    }
+
+    #[test]
+    fn cancel_returns_the_held_key() {
+        let clock = ManualClock::new();
+        let mut repeats = server_repeats(&clock);
+
+        repeats.handle(&key(0x00, true, false));
+        assert_eq!(repeats.cancel().map(|held| held.key_code), Some(0x00));
+        assert_eq!(repeats.cancel().map(|held| held.key_code), None);
+        clock.advance(1000);
+        assert!(repeats.poll().is_none());
+    }
+}
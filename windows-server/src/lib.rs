@@ -0,0 +1,33 @@
+//! SameSame server: receives input from the Mac client and injects it
+//!
+//! The binary in `main.rs` only loads the config and accepts connections,
+//! everything a connection goes through lives here, so tests can drive a
+//! [`Server`] with a [`MockBackend`](backend::MockBackend).
+
+pub mod access;
+pub mod arbiter;
+pub mod backend;
+// CF_HTML conversion is only needed by the Windows clipboard
+#[cfg_attr(not(windows), allow(dead_code))]
+pub mod clipboard;
+pub mod config;
+mod cursor_feedback;
+pub mod datagram;
+pub mod discovery;
+mod file_receiver;
+#[cfg(windows)]
+pub mod input_simulator;
+mod key_repeat;
+pub mod lock_keys;
+pub mod outgoing;
+pub mod pairing;
+mod pointer;
+pub mod policy;
+pub mod quic;
+pub mod scroll;
+mod server;
+pub mod text_input;
+pub mod tls;
+pub mod transport;
+
+pub use server::{serve, Server};
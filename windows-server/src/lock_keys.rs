@@ -0,0 +1,79 @@
+use samesame_protocol::LockState;
+
+/// A key that toggles a lock state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKey {
+    /// Caps Lock
+    Caps,
+    /// Num Lock
+    Num,
+    /// Scroll Lock
+    Scroll,
+}
+
+/// Keys to press so the actual lock state matches the wanted one
+///
+/// Keys the client does not know about (`None`) are left alone, so a Mac
+/// without Num Lock never switches Num Lock off on the server.
+pub fn keys_to_toggle(wanted: &LockState, actual: &LockState) -> Vec<LockKey> {
+    [
+        (LockKey::Caps, wanted.caps_lock, actual.caps_lock),
+        (LockKey::Num, wanted.num_lock, actual.num_lock),
+        (LockKey::Scroll, wanted.scroll_lock, actual.scroll_lock),
+    ]
+    .into_iter()
+    .filter_map(|(key, wanted, actual)| match (wanted, actual) {
+        (Some(wanted), Some(actual)) if wanted != actual => Some(key),
+        _ => None,
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locks(caps_lock: Option<bool>, num_lock: Option<bool>, scroll_lock: Option<bool>) -> LockState {
+        LockState {
+            caps_lock,
+            num_lock,
+            scroll_lock,
+        }
+    }
+
+    #[test]
+    fn matching_locks_toggle_nothing() {
+        let all_on = locks(Some(true), Some(true), Some(true));
+        let all_off = locks(Some(false), Some(false), Some(false));
+        assert!(keys_to_toggle(&all_on, &all_on).is_empty());
+        assert!(keys_to_toggle(&all_off, &all_off).is_empty());
+    }
+
+    #[test]
+    fn each_mismatch_toggles_its_key() {
+        let keys = [LockKey::Caps, LockKey::Num, LockKey::Scroll];
+        let actual = locks(Some(false), Some(false), Some(false));
+
+        // Every combination of wanted locks, in both directions
+        for mask in 0..8u8 {
+            let on = |bit: u8| Some(mask & (1 << bit) != 0);
+            let wanted = locks(on(0), on(1), on(2));
+            let expected: Vec<LockKey> = (0..3).filter(|bit| mask & (1 << bit) != 0).map(|bit| keys[bit]).collect();
+            assert_eq!(keys_to_toggle(&wanted, &actual), expected, "wanted {:?}", wanted);
+            assert_eq!(keys_to_toggle(&actual, &wanted), expected, "actual {:?}", wanted);
+        }
+    }
+
+    #[test]
+    fn unknown_locks_are_left_alone() {
+        let on = locks(Some(true), Some(true), Some(true));
+        let unknown = locks(None, None, None);
+        assert!(keys_to_toggle(&unknown, &on).is_empty(), "the client does not know");
+        assert!(keys_to_toggle(&on, &unknown).is_empty(), "the server cannot tell");
+
+        // A Mac without Num and Scroll Lock still syncs Caps Lock
+        let mac = locks(Some(true), None, None);
+        let server = locks(Some(false), Some(false), Some(true));
+        assert_eq!(keys_to_toggle(&mac, &server), vec![LockKey::Caps]);
+    }
+}
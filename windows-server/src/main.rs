@@ -1,12 +1,23 @@
 use anyhow::Result;
-use samesame_protocol::{InputEvent, Message};
+use samesame_protocol::clipboard::Clipboard;
+use samesame_protocol::discovery::Announcement;
+use samesame_windows_server::access::AccessControl;
+use samesame_windows_server::arbiter::Arbiter;
+use samesame_windows_server::backend::{self, InputBackend};
+use samesame_windows_server::config::ServerConfig;
+use samesame_windows_server::datagram::DatagramReceiver;
+use samesame_windows_server::pairing::{self, Pairing};
+use samesame_windows_server::policy::SessionLimits;
+use samesame_windows_server::transport::Incoming;
+use samesame_windows_server::{discovery, quic, serve, tls, Server};
 use std::net::SocketAddr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{info, warn, error};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
 
-#[cfg(windows)]
-mod input_simulator;
+/// How often triggered limits are summarized in the log
+const METRICS_LOG_INTERVAL: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,83 +26,159 @@ async fn main() -> Result<()> {
         .with_max_level(tracing::Level::INFO)
         .init();
 
+    let config = Arc::new(ServerConfig::load_default()?);
+
+    // Pairing management: `pairings`, `unpair ID-OR-NAME`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(command) = args.first() {
+        return pairing::run_command(&config.pairing, command, &args[1..]);
+    }
+
+    // Both transports use the same certificate
+    let mut fingerprint = None;
+    let acceptor = if config.tls.enabled {
+        let (acceptor, tls_fingerprint) = tls::acceptor(&config.tls)?;
+        info!("TLS enabled, certificate fingerprint:");
+        info!("  {}", tls_fingerprint);
+        info!("Check that the Mac app shows the same fingerprint when it first connects");
+        fingerprint = Some(tls_fingerprint);
+        Some(acceptor)
+    } else {
+        warn!("TLS is disabled, input is sent unencrypted (set \"tls\": {{ \"enabled\": true }} in the config)");
+        None
+    };
+    let quic = if config.quic.enabled {
+        let (endpoint, quic_fingerprint) = quic::endpoint(&config.quic, &config.tls)?;
+        info!("QUIC enabled on UDP port {}, certificate fingerprint:", config.quic.port);
+        info!("  {}", quic_fingerprint);
+        fingerprint = Some(quic_fingerprint);
+        Some(endpoint)
+    } else {
+        None
+    };
+    let pairing = Arc::new(Pairing::new(config.pairing.clone())?);
+    if !config.pairing.required {
+        warn!("Pairing is off, anyone on the network can send input (remove \"required\": false from \"pairing\" in the config)");
+    }
     let addr: SocketAddr = "0.0.0.0:24800".parse()?;
     let listener = TcpListener::bind(addr).await?;
+    // Kept until the server exits
+    let _announcer = if config.discovery.enabled {
+        let announcement = Announcement::new(
+            discovery::server_name(&config.discovery),
+            addr.port(),
+            acceptor.is_some(),
+            fingerprint,
+            quic.as_ref().map(|_| config.quic.port),
+        );
+        match discovery::announce(announcement) {
+            Ok(announcer) => Some(announcer),
+            Err(e) => {
+                warn!("Cannot announce the server on the local network: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let datagrams = match config.datagrams.enabled {
+        true => Some(DatagramReceiver::bind(&config.datagrams).await?),
+        false => None,
+    };
+    let server = Arc::new(Server {
+        backend: create_backend(),
+        clipboard: create_clipboard(),
+        arbiter: Arc::new(Arbiter::new(config.arbitration.clone())),
+        access: Arc::new(AccessControl::new(config.access.clone())),
+        datagrams,
+        config,
+        acceptor,
+        pairing,
+        sessions: Arc::new(SessionLimits::default()),
+    });
 
     info!("SameSame Windows Server listening on {}", addr);
     info!("Waiting for macOS client to connect...");
+    tokio::spawn(log_metrics(server.access.clone()));
 
     loop {
-        match listener.accept().await {
-            Ok((stream, peer_addr)) => {
-                info!("Client connected from {}", peer_addr);
-                tokio::spawn(async move {
-                    if let Err(e) = handle_client(stream, peer_addr).await {
-                        error!("Error handling client {}: {}", peer_addr, e);
-                    }
-                    info!("Client {} disconnected", peer_addr);
-                });
+        let (incoming, peer_addr) = tokio::select! {
+            result = listener.accept() => match result {
+                Ok((stream, peer_addr)) => (Incoming::Tcp(stream), peer_addr),
+                Err(e) => {
+                    error!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            Some(incoming) = accept_quic(quic.as_ref()) => {
+                let peer_addr = incoming.remote_address();
+                (Incoming::Quic(Box::new(incoming)), peer_addr)
             }
-            Err(e) => {
-                error!("Failed to accept connection: {}", e);
+        };
+
+        let connection = match server.access.admit(peer_addr.ip()) {
+            Ok(connection) => connection,
+            Err(refusal) => {
+                warn!("Refused connection from {}: {}", peer_addr, refusal);
+                incoming.refuse();
+                continue;
             }
-        }
+        };
+        info!("Client connected from {}", peer_addr);
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(server, incoming, peer_addr).await {
+                error!("Error handling client {}: {}", peer_addr, e);
+            }
+            info!("Client {} disconnected", peer_addr);
+            drop(connection);
+        });
     }
 }
 
-async fn handle_client(mut stream: TcpStream, peer_addr: SocketAddr) -> Result<()> {
-    let mut buffer = vec![0u8; 8192];
+/// Next QUIC connection attempt (never without an endpoint)
+async fn accept_quic(endpoint: Option<&quinn::Endpoint>) -> Option<quinn::Incoming> {
+    match endpoint {
+        Some(endpoint) => endpoint.accept().await,
+        None => std::future::pending().await,
+    }
+}
 
-    loop {
-        let n = stream.read(&mut buffer).await?;
+#[cfg(windows)]
+fn create_backend() -> Arc<dyn InputBackend> {
+    samesame_windows_server::input_simulator::enable_dpi_awareness();
+    Arc::new(backend::WindowsBackend)
+}
 
-        if n == 0 {
-            info!("Client {} closed connection", peer_addr);
-            break;
-        }
+#[cfg(not(windows))]
+fn create_backend() -> Arc<dyn InputBackend> {
+    warn!("Input simulation only works on Windows. Events will only be logged.");
+    Arc::new(backend::MockBackend::new())
+}
 
-        // Try to deserialize the message
-        // Note: This is a simplified version that assumes each read contains a complete message
-        // For production, you would want proper framing (e.g., length-prefixed messages)
-        match Message::from_bytes(&buffer[..n]) {
-            Ok(message) => {
-                handle_message(message, &mut stream).await?;
-            }
-            Err(e) => {
-                warn!("Failed to deserialize message: {:?}", e);
-                // Continue reading
-            }
-        }
-    }
+#[cfg(windows)]
+fn create_clipboard() -> Arc<dyn Clipboard> {
+    Arc::new(samesame_windows_server::clipboard::WindowsClipboard::new())
+}
 
-    Ok(())
+#[cfg(not(windows))]
+fn create_clipboard() -> Arc<dyn Clipboard> {
+    Arc::new(samesame_protocol::clipboard::MemoryClipboard::new())
 }
 
-async fn handle_message(message: Message, stream: &mut TcpStream) -> Result<()> {
-    match message.event {
-        InputEvent::Ping => {
-            // Respond with Pong
-            let pong = Message::new(message.sequence, InputEvent::Pong);
-            let bytes = pong.to_bytes()?;
-            stream.write_all(&bytes).await?;
-        }
-        InputEvent::Pong => {
-            // Ignore pong messages
-        }
-        event => {
-            // Simulate the input event on Windows
-            #[cfg(windows)]
-            {
-                if let Err(e) = input_simulator::simulate_event(&event) {
-                    warn!("Failed to simulate event: {}", e);
-                }
-            }
-            #[cfg(not(windows))]
-            {
-                warn!("Input simulation only works on Windows. Received: {:?}", event);
-            }
+/// Summarize the limits that triggered, whenever there is something new
+async fn log_metrics(access: Arc<AccessControl>) {
+    let mut ticker = tokio::time::interval(METRICS_LOG_INTERVAL);
+    let mut last = access.metrics.snapshot();
+
+    loop {
+        ticker.tick().await;
+
+        let metrics = access.metrics.snapshot();
+        if metrics != last {
+            info!("Limits since startup: {}", metrics);
+            last = metrics;
         }
     }
-
-    Ok(())
 }
+
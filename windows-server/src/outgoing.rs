@@ -0,0 +1,79 @@
+//! Queues of messages to a client
+//!
+//! Everything sent to a client goes through a queue of limited size that a
+//! single writer task empties. A client that stops reading fills it up; the
+//! session is then closed instead of buffering without end.
+
+use samesame_protocol::Message;
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Notify};
+
+/// Sending end of a queue to one client
+#[derive(Clone)]
+pub struct Outgoing {
+    sender: mpsc::Sender<Message>,
+    overflow: Arc<Notify>,
+}
+
+impl Outgoing {
+    /// Queue of `capacity` messages, reporting an overflow through `overflow`
+    ///
+    /// Queues of one session share `overflow`, see [`Overflow::occurred`].
+    pub fn channel(capacity: usize, overflow: &Overflow) -> (Self, mpsc::Receiver<Message>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let outgoing = Self {
+            sender,
+            overflow: overflow.0.clone(),
+        };
+        (outgoing, receiver)
+    }
+
+    /// Queue a message, false if the client is gone or not keeping up
+    pub fn send(&self, message: Message) -> bool {
+        match self.sender.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.overflow.notify_one();
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// Signal that a queue of a session overflowed
+#[derive(Default)]
+pub struct Overflow(Arc<Notify>);
+
+impl Overflow {
+    /// Wait until a queue overflows, returns right away if one did already
+    pub async fn occurred(&self) {
+        self.0.notified().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use samesame_protocol::InputEvent;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn full_queue_signals_the_overflow() {
+        let overflow = Overflow::default();
+        let (outgoing, mut receiver) = Outgoing::channel(2, &overflow);
+
+        assert!(outgoing.send(Message::new(1, InputEvent::Ping)));
+        assert!(outgoing.send(Message::new(2, InputEvent::Ping)));
+        let waiting = tokio::time::timeout(Duration::from_millis(10), overflow.occurred()).await;
+        assert!(waiting.is_err(), "no overflow yet");
+
+        assert!(!outgoing.send(Message::new(3, InputEvent::Ping)));
+        overflow.occurred().await;
+        assert_eq!(receiver.recv().await.unwrap().sequence, 1);
+
+        drop(receiver);
+        assert!(!outgoing.send(Message::new(4, InputEvent::Ping)));
+    }
+}
@@ -0,0 +1,495 @@
+use anyhow::{anyhow, bail, Context, Result};
+use parking_lot::Mutex;
+use samesame_protocol::pairing::{
+    self, challenge_proof, frame_length, hex, verify_challenge_proof, ClientFinish, ClientHello, ClientId, PairingKey,
+    Role, ServerHello, Spake2,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{info, warn};
+
+/// Pause after a failed pairing attempt, so guessing PINs takes forever
+const FAILED_PAIRING_DELAY: Duration = Duration::from_secs(3);
+
+/// Longest client name kept in the pairing store
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Which clients may connect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PairingSettings {
+    /// Reject clients that have not been paired with the PIN
+    pub required: bool,
+    /// JSON file the paired clients are stored in, with their keys encrypted by DPAPI
+    pub store: PathBuf,
+}
+
+impl Default for PairingSettings {
+    fn default() -> Self {
+        Self {
+            required: true,
+            store: PathBuf::from("samesame-pairings.json"),
+        }
+    }
+}
+
+/// A client paired with this server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairedClient {
+    #[serde(with = "hex")]
+    pub id: ClientId,
+    /// Name the client gave when pairing
+    pub name: String,
+    /// Pairing key as hex, encrypted for the Windows account the server runs as
+    protected_key: String,
+    /// Seconds since the Unix epoch
+    pub paired_at: u64,
+}
+
+impl PairedClient {
+    fn new(id: ClientId, name: String, key: &PairingKey) -> Result<Self> {
+        Ok(Self {
+            id,
+            name,
+            protected_key: hex::encode(&protect(key)?),
+            paired_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        })
+    }
+
+    /// The pairing key, decrypted
+    fn key(&self) -> Result<PairingKey> {
+        let protected = hex::decode_vec(&self.protected_key).ok_or_else(|| anyhow!("Invalid stored key"))?;
+        unprotect(&protected)?
+            .try_into()
+            .map_err(|_| anyhow!("Stored key of {} has the wrong length", self.name))
+    }
+}
+
+/// Encrypt a pairing key with DPAPI, only the same Windows account can decrypt it
+#[cfg(windows)]
+fn protect(key: &[u8]) -> Result<Vec<u8>> {
+    use windows::Win32::Security::Cryptography::{CryptProtectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPT_INTEGER_BLOB};
+
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: key.len() as u32,
+        pbData: key.as_ptr() as *mut u8,
+    };
+    let mut output = CRYPT_INTEGER_BLOB::default();
+    unsafe {
+        CryptProtectData(&input, None, None, None, None, CRYPTPROTECT_UI_FORBIDDEN, &mut output)?;
+        Ok(take_blob(output))
+    }
+}
+
+#[cfg(windows)]
+fn unprotect(protected: &[u8]) -> Result<Vec<u8>> {
+    use windows::Win32::Security::Cryptography::{CryptUnprotectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPT_INTEGER_BLOB};
+
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: protected.len() as u32,
+        pbData: protected.as_ptr() as *mut u8,
+    };
+    let mut output = CRYPT_INTEGER_BLOB::default();
+    unsafe {
+        CryptUnprotectData(&input, None, None, None, None, CRYPTPROTECT_UI_FORBIDDEN, &mut output)
+            .context("Cannot decrypt the stored pairing key, was the store copied from another account?")?;
+        Ok(take_blob(output))
+    }
+}
+
+/// Copy a blob DPAPI allocated and free it
+#[cfg(windows)]
+unsafe fn take_blob(blob: windows::Win32::Security::Cryptography::CRYPT_INTEGER_BLOB) -> Vec<u8> {
+    use windows::Win32::Foundation::{LocalFree, HLOCAL};
+
+    let bytes = std::slice::from_raw_parts(blob.pbData, blob.cbData as usize).to_vec();
+    LocalFree(HLOCAL(blob.pbData as *mut _));
+    bytes
+}
+
+/// Without DPAPI the key is stored as is, in a file only the owner can read
+#[cfg(not(windows))]
+fn protect(key: &[u8]) -> Result<Vec<u8>> {
+    Ok(key.to_vec())
+}
+
+#[cfg(not(windows))]
+fn unprotect(protected: &[u8]) -> Result<Vec<u8>> {
+    Ok(protected.to_vec())
+}
+
+/// Paired clients, as stored on disk
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PairingStore {
+    clients: Vec<PairedClient>,
+}
+
+impl PairingStore {
+    /// Load the store, starting empty if the file does not exist yet
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = fs::read_to_string(path)?;
+        serde_json::from_str(&data).with_context(|| format!("Invalid pairing store {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // The keys are only encrypted on Windows, elsewhere only the owner may read them
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(path)?.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn clients(&self) -> &[PairedClient] {
+        &self.clients
+    }
+
+    pub fn find(&self, id: &ClientId) -> Option<&PairedClient> {
+        self.clients.iter().find(|client| client.id == *id)
+    }
+
+    /// Remove the clients with the given hex id or name, returns them
+    pub fn revoke(&mut self, id_or_name: &str) -> Vec<PairedClient> {
+        let (revoked, kept) = self
+            .clients
+            .drain(..)
+            .partition(|client| hex::encode(&client.id) == id_or_name || client.name == id_or_name);
+        self.clients = kept;
+        revoked
+    }
+}
+
+/// Who is on the other end of an authenticated connection
+#[derive(Debug, Clone)]
+pub struct PairedPeer {
+    pub id: ClientId,
+    pub name: String,
+}
+
+/// PIN pairing and authentication of clients, shared by all connections
+///
+/// The store is read on every connection, so `unpair` takes effect without
+/// restarting the server.
+pub struct Pairing {
+    settings: PairingSettings,
+    pin: Mutex<PinState>,
+    /// Serializes read-modify-write of the store
+    store_lock: Arc<Mutex<()>>,
+}
+
+struct PinState {
+    pin: String,
+    /// No pairing attempts before this, after a failure
+    blocked_until: Option<Instant>,
+}
+
+impl Pairing {
+    /// Generate the first PIN and show it
+    pub fn new(settings: PairingSettings) -> Result<Self> {
+        let pin = pairing::generate_pin()?;
+        show_pin(&pin);
+        Ok(Self {
+            settings,
+            pin: Mutex::new(PinState {
+                pin,
+                blocked_until: None,
+            }),
+            store_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Run the handshake with a new connection before any message is handled
+    ///
+    /// Returns the paired client, or `None` for an anonymous client when
+    /// pairing is not required. `binding` ties the proofs to the TLS session
+    /// (empty without TLS). The client waits for [`admit`] or [`refuse`]
+    /// afterwards.
+    pub async fn authenticate(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin),
+        writer: &mut (impl AsyncWrite + Unpin),
+        peer_addr: SocketAddr,
+        binding: &[u8],
+    ) -> Result<Option<PairedPeer>> {
+        let peer = match read_frame(reader).await? {
+            ClientHello::Anonymous if self.settings.required => {
+                return Err(reject(writer, "This server only accepts paired clients, pair with the PIN first").await);
+            }
+            ClientHello::Anonymous => None,
+            ClientHello::Paired { client_id, nonce } => {
+                Some(self.verify_paired(reader, writer, client_id, nonce, binding).await?)
+            }
+            ClientHello::Pair { name, message } => {
+                let Some(pin) = self.take_pin()? else {
+                    return Err(reject(writer, "Too many pairing attempts, wait a moment and try again").await);
+                };
+                // A client that sees the PIN was wrong just hangs up, so every failure counts
+                let result = self.pair(reader, writer, peer_addr, &name, &pin, message, binding).await;
+                if result.is_err() {
+                    self.block();
+                    warn!("Failed pairing attempt from {}", peer_addr);
+                }
+                Some(result?)
+            }
+        };
+
+        Ok(peer)
+    }
+
+    /// Mutual challenge-response with the stored pairing key
+    async fn verify_paired(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin),
+        writer: &mut (impl AsyncWrite + Unpin),
+        client_id: ClientId,
+        client_nonce: pairing::Nonce,
+        binding: &[u8],
+    ) -> Result<PairedPeer> {
+        let store = self.load_store().await?;
+        let Some(client) = store.find(&client_id).cloned() else {
+            return Err(reject(writer, "Unknown or revoked pairing, pair again with the PIN").await);
+        };
+
+        // Decrypting the key is a DPAPI call
+        let (client, key) = tokio::task::spawn_blocking(move || client.key().map(|key| (client, key))).await??;
+        let server_nonce = pairing::random_bytes()?;
+        let proof = challenge_proof(&key, Role::Server, &client_id, &client_nonce, &server_nonce, binding);
+        write_frame(
+            writer,
+            &ServerHello::Challenge {
+                nonce: server_nonce,
+                proof,
+            },
+        )
+        .await?;
+
+        let finish: ClientFinish = read_frame(reader).await?;
+        if let Err(e) = verify_challenge_proof(
+            &key,
+            Role::Client,
+            &client_id,
+            &client_nonce,
+            &server_nonce,
+            binding,
+            &finish.proof,
+        ) {
+            return Err(reject(writer, &e.to_string()).await);
+        }
+
+        Ok(PairedPeer {
+            id: client.id,
+            name: client.name,
+        })
+    }
+
+    /// SPAKE2 with the PIN, storing the new pairing on success
+    #[allow(clippy::too_many_arguments)]
+    async fn pair(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin),
+        writer: &mut (impl AsyncWrite + Unpin),
+        peer_addr: SocketAddr,
+        name: &str,
+        pin: &str,
+        message: pairing::Tag,
+        binding: &[u8],
+    ) -> Result<PairedPeer> {
+        let spake = Spake2::start(Role::Server, pin)?;
+        let reply = spake.message();
+        let keys = match spake.finish(&message, binding) {
+            Ok(keys) => keys,
+            Err(e) => return Err(reject(writer, &e.to_string()).await),
+        };
+        let client_id = pairing::random_bytes()?;
+        write_frame(
+            writer,
+            &ServerHello::PairReply {
+                message: reply,
+                confirmation: keys.confirmation(Role::Server),
+                client_id,
+            },
+        )
+        .await?;
+
+        let finish: ClientFinish = read_frame(reader).await?;
+        if let Err(e) = keys.verify(Role::Client, &finish.proof) {
+            return Err(reject(writer, &e.to_string()).await);
+        }
+
+        let name: String = name.chars().filter(|c| !c.is_control()).take(MAX_NAME_LENGTH).collect();
+        self.add_client(client_id, name.clone(), keys.key).await?;
+        info!("Paired \"{}\" from {} as {}", name, peer_addr, hex::encode(&client_id));
+
+        Ok(PairedPeer { id: client_id, name })
+    }
+
+    /// Read the store on the blocking pool
+    async fn load_store(&self) -> Result<PairingStore> {
+        let path = self.settings.store.clone();
+        tokio::task::spawn_blocking(move || PairingStore::load(&path)).await?
+    }
+
+    /// Add a client to the store on the blocking pool
+    async fn add_client(&self, id: ClientId, name: String, key: PairingKey) -> Result<()> {
+        let path = self.settings.store.clone();
+        let store_lock = self.store_lock.clone();
+        tokio::task::spawn_blocking(move || {
+            let client = PairedClient::new(id, name, &key)?;
+            let _guard = store_lock.lock();
+            let mut store = PairingStore::load(&path)?;
+            store.clients.push(client);
+            store.save(&path)
+        })
+        .await?
+    }
+
+    /// The PIN for one pairing attempt, replaced by a new one right away
+    ///
+    /// `None` while attempts are blocked after a failure.
+    fn take_pin(&self) -> Result<Option<String>> {
+        let mut state = self.pin.lock();
+        if state.blocked_until.is_some_and(|until| Instant::now() < until) {
+            return Ok(None);
+        }
+        let next = pairing::generate_pin()?;
+        show_pin(&next);
+        Ok(Some(std::mem::replace(&mut state.pin, next)))
+    }
+
+    fn block(&self) {
+        self.pin.lock().blocked_until = Some(Instant::now() + FAILED_PAIRING_DELAY);
+    }
+}
+
+fn show_pin(pin: &str) {
+    info!("Pairing PIN: {} (enter it in the Mac app to pair)", pin);
+}
+
+/// Finish the handshake, the message stream starts
+pub async fn admit(writer: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+    write_frame(writer, &ServerHello::Accepted).await
+}
+
+/// Refuse an authenticated client, returns the error for the caller
+pub async fn refuse(writer: &mut (impl AsyncWrite + Unpin), reason: &str) -> anyhow::Error {
+    reject(writer, reason).await
+}
+
+/// Tell the client why it is refused, returns the error for the caller
+async fn reject(writer: &mut (impl AsyncWrite + Unpin), reason: &str) -> anyhow::Error {
+    let _ = write_frame(
+        writer,
+        &ServerHello::Rejected {
+            reason: reason.to_string(),
+        },
+    )
+    .await;
+    anyhow!("Client rejected: {}", reason)
+}
+
+async fn read_frame<T: DeserializeOwned>(reader: &mut (impl AsyncRead + Unpin)) -> Result<T> {
+    let mut prefix = [0u8; 4];
+    reader.read_exact(&mut prefix).await?;
+    let mut body = vec![0u8; frame_length(prefix)?];
+    reader.read_exact(&mut body).await?;
+    Ok(pairing::decode_frame(&body)?)
+}
+
+async fn write_frame<T: Serialize>(writer: &mut (impl AsyncWrite + Unpin), message: &T) -> Result<()> {
+    writer.write_all(&pairing::encode_frame(message)?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Pairing management from the command line
+///
+/// `pairings` lists the paired clients, `unpair ID-OR-NAME` revokes a pairing.
+pub fn run_command(settings: &PairingSettings, command: &str, args: &[String]) -> Result<()> {
+    match command {
+        "pairings" => {
+            let store = PairingStore::load(&settings.store)?;
+            if store.clients().is_empty() {
+                println!("No paired clients");
+            }
+            for client in store.clients() {
+                println!("{}  {}  (paired at {})", hex::encode(&client.id), client.name, client.paired_at);
+            }
+        }
+        "unpair" => {
+            let id_or_name = args.first().ok_or_else(|| anyhow!("Usage: unpair ID-OR-NAME"))?;
+            let mut store = PairingStore::load(&settings.store)?;
+            let revoked = store.revoke(id_or_name);
+            if revoked.is_empty() {
+                bail!("No paired client {}", id_or_name);
+            }
+            store.save(&settings.store)?;
+            for client in revoked {
+                println!("Revoked {}  {}", hex::encode(&client.id), client.name);
+            }
+        }
+        _ => bail!("Unknown command {} (use pairings or unpair ID-OR-NAME)", command),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairing_is_required_by_default() {
+        assert!(PairingSettings::default().required);
+        let settings: PairingSettings = serde_json::from_str("{}").unwrap();
+        assert!(settings.required);
+    }
+
+    #[test]
+    fn stored_keys_survive_a_round_trip() {
+        let path = std::env::temp_dir().join(format!("samesame-pairings-{}.json", std::process::id()));
+        let key = [0x5a; 32];
+        let mut store = PairingStore::default();
+        store.clients.push(PairedClient::new([1; 16], "Mac".into(), &key).unwrap());
+        store.save(&path).unwrap();
+
+        let loaded = PairingStore::load(&path).unwrap();
+        let client = loaded.find(&[1; 16]).unwrap();
+        assert_eq!(client.name, "Mac");
+        assert_eq!(client.key().unwrap(), key);
+
+        #[cfg(windows)]
+        assert!(!fs::read_to_string(&path).unwrap().contains(&hex::encode(&key)));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn damaged_keys_are_an_error() {
+        let mut client = PairedClient::new([1; 16], "Mac".into(), &[7; 32]).unwrap();
+        client.protected_key = "zz".into();
+        assert!(client.key().is_err());
+    }
+}
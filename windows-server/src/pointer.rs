@@ -0,0 +1,35 @@
+/// Turns fractional movement into whole-pixel moves without losing the remainder
+///
+/// The fractional part of every delta is carried over to the next one, so the
+/// sum of emitted moves never drifts from the sum of received deltas by more
+/// than one pixel.
+#[derive(Debug, Default)]
+pub struct SubPixelAccumulator {
+    remainder_x: f64,
+    remainder_y: f64,
+}
+
+impl SubPixelAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a movement, returns the whole pixels to move now
+    pub fn add(&mut self, dx: f64, dy: f64) -> (i32, i32) {
+        let x = self.remainder_x + dx;
+        let y = self.remainder_y + dy;
+
+        let whole_x = x.trunc();
+        let whole_y = y.trunc();
+        self.remainder_x = x - whole_x;
+        self.remainder_y = y - whole_y;
+
+        (whole_x as i32, whole_y as i32)
+    }
+
+    /// Drop any carried-over fraction (e.g. after an absolute warp)
+    pub fn reset(&mut self) {
+        self.remainder_x = 0.0;
+        self.remainder_y = 0.0;
+    }
+}
@@ -0,0 +1,642 @@
+use chrono::{Datelike, Local, Timelike};
+use parking_lot::Mutex;
+use samesame_protocol::keymap::windows_vk;
+use samesame_protocol::pairing::{hex, ClientId};
+use samesame_protocol::{
+    ConsumerControlEvent, EventKind, InputEvent, KeyboardEvent, MouseButton, MouseButtonEvent, WindowsModifiers,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::pairing::PairedPeer;
+
+/// What clients may do, by client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicySettings {
+    /// Policy of clients without an entry in `clients`, unpaired ones included
+    pub default: ClientPolicy,
+    /// Policies of paired clients, by name or hex id
+    pub clients: HashMap<String, ClientPolicy>,
+    /// Sessions open at the same time across all clients (0 = unlimited)
+    pub max_sessions: usize,
+}
+
+impl Default for PolicySettings {
+    fn default() -> Self {
+        Self {
+            default: ClientPolicy::default(),
+            clients: HashMap::new(),
+            max_sessions: 4,
+        }
+    }
+}
+
+impl PolicySettings {
+    /// Policy for a client: its own by id, then by name, else the default
+    pub fn for_client(&self, peer: Option<&PairedPeer>) -> &ClientPolicy {
+        peer.and_then(|peer| {
+            self.clients
+                .get(&hex::encode(&peer.id))
+                .or_else(|| self.clients.get(&peer.name))
+        })
+        .unwrap_or(&self.default)
+    }
+}
+
+/// What one client may inject
+///
+/// Control messages (ping, screen info, clipboard, files) are not input and
+/// always pass; clipboard and files have settings of their own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientPolicy {
+    /// Kinds of input the client may inject, all if missing (`["Pointer"]` for pointer-only)
+    pub allowed_kinds: Option<HashSet<EventKind>>,
+    /// Key chords that are never injected
+    pub blocked_chords: Vec<KeyChord>,
+    /// Times the client may send input, any time if empty
+    pub time_windows: Vec<TimeWindow>,
+    /// Sessions this client may have open at once (0 = unlimited)
+    pub max_sessions: usize,
+}
+
+impl Default for ClientPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_kinds: None,
+            // Locking the screen or reaching the security screen from afar is never what the user wants
+            blocked_chords: vec![
+                KeyChord::from_str("Win+L").expect("valid chord"),
+                KeyChord::from_str("Ctrl+Alt+Delete").expect("valid chord"),
+            ],
+            time_windows: Vec::new(),
+            max_sessions: 0,
+        }
+    }
+}
+
+/// Why an event was not injected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denial {
+    /// The client may not send this kind of input
+    Kind(EventKind),
+    /// The key press completes a blocked chord
+    Chord(KeyChord),
+    /// The macOS key code has no Windows virtual key
+    UnknownKey(u16),
+    /// Outside the client's time windows
+    OutsideHours,
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Denial::Kind(kind) => write!(f, "{:?} input is not allowed", kind),
+            Denial::Chord(chord) => write!(f, "{} is blocked", chord),
+            Denial::UnknownKey(key_code) => write!(f, "unknown key code {:#04x}", key_code),
+            Denial::OutsideHours => write!(f, "outside the allowed hours"),
+        }
+    }
+}
+
+impl ClientPolicy {
+    /// Check an event from the client at local time `now`, with the keys it holds
+    ///
+    /// Releases pass outside the time windows and for blocked chords, so
+    /// nothing pressed earlier stays stuck down. Key codes without a Windows
+    /// virtual key never pass.
+    pub fn check(&self, event: &InputEvent, held: &HeldKeys, now: LocalTime) -> Result<(), Denial> {
+        let kind = event.kind();
+        if kind == EventKind::Control {
+            return Ok(());
+        }
+        if let Some(allowed) = &self.allowed_kinds {
+            if !allowed.contains(&kind) {
+                return Err(Denial::Kind(kind));
+            }
+        }
+        let key = match event {
+            InputEvent::Keyboard(key_event) => {
+                Some(windows_vk(key_event.key_code).ok_or(Denial::UnknownKey(key_event.key_code))?)
+            }
+            _ => None,
+        };
+        if event.is_release() {
+            return Ok(());
+        }
+        if !self.time_windows.is_empty() && !self.time_windows.iter().any(|window| window.contains(now)) {
+            return Err(Denial::OutsideHours);
+        }
+        if let (Some(key), InputEvent::Keyboard(key_event)) = (key, event) {
+            let pressed = held.chords_with(key, key_event.modifiers.to_windows());
+            let blocked = self.blocked_chords.iter().find(|chord| pressed.iter().any(|pressed| chord.matches(pressed)));
+            if let Some(chord) = blocked {
+                return Err(Denial::Chord(chord.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keys and buttons a client holds down on this machine, with the modifiers injected along
+///
+/// Modifiers travel as flags of each key event, so a key pressed with Win
+/// keeps Win down until it is released. Chords are checked against all of
+/// this, not only the flags of the newest press.
+#[derive(Debug, Default)]
+pub struct HeldKeys {
+    /// Presses by Windows virtual key
+    keys: HashMap<u16, KeyboardEvent>,
+    buttons: HashSet<MouseButton>,
+    /// Consumer keys by HID usage
+    consumer_keys: HashSet<u16>,
+}
+
+impl HeldKeys {
+    /// Record an event that was injected
+    pub fn update(&mut self, event: &InputEvent) {
+        match event {
+            InputEvent::Keyboard(key_event) => {
+                let Some(key) = windows_vk(key_event.key_code) else {
+                    return;
+                };
+                if key_event.pressed {
+                    self.keys.entry(key).or_insert_with(|| key_event.clone());
+                } else {
+                    self.keys.remove(&key);
+                }
+            }
+            InputEvent::MouseButton(button_event) if button_event.pressed => {
+                self.buttons.insert(button_event.button);
+            }
+            InputEvent::MouseButton(button_event) => {
+                self.buttons.remove(&button_event.button);
+            }
+            InputEvent::ConsumerControl(consumer_event) if consumer_event.pressed => {
+                self.consumer_keys.insert(consumer_event.usage);
+            }
+            InputEvent::ConsumerControl(consumer_event) => {
+                self.consumer_keys.remove(&consumer_event.usage);
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether `event` releases a key or button that is held
+    pub fn is_held_release(&self, event: &InputEvent) -> bool {
+        match event {
+            InputEvent::Keyboard(key_event) if !key_event.pressed => {
+                windows_vk(key_event.key_code).is_some_and(|key| self.keys.contains_key(&key))
+            }
+            InputEvent::MouseButton(button_event) if !button_event.pressed => {
+                self.buttons.contains(&button_event.button)
+            }
+            InputEvent::ConsumerControl(consumer_event) if !consumer_event.pressed => {
+                self.consumer_keys.contains(&consumer_event.usage)
+            }
+            _ => false,
+        }
+    }
+
+    /// Releases for everything held, which is then no longer held
+    pub fn release_all(&mut self) -> Vec<InputEvent> {
+        let keys = self.keys.drain().map(|(_, mut key_event)| {
+            key_event.pressed = false;
+            key_event.is_repeat = false;
+            InputEvent::Keyboard(key_event)
+        });
+        let buttons = self
+            .buttons
+            .drain()
+            .map(|button| InputEvent::MouseButton(MouseButtonEvent { button, pressed: false }));
+        let consumer_keys = self
+            .consumer_keys
+            .drain()
+            .map(|usage| InputEvent::ConsumerControl(ConsumerControlEvent { usage, pressed: false }));
+        keys.chain(buttons).chain(consumer_keys).collect()
+    }
+
+    /// Chords held once `key` is pressed with `modifiers`, one per held key
+    fn chords_with(&self, key: u16, modifiers: WindowsModifiers) -> Vec<KeyChord> {
+        let held = self.keys.values().fold(modifiers, |all, key_event| {
+            let held = key_event.modifiers.to_windows();
+            WindowsModifiers {
+                shift: all.shift || held.shift,
+                ctrl: all.ctrl || held.ctrl,
+                alt: all.alt || held.alt,
+                win: all.win || held.win,
+            }
+        });
+        self.keys
+            .keys()
+            .copied()
+            .chain([key])
+            .map(|key| KeyChord {
+                ctrl: held.ctrl,
+                alt: held.alt,
+                shift: held.shift,
+                win: held.win,
+                key,
+            })
+            .collect()
+    }
+}
+
+/// Key with Windows modifiers, written like `Win+L` or `Ctrl+Alt+Delete`
+///
+/// A chord matches presses of its key with at least its modifiers held, so
+/// blocking `Ctrl+Alt+Delete` also blocks `Ctrl+Alt+Shift+Delete`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct KeyChord {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub win: bool,
+    /// Windows virtual key
+    pub key: u16,
+}
+
+impl KeyChord {
+    fn matches(&self, pressed: &KeyChord) -> bool {
+        self.key == pressed.key
+            && (!self.ctrl || pressed.ctrl)
+            && (!self.alt || pressed.alt)
+            && (!self.shift || pressed.shift)
+            && (!self.win || pressed.win)
+    }
+}
+
+/// Key names besides letters, digits and F1-F24
+const KEY_NAMES: &[(&str, u16)] = &[
+    ("Backspace", 0x08),
+    ("Tab", 0x09),
+    ("Enter", 0x0D),
+    ("Escape", 0x1B),
+    ("Space", 0x20),
+    ("PageUp", 0x21),
+    ("PageDown", 0x22),
+    ("End", 0x23),
+    ("Home", 0x24),
+    ("Left", 0x25),
+    ("Up", 0x26),
+    ("Right", 0x27),
+    ("Down", 0x28),
+    ("Insert", 0x2D),
+    ("Delete", 0x2E),
+];
+
+fn key_name(vk: u16) -> String {
+    match vk {
+        0x30..=0x39 | 0x41..=0x5A => char::from(vk as u8).to_string(),
+        0x70..=0x87 => format!("F{}", vk - 0x6F),
+        _ => KEY_NAMES
+            .iter()
+            .find(|(_, key)| *key == vk)
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_else(|| format!("{:#04x}", vk)),
+    }
+}
+
+fn parse_key(name: &str) -> Option<u16> {
+    let upper = name.to_ascii_uppercase();
+    if let [c] = upper.as_bytes() {
+        return c.is_ascii_alphanumeric().then_some(*c as u16);
+    }
+    if let Some(n) = upper.strip_prefix('F').and_then(|n| n.parse::<u16>().ok()) {
+        return (1..=24).contains(&n).then_some(0x6F + n);
+    }
+    match upper.as_str() {
+        "DEL" => return Some(0x2E),
+        "ESC" => return Some(0x1B),
+        "RETURN" => return Some(0x0D),
+        _ => {}
+    }
+    KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|(_, vk)| *vk)
+}
+
+impl FromStr for KeyChord {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut chord = KeyChord {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            win: false,
+            key: 0,
+        };
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        let key = parts.pop().filter(|key| !key.is_empty()).ok_or_else(|| format!("No key in chord {}", text))?;
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => chord.ctrl = true,
+                "alt" => chord.alt = true,
+                "shift" => chord.shift = true,
+                "win" | "windows" => chord.win = true,
+                _ => return Err(format!("Unknown modifier {} in chord {}", modifier, text)),
+            }
+        }
+        chord.key = parse_key(key).ok_or_else(|| format!("Unknown key {} in chord {}", key, text))?;
+        Ok(chord)
+    }
+}
+
+impl TryFrom<String> for KeyChord {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<KeyChord> for String {
+    fn from(chord: KeyChord) -> Self {
+        chord.to_string()
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [(self.ctrl, "Ctrl"), (self.alt, "Alt"), (self.shift, "Shift"), (self.win, "Win")] {
+            if held {
+                write!(f, "{}+", name)?;
+            }
+        }
+        write!(f, "{}", key_name(self.key))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+/// Local day and time, passed in so policies can be checked for any moment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub day: Weekday,
+    /// Minutes since midnight
+    pub minute: u16,
+}
+
+impl LocalTime {
+    pub fn now() -> Self {
+        let now = Local::now();
+        let day = match now.weekday() {
+            chrono::Weekday::Mon => Weekday::Mon,
+            chrono::Weekday::Tue => Weekday::Tue,
+            chrono::Weekday::Wed => Weekday::Wed,
+            chrono::Weekday::Thu => Weekday::Thu,
+            chrono::Weekday::Fri => Weekday::Fri,
+            chrono::Weekday::Sat => Weekday::Sat,
+            chrono::Weekday::Sun => Weekday::Sun,
+        };
+        Self {
+            day,
+            minute: (now.hour() * 60 + now.minute()) as u16,
+        }
+    }
+}
+
+/// Days and hours input is allowed, like `{ "days": ["Mon", "Fri"], "from": "08:00", "until": "18:00" }`
+///
+/// A window whose `until` is before `from` runs past midnight and belongs to
+/// the day it starts on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeWindow {
+    /// Every day if empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub from: ClockTime,
+    pub until: ClockTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: LocalTime) -> bool {
+        let (from, until) = (self.from.0, self.until.0);
+        let on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        if from <= until {
+            on(time.day) && (from..until).contains(&time.minute)
+        } else {
+            (on(time.day) && time.minute >= from) || (on(previous_day(time.day)) && time.minute < until)
+        }
+    }
+}
+
+fn previous_day(day: Weekday) -> Weekday {
+    match day {
+        Weekday::Mon => Weekday::Sun,
+        Weekday::Tue => Weekday::Mon,
+        Weekday::Wed => Weekday::Tue,
+        Weekday::Thu => Weekday::Wed,
+        Weekday::Fri => Weekday::Thu,
+        Weekday::Sat => Weekday::Fri,
+        Weekday::Sun => Weekday::Sat,
+    }
+}
+
+/// Time of day as `HH:MM`, `24:00` for the end of the day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ClockTime(u16);
+
+impl TryFrom<String> for ClockTime {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let (hours, minutes) = text
+            .split_once(':')
+            .and_then(|(hours, minutes)| Some((hours.parse::<u16>().ok()?, minutes.parse::<u16>().ok()?)))
+            .ok_or_else(|| format!("Invalid time {}, expected HH:MM", text))?;
+        if minutes >= 60 || hours * 60 + minutes > 24 * 60 {
+            return Err(format!("Invalid time {}", text));
+        }
+        Ok(ClockTime(hours * 60 + minutes))
+    }
+}
+
+impl From<ClockTime> for String {
+    fn from(time: ClockTime) -> Self {
+        format!("{:02}:{:02}", time.0 / 60, time.0 % 60)
+    }
+}
+
+/// Open sessions, by client
+///
+/// Unpaired clients share one count.
+#[derive(Default)]
+pub struct SessionLimits {
+    open: Mutex<HashMap<Option<ClientId>, usize>>,
+}
+
+/// An open session, counted until dropped
+pub struct SessionGuard {
+    limits: Arc<SessionLimits>,
+    client: Option<ClientId>,
+}
+
+impl SessionLimits {
+    /// Count a new session, or `None` if a limit is reached
+    pub fn open(
+        self: &Arc<Self>,
+        client: Option<ClientId>,
+        client_limit: usize,
+        total_limit: usize,
+    ) -> Option<SessionGuard> {
+        let mut open = self.open.lock();
+        let total: usize = open.values().sum();
+        let for_client = open.get(&client).copied().unwrap_or(0);
+        if (total_limit > 0 && total >= total_limit) || (client_limit > 0 && for_client >= client_limit) {
+            return None;
+        }
+        *open.entry(client).or_default() += 1;
+        Some(SessionGuard {
+            limits: self.clone(),
+            client,
+        })
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let mut open = self.limits.open.lock();
+        if let Some(count) = open.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.client);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use samesame_protocol::{Modifiers, MouseButton, MouseButtonEvent};
+
+    const NOON: LocalTime = LocalTime {
+        day: Weekday::Wed,
+        minute: 12 * 60,
+    };
+
+    fn key(key_code: u16, pressed: bool, modifiers: Modifiers) -> KeyboardEvent {
+        KeyboardEvent {
+            key_code,
+            character: None,
+            pressed,
+            modifiers,
+            is_repeat: false,
+        }
+    }
+
+    /// Check a key event, then record it as injected if it passed
+    fn press(policy: &ClientPolicy, held: &mut HeldKeys, event: KeyboardEvent) -> Result<(), Denial> {
+        let event = InputEvent::Keyboard(event);
+        policy.check(&event, held, NOON)?;
+        held.update(&event);
+        Ok(())
+    }
+
+    fn win() -> Modifiers {
+        // Control on the Mac is Win on Windows
+        Modifiers {
+            control: true,
+            ..Modifiers::default()
+        }
+    }
+
+    #[test]
+    fn chord_from_the_flags_is_blocked() {
+        let policy = ClientPolicy::default();
+        let mut held = HeldKeys::default();
+
+        let denial = press(&policy, &mut held, key(0x25, true, win())).unwrap_err();
+        assert!(matches!(&denial, Denial::Chord(chord) if chord.to_string() == "Win+L"), "{}", denial);
+        assert!(press(&policy, &mut held, key(0x25, false, win())).is_ok(), "releases pass");
+    }
+
+    #[test]
+    fn modifiers_held_by_another_key_count() {
+        let policy = ClientPolicy::default();
+        let mut held = HeldKeys::default();
+
+        // A pressed with Win keeps Win down, L alone then completes Win+L
+        press(&policy, &mut held, key(0x00, true, win())).unwrap();
+        assert!(matches!(press(&policy, &mut held, key(0x25, true, Modifiers::default())), Err(Denial::Chord(_))));
+
+        // Once A is up, L is a plain key again
+        press(&policy, &mut held, key(0x00, false, win())).unwrap();
+        press(&policy, &mut held, key(0x25, true, Modifiers::default())).unwrap();
+    }
+
+    #[test]
+    fn chord_spread_over_several_keys_is_blocked() {
+        let policy = ClientPolicy::default();
+        let mut held = HeldKeys::default();
+        let command = Modifiers {
+            command: true,
+            ..Modifiers::default()
+        };
+        let option = Modifiers {
+            alt: true,
+            ..Modifiers::default()
+        };
+
+        // Ctrl from A, Alt from S, then Forward Delete
+        press(&policy, &mut held, key(0x00, true, command)).unwrap();
+        press(&policy, &mut held, key(0x01, true, option)).unwrap();
+        let denial = press(&policy, &mut held, key(0x75, true, Modifiers::default())).unwrap_err();
+        assert!(matches!(&denial, Denial::Chord(chord) if chord.to_string() == "Ctrl+Alt+Delete"), "{}", denial);
+    }
+
+    #[test]
+    fn extra_modifiers_still_match() {
+        let policy = ClientPolicy::default();
+        let mut held = HeldKeys::default();
+        let modifiers = Modifiers {
+            control: true,
+            shift: true,
+            ..Modifiers::default()
+        };
+
+        assert!(matches!(press(&policy, &mut held, key(0x25, true, modifiers)), Err(Denial::Chord(_))));
+    }
+
+    #[test]
+    fn unmapped_key_codes_are_rejected() {
+        let policy = ClientPolicy::default();
+        let mut held = HeldKeys::default();
+
+        // Keypad 8 has no virtual key here, passed through it would be VK_LWIN
+        let denied = Err(Denial::UnknownKey(0x5B));
+        assert_eq!(press(&policy, &mut held, key(0x5B, true, Modifiers::default())), denied);
+        assert_eq!(press(&policy, &mut held, key(0x5B, false, Modifiers::default())), denied);
+        press(&policy, &mut held, key(0x25, true, Modifiers::default())).unwrap();
+    }
+
+    #[test]
+    fn other_input_passes_without_chords() {
+        let policy = ClientPolicy::default();
+        let held = HeldKeys::default();
+        let click = InputEvent::MouseButton(MouseButtonEvent {
+            button: MouseButton::Left,
+            pressed: true,
+        });
+
+        assert!(policy.check(&click, &held, NOON).is_ok());
+    }
+}
@@ -0,0 +1,183 @@
+use anyhow::{anyhow, bail, Context, Result};
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::{Endpoint, RecvStream, SendStream, TransportConfig};
+use samesame_protocol::pairing::TLS_EXPORTER_LABEL;
+use samesame_protocol::quic::{self as quic_protocol, QuicStream, SegmentSplitter, ALPN, BULK_STREAM_MARKER, IDLE_TIMEOUT};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls;
+use tracing::warn;
+
+use crate::tls::{self, BoxedReader, BoxedWriter, TlsSettings};
+
+/// Buffer between the mux and the streams
+const ROUTER_BUFFER: usize = 64 * 1024;
+
+/// QUIC transport, next to TCP
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuicSettings {
+    /// Accept QUIC connections
+    pub enabled: bool,
+    /// UDP port
+    pub port: u16,
+}
+
+impl Default for QuicSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 24801,
+        }
+    }
+}
+
+/// QUIC endpoint with the TLS certificate, and the certificate's fingerprint
+pub fn endpoint(settings: &QuicSettings, tls_settings: &TlsSettings) -> Result<(Endpoint, String)> {
+    let (mut crypto, fingerprint) = tls::server_config(tls_settings, &[&rustls::version::TLS13])?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicServerConfig::try_from(crypto).map_err(|e| anyhow!("Invalid QUIC TLS configuration: {}", e))?;
+
+    let mut transport = TransportConfig::default();
+    transport.max_idle_timeout(Some(IDLE_TIMEOUT.try_into()?));
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(Arc::new(transport));
+
+    let endpoint = Endpoint::server(config, SocketAddr::from(([0, 0, 0, 0], settings.port)))?;
+    Ok((endpoint, fingerprint))
+}
+
+/// The bulk stream of a QUIC connection, kept until the pairing handshake is done
+pub struct QuicConnection {
+    connection: quinn::Connection,
+    bulk_send: SendStream,
+    bulk_recv: RecvStream,
+}
+
+/// Complete the QUIC handshake and take both streams of the client
+///
+/// Returns the main stream and the channel binding for the pairing handshake.
+pub async fn accept(incoming: quinn::Incoming) -> Result<(BoxedReader, BoxedWriter, Vec<u8>, QuicConnection)> {
+    let connection = incoming.await.context("QUIC handshake failed")?;
+    let (main_send, main_recv) = connection.accept_bi().await?;
+    let (bulk_send, mut bulk_recv) = connection.accept_bi().await?;
+    let mut marker = [0u8; 1];
+    bulk_recv.read_exact(&mut marker).await?;
+    if marker[0] != BULK_STREAM_MARKER {
+        bail!("Second stream is not a bulk stream");
+    }
+
+    let mut binding = vec![0u8; 32];
+    connection
+        .export_keying_material(&mut binding, TLS_EXPORTER_LABEL, b"")
+        .map_err(|_| anyhow!("Cannot export TLS keying material"))?;
+
+    let quic = QuicConnection {
+        connection,
+        bulk_send,
+        bulk_recv,
+    };
+    Ok((Box::new(main_recv), Box::new(main_send), binding, quic))
+}
+
+impl QuicConnection {
+    /// Run the mux on the main and the bulk stream
+    ///
+    /// Returns the reader and writer for the session, and the connection for
+    /// its datagrams.
+    pub fn into_streams(self, main_reader: BoxedReader, main_writer: BoxedWriter) -> (BoxedReader, BoxedWriter, quinn::Connection) {
+        let reader = merge_streams(main_reader, self.bulk_recv);
+        let writer = split_streams(main_writer, self.bulk_send);
+        (reader, writer, self.connection)
+    }
+}
+
+/// Writer for the mux that sends bulk segments on the bulk stream
+fn split_streams(main: BoxedWriter, bulk: SendStream) -> BoxedWriter {
+    let (writer, mut routed) = tokio::io::duplex(ROUTER_BUFFER);
+    let main = forward(main);
+    let bulk = forward(bulk);
+
+    tokio::spawn(async move {
+        let mut splitter = SegmentSplitter::new();
+        let mut buffer = vec![0u8; ROUTER_BUFFER];
+        loop {
+            let n = match routed.read(&mut buffer).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => n,
+            };
+            splitter.extend(&buffer[..n]);
+            while let Ok(Some(segment)) = splitter.next_segment() {
+                let stream = match quic_protocol::stream_of(&segment) {
+                    QuicStream::Main => &main,
+                    QuicStream::Bulk => &bulk,
+                };
+                // Dropping `routed` makes the next write of the mux fail
+                if stream.send(segment).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Box::new(writer)
+}
+
+/// Write segments to a stream until the queue closes, then finish the stream
+///
+/// The mux windows bound what can pile up in the queue.
+fn forward(mut stream: impl AsyncWrite + Unpin + Send + 'static) -> mpsc::UnboundedSender<Vec<u8>> {
+    let (queue, mut segments) = mpsc::unbounded_channel::<Vec<u8>>();
+    tokio::spawn(async move {
+        while let Some(segment) = segments.recv().await {
+            if stream.write_all(&segment).await.is_err() {
+                return;
+            }
+        }
+        let _ = stream.shutdown().await;
+    });
+    queue
+}
+
+/// Reader for the mux that interleaves the segments of both streams
+///
+/// Ends when the main stream ends.
+fn merge_streams(main: BoxedReader, bulk: RecvStream) -> BoxedReader {
+    let (reader, writer) = tokio::io::duplex(ROUTER_BUFFER);
+    let writer = Arc::new(tokio::sync::Mutex::new(writer));
+    tokio::spawn(collect(main, writer.clone(), true));
+    tokio::spawn(collect(bulk, writer, false));
+    Box::new(reader)
+}
+
+/// Pass whole segments from a stream to the merged reader
+async fn collect(mut stream: impl AsyncRead + Unpin, merged: Arc<tokio::sync::Mutex<DuplexStream>>, main: bool) {
+    let mut splitter = SegmentSplitter::new();
+    let mut buffer = vec![0u8; 8192];
+    'read: loop {
+        let n = match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        splitter.extend(&buffer[..n]);
+        loop {
+            match splitter.next_segment() {
+                Ok(Some(segment)) => {
+                    if merged.lock().await.write_all(&segment).await.is_err() {
+                        break 'read;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Closing QUIC stream: {}", e);
+                    break 'read;
+                }
+            }
+        }
+    }
+    if main {
+        let _ = merged.lock().await.shutdown().await;
+    }
+}
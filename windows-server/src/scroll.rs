@@ -0,0 +1,296 @@
+use samesame_protocol::{MomentumPhase, MouseScrollEvent, ScrollPhase};
+use serde::{Deserialize, Serialize};
+
+/// Wheel units of one notch of a classic mouse wheel (Windows WHEEL_DELTA)
+pub const WHEEL_DELTA: f64 = 120.0;
+
+/// How scroll deltas from the client are turned into wheel input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScrollSettings {
+    /// Trackpad pixels that make up one wheel notch
+    pub pixels_per_notch: f64,
+    /// Multiplier applied to all scrolling
+    pub speed: f64,
+    /// Reverse vertical scrolling (on by default, undoes macOS natural scrolling)
+    pub invert_vertical: bool,
+    /// Reverse horizontal scrolling
+    pub invert_horizontal: bool,
+    /// Replay the trackpad's inertia; when off, scrolling stops as the fingers lift
+    pub momentum: bool,
+}
+
+impl Default for ScrollSettings {
+    fn default() -> Self {
+        Self {
+            pixels_per_notch: 40.0,
+            speed: 1.0,
+            invert_vertical: true,
+            invert_horizontal: false,
+            momentum: true,
+        }
+    }
+}
+
+/// Wheel movement to inject, in WHEEL_DELTA units (positive = up / right)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WheelDelta {
+    pub vertical: i32,
+    pub horizontal: i32,
+}
+
+impl WheelDelta {
+    pub fn is_zero(&self) -> bool {
+        self.vertical == 0 && self.horizontal == 0
+    }
+}
+
+/// Converts scroll events into wheel units without losing small deltas
+///
+/// Fractions of a wheel unit are carried over to the next event. The
+/// carried fraction is dropped when a new gesture begins or the direction
+/// changes, so a new scroll never starts with a leftover from the last one.
+#[derive(Debug)]
+pub struct ScrollTranslator {
+    settings: ScrollSettings,
+    remainder_vertical: f64,
+    remainder_horizontal: f64,
+}
+
+impl ScrollTranslator {
+    pub fn new(settings: ScrollSettings) -> Self {
+        Self {
+            settings,
+            remainder_vertical: 0.0,
+            remainder_horizontal: 0.0,
+        }
+    }
+
+    /// Wheel movement for a scroll event
+    pub fn translate(&mut self, event: &MouseScrollEvent) -> WheelDelta {
+        if matches!(event.phase, ScrollPhase::MayBegin | ScrollPhase::Began) {
+            self.reset();
+        }
+
+        if event.momentum_phase != MomentumPhase::None && !self.settings.momentum {
+            self.reset();
+            return WheelDelta::default();
+        }
+
+        let units_per_delta = if event.is_pixel_based {
+            WHEEL_DELTA / self.settings.pixels_per_notch.max(f64::EPSILON)
+        } else {
+            WHEEL_DELTA
+        } * self.settings.speed;
+
+        let vertical = event.delta_y * units_per_delta * sign(self.settings.invert_vertical);
+        let horizontal = event.delta_x * units_per_delta * sign(self.settings.invert_horizontal);
+
+        let delta = WheelDelta {
+            vertical: accumulate(&mut self.remainder_vertical, vertical),
+            horizontal: accumulate(&mut self.remainder_horizontal, horizontal),
+        };
+
+        if matches!(event.phase, ScrollPhase::Ended | ScrollPhase::Cancelled)
+            || event.momentum_phase == MomentumPhase::Ended
+        {
+            self.reset();
+        }
+
+        delta
+    }
+
+    fn reset(&mut self) {
+        self.remainder_vertical = 0.0;
+        self.remainder_horizontal = 0.0;
+    }
+}
+
+fn sign(invert: bool) -> f64 {
+    if invert {
+        -1.0
+    } else {
+        1.0
+    }
+}
+
+/// Add `units` to the carried fraction and take out the whole units
+fn accumulate(remainder: &mut f64, units: f64) -> i32 {
+    if units != 0.0 && remainder.signum() != units.signum() {
+        // Direction changed, the old fraction no longer counts
+        *remainder = 0.0;
+    }
+
+    let total = *remainder + units;
+    let whole = total.trunc();
+    *remainder = total - whole;
+    whole as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use MomentumPhase as M;
+    use ScrollPhase as P;
+
+    /// A scroll event as the client captured it: phase, momentum phase, pixel deltas (x, y)
+    type Recorded = (ScrollPhase, MomentumPhase, f64, f64);
+
+    /// Two-finger flick down the page on a MacBook trackpad, with inertia
+    const FLICK: [Recorded; 16] = [
+        (P::MayBegin, M::None, 0.0, 0.0),
+        (P::Began, M::None, 0.0, -1.0),
+        (P::Changed, M::None, 0.0, -3.0),
+        (P::Changed, M::None, 0.0, -6.5),
+        (P::Changed, M::None, 0.0, -12.0),
+        (P::Changed, M::None, -1.0, -10.25),
+        (P::Changed, M::None, 0.0, -6.0),
+        (P::Changed, M::None, 0.0, -3.0),
+        (P::Ended, M::None, 0.0, 0.0),
+        (P::None, M::Began, 0.0, -8.0),
+        (P::None, M::Changed, 0.0, -5.0),
+        (P::None, M::Changed, 0.0, -3.0),
+        (P::None, M::Changed, 0.0, -2.0),
+        (P::None, M::Changed, 0.0, -1.0),
+        (P::None, M::Changed, 0.0, -0.5),
+        (P::None, M::Ended, 0.0, 0.0),
+    ];
+
+    /// Very slow drag of one finger, a fraction of a pixel per event
+    const CREEP: [Recorded; 12] = [
+        (P::Began, M::None, 0.0, 0.1),
+        (P::Changed, M::None, 0.0, 0.1),
+        (P::Changed, M::None, 0.0, 0.1),
+        (P::Changed, M::None, 0.0, 0.1),
+        (P::Changed, M::None, 0.0, 0.1),
+        (P::Changed, M::None, 0.0, 0.1),
+        (P::Changed, M::None, 0.0, 0.1),
+        (P::Changed, M::None, 0.0, 0.1),
+        (P::Changed, M::None, 0.0, 0.1),
+        (P::Changed, M::None, 0.0, 0.1),
+        (P::Changed, M::None, 0.0, 0.1),
+        (P::Ended, M::None, 0.0, 0.0),
+    ];
+
+    fn event(&(phase, momentum_phase, delta_x, delta_y): &Recorded) -> MouseScrollEvent {
+        MouseScrollEvent {
+            delta_x,
+            delta_y,
+            is_pixel_based: true,
+            phase,
+            momentum_phase,
+        }
+    }
+
+    /// Feed a recording through a translator, returns the wheel deltas per event
+    fn replay(settings: ScrollSettings, recording: &[Recorded]) -> Vec<WheelDelta> {
+        let mut translator = ScrollTranslator::new(settings);
+        recording.iter().map(|recorded| translator.translate(&event(recorded))).collect()
+    }
+
+    fn total(deltas: &[WheelDelta]) -> (i32, i32) {
+        deltas.iter().fold((0, 0), |(v, h), delta| (v + delta.vertical, h + delta.horizontal))
+    }
+
+    #[test]
+    fn flick_scrolls_the_whole_distance() {
+        let deltas = replay(ScrollSettings::default(), &FLICK);
+
+        // 41.75 px with the fingers, 19.5 px of inertia, 3 wheel units per pixel
+        // (120 per 40 px notch), reversed to undo natural scrolling
+        let finger = total(&deltas[..9]);
+        let inertia = total(&deltas[9..]);
+        assert_eq!(finger, (125, -3));
+        assert_eq!(inertia, (58, 0));
+    }
+
+    #[test]
+    fn every_event_scrolls_right_away() {
+        let deltas = replay(ScrollSettings::default(), &FLICK);
+        for (recorded, delta) in FLICK.iter().zip(&deltas) {
+            if recorded.3.abs() >= 1.0 {
+                assert_ne!(delta.vertical, 0, "{:?} was held back", recorded);
+            }
+        }
+    }
+
+    #[test]
+    fn slow_scrolls_add_up() {
+        let deltas = replay(ScrollSettings::default(), &CREEP);
+
+        // 1.1 px are 3.3 units, the fraction left when the fingers lift is dropped
+        assert_eq!(total(&deltas), (-3, 0));
+        assert!(deltas.iter().filter(|delta| !delta.is_zero()).count() >= 3);
+    }
+
+    #[test]
+    fn momentum_can_be_turned_off() {
+        let settings = ScrollSettings {
+            momentum: false,
+            ..ScrollSettings::default()
+        };
+        let deltas = replay(settings, &FLICK);
+
+        assert_eq!(total(&deltas[..9]), (125, -3));
+        assert!(deltas[9..].iter().all(WheelDelta::is_zero));
+    }
+
+    #[test]
+    fn fractions_do_not_leak_into_the_next_gesture() {
+        let mut recording = CREEP[..4].to_vec();
+        recording.push((P::Ended, M::None, 0.0, 0.0));
+        recording.extend_from_slice(&CREEP[..4]);
+
+        // 0.4 px per gesture are 1.2 units, so each gesture scrolls exactly one
+        let deltas = replay(ScrollSettings::default(), &recording);
+        assert_eq!(total(&deltas[..5]), (-1, 0));
+        assert_eq!(total(&deltas[5..]), (-1, 0));
+    }
+
+    #[test]
+    fn reversing_drops_the_fraction() {
+        let recording = [
+            (P::Began, M::None, 0.0, 0.3),
+            (P::Changed, M::None, 0.0, -0.3),
+            (P::Changed, M::None, 0.0, -0.3),
+        ];
+        let deltas = replay(ScrollSettings::default(), &recording);
+
+        // 0.9 units up are dropped, then 0.9 + 0.9 down make one unit
+        assert_eq!(deltas.iter().map(|delta| delta.vertical).collect::<Vec<_>>(), vec![0, 0, 1]);
+    }
+
+    #[test]
+    fn mouse_wheel_notches_are_whole_wheel_deltas() {
+        let settings = ScrollSettings {
+            invert_vertical: false,
+            ..ScrollSettings::default()
+        };
+        let mut translator = ScrollTranslator::new(settings);
+        let notch = |delta_y| MouseScrollEvent {
+            delta_x: 0.0,
+            delta_y,
+            is_pixel_based: false,
+            phase: ScrollPhase::None,
+            momentum_phase: MomentumPhase::None,
+        };
+
+        assert_eq!(translator.translate(&notch(1.0)).vertical, 120);
+        assert_eq!(translator.translate(&notch(-3.0)).vertical, -360);
+    }
+
+    #[test]
+    fn speed_and_notch_size_scale_the_flick() {
+        let settings = ScrollSettings {
+            speed: 2.0,
+            pixels_per_notch: 120.0,
+            invert_horizontal: true,
+            ..ScrollSettings::default()
+        };
+        let deltas = replay(settings, &FLICK);
+
+        // One unit per pixel, doubled
+        assert_eq!(total(&deltas[..9]), (83, 2));
+    }
+}
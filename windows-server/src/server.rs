@@ -0,0 +1,1138 @@
+use anyhow::{anyhow, bail, Result};
+use parking_lot::Mutex;
+use samesame_protocol::acceleration::PointerAccelerator;
+use samesame_protocol::arbitration::Arbitration;
+use samesame_protocol::clipboard::{self as clipboard_sync, Clipboard, ClipboardSync, ClipboardSyncMode};
+use samesame_protocol::datagram::{self as datagrams, PointerTransport};
+use samesame_protocol::mapping::PointerMapper;
+use samesame_protocol::pairing::hex;
+use samesame_protocol::{
+    Channel, FlowUpdate, InputEvent, LockState, Message, MouseMoveEvent, MuxConfig, MuxDecoder, MuxEncoder,
+    MuxError, PointerMode,
+};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_rustls::TlsAcceptor;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, info, warn};
+
+use crate::access::{AccessControl, RateLimiter};
+use crate::arbiter::{Arbiter, MemberId};
+use crate::backend::InputBackend;
+use crate::config::ServerConfig;
+use crate::cursor_feedback::CursorReporter;
+use crate::datagram::{DatagramReceiver, DatagramRoute};
+use crate::file_receiver::FileWorker;
+use crate::key_repeat::RepeatGenerator;
+use crate::outgoing::{Outgoing, Overflow};
+use crate::pairing::{self, Pairing};
+use crate::pointer::SubPixelAccumulator;
+use crate::policy::{self, ClientPolicy, HeldKeys, LocalTime, SessionLimits};
+use crate::scroll::ScrollTranslator;
+use crate::text_input::Composition;
+use crate::transport::{Connection, Incoming};
+use crate::{lock_keys, tls};
+
+/// How often the cursor position is sampled
+const CURSOR_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Minimum time between two cursor reports (edge hits are sent immediately)
+const CURSOR_REPORT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often the lock keys are checked for changes made on this machine
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the clipboard is checked for changes made on this machine
+const CLIPBOARD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Messages queued to a client before it counts as not keeping up
+const SEND_QUEUE_SIZE: usize = 256;
+
+/// Longest time a client may take to accept a segment
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest time a client may take for the TLS and pairing handshakes
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// State shared by all connections
+pub struct Server {
+    pub backend: Arc<dyn InputBackend>,
+    pub clipboard: Arc<dyn Clipboard>,
+    pub config: Arc<ServerConfig>,
+    pub acceptor: Option<TlsAcceptor>,
+    pub pairing: Arc<Pairing>,
+    /// Open sessions, for the session limits of the policies
+    pub sessions: Arc<SessionLimits>,
+    /// Which client is in control
+    pub arbiter: Arc<Arbiter>,
+    /// Address lists, connection and message limits
+    pub access: Arc<AccessControl>,
+    /// Pointer events over UDP, if enabled
+    pub datagrams: Option<Arc<DatagramReceiver>>,
+}
+
+/// Authenticate a new connection and handle it under the client's policy
+pub async fn serve(server: Arc<Server>, incoming: Incoming, peer_addr: SocketAddr) -> Result<()> {
+    // A peer that connects and then stalls must not hold its connection slot
+    let handshake = async {
+        let mut connection = Connection::accept(incoming, server.acceptor.as_ref()).await?;
+        let peer = server
+            .pairing
+            .authenticate(&mut connection.reader, &mut connection.writer, peer_addr, &connection.binding)
+            .await?;
+        anyhow::Ok((connection, peer))
+    };
+    let (mut connection, peer) = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+        .await
+        .map_err(|_| anyhow!("Handshake timed out"))??;
+    match &peer {
+        Some(peer) => info!("Client {} authenticated as \"{}\" ({})", peer_addr, peer.name, hex::encode(&peer.id)),
+        None => info!("Client {} is not paired", peer_addr),
+    }
+
+    let policies = &server.config.policies;
+    let policy = policies.for_client(peer.as_ref()).clone();
+    let Some(_session) = server
+        .sessions
+        .open(peer.as_ref().map(|peer| peer.id), policy.max_sessions, policies.max_sessions)
+    else {
+        return Err(pairing::refuse(&mut connection.writer, "Too many open sessions").await);
+    };
+    pairing::admit(&mut connection.writer).await?;
+
+    let name = peer.map_or_else(|| peer_addr.to_string(), |peer| peer.name);
+    let (reader, writer, datagram_route) = connection.into_session();
+    handle_client(reader, writer, datagram_route, peer_addr, &server, policy, name).await
+}
+
+/// State of one client connection
+struct Session {
+    peer_addr: SocketAddr,
+    /// Queue of messages to the client
+    sender: Outgoing,
+    /// Queue of clipboard transfers, written only while `sender` is empty
+    bulk_sender: Outgoing,
+    backend: Arc<dyn InputBackend>,
+    clipboard: Arc<dyn Clipboard>,
+    /// Clipboard changes exchanged with this client, shared with the clipboard poller
+    clipboard_sync: Arc<Mutex<ClipboardSync>>,
+    /// Maps the client's pointer positions onto this machine's monitors
+    pointer_mapper: Option<PointerMapper>,
+    /// How mouse movement from this client is applied
+    pointer_mode: PointerMode,
+    /// Acceleration curve for relative moves
+    accelerator: PointerAccelerator,
+    /// Fractional pixels left over from relative moves
+    accumulator: SubPixelAccumulator,
+    /// Turns trackpad and wheel deltas into wheel units
+    scroll_translator: ScrollTranslator,
+    /// Held key and server-side key repeats
+    key_repeat: RepeatGenerator,
+    /// Marked text of the client's input method, typed here while it is composed
+    composition: Composition,
+    /// Files being received from this client
+    files: FileWorker,
+    /// What this client may inject
+    policy: ClientPolicy,
+    /// Keys this client holds down, for the chord checks of the policy
+    held_keys: HeldKeys,
+    /// Decides whether this client is in control
+    arbiter: Arc<Arbiter>,
+    /// This session in the arbiter
+    member: MemberId,
+    /// Limits shared by all connections, and their metrics
+    access: Arc<AccessControl>,
+    /// Messages this client may send
+    rate_limiter: RateLimiter,
+    /// Messages dropped since the client went over the rate limit
+    throttled: u64,
+    /// Messages that could not be decoded
+    invalid_messages: u32,
+    /// Receiver of pointer datagrams, if enabled on this server
+    datagrams: Option<Arc<DatagramReceiver>>,
+    /// Pointer messages this client sends over UDP
+    datagram_route: Option<DatagramRoute>,
+    /// Sequence of the newest pointer message applied, older ones are stale
+    last_pointer_sequence: u64,
+}
+
+impl Session {
+    /// Session of a client that just connected, joins the arbiter
+    fn new(
+        server: &Server,
+        peer_addr: SocketAddr,
+        policy: ClientPolicy,
+        name: String,
+        sender: Outgoing,
+        bulk_sender: Outgoing,
+        datagram_route: Option<DatagramRoute>,
+    ) -> Self {
+        let config = &server.config;
+        let member = server.arbiter.join(name, sender.clone(), Instant::now());
+        let files = FileWorker::new(config.files.clone(), sender.clone());
+        Self {
+            peer_addr,
+            sender,
+            bulk_sender,
+            backend: server.backend.clone(),
+            clipboard: server.clipboard.clone(),
+            clipboard_sync: Arc::new(Mutex::new(ClipboardSync::new(config.clipboard.clone()))),
+            pointer_mapper: None,
+            pointer_mode: PointerMode::default(),
+            accelerator: PointerAccelerator::new(config.acceleration.clone()),
+            accumulator: SubPixelAccumulator::new(),
+            scroll_translator: ScrollTranslator::new(config.scroll.clone()),
+            key_repeat: RepeatGenerator::new(config.key_repeat.clone()),
+            composition: Composition::default(),
+            files,
+            policy,
+            held_keys: HeldKeys::default(),
+            arbiter: server.arbiter.clone(),
+            member,
+            access: server.access.clone(),
+            rate_limiter: server.access.rate_limiter(),
+            throttled: 0,
+            invalid_messages: 0,
+            datagrams: server.datagrams.clone(),
+            datagram_route,
+            last_pointer_sequence: 0,
+        }
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        // Releases of what the client holds still go through, so nothing stays held while it is throttled
+        if !self.rate_limiter.allow(Instant::now()) && !self.held_keys.is_held_release(&message.event) {
+            if self.throttled == 0 {
+                warn!("Client {} exceeds the message rate limit, dropping messages", self.peer_addr);
+            }
+            self.throttled += 1;
+            self.access.metrics.rate_limited.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if self.throttled > 0 {
+            info!("Client {} is back under the rate limit, {} message(s) dropped", self.peer_addr, self.throttled);
+            self.throttled = 0;
+        }
+
+        // Datagrams arrive out of order and the stream repeats the last one, only the newest counts
+        if self.datagram_route.is_some() && datagrams::is_datagram_event(&message.event) {
+            if message.sequence <= self.last_pointer_sequence {
+                return;
+            }
+            self.last_pointer_sequence = message.sequence;
+        }
+
+        if let Err(denial) = self.policy.check(&message.event, &self.held_keys, LocalTime::now()) {
+            match denial {
+                policy::Denial::Chord(_) | policy::Denial::UnknownKey(_) => warn!("Dropped key press from {}: {}", self.peer_addr, denial),
+                _ => debug!("Dropped {:?} from {}: {}", message.event.kind(), self.peer_addr, denial),
+            }
+            return;
+        }
+        // Only the controller changes this machine, setting up the session and asking for control is free
+        let gated = !matches!(
+            message.event,
+            InputEvent::Ping
+                | InputEvent::Pong
+                | InputEvent::Arbitration(_)
+                | InputEvent::ScreenInfo(_)
+                | InputEvent::PointerTransport(_)
+        );
+        if gated && !self.arbiter.allow_input(self.member, &message.event, &self.held_keys, Instant::now()) {
+            debug!("Dropped {:?} from {}: another client is in control", Channel::of(&message.event), self.peer_addr);
+            return;
+        }
+
+        self.held_keys.update(&message.event);
+
+        match message.event {
+            InputEvent::Ping => {
+                // Respond with Pong
+                self.sender.send(Message::new(message.sequence, InputEvent::Pong));
+            }
+            InputEvent::Pong | InputEvent::CursorPosition(_) | InputEvent::RequestRawDeltas(_) => {
+                // Ignore server → client messages
+            }
+            InputEvent::ScreenInfo(client_screens) => {
+                info!(
+                    "Client {} has {} monitor(s)",
+                    self.peer_addr,
+                    client_screens.monitors.len()
+                );
+                self.pointer_mapper = Some(PointerMapper::new(client_screens, self.backend.screen_info()));
+            }
+            InputEvent::SetPointerMode(mode) => self.set_pointer_mode(mode),
+            InputEvent::MouseMove(move_event) if self.pointer_mode.is_relative() => {
+                self.move_relative(&move_event);
+            }
+            InputEvent::MouseMove(mut move_event) => {
+                if let Some(mapper) = &self.pointer_mapper {
+                    if let Some((x, y)) = mapper.map_normalized(move_event.x, move_event.y) {
+                        move_event.x = x;
+                        move_event.y = y;
+                    }
+                }
+                self.simulate(&InputEvent::MouseMove(move_event));
+            }
+            InputEvent::LockState(wanted) => self.sync_locks(&wanted),
+            InputEvent::Keyboard(key_event) => {
+                if self.key_repeat.handle(&key_event) {
+                    self.simulate(&InputEvent::Keyboard(key_event));
+                }
+            }
+            InputEvent::MouseScroll(scroll_event) => {
+                let delta = self.scroll_translator.translate(&scroll_event);
+                if !delta.is_zero() {
+                    if let Err(e) = self.backend.scroll(delta) {
+                        warn!("Failed to scroll: {}", e);
+                    }
+                }
+            }
+            InputEvent::TextInput(text_event) => {
+                let keystrokes = self.composition.update(&text_event);
+                if !keystrokes.is_empty() {
+                    if let Err(e) = self.backend.type_text(&keystrokes) {
+                        warn!("Failed to type text: {}", e);
+                    }
+                }
+            }
+            InputEvent::Clipboard(chunk) => {
+                let result = clipboard_sync::apply_incoming(&mut self.clipboard_sync.lock(), &*self.clipboard, chunk);
+                match result {
+                    Ok(true) => debug!("Clipboard updated from client {}", self.peer_addr),
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to apply clipboard from client {}: {}", self.peer_addr, e),
+                }
+            }
+            InputEvent::FileTransfer(transfer) => self.files.handle(message.sequence, transfer),
+            InputEvent::Arbitration(Arbitration::Request) => self.arbiter.request(self.member, Instant::now()),
+            InputEvent::Arbitration(Arbitration::Release) => self.arbiter.release(self.member, Instant::now()),
+            InputEvent::Arbitration(Arbitration::Status(_)) => {
+                // Ignore server → client messages
+            }
+            InputEvent::PointerTransport(PointerTransport::Request) => self.offer_datagrams(message.sequence),
+            InputEvent::PointerTransport(_) => {
+                // Ignore server → client messages
+            }
+            InputEvent::LeaveScreen => {
+                // The user is heading back to the Mac, take the clipboard along
+                if self.clipboard_sync.lock().settings().mode == ClipboardSyncMode::OnSwitch {
+                    send_clipboard(&*self.clipboard, &self.clipboard_sync, &self.bulk_sender);
+                }
+                self.simulate(&InputEvent::LeaveScreen);
+            }
+            InputEvent::EnterScreen(enter_event) => {
+                // The cursor is placed absolutely, leftover fractions no longer apply
+                self.accumulator.reset();
+                self.simulate(&InputEvent::EnterScreen(enter_event));
+            }
+            event => self.simulate(&event),
+        }
+    }
+
+    /// Count a message that could not be decoded, fails once the client sent too many
+    fn invalid_message(&mut self, error: MuxError) -> Result<()> {
+        warn!("Failed to decode message from {}: {}", self.peer_addr, error);
+        self.access.metrics.invalid_messages.fetch_add(1, Ordering::Relaxed);
+        self.invalid_messages += 1;
+
+        let limit = self.access.settings().max_invalid_messages;
+        if limit > 0 && self.invalid_messages >= limit {
+            self.access.metrics.disconnected.fetch_add(1, Ordering::Relaxed);
+            bail!("Disconnecting after {} invalid messages", self.invalid_messages);
+        }
+        Ok(())
+    }
+
+    /// Answer a client that wants to send pointer events over UDP
+    fn offer_datagrams(&mut self, sequence: u64) {
+        let reply = match self.datagrams.as_ref().map(|receiver| receiver.register(self.peer_addr.ip())) {
+            Some(Ok((offer, route))) => {
+                info!("Client {} sends pointer events over UDP port {}", self.peer_addr, offer.port);
+                self.datagram_route = Some(route);
+                PointerTransport::Offer(offer)
+            }
+            Some(Err(e)) => {
+                warn!("Failed to open a datagram route for {}: {}", self.peer_addr, e);
+                PointerTransport::Unavailable
+            }
+            None => PointerTransport::Unavailable,
+        };
+        self.sender.send(Message::new(sequence, InputEvent::PointerTransport(reply)));
+    }
+
+    fn set_pointer_mode(&mut self, mode: PointerMode) {
+        if mode == self.pointer_mode {
+            return;
+        }
+        info!("Client {} switched pointer mode to {:?}", self.peer_addr, mode);
+
+        let was_game = self.pointer_mode == PointerMode::Game;
+        let is_game = mode == PointerMode::Game;
+        if was_game != is_game {
+            if let Err(e) = self.backend.set_pointer_acceleration(!is_game) {
+                warn!("Failed to change pointer acceleration: {}", e);
+            }
+        }
+
+        self.pointer_mode = mode;
+        self.accumulator.reset();
+    }
+
+    /// Inject the server-side key repeat that is due, if any
+    fn repeat_key(&mut self) {
+        if let Some(repeat) = self.key_repeat.poll() {
+            self.simulate(&InputEvent::Keyboard(repeat));
+        }
+    }
+
+    /// Toggle the lock keys that differ from the client's keyboard
+    fn sync_locks(&self, wanted: &LockState) {
+        for key in lock_keys::keys_to_toggle(wanted, &self.backend.lock_state()) {
+            debug!("Toggling {:?} to match client {}", key, self.peer_addr);
+            if let Err(e) = self.backend.toggle_lock(key) {
+                warn!("Failed to toggle {:?}: {}", key, e);
+            }
+        }
+    }
+
+    /// Apply the movement deltas, carrying sub-pixel fractions over
+    fn move_relative(&mut self, move_event: &MouseMoveEvent) {
+        let (dx, dy) = match &self.pointer_mapper {
+            Some(mapper) => {
+                let (x, y) = mapper.source().denormalize(move_event.x, move_event.y);
+                mapper.map_delta(x, y, move_event.delta_x, move_event.delta_y)
+            }
+            None => (move_event.delta_x, move_event.delta_y),
+        };
+        let (dx, dy) = self.accelerator.apply(dx, dy, move_event.timestamp_us);
+
+        let (dx, dy) = self.accumulator.add(dx, dy);
+        if dx == 0 && dy == 0 {
+            return;
+        }
+        if let Err(e) = self.backend.move_relative(dx, dy) {
+            warn!("Failed to move pointer: {}", e);
+        }
+    }
+
+    fn simulate(&self, event: &InputEvent) {
+        if let Err(e) = self.backend.simulate(event) {
+            warn!("Failed to simulate event: {}", e);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Don't leave keys or buttons stuck down when the client goes away mid-press
+        self.key_repeat.cancel();
+        for release in self.held_keys.release_all() {
+            self.simulate(&release);
+        }
+
+        // Game mode changes a system-wide setting, give it back
+        if self.pointer_mode == PointerMode::Game {
+            if let Err(e) = self.backend.set_pointer_acceleration(true) {
+                warn!("Failed to restore pointer acceleration: {}", e);
+            }
+        }
+
+        self.arbiter.leave(self.member, Instant::now());
+    }
+}
+
+async fn handle_client(
+    mut reader: tls::BoxedReader,
+    writer: tls::BoxedWriter,
+    datagram_route: Option<DatagramRoute>,
+    peer_addr: SocketAddr,
+    server: &Server,
+    policy: ClientPolicy,
+    name: String,
+) -> Result<()> {
+    let backend = server.backend.clone();
+    let clipboard = server.clipboard.clone();
+    let config = &server.config;
+
+    // Replies, reports and clipboard transfers are written by a single task
+    let overflow = Overflow::default();
+    let (sender, receiver) = Outgoing::channel(SEND_QUEUE_SIZE, &overflow);
+    let (bulk_sender, bulk_receiver) = Outgoing::channel(bulk_queue_size(config), &overflow);
+    let (flow_sender, flow_receiver) = mpsc::channel(SEND_QUEUE_SIZE);
+    let writer_task = tokio::spawn(write_loop(writer, receiver, bulk_receiver, flow_receiver));
+    let reporter_task = tokio::spawn(report_cursor(backend.clone(), sender.clone()));
+    let lock_task = tokio::spawn(report_lock_state(backend.clone(), sender.clone()));
+
+    // Tell the client about our monitors so it can map coordinates
+    sender.send(Message::new(0, InputEvent::ScreenInfo(backend.screen_info())));
+    if config.raw_deltas {
+        sender.send(Message::new(0, InputEvent::RequestRawDeltas(true)));
+    }
+
+    let mut session = Session::new(server, peer_addr, policy, name, sender, bulk_sender, datagram_route);
+    let clipboard_task = (config.clipboard.mode == ClipboardSyncMode::Continuous).then(|| {
+        tokio::spawn(report_clipboard(clipboard, session.clipboard_sync.clone(), session.bulk_sender.clone()))
+    });
+
+    let result = read_loop(&mut reader, &mut session, flow_sender, &overflow).await;
+
+    reporter_task.abort();
+    lock_task.abort();
+    if let Some(task) = clipboard_task {
+        task.abort();
+    }
+    drop(session);
+    let _ = writer_task.await;
+
+    result
+}
+
+/// Clipboard chunks queued to a client, room for two transfers of the largest size
+fn bulk_queue_size(config: &ServerConfig) -> usize {
+    2 * (config.clipboard.max_bytes.div_ceil(clipboard_sync::CHUNK_SIZE) + 1)
+}
+
+/// Handle messages from the client until it closes the connection
+///
+/// Flow-control updates go to the writer through `flow`. Fails once the
+/// client stops reading and a queue to it overflows.
+async fn read_loop(
+    reader: &mut (impl AsyncRead + Unpin),
+    session: &mut Session,
+    flow: mpsc::Sender<FlowUpdate>,
+    overflow: &Overflow,
+) -> Result<()> {
+    let mut buffer = vec![0u8; 8192];
+    let mut decoder = MuxDecoder::new(session.access.mux_config());
+
+    loop {
+        let repeat_at = session.key_repeat.next_repeat();
+        let n = tokio::select! {
+            result = reader.read(&mut buffer) => result?,
+            _ = sleep_until(repeat_at), if repeat_at.is_some() => {
+                session.repeat_key();
+                continue;
+            }
+            Some(message) = next_datagram(&mut session.datagram_route) => {
+                session.handle_message(message);
+                continue;
+            }
+            _ = overflow.occurred() => bail!("Client {} does not keep up with the messages sent to it", session.peer_addr),
+        };
+
+        if n == 0 {
+            info!("Client {} closed connection", session.peer_addr);
+            break;
+        }
+
+        decoder.extend(&buffer[..n]);
+
+        // A single read may contain several messages, or only part of one
+        loop {
+            match decoder.next_message() {
+                Ok(Some(message)) => session.handle_message(message),
+                Ok(None) => break,
+                // Continue with the next segment, unless the client keeps sending garbage
+                Err(e) => session.invalid_message(e)?,
+            }
+        }
+        for update in decoder.take_flow_updates() {
+            if let Err(TrySendError::Full(_)) = flow.try_send(update) {
+                bail!("Client {} does not keep up with the messages sent to it", session.peer_addr);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Next pointer message received over UDP (never without a route)
+async fn next_datagram(route: &mut Option<DatagramRoute>) -> Option<Message> {
+    match route {
+        Some(route) => route.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sleep until `deadline` (forever if there is none)
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Write outgoing messages until all senders are gone or the stream fails
+///
+/// Everything queued is handed to the multiplexer before each segment, so
+/// replies and reports never wait for a clipboard transfer to finish. A
+/// client that takes longer than [`WRITE_TIMEOUT`] for a segment is given up.
+async fn write_loop(
+    mut writer: impl AsyncWrite + Unpin,
+    mut receiver: mpsc::Receiver<Message>,
+    mut bulk_receiver: mpsc::Receiver<Message>,
+    mut flow: mpsc::Receiver<FlowUpdate>,
+) {
+    let mut mux = MuxEncoder::new(MuxConfig::default());
+
+    loop {
+        // Checked before draining, so nothing queued before the close is lost
+        let closed = receiver.is_closed() && bulk_receiver.is_closed();
+        while let Ok(update) = flow.try_recv() {
+            mux.apply(update);
+        }
+        while let Ok(message) = receiver.try_recv() {
+            queue(&mut mux, message);
+        }
+        while let Ok(message) = bulk_receiver.try_recv() {
+            queue(&mut mux, message);
+        }
+
+        if let Some(segment) = mux.next_segment() {
+            // Flushing pushes TLS records out right away
+            let write = async {
+                writer.write_all(&segment).await?;
+                writer.flush().await
+            };
+            match tokio::time::timeout(WRITE_TIMEOUT, write).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    warn!("Failed to send message: {}", e);
+                    break;
+                }
+                Err(_) => {
+                    warn!("Client stopped accepting data, closing the connection");
+                    break;
+                }
+            }
+            continue;
+        }
+        if closed {
+            break;
+        }
+
+        // Nothing to send until a message or more window arrives
+        tokio::select! {
+            Some(update) = flow.recv() => mux.apply(update),
+            Some(message) = receiver.recv() => queue(&mut mux, message),
+            Some(message) = bulk_receiver.recv() => queue(&mut mux, message),
+            else => break,
+        }
+    }
+}
+
+/// Hand a message to the multiplexer
+fn queue(mux: &mut MuxEncoder, message: Message) {
+    if let Err(e) = mux.push(&message) {
+        warn!("Failed to send message: {}", e);
+    }
+}
+
+/// Sample the cursor and report changes to the client
+async fn report_cursor(backend: Arc<dyn InputBackend>, sender: Outgoing) {
+    let mut reporter = CursorReporter::new(CURSOR_REPORT_INTERVAL);
+    let mut ticker = tokio::time::interval(CURSOR_POLL_INTERVAL);
+    let mut sequence = 0;
+
+    loop {
+        ticker.tick().await;
+
+        let Some(position) = backend.cursor_position() else {
+            continue;
+        };
+
+        if let Some(report) = reporter.update(position, Instant::now()) {
+            sequence += 1;
+            if !sender.send(Message::new(sequence, InputEvent::CursorPosition(report))) {
+                break;
+            }
+        }
+    }
+}
+
+/// Report the lock keys to the client whenever they change
+async fn report_lock_state(backend: Arc<dyn InputBackend>, sender: Outgoing) {
+    let mut ticker = tokio::time::interval(LOCK_POLL_INTERVAL);
+    let mut last = None;
+    let mut sequence = 0;
+
+    loop {
+        ticker.tick().await;
+
+        let locks = backend.lock_state();
+        if last == Some(locks) {
+            continue;
+        }
+        last = Some(locks);
+
+        sequence += 1;
+        if !sender.send(Message::new(sequence, InputEvent::LockState(locks))) {
+            break;
+        }
+    }
+}
+
+/// Send clipboard changes made on this machine to the client
+async fn report_clipboard(
+    clipboard: Arc<dyn Clipboard>,
+    sync: Arc<Mutex<ClipboardSync>>,
+    sender: Outgoing,
+) {
+    let mut ticker = tokio::time::interval(CLIPBOARD_POLL_INTERVAL);
+    let mut last_change = None;
+
+    loop {
+        ticker.tick().await;
+
+        // Reading the whole clipboard is expensive, skip it while nothing changed
+        let change = clipboard.change_count();
+        if change.is_some() && change == last_change {
+            continue;
+        }
+        last_change = change;
+
+        if !send_clipboard(&*clipboard, &sync, &sender) {
+            break;
+        }
+    }
+}
+
+/// Send the clipboard if it changed since the last sync, returns false once the client is gone
+fn send_clipboard(clipboard: &dyn Clipboard, sync: &Mutex<ClipboardSync>, sender: &Outgoing) -> bool {
+    let content = match clipboard.read() {
+        Ok(content) => content,
+        Err(e) => {
+            warn!("Failed to read clipboard: {}", e);
+            return true;
+        }
+    };
+    let Some(chunks) = sync.lock().outgoing(content) else {
+        return true;
+    };
+
+    debug!("Sending clipboard in {} chunk(s)", chunks.len());
+    chunks
+        .into_iter()
+        .all(|chunk| sender.send(Message::new(0, InputEvent::Clipboard(chunk))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::key_repeat::RepeatMode;
+    use crate::text_input::TextKeystroke;
+    use samesame_protocol::clipboard::{ClipboardContent, MemoryClipboard};
+    use samesame_protocol::mapping::{CoordinateSpace, MonitorInfo, ScreenInfo};
+    use samesame_protocol::{
+        CursorPositionEvent, KeyboardEvent, Modifiers, MomentumPhase, MouseButton, MouseButtonEvent, MouseScrollEvent,
+        ScrollPhase, TextInputEvent,
+    };
+
+    /// Server injecting into a mock, with the given config
+    fn server(config: ServerConfig) -> (Server, Arc<MockBackend>) {
+        let backend = Arc::new(MockBackend::new());
+        let server = Server {
+            backend: backend.clone(),
+            clipboard: Arc::new(MemoryClipboard::new()),
+            arbiter: Arc::new(Arbiter::new(config.arbitration.clone())),
+            access: Arc::new(AccessControl::new(config.access.clone())),
+            pairing: Arc::new(Pairing::new(config.pairing.clone()).unwrap()),
+            sessions: Arc::new(SessionLimits::default()),
+            datagrams: None,
+            acceptor: None,
+            config: Arc::new(config),
+        };
+        (server, backend)
+    }
+
+    /// Session of a client at 127.0.0.1, and what it sends back
+    fn connect(server: &Server) -> (Session, mpsc::Receiver<Message>) {
+        let overflow = Overflow::default();
+        let (sender, receiver) = Outgoing::channel(SEND_QUEUE_SIZE, &overflow);
+        let (bulk_sender, _) = Outgoing::channel(SEND_QUEUE_SIZE, &overflow);
+        let peer_addr = "127.0.0.1:50000".parse().unwrap();
+        let session = Session::new(server, peer_addr, ClientPolicy::default(), "test".into(), sender, bulk_sender, None);
+        (session, receiver)
+    }
+
+    fn send(session: &mut Session, event: InputEvent) {
+        session.handle_message(Message::new(1, event));
+    }
+
+    fn key(key_code: u16, pressed: bool, modifiers: Modifiers) -> InputEvent {
+        InputEvent::Keyboard(KeyboardEvent {
+            key_code,
+            character: None,
+            pressed,
+            modifiers,
+            is_repeat: false,
+        })
+    }
+
+    fn relative_move(delta_x: f64, delta_y: f64) -> InputEvent {
+        InputEvent::MouseMove(MouseMoveEvent {
+            x: 0.5,
+            y: 0.5,
+            delta_x,
+            delta_y,
+            timestamp_us: 0,
+        })
+    }
+
+    fn monitor(id: u32, x: f64, width: f64, height: f64, scale_factor: f64) -> MonitorInfo {
+        MonitorInfo {
+            id,
+            x,
+            y: 0.0,
+            width,
+            height,
+            scale_factor,
+            primary: id == 0,
+        }
+    }
+
+    #[test]
+    fn key_presses_are_injected() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut session, _replies) = connect(&server);
+
+        send(&mut session, key(0x00, true, Modifiers::default()));
+        send(&mut session, key(0x00, false, Modifiers::default()));
+
+        let events = backend.take_events();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], InputEvent::Keyboard(event) if event.key_code == 0x00 && event.pressed));
+        assert!(matches!(&events[1], InputEvent::Keyboard(event) if !event.pressed));
+    }
+
+    #[test]
+    fn blocked_chords_are_not_injected() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut session, _replies) = connect(&server);
+        let control = Modifiers {
+            control: true,
+            ..Modifiers::default()
+        };
+
+        // Control maps to Win, 0x25 is L
+        send(&mut session, key(0x25, true, control));
+        send(&mut session, key(0x25, false, control));
+
+        // The press never reached this machine, so there is nothing to release
+        assert!(backend.take_events().is_empty());
+    }
+
+    #[test]
+    fn unknown_keys_are_not_injected() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut session, _replies) = connect(&server);
+
+        // 0x5B is keypad 8 on the Mac and has no virtual key, as VK_LWIN it would make Win+L
+        send(&mut session, key(0x5B, true, Modifiers::default()));
+        send(&mut session, key(0x25, true, Modifiers::default()));
+
+        let events = backend.take_events();
+        assert_eq!(events.len(), 1, "only the L passes");
+        assert!(matches!(&events[0], InputEvent::Keyboard(event) if event.key_code == 0x25));
+    }
+
+    #[test]
+    fn relative_moves_keep_fractions() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut session, _replies) = connect(&server);
+
+        send(&mut session, InputEvent::SetPointerMode(PointerMode::Relative));
+        for _ in 0..4 {
+            send(&mut session, relative_move(1.5, -0.25));
+        }
+
+        let moves = backend.take_relative_moves();
+        let total = moves.iter().fold((0, 0), |(x, y), (dx, dy)| (x + dx, y + dy));
+        assert_eq!(total, (6, -1));
+        assert!(backend.take_events().is_empty(), "relative moves do not place the cursor");
+    }
+
+    #[test]
+    fn game_mode_disables_acceleration_until_the_client_leaves() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut session, _replies) = connect(&server);
+
+        send(&mut session, InputEvent::SetPointerMode(PointerMode::Game));
+        assert!(!backend.pointer_acceleration());
+        send(&mut session, relative_move(5.0, 0.0));
+        assert_eq!(backend.take_relative_moves(), vec![(5, 0)]);
+
+        drop(session);
+        assert!(backend.pointer_acceleration());
+    }
+
+    #[test]
+    fn absolute_moves_are_mapped_onto_the_server_monitors() {
+        let (server, backend) = server(ServerConfig::default());
+        backend.set_screen_info(ScreenInfo {
+            coordinate_space: CoordinateSpace::Physical,
+            monitors: vec![monitor(0, 0.0, 1920.0, 1080.0, 1.0), monitor(1, 1920.0, 1920.0, 1080.0, 1.0)],
+        });
+        let (mut session, _replies) = connect(&server);
+
+        send(
+            &mut session,
+            InputEvent::ScreenInfo(ScreenInfo {
+                coordinate_space: CoordinateSpace::Logical,
+                monitors: vec![monitor(0, 0.0, 1440.0, 900.0, 2.0)],
+            }),
+        );
+        send(&mut session, relative_move(0.0, 0.0));
+
+        // The middle of the Mac lands in the middle of the primary monitor
+        let events = backend.take_events();
+        let [InputEvent::MouseMove(moved)] = &events[..] else {
+            panic!("expected one move, got {:?}", events);
+        };
+        assert!((moved.x - 0.25).abs() < 0.001, "x = {}", moved.x);
+        assert!((moved.y - 0.5).abs() < 0.001, "y = {}", moved.y);
+    }
+
+    #[test]
+    fn wheel_notches_scroll() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut session, _replies) = connect(&server);
+
+        send(
+            &mut session,
+            InputEvent::MouseScroll(MouseScrollEvent {
+                delta_x: 0.0,
+                delta_y: 1.0,
+                is_pixel_based: false,
+                phase: ScrollPhase::None,
+                momentum_phase: MomentumPhase::None,
+            }),
+        );
+
+        let scrolls = backend.take_scrolls();
+        assert_eq!(scrolls.len(), 1);
+        assert_eq!(scrolls[0].vertical.abs(), 120);
+        assert_eq!(scrolls[0].horizontal, 0);
+    }
+
+    #[test]
+    fn lock_keys_follow_the_client() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut session, _replies) = connect(&server);
+
+        let wanted = LockState {
+            caps_lock: Some(true),
+            num_lock: Some(true),
+            scroll_lock: None,
+        };
+        send(&mut session, InputEvent::LockState(wanted));
+
+        let locks = backend.lock_state();
+        assert_eq!(locks.caps_lock, Some(true));
+        assert_eq!(locks.num_lock, Some(true));
+        assert_eq!(locks.scroll_lock, Some(false));
+    }
+
+    #[test]
+    fn held_keys_are_released_when_the_client_leaves() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut session, _replies) = connect(&server);
+
+        send(&mut session, key(0x00, true, Modifiers::default()));
+        drop(session);
+
+        let events = backend.take_events();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[1], InputEvent::Keyboard(event) if event.key_code == 0x00 && !event.pressed));
+    }
+
+    #[test]
+    fn held_buttons_are_released_when_the_client_leaves() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut session, _replies) = connect(&server);
+
+        let button = |button, pressed| InputEvent::MouseButton(MouseButtonEvent { button, pressed });
+        send(&mut session, button(MouseButton::Left, true));
+        send(&mut session, button(MouseButton::Right, true));
+        send(&mut session, button(MouseButton::Right, false));
+        backend.take_events();
+        drop(session);
+
+        let events = backend.take_events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], InputEvent::MouseButton(MouseButtonEvent { button: MouseButton::Left, pressed: false })));
+    }
+
+    #[test]
+    fn server_repeat_mode_drops_the_client_repeats() {
+        let mut config = ServerConfig::default();
+        config.key_repeat.mode = RepeatMode::Server;
+        let (server, backend) = server(config);
+        let (mut session, _replies) = connect(&server);
+
+        let mut repeat = key(0x00, true, Modifiers::default());
+        if let InputEvent::Keyboard(event) = &mut repeat {
+            event.is_repeat = true;
+        }
+        send(&mut session, key(0x00, true, Modifiers::default()));
+        send(&mut session, repeat);
+        send(&mut session, key(0x00, false, Modifiers::default()));
+
+        assert_eq!(backend.take_events().len(), 2);
+        assert_eq!(session.key_repeat.next_repeat(), None, "released keys stop repeating");
+    }
+
+    #[test]
+    fn composed_text_is_typed_and_replaced() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut session, _replies) = connect(&server);
+        let text = |text: &str, composing| {
+            InputEvent::TextInput(TextInputEvent {
+                text: text.to_string(),
+                composing,
+            })
+        };
+
+        send(&mut session, text("に", true));
+        send(&mut session, text("日", false));
+
+        assert_eq!(
+            backend.take_typed(),
+            vec![TextKeystroke::Unicode(0x306B), TextKeystroke::Backspace, TextKeystroke::Unicode(0x65E5)]
+        );
+        assert!(backend.take_events().is_empty());
+    }
+
+    #[test]
+    fn pings_are_answered() {
+        let (server, _backend) = server(ServerConfig::default());
+        let (mut session, mut replies) = connect(&server);
+
+        session.handle_message(Message::new(42, InputEvent::Ping));
+
+        let reply = std::iter::from_fn(|| replies.try_recv().ok())
+            .find(|reply| matches!(reply.event, InputEvent::Pong))
+            .expect("pong");
+        assert_eq!(reply.sequence, 42);
+    }
+
+    #[test]
+    fn only_the_controller_injects() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut first, _first_replies) = connect(&server);
+        let (mut second, _second_replies) = connect(&server);
+
+        send(&mut second, key(0x00, true, Modifiers::default()));
+        assert!(backend.take_events().is_empty());
+
+        send(&mut first, key(0x01, true, Modifiers::default()));
+        assert_eq!(backend.take_events().len(), 1);
+    }
+
+    #[test]
+    fn waiting_clients_release_only_their_own_keys() {
+        let (server, backend) = server(ServerConfig::default());
+        let (mut first, _first_replies) = connect(&server);
+        let (mut second, _second_replies) = connect(&server);
+
+        send(&mut first, key(0x00, true, Modifiers::default()));
+        send(&mut second, key(0x00, false, Modifiers::default()));
+        assert_eq!(backend.take_events().len(), 1, "the waiting client cannot lift the controller's key");
+
+        send(&mut first, InputEvent::Arbitration(Arbitration::Release));
+        send(&mut first, key(0x00, false, Modifiers::default()));
+        assert!(matches!(&backend.take_events()[..], [InputEvent::Keyboard(event)] if !event.pressed));
+    }
+
+    #[test]
+    fn waiting_clients_cannot_change_the_pointer_mode() {
+        let (server, backend) = server(ServerConfig::default());
+        let (_first, _first_replies) = connect(&server);
+        let (mut second, _second_replies) = connect(&server);
+
+        send(&mut second, InputEvent::SetPointerMode(PointerMode::Game));
+        assert!(backend.pointer_acceleration());
+        assert_eq!(second.pointer_mode, PointerMode::default());
+    }
+
+    #[test]
+    fn throttled_clients_only_release_what_they_hold() {
+        let mut config = ServerConfig::default();
+        config.access.rate.messages_per_sec = 1;
+        config.access.rate.burst = 2;
+        let (server, backend) = server(config);
+        let (mut session, _replies) = connect(&server);
+
+        send(&mut session, key(0x00, true, Modifiers::default()));
+        send(&mut session, key(0x01, true, Modifiers::default()));
+        for _ in 0..10 {
+            send(&mut session, key(0x02, false, Modifiers::default()));
+        }
+        send(&mut session, key(0x00, false, Modifiers::default()));
+
+        assert_eq!(server.access.metrics.rate_limited.load(Ordering::Relaxed), 10);
+        let events = backend.take_events();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[2], InputEvent::Keyboard(event) if event.key_code == 0x00 && !event.pressed));
+    }
+
+    #[test]
+    fn only_the_controller_sets_the_clipboard() {
+        let (server, _backend) = server(ServerConfig::default());
+        let (mut first, _first_replies) = connect(&server);
+        let (mut second, _second_replies) = connect(&server);
+        let clipboard = |text: &str, id| clipboard_sync::split(&ClipboardContent::text(text), id).unwrap();
+
+        for chunk in clipboard("waiting", 1) {
+            send(&mut second, InputEvent::Clipboard(chunk));
+        }
+        assert!(server.clipboard.read().unwrap().is_empty());
+
+        for chunk in clipboard("in control", 2) {
+            send(&mut first, InputEvent::Clipboard(chunk));
+        }
+        assert_eq!(server.clipboard.read().unwrap(), ClipboardContent::text("in control"));
+    }
+
+    #[tokio::test]
+    async fn writer_gives_up_on_a_client_that_stops_reading() {
+        // Nobody reads the other end, so the buffer fills up and the write stalls
+        let (stream, _client) = tokio::io::duplex(64);
+        let overflow = Overflow::default();
+        let (sender, receiver) = Outgoing::channel(SEND_QUEUE_SIZE, &overflow);
+        let (_bulk_sender, bulk_receiver) = Outgoing::channel(SEND_QUEUE_SIZE, &overflow);
+        let (_flow_sender, flow_receiver) = mpsc::channel(SEND_QUEUE_SIZE);
+        let writer = tokio::spawn(write_loop(stream, receiver, bulk_receiver, flow_receiver));
+
+        sender.send(Message::new(1, InputEvent::ScreenInfo(MockBackend::new().screen_info())));
+        let finished = tokio::time::timeout(2 * WRITE_TIMEOUT, writer).await;
+        assert!(finished.is_ok(), "the writer is still waiting for the client");
+    }
+
+    #[tokio::test]
+    async fn cursor_positions_are_reported() {
+        let backend = Arc::new(MockBackend::new());
+        let (sender, mut receiver) = Outgoing::channel(SEND_QUEUE_SIZE, &Overflow::default());
+        let reporter = tokio::spawn(report_cursor(backend.clone(), sender));
+
+        backend.set_cursor_position(Some(CursorPositionEvent {
+            x: 0.25,
+            y: 0.75,
+            screen: 0,
+            edge: None,
+        }));
+        let report = tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await.unwrap().unwrap();
+        reporter.abort();
+
+        let InputEvent::CursorPosition(position) = report.event else {
+            panic!("expected a cursor position, got {:?}", report.event);
+        };
+        assert_eq!((position.x, position.y), (0.25, 0.75));
+    }
+}
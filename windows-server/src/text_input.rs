@@ -0,0 +1,251 @@
+use samesame_protocol::TextInputEvent;
+
+/// One keystroke needed to type a piece of text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextKeystroke {
+    /// A UTF-16 code unit typed through the Unicode input path
+    Unicode(u16),
+    /// Enter, for line breaks (applications ignore Unicode line breaks)
+    Enter,
+    /// Tab
+    Tab,
+    /// Backspace, erasing one character of marked text
+    Backspace,
+}
+
+/// Keystrokes that type `text`
+///
+/// Characters outside the Basic Multilingual Plane (emoji, rare CJK) become
+/// a surrogate pair of two Unicode keystrokes, which Windows reassembles in
+/// the receiving application. "\r\n" counts as a single line break.
+pub fn text_keystrokes(text: &str) -> Vec<TextKeystroke> {
+    let mut keystrokes = Vec::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                keystrokes.push(TextKeystroke::Enter);
+            }
+            '\n' => keystrokes.push(TextKeystroke::Enter),
+            '\t' => keystrokes.push(TextKeystroke::Tab),
+            c => {
+                let mut units = [0u16; 2];
+                keystrokes.extend(c.encode_utf16(&mut units).iter().map(|&unit| TextKeystroke::Unicode(unit)));
+            }
+        }
+    }
+
+    keystrokes
+}
+
+/// Marked text of the client's input method, as typed on this machine
+///
+/// Windows applications cannot show another machine's marked text, so it is
+/// typed like committed text and erased with Backspace as the composition
+/// changes. Only the part after the common prefix is retyped, and the
+/// committed text replaces what is left. One Backspace is sent per
+/// character, which is what applications erase for a surrogate pair.
+#[derive(Debug, Default)]
+pub struct Composition {
+    /// Characters of marked text currently typed
+    shown: Vec<char>,
+}
+
+impl Composition {
+    /// Keystrokes that turn the typed marked text into the text of `event`
+    pub fn update(&mut self, event: &TextInputEvent) -> Vec<TextKeystroke> {
+        // "\r\n" is typed as a single Enter, so it has to be erased as one character
+        let text: Vec<char> = event.text.replace("\r\n", "\n").chars().collect();
+        let common = self.shown.iter().zip(&text).take_while(|(shown, new)| shown == new).count();
+
+        let mut keystrokes = vec![TextKeystroke::Backspace; self.shown.len() - common];
+        keystrokes.extend(text_keystrokes(&text[common..].iter().collect::<String>()));
+
+        self.shown = match event.composing {
+            true => text,
+            false => Vec::new(),
+        };
+        keystrokes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What an application shows after the keystrokes, like a text field would
+    fn apply(field: &mut String, keystrokes: &[TextKeystroke]) {
+        let mut units = Vec::new();
+        let flush = |field: &mut String, units: &mut Vec<u16>| {
+            field.push_str(&String::from_utf16(units).expect("surrogate pairs stay together"));
+            units.clear();
+        };
+        for keystroke in keystrokes {
+            match keystroke {
+                TextKeystroke::Unicode(unit) => units.push(*unit),
+                TextKeystroke::Enter => {
+                    flush(field, &mut units);
+                    field.push('\n');
+                }
+                TextKeystroke::Tab => {
+                    flush(field, &mut units);
+                    field.push('\t');
+                }
+                TextKeystroke::Backspace => {
+                    flush(field, &mut units);
+                    field.pop();
+                }
+            }
+        }
+        flush(field, &mut units);
+    }
+
+    fn typed(text: &str) -> String {
+        let mut field = String::new();
+        apply(&mut field, &text_keystrokes(text));
+        field
+    }
+
+    fn event(text: &str, composing: bool) -> TextInputEvent {
+        TextInputEvent {
+            text: text.to_string(),
+            composing,
+        }
+    }
+
+    #[test]
+    fn basic_multilingual_plane_is_one_unit_per_character() {
+        assert_eq!(
+            text_keystrokes("é漢"),
+            vec![TextKeystroke::Unicode(0xE9), TextKeystroke::Unicode(0x6F22)]
+        );
+    }
+
+    #[test]
+    fn emoji_become_surrogate_pairs() {
+        assert_eq!(
+            text_keystrokes("😀"),
+            vec![TextKeystroke::Unicode(0xD83D), TextKeystroke::Unicode(0xDE00)]
+        );
+        // CJK Extension B
+        assert_eq!(
+            text_keystrokes("𠮷"),
+            vec![TextKeystroke::Unicode(0xD842), TextKeystroke::Unicode(0xDFB7)]
+        );
+    }
+
+    #[test]
+    fn text_round_trips() {
+        for text in [
+            "Grüße, ça va?",
+            "😀👍🏽",
+            // Family: four emoji joined with zero width joiners
+            "👨\u{200D}👩\u{200D}👧\u{200D}👦",
+            // Flag: two regional indicators
+            "🇩🇪",
+            "𠮷野家で🍣",
+            "a\u{0301}",
+        ] {
+            assert_eq!(typed(text), text);
+        }
+    }
+
+    #[test]
+    fn line_breaks_and_tabs_are_keys() {
+        assert_eq!(
+            text_keystrokes("a\r\nb\nc\td"),
+            vec![
+                TextKeystroke::Unicode(0x61),
+                TextKeystroke::Enter,
+                TextKeystroke::Unicode(0x62),
+                TextKeystroke::Enter,
+                TextKeystroke::Unicode(0x63),
+                TextKeystroke::Tab,
+                TextKeystroke::Unicode(0x64),
+            ]
+        );
+    }
+
+    #[test]
+    fn committed_text_without_composition_is_typed() {
+        let mut composition = Composition::default();
+        assert_eq!(composition.update(&event("ok", false)), text_keystrokes("ok"));
+        assert_eq!(composition.update(&event("", false)), Vec::new());
+    }
+
+    #[test]
+    fn composition_is_replaced_by_the_committed_text() {
+        let mut composition = Composition::default();
+        let mut field = String::from("> ");
+
+        // Japanese romaji input: k → か → かn → かな, committed as 仮名
+        for (text, shown) in [("k", "> k"), ("か", "> か"), ("かn", "> かn"), ("かな", "> かな")] {
+            apply(&mut field, &composition.update(&event(text, true)));
+            assert_eq!(field, shown);
+        }
+        apply(&mut field, &composition.update(&event("仮名", false)));
+        assert_eq!(field, "> 仮名");
+
+        // The next composition starts fresh
+        apply(&mut field, &composition.update(&event("a", true)));
+        assert_eq!(field, "> 仮名a");
+    }
+
+    #[test]
+    fn only_the_changed_part_is_retyped() {
+        let mut composition = Composition::default();
+        composition.update(&event("かな", true));
+
+        assert_eq!(
+            composition.update(&event("かなz", true)),
+            vec![TextKeystroke::Unicode(0x7A)]
+        );
+        assert_eq!(
+            composition.update(&event("かに", true)),
+            vec![TextKeystroke::Backspace, TextKeystroke::Backspace, TextKeystroke::Unicode(0x306B)]
+        );
+    }
+
+    #[test]
+    fn emoji_in_marked_text_are_erased_whole() {
+        let mut composition = Composition::default();
+        let mut field = String::new();
+
+        apply(&mut field, &composition.update(&event("😀", true)));
+        apply(&mut field, &composition.update(&event("😀👍", true)));
+        assert_eq!(field, "😀👍");
+
+        // One Backspace per character, not per UTF-16 unit
+        let keystrokes = composition.update(&event("😀", true));
+        assert_eq!(keystrokes, vec![TextKeystroke::Backspace]);
+        apply(&mut field, &keystrokes);
+        assert_eq!(field, "😀");
+
+        apply(&mut field, &composition.update(&event("🙂", false)));
+        assert_eq!(field, "🙂");
+    }
+
+    #[test]
+    fn cancelled_composition_is_erased() {
+        let mut composition = Composition::default();
+        let mut field = String::from("x");
+
+        apply(&mut field, &composition.update(&event("ni", true)));
+        apply(&mut field, &composition.update(&event("", false)));
+        assert_eq!(field, "x");
+    }
+
+    #[test]
+    fn line_breaks_in_marked_text_count_once() {
+        let mut composition = Composition::default();
+        let mut field = String::new();
+
+        apply(&mut field, &composition.update(&event("a\r\nb", true)));
+        apply(&mut field, &composition.update(&event("", false)));
+        assert_eq!(field, "");
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Context, Result};
+use samesame_protocol::pairing::TLS_EXPORTER_LABEL;
+use samesame_protocol::tls::certificate_fingerprint;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::{self, ServerConfig, SupportedProtocolVersion};
+use tokio_rustls::TlsAcceptor;
+use tracing::info;
+
+/// Host name written into a generated certificate (clients pin the fingerprint, not the name)
+const CERTIFICATE_NAME: &str = "samesame-server";
+
+/// Encryption of client connections
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsSettings {
+    /// Accept TLS connections only
+    pub enabled: bool,
+    /// PEM certificate, generated as a self-signed certificate if missing
+    pub certificate: PathBuf,
+    /// PEM private key of the certificate
+    pub key: PathBuf,
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            certificate: PathBuf::from("samesame-cert.pem"),
+            key: PathBuf::from("samesame-key.pem"),
+        }
+    }
+}
+
+pub type BoxedReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type BoxedWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// TLS acceptor for the configured certificate, with the certificate's fingerprint
+pub fn acceptor(settings: &TlsSettings) -> Result<(TlsAcceptor, String)> {
+    let (config, fingerprint) = server_config(settings, rustls::DEFAULT_VERSIONS)?;
+    Ok((TlsAcceptor::from(Arc::new(config)), fingerprint))
+}
+
+/// TLS configuration for the configured certificate, with the certificate's fingerprint
+///
+/// The certificate is created on first start and kept, so the fingerprint
+/// clients pinned stays valid across restarts. QUIC uses the same certificate.
+pub fn server_config(
+    settings: &TlsSettings,
+    versions: &[&'static SupportedProtocolVersion],
+) -> Result<(ServerConfig, String)> {
+    if !settings.certificate.exists() && !settings.key.exists() {
+        generate_certificate(&settings.certificate, &settings.key)?;
+    }
+
+    let certificate = CertificateDer::from_pem_file(&settings.certificate)
+        .with_context(|| format!("Cannot read certificate {}", settings.certificate.display()))?;
+    let key = PrivateKeyDer::from_pem_file(&settings.key)
+        .with_context(|| format!("Cannot read private key {}", settings.key.display()))?;
+    let fingerprint = certificate_fingerprint(&certificate);
+
+    let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_protocol_versions(versions)?
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate], key)
+        .map_err(|e| anyhow!("Invalid certificate or key: {}", e))?;
+
+    Ok((config, fingerprint))
+}
+
+/// Write a new self-signed certificate and its key
+fn generate_certificate(certificate: &Path, key: &Path) -> Result<()> {
+    let generated = rcgen::generate_simple_self_signed(vec![CERTIFICATE_NAME.to_string()])?;
+    for path in [certificate, key] {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    fs::write(certificate, generated.cert.pem())?;
+
+    // Only the owner may read the key, from the moment it exists
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(key)?.write_all(generated.signing_key.serialize_pem().as_bytes())?;
+
+    info!("Generated a self-signed certificate in {}", certificate.display());
+    Ok(())
+}
+
+/// Split a client connection, after the TLS handshake if TLS is enabled
+///
+/// Also returns the channel binding for the pairing handshake: keying
+/// material exported from the TLS session, empty without TLS.
+pub async fn accept(stream: TcpStream, acceptor: Option<&TlsAcceptor>) -> Result<(BoxedReader, BoxedWriter, Vec<u8>)> {
+    match acceptor {
+        Some(acceptor) => {
+            let stream = acceptor.accept(stream).await.context("TLS handshake failed")?;
+            let binding = stream
+                .get_ref()
+                .1
+                .export_keying_material(vec![0u8; 32], TLS_EXPORTER_LABEL, None)
+                .map_err(|e| anyhow!("Cannot export TLS keying material: {}", e))?;
+            let (reader, writer) = tokio::io::split(stream);
+            Ok((Box::new(reader), Box::new(writer), binding))
+        }
+        None => {
+            let (reader, writer) = stream.into_split();
+            Ok((Box::new(reader), Box::new(writer), Vec::new()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_key_is_private() {
+        let directory = std::env::temp_dir().join(format!("samesame-tls-{}", std::process::id()));
+        let settings = TlsSettings {
+            enabled: true,
+            certificate: directory.join("cert.pem"),
+            key: directory.join("key.pem"),
+        };
+        let (_, fingerprint) = server_config(&settings, rustls::DEFAULT_VERSIONS).unwrap();
+        assert_eq!(server_config(&settings, rustls::DEFAULT_VERSIONS).unwrap().1, fingerprint);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&settings.key).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
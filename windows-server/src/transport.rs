@@ -0,0 +1,72 @@
+use anyhow::Result;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::datagram::DatagramRoute;
+use crate::quic::{self, QuicConnection};
+use crate::tls::{self, BoxedReader, BoxedWriter};
+
+/// A new client connection, before any handshake
+pub enum Incoming {
+    Tcp(TcpStream),
+    Quic(Box<quinn::Incoming>),
+}
+
+impl Incoming {
+    /// Turn the client away without a handshake
+    pub fn refuse(self) {
+        if let Incoming::Quic(incoming) = self {
+            (*incoming).refuse();
+        }
+    }
+}
+
+/// A client connection after the transport handshake
+///
+/// The pairing handshake runs on `reader` and `writer` the same way on every
+/// transport. Afterwards [`Connection::into_session`] gives the streams the
+/// messages are exchanged on.
+pub struct Connection {
+    pub reader: BoxedReader,
+    pub writer: BoxedWriter,
+    /// Channel binding for the pairing handshake, empty without encryption
+    pub binding: Vec<u8>,
+    quic: Option<QuicConnection>,
+}
+
+impl Connection {
+    /// TLS (if `acceptor` is set) or QUIC handshake
+    pub async fn accept(incoming: Incoming, acceptor: Option<&TlsAcceptor>) -> Result<Self> {
+        match incoming {
+            Incoming::Tcp(stream) => {
+                let (reader, writer, binding) = tls::accept(stream, acceptor).await?;
+                Ok(Self {
+                    reader,
+                    writer,
+                    binding,
+                    quic: None,
+                })
+            }
+            Incoming::Quic(incoming) => {
+                let (reader, writer, binding, quic) = quic::accept(*incoming).await?;
+                Ok(Self {
+                    reader,
+                    writer,
+                    binding,
+                    quic: Some(quic),
+                })
+            }
+        }
+    }
+
+    /// Streams for the messages of the session, and its pointer datagrams if the transport carries them
+    pub fn into_session(self) -> (BoxedReader, BoxedWriter, Option<DatagramRoute>) {
+        match self.quic {
+            Some(quic) => {
+                let (reader, writer, connection) = quic.into_streams(self.reader, self.writer);
+                (reader, writer, Some(DatagramRoute::quic(connection)))
+            }
+            None => (self.reader, self.writer, None),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! The server's announcement seen by a second mDNS daemon, as a client on this machine sees it
+
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent};
+use samesame_protocol::discovery::{Announcement, SERVICE_TYPE};
+use samesame_windows_server::discovery;
+use std::time::Duration;
+
+/// Longest wait for an announcement to arrive or go away
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Daemon browsing for servers, on loopback like the client
+fn browser() -> (ServiceDaemon, mdns_sd::Receiver<ServiceEvent>) {
+    let daemon = ServiceDaemon::new().unwrap();
+    daemon.enable_interface(IfKind::LoopbackV4).unwrap();
+    let events = daemon.browse(SERVICE_TYPE).unwrap();
+    (daemon, events)
+}
+
+/// Wait for an event about the instance `name`
+async fn next_event(
+    events: &mdns_sd::Receiver<ServiceEvent>,
+    name: &str,
+    mut wanted: impl FnMut(&ServiceEvent) -> bool,
+) -> ServiceEvent {
+    let fullname = format!("{}.{}", name, SERVICE_TYPE);
+    let receive = async {
+        loop {
+            let event = events.recv_async().await.unwrap();
+            let about = match &event {
+                ServiceEvent::ServiceResolved(info) => info.get_fullname() == fullname,
+                ServiceEvent::ServiceRemoved(_, removed) => *removed == fullname,
+                _ => false,
+            };
+            if about && wanted(&event) {
+                return event;
+            }
+        }
+    };
+    tokio::time::timeout(TIMEOUT, receive).await.expect("no announcement on loopback")
+}
+
+#[tokio::test]
+async fn announcement_is_found_and_withdrawn() {
+    // Unique per run, so a server announced by another test run does not answer
+    let name = format!("Discovery Test {}", std::process::id());
+    let fingerprint = vec!["AB"; 32].join(":");
+    let announcement = Announcement::new(&name, 24800, true, Some(fingerprint), Some(24801));
+    let (daemon, events) = browser();
+    let announcer = discovery::announce(announcement.clone()).unwrap();
+
+    let resolved = next_event(&events, &name, |event| matches!(event, ServiceEvent::ServiceResolved(_))).await;
+    let ServiceEvent::ServiceResolved(info) = resolved else {
+        unreachable!()
+    };
+    let found = Announcement::from_properties(&name, info.get_port(), |key| info.get_property_val_str(key)).unwrap();
+    assert_eq!(found, announcement);
+    assert!(found.is_compatible());
+    assert!(!info.get_addresses().is_empty());
+
+    // Dropping the announcer says goodbye, the browser forgets the server
+    drop(announcer);
+    next_event(&events, &name, |event| matches!(event, ServiceEvent::ServiceRemoved(..))).await;
+    let _ = daemon.shutdown();
+}
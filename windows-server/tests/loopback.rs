@@ -0,0 +1,282 @@
+//! A client speaking the wire protocol to a server over loopback TCP
+
+use samesame_protocol::clipboard::MemoryClipboard;
+use samesame_protocol::datagram::{DatagramCodec, LossSimulator, PointerTransport};
+use samesame_protocol::pairing::{self, ClientHello, ServerHello};
+use samesame_protocol::{
+    EventKind, InputEvent, KeyboardEvent, LockState, Message, Modifiers, MouseButton, MouseButtonEvent, MouseMoveEvent,
+    MuxConfig, MuxDecoder, MuxEncoder, TextInputEvent,
+};
+use samesame_windows_server::access::AccessControl;
+use samesame_windows_server::arbiter::Arbiter;
+use samesame_windows_server::backend::MockBackend;
+use samesame_windows_server::config::ServerConfig;
+use samesame_windows_server::datagram::DatagramReceiver;
+use samesame_windows_server::pairing::Pairing;
+use samesame_windows_server::policy::SessionLimits;
+use samesame_windows_server::transport::Incoming;
+use samesame_windows_server::{serve, Server};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+/// Longest wait for an answer from the server
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Server with a mock backend, accepting connections on a loopback port
+async fn start_server(mut config: ServerConfig) -> (SocketAddr, Arc<MockBackend>) {
+    config.pairing.required = false;
+    let backend = Arc::new(MockBackend::new());
+    let datagrams = match config.datagrams.enabled {
+        true => Some(DatagramReceiver::bind(&config.datagrams).await.unwrap()),
+        false => None,
+    };
+    let server = Arc::new(Server {
+        backend: backend.clone(),
+        clipboard: Arc::new(MemoryClipboard::new()),
+        arbiter: Arc::new(Arbiter::new(config.arbitration.clone())),
+        access: Arc::new(AccessControl::new(config.access.clone())),
+        pairing: Arc::new(Pairing::new(config.pairing.clone()).unwrap()),
+        sessions: Arc::new(SessionLimits::default()),
+        datagrams,
+        acceptor: None,
+        config: Arc::new(config),
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, peer_addr)) = listener.accept().await {
+            tokio::spawn(serve(server.clone(), Incoming::Tcp(stream), peer_addr));
+        }
+    });
+    (addr, backend)
+}
+
+/// The client side of a session: the handshake, then multiplexed messages
+struct Client {
+    stream: TcpStream,
+    encoder: MuxEncoder,
+    decoder: MuxDecoder,
+    sequence: u64,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr) -> Self {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&pairing::encode_frame(&ClientHello::Anonymous).unwrap()).await.unwrap();
+
+        let mut prefix = [0u8; 4];
+        stream.read_exact(&mut prefix).await.unwrap();
+        let mut body = vec![0u8; pairing::frame_length(prefix).unwrap()];
+        stream.read_exact(&mut body).await.unwrap();
+        let hello: ServerHello = pairing::decode_frame(&body).unwrap();
+        assert!(matches!(hello, ServerHello::Accepted), "{:?}", hello);
+
+        Self {
+            stream,
+            encoder: MuxEncoder::new(MuxConfig::default()),
+            decoder: MuxDecoder::new(MuxConfig::default()),
+            sequence: 0,
+        }
+    }
+
+    async fn send(&mut self, event: InputEvent) {
+        self.sequence += 1;
+        self.send_message(&Message::new(self.sequence, event)).await;
+    }
+
+    async fn send_message(&mut self, message: &Message) {
+        self.encoder.push(message).unwrap();
+        while let Some(segment) = self.encoder.next_segment() {
+            self.stream.write_all(&segment).await.unwrap();
+        }
+    }
+
+    /// Read messages until one matches, the server's window updates are applied on the way
+    async fn receive_until(&mut self, mut wanted: impl FnMut(&InputEvent) -> bool) -> InputEvent {
+        let receive = async {
+            let mut buffer = vec![0u8; 8192];
+            loop {
+                while let Some(message) = self.decoder.next_message().unwrap() {
+                    if wanted(&message.event) {
+                        return message.event;
+                    }
+                }
+                for update in self.decoder.take_flow_updates() {
+                    self.encoder.apply(update);
+                }
+                let n = self.stream.read(&mut buffer).await.unwrap();
+                assert!(n > 0, "server closed the connection");
+                self.decoder.extend(&buffer[..n]);
+            }
+        };
+        tokio::time::timeout(TIMEOUT, receive).await.expect("no answer from the server")
+    }
+
+    /// Wait until the server has handled everything sent before
+    async fn sync(&mut self) {
+        self.send(InputEvent::Ping).await;
+        self.receive_until(|event| matches!(event, InputEvent::Pong)).await;
+    }
+}
+
+fn key(key_code: u16, pressed: bool) -> InputEvent {
+    InputEvent::Keyboard(KeyboardEvent {
+        key_code,
+        character: None,
+        pressed,
+        modifiers: Modifiers::default(),
+        is_repeat: false,
+    })
+}
+
+#[tokio::test]
+async fn input_reaches_the_backend_in_order() {
+    let (addr, backend) = start_server(ServerConfig::default()).await;
+    let mut client = Client::connect(addr).await;
+
+    let events = vec![
+        key(0x00, true),
+        key(0x00, false),
+        InputEvent::MouseButton(MouseButtonEvent {
+            button: MouseButton::Left,
+            pressed: true,
+        }),
+        InputEvent::MouseButton(MouseButtonEvent {
+            button: MouseButton::Left,
+            pressed: false,
+        }),
+    ];
+    for event in &events {
+        client.send(event.clone()).await;
+    }
+    client.sync().await;
+
+    let applied: Vec<String> = backend
+        .take_events()
+        .iter()
+        .filter(|event| event.kind() != EventKind::Control)
+        .map(|event| format!("{:?}", event))
+        .collect();
+    let sent: Vec<String> = events.iter().map(|event| format!("{:?}", event)).collect();
+    assert_eq!(applied, sent);
+}
+
+#[tokio::test]
+async fn server_reports_its_lock_keys() {
+    let (addr, backend) = start_server(ServerConfig::default()).await;
+    let locks = LockState {
+        caps_lock: Some(true),
+        num_lock: Some(false),
+        scroll_lock: Some(false),
+    };
+    backend.set_lock_state(locks);
+    let mut client = Client::connect(addr).await;
+
+    let reported = client.receive_until(|event| matches!(event, InputEvent::LockState(_))).await;
+    assert!(matches!(reported, InputEvent::LockState(reported) if reported == locks));
+}
+
+#[tokio::test]
+async fn large_messages_cross_in_segments() {
+    let (addr, backend) = start_server(ServerConfig::default()).await;
+    let mut client = Client::connect(addr).await;
+
+    // Larger than a mux segment, so the server reassembles it
+    let text = "ä".repeat(20_000);
+    client
+        .send(InputEvent::TextInput(TextInputEvent {
+            text: text.clone(),
+            composing: false,
+        }))
+        .await;
+    client.sync().await;
+
+    assert_eq!(backend.take_typed().len(), text.chars().count());
+}
+
+fn mouse_move(x: f64, y: f64, delta: f64) -> InputEvent {
+    InputEvent::MouseMove(MouseMoveEvent {
+        x,
+        y,
+        delta_x: delta,
+        delta_y: delta,
+        timestamp_us: 0,
+    })
+}
+
+/// Position of the last absolute move the backend received
+fn last_position(events: &[InputEvent]) -> Option<(f64, f64)> {
+    events.iter().rev().find_map(|event| match event {
+        InputEvent::MouseMove(move_event) => Some((move_event.x, move_event.y)),
+        _ => None,
+    })
+}
+
+#[tokio::test]
+async fn pointer_converges_over_lossy_datagrams() {
+    let mut config = ServerConfig::default();
+    config.datagrams.enabled = true;
+    config.datagrams.port = 0;
+    let (addr, backend) = start_server(config).await;
+    let mut client = Client::connect(addr).await;
+
+    client.send(InputEvent::PointerTransport(PointerTransport::Request)).await;
+    let reply = client.receive_until(|event| matches!(event, InputEvent::PointerTransport(_))).await;
+    let InputEvent::PointerTransport(PointerTransport::Offer(offer)) = reply else {
+        panic!("no datagram offer: {:?}", reply);
+    };
+    let codec = DatagramCodec::from_offer(&offer);
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(("127.0.0.1", offer.port)).await.unwrap();
+
+    // About 30% of the moves get lost, the last one always; the stream repeats the last move now and then
+    let mut loss = LossSimulator::new(0.3, 42);
+    let mut lost = 0;
+    let steps = 200;
+    for step in 1..=steps {
+        client.sequence += 1;
+        let position = step as f64 / steps as f64;
+        let message = Message::new(client.sequence, mouse_move(position, 1.0 - position, 1.0));
+        if loss.drop_next() || step == steps {
+            lost += 1;
+        } else {
+            socket.send(&codec.seal(&message).unwrap()).await.unwrap();
+        }
+
+        // The resync: same sequence, position only
+        if step % 25 == 0 {
+            let resync = Message::new(message.sequence, mouse_move(position, 1.0 - position, 0.0));
+            client.send_message(&resync).await;
+        }
+    }
+    assert!(lost > steps / 5, "only {} of {} moves lost", lost, steps);
+
+    // Datagrams and the stream race, wait until the newest position is in
+    let mut events = Vec::new();
+    let converged = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            client.sync().await;
+            events.extend(backend.take_events());
+            if last_position(&events) == Some((1.0, 0.0)) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert!(converged.is_ok(), "pointer stopped at {:?}", last_position(&events));
+
+    // Only newer moves are applied, a resync never moves the pointer back
+    let xs: Vec<f64> = events
+        .iter()
+        .filter_map(|event| match event {
+            InputEvent::MouseMove(move_event) => Some(move_event.x),
+            _ => None,
+        })
+        .collect();
+    assert!(xs.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", xs);
+}